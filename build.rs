@@ -0,0 +1,11 @@
+fn main() {
+    // protoc comes with the build, so none has to be installed
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("Bundled protoc");
+    std::env::set_var("PROTOC", protoc);
+    println!("cargo:rerun-if-changed=src/transaction_system.proto");
+    // Metadata is kept in key order, which signatures cover
+    tonic_prost_build::configure()
+        .btree_map(".transaction_system.Transaction.metadata")
+        .compile_protos(&["src/transaction_system.proto"], &["src"])
+        .expect("Valid service definition");
+}
@@ -1,57 +1,610 @@
-use super::{Transaction, TransactionType};
-use serde::{Serialize, Serializer};
-use std::collections::{HashMap, VecDeque};
-use std::fmt;
-
-fn serialize_w_precision<S>(x: &f32, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let x = (x * 10000.0).round() / 10000.0;
-    s.serialize_f32(x)
-}
+use crate::currency::Currency;
+use crate::double_entry::{self, LedgerAccount, Posting};
+use crate::engine::OutcomeStatus;
+use crate::events::AccountEvent;
+use crate::fees::{FeeEntry, FeeSchedule};
+use crate::fraud::{FraudHit, FraudRules, Verdict};
+use crate::history::HistoryWindow;
+use crate::interest::{self, InterestPosting};
+use crate::ledger::LedgerEntry;
+use crate::limits::{Limits, MILLIS_PER_MINUTE};
+use crate::money::{Money, MoneyFormat};
+use crate::snapshot::{AccountSnapshot, HistoryState};
+use crate::statement::StatementLine;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
 
-#[derive(Debug)]
-pub enum TransactionProcessingError {
+/// Why a transaction couldn't be applied, see [`TransactionProcessingError`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ErrorKind {
+    #[error("No transaction to process")]
     NoTransactionToProcess,
+    #[error("Account is locked")]
     AccountLocked(u32),
+    #[error("Amount is missing or not allowed for the transaction")]
     InvalidAmount,
+    #[error("Amount is negative")]
     NegativeAmount,
+    #[error("Not enough available funds")]
     InsufficientAmount,
+    #[error("Disputed transaction can't be disputed")]
     InvalidDisputeTarget,
+    #[error("Transaction is not under dispute")]
     TransactionNotUnderDispute,
+    #[error("Client {0} is outside the partition")]
+    ClientOutsidePartition(u16),
+    #[error("Transaction id {0} was seen before")]
+    DuplicateTransactionId(u32),
+    /// Carries the id the exact list of seen ids couldn't be checked or extended with
+    #[error("Transaction id {0} couldn't be checked for duplicates")]
+    DuplicateCheckFailed(u32),
+    /// New transaction id not above the ids of the state the engine was restored from
+    #[error("Transaction id {0} isn't above the ids of the restored state")]
+    TransactionIdRegression(u32),
+    #[error("Invariant violated: {0}")]
+    InvariantViolation(&'static str),
+    #[error("Conversion is invalid")]
+    InvalidConversion,
+    #[error("No exchange rate for the currencies")]
+    MissingExchangeRate,
+    #[error("Transfer is invalid")]
+    InvalidTransfer,
+    #[error("Account is not locked")]
+    AccountNotLocked,
+    #[error("Administrative operations are not allowed")]
+    AdminOperationNotAllowed,
+    #[error("Reason code is invalid")]
+    InvalidReasonCode,
+    #[error("Refunded transaction can't be refunded")]
+    InvalidRefundTarget,
+    #[error("Refund exceeds what is left of the deposit")]
+    RefundExceedsDeposit,
+    #[error("Dispute exceeds what is left of the transaction")]
+    DisputeExceedsTransaction,
+    #[error("Transaction wasn't charged back")]
+    InvalidRepresentmentTarget,
+    #[error("Account is closed")]
+    AccountClosed,
+    #[error("Account still holds funds")]
+    AccountNotEmpty,
+    #[error("Overdraft limit exceeded")]
+    OverdraftExceeded,
+    #[error("Authorization is invalid")]
+    InvalidAuthorization,
+    #[error("Authorization expired")]
+    AuthorizationExpired,
+    #[error("Capture exceeds the authorization")]
+    CaptureExceedsAuthorization,
+    #[error("Per transaction limit exceeded")]
+    TransactionLimitExceeded,
+    /// Carries the number of withdrawals already made that day
+    #[error("Daily withdrawal limit exceeded after {0} withdrawals")]
+    DailyLimitExceeded(u32),
+    /// Carries the number of transactions accepted within the last minute
+    #[error("Velocity limit exceeded after {0} transactions within a minute")]
+    VelocityLimitExceeded(u32),
+    /// Carries the name of the fraud rule that blocked the transaction
+    #[error("Blocked by fraud rule {0}")]
+    FraudBlocked(&'static str),
+    /// Carries the id of a spilled history entry that couldn't be read back
+    #[error("History entry of transaction {0} is unavailable")]
+    HistoryUnavailable(u32),
+    /// The worker of the recipient of a transfer stopped before lending its account
+    #[error("Account of the recipient is unavailable")]
+    RecipientUnavailable,
+}
+
+impl ErrorKind {
+    /// Stable numeric code of the error, for machines to tell errors apart.
+    ///
+    /// Codes are grouped by their hundreds: 1xx invalid transactions, 2xx transaction ids,
+    /// 3xx the account's state, 4xx references to earlier transactions, 5xx limits and
+    /// fraud rules and 9xx internal failures. A code never changes its meaning.
+    pub fn code(&self) -> u16 {
+        self.describe().0
+    }
+
+    /// Stable snake_case name of the error, e.g. `insufficient_amount`, that goes with its
+    /// [code](ErrorKind::code). Unlike the message it carries none of the error's values.
+    pub fn reason(&self) -> &'static str {
+        self.describe().1
+    }
+
+    fn describe(&self) -> (u16, &'static str) {
+        use ErrorKind::*;
+        match self {
+            NoTransactionToProcess => (100, "no_transaction_to_process"),
+            InvalidAmount => (101, "invalid_amount"),
+            NegativeAmount => (102, "negative_amount"),
+            InvalidConversion => (103, "invalid_conversion"),
+            MissingExchangeRate => (104, "missing_exchange_rate"),
+            InvalidTransfer => (105, "invalid_transfer"),
+            InvalidReasonCode => (106, "invalid_reason_code"),
+            AdminOperationNotAllowed => (107, "admin_operation_not_allowed"),
+            ClientOutsidePartition(_) => (108, "client_outside_partition"),
+            DuplicateTransactionId(_) => (200, "duplicate_transaction_id"),
+            DuplicateCheckFailed(_) => (201, "duplicate_check_failed"),
+            TransactionIdRegression(_) => (202, "transaction_id_regression"),
+            AccountLocked(_) => (300, "account_locked"),
+            AccountNotLocked => (301, "account_not_locked"),
+            AccountClosed => (302, "account_closed"),
+            AccountNotEmpty => (303, "account_not_empty"),
+            InsufficientAmount => (304, "insufficient_amount"),
+            OverdraftExceeded => (305, "overdraft_exceeded"),
+            InvalidDisputeTarget => (400, "invalid_dispute_target"),
+            TransactionNotUnderDispute => (401, "transaction_not_under_dispute"),
+            DisputeExceedsTransaction => (402, "dispute_exceeds_transaction"),
+            InvalidRepresentmentTarget => (403, "invalid_representment_target"),
+            InvalidRefundTarget => (404, "invalid_refund_target"),
+            RefundExceedsDeposit => (405, "refund_exceeds_deposit"),
+            InvalidAuthorization => (406, "invalid_authorization"),
+            AuthorizationExpired => (407, "authorization_expired"),
+            CaptureExceedsAuthorization => (408, "capture_exceeds_authorization"),
+            TransactionLimitExceeded => (500, "transaction_limit_exceeded"),
+            DailyLimitExceeded(_) => (501, "daily_limit_exceeded"),
+            VelocityLimitExceeded(_) => (502, "velocity_limit_exceeded"),
+            FraudBlocked(_) => (503, "fraud_blocked"),
+            InvariantViolation(_) => (900, "invariant_violation"),
+            HistoryUnavailable(_) => (901, "history_unavailable"),
+            RecipientUnavailable => (902, "recipient_unavailable"),
+        }
+    }
+}
+
+/// Serialized as its code, its reason and its message.
+impl Serialize for ErrorKind {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut error = serializer.serialize_struct("ErrorKind", 3)?;
+        error.serialize_field("code", &self.code())?;
+        error.serialize_field("reason", self.reason())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
+
+/// Transaction the engine refused to apply: which client's transaction it was, its amount
+/// and why it was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize)]
+#[error("Transaction {tx} of client {client}{} rejected: {kind}", over(.amount))]
+pub struct TransactionProcessingError {
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Money>,
+    /// Serialized flattened into its code, reason and message
+    #[serde(flatten)]
+    pub kind: ErrorKind,
+}
+
+fn over(amount: &Option<Money>) -> String {
+    amount.map_or_else(String::new, |amount| format!(" over {}", amount))
+}
+
+impl TransactionProcessingError {
+    pub fn new(transaction: &Transaction, kind: ErrorKind) -> Self {
+        Self {
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: transaction.amount,
+            kind,
+        }
+    }
+
+    /// See [`ErrorKind::code`].
+    pub fn code(&self) -> u16 {
+        self.kind.code()
+    }
+
+    /// See [`ErrorKind::reason`].
+    pub fn reason(&self) -> &'static str {
+        self.kind.reason()
+    }
+}
+
+/// Reason codes of adjustments are short identifiers like `FEE_REVERSAL`.
+fn is_valid_reason_code(reason: &str) -> bool {
+    !reason.is_empty()
+        && reason.len() <= 32
+        && reason
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputeState {
+    #[default]
+    None,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    /// The chargeback was reversed after a successful representment
+    Represented,
+}
+
+impl DisputeState {
+    /// Stable snake_case name of the state in reports and events.
+    pub fn name(self) -> &'static str {
+        match self {
+            DisputeState::None => "none",
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+            DisputeState::Represented => "represented",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthorizationState {
+    /// Funds are held until the authorization is captured, voided or expires
+    Authorized,
+    Captured,
+    Voided,
+    Expired,
+}
+
+/// How chargebacks affect an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChargebackPolicy {
+    /// Lock the account once a chargeback went through
+    pub lock_account: bool,
+    /// Let disputes of already spent deposits hold more than is available, driving
+    /// available funds negative until the chargeback
+    pub allow_negative_available: bool,
+    /// Keep accepting deposits on accounts locked by a chargeback
+    pub deposits_when_locked: bool,
+    /// Keep processing disputes, resolves and chargebacks on locked accounts
+    pub disputes_when_locked: bool,
+}
+
+impl Default for ChargebackPolicy {
+    fn default() -> Self {
+        Self {
+            lock_account: true,
+            allow_negative_available: true,
+            deposits_when_locked: false,
+            disputes_when_locked: false,
+        }
+    }
+}
+
+/// Transaction kept in an account's history together with its dispute lifecycle.
+///
+/// Disputes, refunds and authorizations only need the type, currency and amount of the
+/// transaction, so that's all an entry keeps unless its account retains full history. The
+/// amount is kept as minor units, and what few entries need is only allocated for them.
+#[derive(Debug)]
+pub struct HistoryEntry {
+    transaction_type: TransactionType,
+    /// Amount in minor units of `scale`
+    units: i64,
+    scale: u8,
+    currency: Option<Currency>,
+    dispute_state: DisputeState,
+    authorization_state: Option<AuthorizationState>,
+    details: Option<Box<EntryDetails>>,
+}
+
+/// Parts of a history entry most entries never need.
+#[derive(Debug, Default)]
+struct EntryDetails {
+    /// Every dispute state the transaction went through, in order
+    dispute_lifecycle: Vec<DisputeState>,
+    refunded: Money,
+    disputed: Money,
+    expires_at: Option<Timestamp>,
+    /// Metadata of the transaction when the transaction itself isn't kept
+    metadata: BTreeMap<String, String>,
+    /// Kept with full history, or when the amount doesn't fit minor units
+    transaction: Option<Transaction>,
+}
+
+static NO_METADATA: BTreeMap<String, String> = BTreeMap::new();
+
+impl HistoryEntry {
+    fn new(transaction: Transaction, full: bool) -> Self {
+        let authorization_state = (transaction.transaction_type == TransactionType::Authorize)
+            .then_some(AuthorizationState::Authorized);
+        let minor_units = transaction
+            .amount
+            .expect("Transaction stored in transaction_history is valid")
+            .to_minor_units();
+        let (units, scale) = minor_units.unwrap_or_default();
+        let mut entry = Self {
+            transaction_type: transaction.transaction_type.clone(),
+            units,
+            scale: scale as u8,
+            currency: transaction.currency.clone(),
+            dispute_state: DisputeState::None,
+            authorization_state,
+            details: None,
+        };
+        if transaction.expires_at.is_some() {
+            entry.details_mut().expires_at = transaction.expires_at;
+        }
+        if full || minor_units.is_none() {
+            entry.details_mut().transaction = Some(transaction);
+        } else if !transaction.metadata.is_empty() {
+            entry.details_mut().metadata = transaction.metadata;
+        }
+        entry
+    }
+
+    fn details_mut(&mut self) -> &mut EntryDetails {
+        self.details.get_or_insert_with(Box::default)
+    }
+
+    /// Lifecycle of an `authorize` transaction, `None` for every other type.
+    pub fn authorization_state(&self) -> Option<AuthorizationState> {
+        self.authorization_state
+    }
+
+    pub fn transaction_type(&self) -> &TransactionType {
+        &self.transaction_type
+    }
+
+    pub fn amount(&self) -> Money {
+        match self.transaction() {
+            Some(transaction) => transaction
+                .amount
+                .expect("Transaction stored in transaction_history is valid"),
+            None => Money::new(self.units, self.scale as u32),
+        }
+    }
+
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    /// The whole transaction, only kept by accounts retaining full history.
+    pub fn transaction(&self) -> Option<&Transaction> {
+        self.details.as_ref()?.transaction.as_ref()
+    }
+
+    /// Input columns of the transaction the engine doesn't know, by name.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        match (self.transaction(), &self.details) {
+            (Some(transaction), _) => &transaction.metadata,
+            (None, Some(details)) => &details.metadata,
+            (None, None) => &NO_METADATA,
+        }
+    }
+
+    pub fn dispute_state(&self) -> DisputeState {
+        self.dispute_state
+    }
+
+    /// Dispute states the transaction went through, empty if it was never disputed.
+    pub fn dispute_lifecycle(&self) -> &[DisputeState] {
+        self.details
+            .as_ref()
+            .map_or(&[], |details| &details.dispute_lifecycle)
+    }
+
+    /// Part of a deposit already returned by refunds.
+    pub fn refunded(&self) -> Money {
+        self.details
+            .as_ref()
+            .map_or(Money::ZERO, |details| details.refunded)
+    }
+
+    /// Amount held by the open dispute, or taken back by the chargeback.
+    pub fn disputed(&self) -> Money {
+        self.details
+            .as_ref()
+            .map_or(Money::ZERO, |details| details.disputed)
+    }
+
+    fn expires_at(&self) -> Option<Timestamp> {
+        self.details.as_ref()?.expires_at
+    }
+
+    /// Amount a dispute puts on hold: the transaction's amount minus anything refunded.
+    fn disputable_amount(&self) -> Money {
+        self.amount() - self.refunded()
+    }
+
+    /// State to persist or spill the entry of transaction `tx` of `client` with.
+    fn state(&self, client: u16, tx: u32) -> HistoryState {
+        let transaction = match self.transaction() {
+            Some(transaction) => transaction.into(),
+            None => {
+                let mut transaction = Transaction::new(
+                    self.transaction_type.clone(),
+                    client,
+                    tx,
+                    Some(self.amount()),
+                );
+                transaction.currency = self.currency.clone();
+                transaction.expires_at = self.expires_at();
+                transaction.metadata = self.metadata().clone();
+                (&transaction).into()
+            }
+        };
+        HistoryState {
+            transaction,
+            dispute_state: self.dispute_state,
+            dispute_lifecycle: self.dispute_lifecycle().to_vec(),
+            refunded: self.refunded(),
+            disputed: self.disputed(),
+            authorization_state: self.authorization_state,
+        }
+    }
+
+    fn from_state(state: HistoryState, full: bool) -> Self {
+        let mut entry = Self::new(state.transaction.into(), full);
+        entry.dispute_state = state.dispute_state;
+        entry.authorization_state = state.authorization_state;
+        if !state.dispute_lifecycle.is_empty()
+            || state.refunded != Money::ZERO
+            || state.disputed != Money::ZERO
+        {
+            let details = entry.details_mut();
+            details.dispute_lifecycle = state.dispute_lifecycle;
+            details.refunded = state.refunded;
+            details.disputed = state.disputed;
+        }
+        entry
+    }
+}
+
+/// Funds of an account in a single currency.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    available: Money,
+    held: Money,
+    total: Money,
+}
+
+/// Transfer to a client of another partition whose amount left the account, waiting for
+/// that partition to credit or refuse it, see [`Account::transfer_out`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutgoingTransfer {
+    pub to_client: u16,
+    pub currency: Option<Currency>,
+    pub amount: Money,
 }
 
-impl fmt::Display for TransactionProcessingError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Transaction processing failed {:?}", self)
+/// Transfer from a client of another partition credited to the account, kept so that a
+/// prepare delivered again is recognized rather than credited twice, see
+/// [`Account::transfer_in`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IncomingTransfer {
+    pub from_client: u16,
+    pub currency: Option<Currency>,
+    pub amount: Money,
+}
+
+/// Balances and status of an account at one point, see [`crate::Engine::watch`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccountState {
+    pub client: u16,
+    /// Funds in every currency the account holds, `None` for the default one
+    pub balances: BTreeMap<Option<Currency>, Balance>,
+    pub locked: bool,
+    pub closed: bool,
+}
+
+impl Balance {
+    pub fn available(&self) -> Money {
+        self.available
+    }
+
+    pub fn held(&self) -> Money {
+        self.held
+    }
+
+    pub fn total(&self) -> Money {
+        self.total
     }
 }
 
-#[derive(Default, Debug, Serialize)]
+#[derive(Default, Debug)]
 pub struct Account {
     client: u16,
-    #[serde(serialize_with = "serialize_w_precision")]
-    available: f32,
-    #[serde(serialize_with = "serialize_w_precision")]
-    held: f32,
-    #[serde(serialize_with = "serialize_w_precision")]
-    total: f32,
+    /// Balances per currency, `None` being the default currency
+    balances: BTreeMap<Option<Currency>, Balance>,
     locked: bool,
-    #[serde(skip_serializing)]
+    closed: bool,
     pending_transactions: VecDeque<Transaction>,
-    #[serde(skip_serializing)]
-    transactions_history: HashMap<u32, Transaction>,
+    transactions_history: HashMap<u32, HistoryEntry>,
+    /// Keep whole transactions in history rather than just what disputes need
+    full_history: bool,
+    history_window: Option<HistoryWindow>,
+    /// Ids of the entries in memory, oldest first, for spilling beyond the window
+    history_order: VecDeque<u32>,
+    /// Offsets of the entries spilled to the window's file
+    spilled_history: HashMap<u32, u64>,
+    /// Authorizations still holding funds, so expiry doesn't have to scan the whole history
+    open_authorizations: Vec<u32>,
+    /// Transfers to other partitions not settled yet, by transaction id
+    outgoing_transfers: BTreeMap<u32, OutgoingTransfer>,
+    /// Transfers from other partitions credited so far, by transaction id
+    incoming_transfers: BTreeMap<u32, IncomingTransfer>,
+    chargeback_policy: ChargebackPolicy,
+    fee_schedule: Option<Arc<FeeSchedule>>,
+    /// Fees charged so far, in the order they were posted
+    fee_ledger: Vec<FeeEntry>,
+    /// Last calendar month the maintenance fee was charged for
+    maintenance_month: Option<i64>,
+    /// Annual rate of the interest accrued on available funds
+    interest_rate: Option<Decimal>,
+    /// Interest accrued per currency but not posted yet, unrounded
+    accrued_interest: BTreeMap<Option<Currency>, Money>,
+    /// Day interest has been accrued up to
+    accrual_day: Option<i64>,
+    interest_ledger: Vec<InterestPosting>,
+    /// How far withdrawals may take available funds below zero
+    overdraft_limit: Money,
+    limits: Limits,
+    /// Day of the last withdrawal, with the total withdrawn and number of withdrawals that day
+    daily_withdrawals: Option<(i64, Money, u32)>,
+    /// Timestamps of transactions accepted within the last minute
+    recent_transactions: VecDeque<Timestamp>,
+    fraud_rules: FraudRules,
+    /// Transactions the fraud rules flagged or blocked, in the order they were checked
+    fraud_hits: Vec<FraudHit>,
+    /// Accepted transactions with the balances they left, when a statement is kept
+    statement: Option<Vec<StatementLine>>,
+    /// Events emitted so far, when an event log is kept
+    events: Option<Vec<AccountEvent>>,
+}
+
+/// Row of the account report with balances formatted for output.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AccountRecord {
+    pub client: u16,
+    /// Only present in multi-currency reports, empty for the default currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    /// Only present once some account of the report is closed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<bool>,
+    /// Only present once some account of the report has negative available funds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overdrawn: Option<bool>,
+}
+
+impl Serialize for Account {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.record(&MoneyFormat::default()).serialize(s)
+    }
 }
 
 impl Clone for Account {
     fn clone(&self) -> Self {
         Self {
             client: self.client,
-            available: self.available,
-            held: self.held,
-            total: self.total,
+            balances: self.balances.clone(),
             locked: self.locked,
+            closed: self.closed,
+            chargeback_policy: self.chargeback_policy,
+            fee_schedule: self.fee_schedule.clone(),
+            interest_rate: self.interest_rate,
+            overdraft_limit: self.overdraft_limit,
+            limits: self.limits,
+            fraud_rules: self.fraud_rules.clone(),
+            full_history: self.full_history,
+            history_window: self.history_window.clone(),
+            statement: self.statement.clone(),
+            events: self.events.clone(),
+            outgoing_transfers: self.outgoing_transfers.clone(),
+            incoming_transfers: self.incoming_transfers.clone(),
             ..Self::default()
         }
     }
@@ -65,234 +618,2101 @@ impl Account {
         }
     }
 
-    pub fn add_transaction(&mut self, new_transaction: Transaction) {
-        self.pending_transactions.push_back(new_transaction);
+    pub fn with_chargeback_policy(mut self, policy: ChargebackPolicy) -> Self {
+        self.chargeback_policy = policy;
+        self
     }
 
-    fn assert_balance(&mut self) {
-        self.total = self.available + self.held;
-        assert_eq!(self.total, self.available + self.held);
+    pub fn with_fee_schedule(mut self, fees: Arc<FeeSchedule>) -> Self {
+        self.fee_schedule = Some(fees);
+        self
     }
 
-    fn is_account_state_valid_for_transaction(&self) -> Result<(), TransactionProcessingError> {
-        if self.locked {
-            Err(TransactionProcessingError::AccountLocked(
-                self.pending_transactions.len() as u32,
-            ))
-        } else {
-            Ok(())
-        }
+    pub fn with_overdraft_limit(mut self, limit: Money) -> Self {
+        self.overdraft_limit = limit;
+        self
     }
 
-    fn deposit(&mut self, amount: f32) -> Result<(), TransactionProcessingError> {
-        self.is_account_state_valid_for_transaction()?;
+    pub fn with_interest_rate(mut self, apr: Decimal) -> Self {
+        self.interest_rate = Some(apr);
+        self
+    }
 
-        if amount > 0.0 {
-            self.available += amount;
-            self.assert_balance();
-            Ok(())
-        } else {
-            Err(TransactionProcessingError::NegativeAmount)
-        }
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
-    fn withdraw(&mut self, amount: f32) -> Result<(), TransactionProcessingError> {
-        self.is_account_state_valid_for_transaction()?;
+    pub fn with_fraud_rules(mut self, rules: FraudRules) -> Self {
+        self.fraud_rules = rules;
+        self
+    }
 
-        if amount > 0.0 {
-            if self.available - amount >= 0.0 {
-                self.available -= amount;
-                self.assert_balance();
-                Ok(())
-            } else {
-                Err(TransactionProcessingError::InsufficientAmount)
-            }
-        } else {
-            Err(TransactionProcessingError::NegativeAmount)
-        }
+    pub fn with_full_history(mut self) -> Self {
+        self.full_history = true;
+        self
     }
 
-    fn dispute(&mut self, transaction_id: u32) -> Result<(), TransactionProcessingError> {
-        if let Some(transaction) = self.transactions_history.get_mut(&transaction_id) {
-            if transaction.transaction_type == TransactionType::Deposit {
-                let amount = transaction
-                    .amount
-                    .expect("Transaction stored in transaction_history is valid");
+    pub fn with_history_window(mut self, window: HistoryWindow) -> Self {
+        self.history_window = Some(window);
+        self
+    }
 
-                transaction.transaction_type = TransactionType::Dispute;
-                self.available -= amount;
-                self.held += amount;
-                self.assert_balance();
-                return Ok(());
-            }
-        }
-        Err(TransactionProcessingError::InvalidDisputeTarget)
+    /// Keeps a statement of the transactions the account accepts from now on.
+    pub fn with_statement(mut self) -> Self {
+        self.statement = Some(Vec::new());
+        self
+    }
+
+    /// Keeps a log of the events the account emits from now on.
+    pub fn with_event_log(mut self) -> Self {
+        self.events = Some(Vec::new());
+        self
     }
 
-    fn find_dispute_transaction(
+    /// Replaces the fees, overdraft limit, limits and fraud rules the account's further
+    /// transactions are checked against. Balances, history and what the limits counted so
+    /// far stay; the new fraud rules start without state.
+    pub fn reconfigure(
         &mut self,
-        dispute_id: u32,
-    ) -> Result<&mut Transaction, TransactionProcessingError> {
-        if let Some(transaction) = self.transactions_history.get_mut(&dispute_id) {
-            if transaction.transaction_type == TransactionType::Dispute {
-                return Ok(transaction);
-            }
+        fees: Option<Arc<FeeSchedule>>,
+        overdraft_limit: Money,
+        limits: Limits,
+        fraud_rules: FraudRules,
+    ) {
+        self.fee_schedule = fees;
+        self.overdraft_limit = overdraft_limit;
+        self.limits = limits;
+        self.fraud_rules = fraud_rules;
+    }
+
+    /// Account of the client rebuilt by applying the events in order. It keeps an event log
+    /// holding them.
+    pub fn from_events(
+        client: u16,
+        events: impl IntoIterator<Item = AccountEvent>,
+    ) -> Result<Self, ErrorKind> {
+        let mut account = Self::new(client).with_event_log();
+        for event in events {
+            account.emit(event)?;
         }
+        Ok(account)
+    }
 
-        Err(TransactionProcessingError::TransactionNotUnderDispute)
+    pub fn client(&self) -> u16 {
+        self.client
     }
 
-    fn resolve(&mut self, dispute_id: u32) -> Result<(), TransactionProcessingError> {
-        let dispute_transaction = self.find_dispute_transaction(dispute_id)?;
-        let amount = dispute_transaction
-            .amount
-            .expect("Dispute transaction stored in history contains amount");
+    /// Balance in the given currency, zero if the account never held it.
+    pub fn balance(&self, currency: Option<&Currency>) -> Balance {
+        self.balances
+            .get(&currency.cloned())
+            .copied()
+            .unwrap_or_default()
+    }
 
-        dispute_transaction.transaction_type = TransactionType::Deposit;
-        self.held -= amount;
-        self.available += amount;
-        self.assert_balance();
-        Ok(())
+    /// Balances per currency, `None` being the default currency.
+    pub(crate) fn balances(&self) -> &BTreeMap<Option<Currency>, Balance> {
+        &self.balances
     }
 
-    fn chargeback(&mut self, dispute_id: u32) -> Result<(), TransactionProcessingError> {
-        let dispute_transaction = self.find_dispute_transaction(dispute_id)?;
-        let amount = dispute_transaction
-            .amount
-            .expect("Dispute transaction stored in history contains amount");
+    /// Currencies the account holds funds in, other than the default one.
+    pub fn currencies(&self) -> impl Iterator<Item = &Currency> {
+        self.balances.keys().flatten()
+    }
 
-        dispute_transaction.transaction_type = TransactionType::Chargeback;
-        self.held -= amount;
-        self.locked = true;
-        self.assert_balance();
-        Ok(())
+    pub fn is_multi_currency(&self) -> bool {
+        self.currencies().next().is_some()
     }
 
-    pub fn process_pending_transaction(&mut self) -> Result<(), TransactionProcessingError> {
-        self.is_account_state_valid_for_transaction()?;
-        let transaction = match self.pending_transactions.pop_front() {
-            Some(t) => t,
-            None => return Err(TransactionProcessingError::NoTransactionToProcess),
-        };
-        match transaction.transaction_type {
-            TransactionType::Deposit => {
-                let amount = match transaction.amount {
-                    Some(a) => a,
-                    None => {
-                        return Err(TransactionProcessingError::InvalidAmount);
-                    }
-                };
+    pub fn available(&self) -> Money {
+        self.balance(None).available
+    }
 
-                self.deposit(amount)?;
-                self.transactions_history
-                    .insert(transaction.tx, transaction);
-            }
-            TransactionType::Withdrawal => {
-                let amount = match transaction.amount {
-                    Some(a) => a,
-                    None => {
-                        return Err(TransactionProcessingError::InvalidAmount);
-                    }
-                };
+    pub fn held(&self) -> Money {
+        self.balance(None).held
+    }
 
-                self.withdraw(amount)?;
-                self.transactions_history
-                    .insert(transaction.tx, transaction);
+    pub fn total(&self) -> Money {
+        self.balance(None).total
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Balances and status of the account as they are now.
+    pub fn state(&self) -> AccountState {
+        AccountState {
+            client: self.client,
+            balances: self.balances.clone(),
+            locked: self.locked,
+            closed: self.closed,
+        }
+    }
+
+    /// Transfers to clients of other partitions waiting to be settled, by transaction id.
+    pub fn outgoing_transfers(&self) -> &BTreeMap<u32, OutgoingTransfer> {
+        &self.outgoing_transfers
+    }
+
+    /// Transfer from a client of another partition credited with the transaction id.
+    pub fn incoming_transfer(&self, tx: u32) -> Option<&IncomingTransfer> {
+        self.incoming_transfers.get(&tx)
+    }
+
+    /// Fees charged to the account, in the order they were posted.
+    pub fn fees(&self) -> &[FeeEntry] {
+        &self.fee_ledger
+    }
+
+    /// Transactions the fraud rules flagged or blocked, in the order they were checked.
+    pub fn fraud_hits(&self) -> &[FraudHit] {
+        &self.fraud_hits
+    }
+
+    /// Interest credited to the account, in the order it was posted.
+    pub fn interest(&self) -> &[InterestPosting] {
+        &self.interest_ledger
+    }
+
+    /// Events the account emitted, oldest first. Empty unless the account keeps an event log.
+    pub fn events(&self) -> &[AccountEvent] {
+        self.events.as_deref().unwrap_or_default()
+    }
+
+    /// Applies the event to the account and appends it to the event log, if there is one.
+    /// Nothing changes when applying it fails.
+    fn emit(&mut self, event: AccountEvent) -> Result<(), ErrorKind> {
+        self.apply_event(&event)?;
+        if let Some(events) = &mut self.events {
+            events.push(event);
+        }
+        Ok(())
+    }
+
+    /// Posts the event to the books, then records what it did to the transaction it refers
+    /// to and to the account's fees, interest and state.
+    fn apply_event(&mut self, event: &AccountEvent) -> Result<(), ErrorKind> {
+        self.post(&double_entry::postings(self.client, event))?;
+        match event {
+            AccountEvent::DisputeOpened { tx, amount, .. } => {
+                if let Some(entry) = self.transactions_history.get_mut(tx) {
+                    entry.details_mut().disputed += *amount;
+                }
+                self.set_dispute_state(*tx, DisputeState::Disputed);
             }
-            TransactionType::Dispute => {
-                self.dispute(transaction.tx)?;
+            AccountEvent::Resolved { tx, .. } => {
+                if let Some(entry) = self.transactions_history.get_mut(tx) {
+                    entry.details_mut().disputed = Money::ZERO;
+                }
+                self.set_dispute_state(*tx, DisputeState::Resolved);
             }
-            TransactionType::Resolve => {
-                self.resolve(transaction.tx)?;
+            AccountEvent::ChargedBack { tx, .. } => {
+                self.set_dispute_state(*tx, DisputeState::ChargedBack);
             }
-            TransactionType::Chargeback => {
-                self.chargeback(transaction.tx)?;
+            AccountEvent::Represented { tx, .. } => {
+                if let Some(entry) = self.transactions_history.get_mut(tx) {
+                    entry.details_mut().disputed = Money::ZERO;
+                }
+                self.set_dispute_state(*tx, DisputeState::Represented);
             }
+            AccountEvent::Refunded { tx, amount, .. } => {
+                if let Some(entry) = self.transactions_history.get_mut(tx) {
+                    entry.details_mut().refunded += *amount;
+                }
+            }
+            AccountEvent::Authorized { tx, .. } => self.open_authorizations.push(*tx),
+            AccountEvent::Captured { tx, .. } => {
+                self.close_authorization(*tx, AuthorizationState::Captured)
+            }
+            AccountEvent::Voided { tx, .. } => {
+                self.close_authorization(*tx, AuthorizationState::Voided)
+            }
+            AccountEvent::AuthorizationExpired { tx, .. } => {
+                self.close_authorization(*tx, AuthorizationState::Expired)
+            }
+            AccountEvent::FeeCharged {
+                tx,
+                currency,
+                amount,
+            } => self
+                .fee_ledger
+                .push(FeeEntry::new(*tx, currency.clone(), *amount)),
+            AccountEvent::InterestPosted {
+                currency,
+                amount,
+                posted_at,
+            } => self.interest_ledger.push(InterestPosting::new(
+                currency.clone(),
+                *amount,
+                *posted_at,
+            )),
+            AccountEvent::Locked => self.locked = true,
+            AccountEvent::Unlocked => self.locked = false,
+            AccountEvent::Closed => self.closed = true,
+            AccountEvent::Deposited { .. }
+            | AccountEvent::Withdrawn { .. }
+            | AccountEvent::Converted { .. }
+            | AccountEvent::TransferredOut { .. }
+            | AccountEvent::TransferredIn { .. }
+            | AccountEvent::TransferReturned { .. }
+            | AccountEvent::Adjusted { .. } => {}
         }
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{Account, Transaction, TransactionType};
+    /// Applies the postings to the client's available and held funds, leaving the account
+    /// untouched when the resulting balances would break an invariant. Postings to other
+    /// accounts of the books are the other side and change nothing here.
+    fn post(&mut self, postings: &[Posting]) -> Result<(), ErrorKind> {
+        debug_assert_eq!(double_entry::is_balanced(postings), Ok(true));
+        let mut funds = BTreeMap::<Option<Currency>, (Option<Money>, Option<Money>)>::new();
+        for posting in postings {
+            let (available, held) = funds.entry(posting.currency.clone()).or_insert_with(|| {
+                let balance = self.balance(posting.currency.as_ref());
+                (Some(balance.available), Some(balance.held))
+            });
+            let funds = match posting.account {
+                LedgerAccount::Available(client) if client == self.client => available,
+                LedgerAccount::Held(client) if client == self.client => held,
+                _ => continue,
+            };
+            *funds = funds.and_then(|funds| funds.checked_add(posting.signed_amount()));
+        }
+        let balances = funds
+            .into_iter()
+            .map(|(currency, (available, held))| {
+                Self::checked_balance(available, held).map(|balance| (currency, balance))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.balances.extend(balances);
+        Ok(())
+    }
 
-    fn prepare_acc(initial_funds: f32) -> Account {
-        let mut acc = Account::new(0);
-        acc.add_transaction(Transaction::new(
-            TransactionType::Deposit,
-            0,
-            0,
-            Some(initial_funds),
-        ));
-        acc.process_pending_transaction().unwrap();
-        acc
+    /// Every transaction in the account's history, spilled ones included, by id, with the
+    /// dispute state it ended up in. Disputes, resolves and the other transactions referring
+    /// to an earlier one only show in the state of that one. Fails when spilled entries
+    /// can't be read back.
+    pub fn ledger(&self) -> io::Result<Vec<LedgerEntry>> {
+        Ok(self
+            .snapshot()?
+            .history
+            .into_iter()
+            .map(|entry| {
+                let transaction = Transaction::from(entry.transaction);
+                LedgerEntry {
+                    client: self.client,
+                    tx: transaction.tx,
+                    transaction_type: transaction.transaction_type,
+                    amount: transaction.amount,
+                    currency: transaction.currency,
+                    dispute_state: Some(entry.dispute_state),
+                    outcome: OutcomeStatus::Accepted,
+                    error: None,
+                    metadata: transaction.metadata,
+                }
+            })
+            .collect())
     }
 
-    #[test]
-    fn deposit() {
-        let mut acc = prepare_acc(5.0);
-        assert_eq!(acc.available, 5.0);
-        assert_eq!(acc.total, 5.0);
+    /// Transactions the account accepted, oldest first, each with the balance of its
+    /// currency right after it. Empty unless the account keeps a statement.
+    pub fn statement(&self) -> &[StatementLine] {
+        self.statement.as_deref().unwrap_or_default()
+    }
 
-        acc.add_transaction(Transaction::new(TransactionType::Deposit, 0, 1, Some(-5.0)));
-        assert!(acc.process_pending_transaction().is_err());
-        assert_eq!(acc.available, 5.0);
-        assert_eq!(acc.total, 5.0);
+    fn record_statement(&mut self, transaction: &Transaction, currency: Option<Currency>) {
+        let balance = self.balance(currency.as_ref());
+        if let Some(statement) = &mut self.statement {
+            statement.push(StatementLine {
+                row: transaction.row,
+                tx: transaction.tx,
+                timestamp: transaction.timestamp,
+                transaction_type: transaction.transaction_type.clone(),
+                amount: transaction.amount,
+                currency,
+                counterparty: None,
+                balance,
+                metadata: transaction.metadata.clone(),
+            });
+        }
     }
 
-    #[test]
-    fn withdraw() {
-        let mut acc = prepare_acc(10.0);
-        assert_eq!(acc.available, 10.0);
-        assert_eq!(acc.total, 10.0);
+    /// Report row of the default currency balance.
+    pub fn record(&self, format: &MoneyFormat) -> AccountRecord {
+        self.currency_record(None, format, false)
+    }
 
-        acc.add_transaction(Transaction::new(
-            TransactionType::Withdrawal,
-            0,
-            1,
-            Some(5.0),
-        ));
-        acc.process_pending_transaction().unwrap();
-        assert_eq!(acc.available, 5.0);
-        assert_eq!(acc.total, 5.0);
+    /// Report rows of every currency the account holds, tagged with their currency. An
+    /// account that never held any funds still gets a default currency row.
+    pub fn records(&self, format: &MoneyFormat) -> Vec<AccountRecord> {
+        if self.balances.is_empty() {
+            return vec![self.currency_record(None, format, true)];
+        }
+        self.balances
+            .keys()
+            .map(|currency| self.currency_record(currency.as_ref(), format, true))
+            .collect()
+    }
 
-        acc.add_transaction(Transaction::new(
-            TransactionType::Withdrawal,
-            0,
-            2,
-            Some(6.0),
-        ));
-        assert!(acc.process_pending_transaction().is_err());
-        assert_eq!(acc.available, 5.0);
-        assert_eq!(acc.total, 5.0);
+    fn currency_record(
+        &self,
+        currency: Option<&Currency>,
+        format: &MoneyFormat,
+        tagged: bool,
+    ) -> AccountRecord {
+        let balance = self.balance(currency);
+        AccountRecord {
+            client: self.client,
+            currency: tagged.then(|| currency.map_or_else(String::new, |c| c.to_string())),
+            available: balance.available.format(format),
+            held: balance.held.format(format),
+            total: balance.total.format(format),
+            locked: self.locked,
+            closed: None,
+            overdrawn: balance.available.is_negative().then_some(true),
+        }
+    }
 
-        acc.add_transaction(Transaction::new(
-            TransactionType::Withdrawal,
-            0,
-            3,
-            Some(-1.0),
-        ));
-        assert!(acc.process_pending_transaction().is_err());
-        assert_eq!(acc.available, 5.0);
-        assert_eq!(acc.total, 5.0);
+    /// Reinstates an account locked by a chargeback. Balances and history are left as they are.
+    pub fn unlock(&mut self) -> Result<(), ErrorKind> {
+        if !self.locked {
+            return Err(ErrorKind::AccountNotLocked);
+        }
+        self.emit(AccountEvent::Unlocked)
     }
 
-    #[test]
-    fn dispute() {
-        let mut acc = prepare_acc(10.0);
-        assert_eq!(acc.available, 10.0);
-        assert_eq!(acc.total, 10.0);
-        const TRANSACTION_TO_DISPUTE_ID: u32 = 5;
-        const INVALID_DISPUTE_ID: u32 = 999;
-        const WITHDRAW_TRANSACTION_ID: u32 = 10;
+    /// Closes the account once it holds nothing in any currency.
+    fn close(&mut self) -> Result<(), ErrorKind> {
+        if self
+            .balances
+            .values()
+            .any(|balance| *balance != Balance::default())
+        {
+            return Err(ErrorKind::AccountNotEmpty);
+        }
+        self.emit(AccountEvent::Closed)
+    }
 
-        let deposit_transaction = Transaction::new(
-            TransactionType::Deposit,
-            0,
-            TRANSACTION_TO_DISPUTE_ID,
-            Some(5.0),
-        );
+    /// Transactions under dispute. Disputed entries never leave memory, so none is missed.
+    pub fn open_disputes(&self) -> usize {
+        self.transactions_history
+            .values()
+            .filter(|entry| entry.dispute_state == DisputeState::Disputed)
+            .count()
+    }
+
+    /// Transactions under dispute, by id, as ledger entries.
+    pub fn disputed_transactions(&self) -> Vec<LedgerEntry> {
+        let mut entries = self
+            .transactions_history
+            .iter()
+            .filter(|(_, entry)| entry.dispute_state == DisputeState::Disputed)
+            .map(|(tx, entry)| LedgerEntry {
+                client: self.client,
+                tx: *tx,
+                transaction_type: entry.transaction_type.clone(),
+                amount: Some(entry.amount()),
+                currency: entry.currency.clone(),
+                dispute_state: Some(entry.dispute_state),
+                outcome: OutcomeStatus::Accepted,
+                error: None,
+                metadata: entry.metadata().clone(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.tx);
+        entries
+    }
+
+    /// History entry of the transaction, `None` for entries spilled beyond the window.
+    pub fn history_entry(&self, tx: u32) -> Option<&HistoryEntry> {
+        self.transactions_history.get(&tx)
+    }
+
+    fn record_history(&mut self, transaction: Transaction) {
+        if self.history_window.is_some() {
+            self.history_order.push_back(transaction.tx);
+        }
+        self.transactions_history.insert(
+            transaction.tx,
+            HistoryEntry::new(transaction, self.full_history),
+        );
+        self.spill_history();
+    }
+
+    /// Moves the oldest entries beyond the history window out of memory. Entries that can
+    /// still change on their own stay, and so do entries the spill file fails to take.
+    fn spill_history(&mut self) {
+        let Some(window) = &self.history_window else {
+            return;
+        };
+        let mut kept = Vec::new();
+        while self.transactions_history.len() > window.size {
+            let Some(tx) = self.history_order.pop_front() else {
+                break;
+            };
+            let Some(entry) = self.transactions_history.get(&tx) else {
+                continue;
+            };
+            if matches!(
+                entry.dispute_state,
+                DisputeState::Disputed | DisputeState::ChargedBack
+            ) || entry.authorization_state == Some(AuthorizationState::Authorized)
+            {
+                kept.push(tx);
+                continue;
+            }
+            match window.spill().write(&entry.state(self.client, tx)) {
+                Ok(offset) => {
+                    self.spilled_history.insert(tx, offset);
+                }
+                Err(_) => {
+                    kept.push(tx);
+                    break;
+                }
+            }
+            self.transactions_history.remove(&tx);
+        }
+        for tx in kept.into_iter().rev() {
+            self.history_order.push_front(tx);
+        }
+    }
+
+    /// Brings a spilled entry back into memory for a transaction referring to it.
+    fn load_history(&mut self, tx: u32) -> Result<(), ErrorKind> {
+        let (Some(window), Some(offset)) =
+            (&self.history_window, self.spilled_history.get(&tx).copied())
+        else {
+            return Ok(());
+        };
+        let entry = window
+            .spill()
+            .read(offset)
+            .map_err(|_| ErrorKind::HistoryUnavailable(tx))?;
+        self.spilled_history.remove(&tx);
+        self.history_order.push_back(tx);
+        self.transactions_history
+            .insert(tx, HistoryEntry::from_state(entry, self.full_history));
+        Ok(())
+    }
+
+    pub fn add_transaction(&mut self, new_transaction: Transaction) {
+        self.pending_transactions.push_back(new_transaction);
+    }
+
+    fn check_invariants(available: Money, held: Money, total: Money) -> Result<(), ErrorKind> {
+        if held.is_negative() {
+            return Err(ErrorKind::InvariantViolation("held funds are negative"));
+        }
+        if available.checked_add(held) != Some(total) {
+            return Err(ErrorKind::InvariantViolation(
+                "total differs from available + held",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the stored balances are consistent with each other.
+    pub fn reconcile(&self) -> Result<(), ErrorKind> {
+        self.balances.values().try_for_each(|balance| {
+            Self::check_invariants(balance.available, balance.held, balance.total)
+        })
+    }
+
+    /// Balance with the given funds, as long as it keeps every invariant.
+    fn checked_balance(
+        available: Option<Money>,
+        held: Option<Money>,
+    ) -> Result<Balance, ErrorKind> {
+        let overflow = ErrorKind::InvariantViolation("balance overflow");
+        let (available, held) = match (available, held) {
+            (Some(available), Some(held)) => (available, held),
+            _ => return Err(overflow),
+        };
+        let total = match available.checked_add(held) {
+            Some(total) => total,
+            None => return Err(overflow),
+        };
+        Self::check_invariants(available, held, total)?;
+        Ok(Balance {
+            available,
+            held,
+            total,
+        })
+    }
+
+    /// Whether a locked account still accepts the transaction type. Unlocking and reversing a
+    /// chargeback always go through, deposits and the dispute lifecycle depending on the
+    /// chargeback policy.
+    fn accepts_when_locked(&self, transaction_type: &TransactionType) -> bool {
+        match transaction_type {
+            TransactionType::Unlock | TransactionType::Representment => true,
+            TransactionType::Deposit => self.chargeback_policy.deposits_when_locked,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.chargeback_policy.disputes_when_locked
+            }
+            _ => false,
+        }
+    }
+
+    fn is_account_state_valid_for_transaction(
+        &self,
+        transaction_type: &TransactionType,
+    ) -> Result<(), ErrorKind> {
+        if self.locked && !self.accepts_when_locked(transaction_type) {
+            Err(ErrorKind::AccountLocked(
+                self.pending_transactions.len() as u32
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn deposit(
+        &mut self,
+        tx: u32,
+        currency: Option<&Currency>,
+        amount: Money,
+    ) -> Result<(), ErrorKind> {
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+        self.emit(AccountEvent::Deposited {
+            tx,
+            currency: currency.cloned(),
+            amount,
+        })
+    }
+
+    fn withdraw(
+        &mut self,
+        tx: u32,
+        currency: Option<&Currency>,
+        amount: Money,
+    ) -> Result<(), ErrorKind> {
+        self.is_account_state_valid_for_transaction(&TransactionType::Withdrawal)?;
+
+        let balance = self.balance(currency);
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+        let available = balance
+            .available
+            .checked_sub(amount)
+            .ok_or(ErrorKind::InvariantViolation("balance overflow"))?;
+        if available < -self.overdraft_limit {
+            return Err(match self.overdraft_limit.is_positive() {
+                true => ErrorKind::OverdraftExceeded,
+                false => ErrorKind::InsufficientAmount,
+            });
+        }
+        self.emit(AccountEvent::Withdrawn {
+            tx,
+            currency: currency.cloned(),
+            amount,
+        })
+    }
+
+    /// Debits `amount` in one currency and credits `converted` in another. Either both sides
+    /// are applied or neither is.
+    fn convert(
+        &mut self,
+        tx: u32,
+        from: &Currency,
+        to: &Currency,
+        amount: Money,
+        converted: Money,
+    ) -> Result<(), ErrorKind> {
+        self.is_account_state_valid_for_transaction(&TransactionType::Convert)?;
+        if from == to {
+            return Err(ErrorKind::InvalidConversion);
+        }
+        if !amount.is_positive() || converted.is_negative() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+
+        if self.balance(Some(from)).available < amount {
+            return Err(ErrorKind::InsufficientAmount);
+        }
+        self.emit(AccountEvent::Converted {
+            tx,
+            from: from.clone(),
+            to: to.clone(),
+            amount,
+            converted,
+        })
+    }
+
+    /// Moves the transfer's amount from this account to `destination`, in the transfer's
+    /// currency. Both accounts are checked before either is touched, so a failing transfer
+    /// leaves both of them as they were.
+    pub fn transfer(
+        &mut self,
+        destination: &mut Account,
+        transaction: Transaction,
+    ) -> Result<(), ErrorKind> {
+        if transaction.to_client != Some(destination.client) {
+            return Err(ErrorKind::InvalidTransfer);
+        }
+        if destination.closed {
+            return Err(ErrorKind::AccountClosed);
+        }
+        destination.is_account_state_valid_for_transaction(&transaction.transaction_type)?;
+        let (amount, fee) = self.check_transfer_out(&transaction)?;
+        // Checked up front, so the destination can't refuse what the source already paid
+        destination.check_transfer_in(&transaction)?;
+
+        let currency = transaction.currency.as_ref();
+        self.emit(AccountEvent::TransferredOut {
+            tx: transaction.tx,
+            to_client: destination.client,
+            currency: currency.cloned(),
+            amount,
+        })?;
+        if let Some(fee) = fee {
+            self.emit(fee.into())?;
+        }
+        destination.emit(AccountEvent::TransferredIn {
+            tx: transaction.tx,
+            from_client: self.client,
+            currency: currency.cloned(),
+            amount,
+        })?;
+        self.record_transfer_statement(&transaction, destination.client);
+        destination.record_transfer_statement(&transaction, self.client);
+        self.record_history(transaction);
+        Ok(())
+    }
+
+    /// Takes the amount of a transfer to a client of another partition, and its fee, from
+    /// the account. The transfer stays outgoing until that partition credited it, see
+    /// [`Account::complete_transfer`], or refused it, see [`Account::return_transfer`].
+    pub fn transfer_out(&mut self, transaction: Transaction) -> Result<(), ErrorKind> {
+        let to_client = transaction.to_client.ok_or(ErrorKind::InvalidTransfer)?;
+        let (amount, fee) = self.check_transfer_out(&transaction)?;
+
+        let currency = transaction.currency.clone();
+        self.emit(AccountEvent::TransferredOut {
+            tx: transaction.tx,
+            to_client,
+            currency: currency.clone(),
+            amount,
+        })?;
+        if let Some(fee) = fee {
+            self.emit(fee.into())?;
+        }
+        self.record_transfer_statement(&transaction, to_client);
+        self.outgoing_transfers.insert(
+            transaction.tx,
+            OutgoingTransfer {
+                to_client,
+                currency,
+                amount,
+            },
+        );
+        self.record_history(transaction);
+        Ok(())
+    }
+
+    /// Credits a transfer that the partition of its sender already took from the sender's
+    /// account.
+    pub fn transfer_in(&mut self, transaction: &Transaction) -> Result<(), ErrorKind> {
+        if transaction.to_client != Some(self.client) || transaction.client == self.client {
+            return Err(ErrorKind::InvalidTransfer);
+        }
+        self.check_transfer_in(transaction)?;
+        let amount = transaction.amount.ok_or(ErrorKind::InvalidAmount)?;
+        self.emit(AccountEvent::TransferredIn {
+            tx: transaction.tx,
+            from_client: transaction.client,
+            currency: transaction.currency.clone(),
+            amount,
+        })?;
+        self.record_transfer_statement(transaction, transaction.client);
+        self.incoming_transfers.insert(
+            transaction.tx,
+            IncomingTransfer {
+                from_client: transaction.client,
+                currency: transaction.currency.clone(),
+                amount,
+            },
+        );
+        Ok(())
+    }
+
+    /// Settles an outgoing transfer the destination's partition credited.
+    pub fn complete_transfer(&mut self, tx: u32) -> Result<(), ErrorKind> {
+        match self.outgoing_transfers.remove(&tx) {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::InvalidTransfer),
+        }
+    }
+
+    /// Gives the amount of an outgoing transfer the destination's partition refused back to
+    /// the account, even when it got locked or closed meanwhile. The fee stays charged.
+    pub fn return_transfer(&mut self, tx: u32) -> Result<(), ErrorKind> {
+        let transfer = self
+            .outgoing_transfers
+            .get(&tx)
+            .ok_or(ErrorKind::InvalidTransfer)?;
+        self.emit(AccountEvent::TransferReturned {
+            tx,
+            to_client: transfer.to_client,
+            currency: transfer.currency.clone(),
+            amount: transfer.amount,
+        })?;
+        self.outgoing_transfers.remove(&tx);
+        Ok(())
+    }
+
+    /// Checks that the account can send the transfer, returning its amount and fee.
+    fn check_transfer_out(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<(Money, Option<FeeEntry>), ErrorKind> {
+        if transaction.transaction_type != TransactionType::Transfer
+            || transaction.client != self.client
+            || transaction.to_client.is_none()
+            || transaction.to_client == Some(self.client)
+        {
+            return Err(ErrorKind::InvalidTransfer);
+        }
+        if self.closed {
+            return Err(ErrorKind::AccountClosed);
+        }
+        self.is_account_state_valid_for_transaction(&transaction.transaction_type)?;
+        let amount = transaction.amount.ok_or(ErrorKind::InvalidAmount)?;
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+
+        // The sender pays the transfer's fee
+        let fee = self.transaction_fee(transaction)?;
+        let charged = match fee.as_ref().map(|fee| amount.checked_add(fee.amount())) {
+            Some(charged) => charged.ok_or(ErrorKind::InvariantViolation("fee overflow"))?,
+            None => amount,
+        };
+        if self.balance(transaction.currency.as_ref()).available < charged {
+            return Err(ErrorKind::InsufficientAmount);
+        }
+        Ok((amount, fee))
+    }
+
+    /// Checks that the account can be credited the transfer.
+    fn check_transfer_in(&self, transaction: &Transaction) -> Result<(), ErrorKind> {
+        if self.closed {
+            return Err(ErrorKind::AccountClosed);
+        }
+        self.is_account_state_valid_for_transaction(&transaction.transaction_type)?;
+        let amount = transaction.amount.ok_or(ErrorKind::InvalidAmount)?;
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+        let credited = self.balance(transaction.currency.as_ref());
+        Self::checked_balance(credited.available.checked_add(amount), Some(credited.held))?;
+        Ok(())
+    }
+
+    /// Adds the transfer to the statement, with the client on the other side.
+    fn record_transfer_statement(&mut self, transaction: &Transaction, counterparty: u16) {
+        self.record_statement(transaction, transaction.currency.clone());
+        if let Some(line) = self.statement.as_mut().and_then(|lines| lines.last_mut()) {
+            line.counterparty = Some(counterparty);
+        }
+    }
+
+    /// Returns `amount` of an earlier deposit, or whatever is left of it when no amount is
+    /// given. The account stays unlocked and the deposit can only be disputed for the part
+    /// that hasn't been refunded.
+    fn refund(&mut self, deposit_id: u32, amount: Option<Money>) -> Result<(), ErrorKind> {
+        self.is_account_state_valid_for_transaction(&TransactionType::Refund)?;
+        let entry = match self.transactions_history.get(&deposit_id) {
+            Some(entry)
+                if entry.transaction_type == TransactionType::Deposit
+                    && matches!(
+                        entry.dispute_state,
+                        DisputeState::None | DisputeState::Resolved | DisputeState::Represented
+                    ) =>
+            {
+                entry
+            }
+            _ => return Err(ErrorKind::InvalidRefundTarget),
+        };
+
+        let remaining = entry.disputable_amount();
+        let amount = amount.unwrap_or(remaining);
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+        if amount > remaining {
+            return Err(ErrorKind::RefundExceedsDeposit);
+        }
+        let currency = entry.currency.clone();
+        if self.balance(currency.as_ref()).available < amount {
+            return Err(ErrorKind::InsufficientAmount);
+        }
+        self.emit(AccountEvent::Refunded {
+            tx: deposit_id,
+            currency,
+            amount,
+        })
+    }
+
+    /// Moves the amount from available to held funds until it is captured or released.
+    fn authorize(
+        &mut self,
+        tx: u32,
+        currency: Option<&Currency>,
+        amount: Money,
+    ) -> Result<(), ErrorKind> {
+        self.is_account_state_valid_for_transaction(&TransactionType::Authorize)?;
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+
+        if self.balance(currency).available < amount {
+            return Err(ErrorKind::InsufficientAmount);
+        }
+        self.emit(AccountEvent::Authorized {
+            tx,
+            currency: currency.cloned(),
+            amount,
+        })
+    }
+
+    fn open_authorization(&self, authorization_id: u32) -> Result<&HistoryEntry, ErrorKind> {
+        match self.transactions_history.get(&authorization_id) {
+            Some(entry) => match entry.authorization_state {
+                Some(AuthorizationState::Authorized) => Ok(entry),
+                Some(AuthorizationState::Expired) => Err(ErrorKind::AuthorizationExpired),
+                _ => Err(ErrorKind::InvalidAuthorization),
+            },
+            None => Err(ErrorKind::InvalidAuthorization),
+        }
+    }
+
+    /// Settles an open authorization: `captured` leaves the account for good, the rest of the
+    /// held amount goes back to available funds.
+    fn settle_authorization(
+        &mut self,
+        authorization_id: u32,
+        captured: Money,
+        state: AuthorizationState,
+    ) -> Result<(), ErrorKind> {
+        let entry = self.open_authorization(authorization_id)?;
+        let amount = entry.amount();
+        if captured > amount {
+            return Err(ErrorKind::CaptureExceedsAuthorization);
+        }
+        let (tx, currency) = (authorization_id, entry.currency.clone());
+        self.emit(match state {
+            AuthorizationState::Captured => AccountEvent::Captured {
+                tx,
+                currency,
+                amount: captured,
+                released: amount - captured,
+            },
+            AuthorizationState::Expired => AccountEvent::AuthorizationExpired {
+                tx,
+                currency,
+                amount,
+            },
+            AuthorizationState::Authorized | AuthorizationState::Voided => AccountEvent::Voided {
+                tx,
+                currency,
+                amount,
+            },
+        })
+    }
+
+    /// Records the authorization settled, its funds no longer being held.
+    fn close_authorization(&mut self, authorization_id: u32, state: AuthorizationState) {
+        if let Some(entry) = self.transactions_history.get_mut(&authorization_id) {
+            entry.authorization_state = Some(state);
+        }
+        self.open_authorizations
+            .retain(|id| *id != authorization_id);
+    }
+
+    /// Takes `amount` of the authorization's held funds, or all of them when no amount is
+    /// given, and releases whatever is left.
+    fn capture(&mut self, authorization_id: u32, amount: Option<Money>) -> Result<(), ErrorKind> {
+        let authorized = self.open_authorization(authorization_id)?.amount();
+        let amount = amount.unwrap_or(authorized);
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+        self.settle_authorization(authorization_id, amount, AuthorizationState::Captured)
+    }
+
+    /// Releases authorizations that expired before `now` back to available funds.
+    fn expire_authorizations(&mut self, now: Timestamp) {
+        let expired = self
+            .open_authorizations
+            .iter()
+            .filter(|id| {
+                self.transactions_history
+                    .get(id)
+                    .and_then(|entry| entry.expires_at())
+                    .is_some_and(|expires_at| expires_at < now)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        for id in expired {
+            // Releasing a hold can't break an invariant, it only moves funds back
+            let _ = self.settle_authorization(id, Money::ZERO, AuthorizationState::Expired);
+        }
+    }
+
+    /// Credits (positive amount) or debits (negative amount) available funds. Unlike deposits
+    /// and withdrawals the amount may be negative, but it can't be zero and a debit can't take
+    /// more than is available.
+    fn adjust(
+        &mut self,
+        tx: u32,
+        currency: Option<&Currency>,
+        amount: Money,
+    ) -> Result<(), ErrorKind> {
+        self.is_account_state_valid_for_transaction(&TransactionType::Adjustment)?;
+        if amount == Money::ZERO {
+            return Err(ErrorKind::InvalidAmount);
+        }
+
+        let available = self.balance(currency).available.checked_add(amount);
+        if available.is_some_and(|available| available.is_negative()) {
+            return Err(ErrorKind::InsufficientAmount);
+        }
+        self.emit(AccountEvent::Adjusted {
+            tx,
+            currency: currency.cloned(),
+            amount,
+        })
+    }
+
+    /// Disputed deposits move the disputed amount from available to held funds. Disputed
+    /// withdrawals put the withdrawn amount on hold until the dispute is settled.
+    /// Funds are always held in the currency of the disputed transaction.
+    ///
+    /// Without an amount the whole undisputed rest of the transaction is disputed. Further
+    /// disputes of a transaction under dispute add to the amount on hold.
+    fn dispute(&mut self, transaction_id: u32, amount: Option<Money>) -> Result<(), ErrorKind> {
+        let entry = match self.transactions_history.get(&transaction_id) {
+            Some(entry) => entry,
+            None => return Err(ErrorKind::InvalidDisputeTarget),
+        };
+        if entry.dispute_state == DisputeState::ChargedBack {
+            return Err(ErrorKind::InvalidDisputeTarget);
+        }
+
+        let remaining = entry.disputable_amount() - entry.disputed();
+        if !remaining.is_positive() {
+            return Err(ErrorKind::InvalidDisputeTarget);
+        }
+        let amount = amount.unwrap_or(remaining);
+        if !amount.is_positive() {
+            return Err(ErrorKind::NegativeAmount);
+        }
+        if amount > remaining {
+            return Err(ErrorKind::DisputeExceedsTransaction);
+        }
+        let currency = entry.currency.clone();
+        let balance = self.balance(currency.as_ref());
+        match entry.transaction_type {
+            TransactionType::Deposit
+                if !self.chargeback_policy.allow_negative_available
+                    && balance.available < amount =>
+            {
+                return Err(ErrorKind::InsufficientAmount)
+            }
+            TransactionType::Deposit | TransactionType::Withdrawal => {}
+            _ => return Err(ErrorKind::InvalidDisputeTarget),
+        }
+        self.emit(AccountEvent::DisputeOpened {
+            tx: transaction_id,
+            disputed: entry.transaction_type.clone(),
+            currency,
+            amount,
+        })
+    }
+
+    fn find_dispute_transaction(&self, dispute_id: u32) -> Result<&HistoryEntry, ErrorKind> {
+        if let Some(entry) = self.transactions_history.get(&dispute_id) {
+            if entry.dispute_state == DisputeState::Disputed {
+                return Ok(entry);
+            }
+        }
+
+        Err(ErrorKind::TransactionNotUnderDispute)
+    }
+
+    fn set_dispute_state(&mut self, transaction_id: u32, dispute_state: DisputeState) {
+        if let Some(entry) = self.transactions_history.get_mut(&transaction_id) {
+            entry.dispute_state = dispute_state;
+            entry.details_mut().dispute_lifecycle.push(dispute_state);
+        }
+    }
+
+    /// Dismisses the dispute, the original transaction stands.
+    fn resolve(&mut self, dispute_id: u32) -> Result<(), ErrorKind> {
+        let dispute_entry = self.find_dispute_transaction(dispute_id)?;
+        self.emit(AccountEvent::Resolved {
+            tx: dispute_id,
+            disputed: dispute_entry.transaction_type.clone(),
+            currency: dispute_entry.currency.clone(),
+            amount: dispute_entry.disputed(),
+        })
+    }
+
+    /// Reverses the disputed part of the original transaction and, unless the chargeback
+    /// policy says otherwise, locks the account.
+    fn chargeback(&mut self, dispute_id: u32) -> Result<(), ErrorKind> {
+        let dispute_entry = self.find_dispute_transaction(dispute_id)?;
+        self.emit(AccountEvent::ChargedBack {
+            tx: dispute_id,
+            disputed: dispute_entry.transaction_type.clone(),
+            currency: dispute_entry.currency.clone(),
+            amount: dispute_entry.disputed(),
+        })?;
+        if self.chargeback_policy.lock_account && !self.locked {
+            self.emit(AccountEvent::Locked)?;
+        }
+        Ok(())
+    }
+
+    /// Reverses a chargeback: the charged back amount is credited again (or, for a
+    /// withdrawal, debited again). The account is unlocked once none of its transactions
+    /// remains charged back.
+    fn represent(&mut self, transaction_id: u32) -> Result<(), ErrorKind> {
+        let entry = match self.transactions_history.get(&transaction_id) {
+            Some(entry) if entry.dispute_state == DisputeState::ChargedBack => entry,
+            _ => return Err(ErrorKind::InvalidRepresentmentTarget),
+        };
+        let amount = entry.disputed();
+        let currency = entry.currency.clone();
+        if entry.transaction_type != TransactionType::Deposit
+            && self.balance(currency.as_ref()).available < amount
+        {
+            return Err(ErrorKind::InsufficientAmount);
+        }
+        self.emit(AccountEvent::Represented {
+            tx: transaction_id,
+            disputed: entry.transaction_type.clone(),
+            currency,
+            amount,
+        })?;
+
+        if self.locked
+            && !self
+                .transactions_history
+                .values()
+                .any(|entry| entry.dispute_state == DisputeState::ChargedBack)
+        {
+            self.emit(AccountEvent::Unlocked)?;
+        }
+        Ok(())
+    }
+
+    /// Fee the schedule charges for the transaction, in the transaction's currency.
+    fn transaction_fee(&self, transaction: &Transaction) -> Result<Option<FeeEntry>, ErrorKind> {
+        let fee = match self
+            .fee_schedule
+            .as_ref()
+            .and_then(|schedule| schedule.fee(&transaction.transaction_type))
+        {
+            Some(fee) => fee,
+            None => return Ok(None),
+        };
+        let amount = fee
+            .charge(transaction.amount.unwrap_or(Money::ZERO))
+            .ok_or(ErrorKind::InvariantViolation("fee overflow"))?;
+        Ok(amount
+            .is_positive()
+            .then(|| FeeEntry::new(Some(transaction.tx), transaction.currency.clone(), amount)))
+    }
+
+    /// Takes back a fee applied up front for a transaction that failed.
+    fn give_back_fee(&mut self, fee: &AccountEvent) -> Result<(), ErrorKind> {
+        let postings = double_entry::postings(self.client, fee)
+            .into_iter()
+            .map(Posting::reversed)
+            .collect::<Vec<_>>();
+        self.post(&postings)?;
+        self.fee_ledger.pop();
+        Ok(())
+    }
+
+    /// Charges the monthly fee for every calendar month started since the last transaction.
+    fn charge_maintenance(&mut self, now: Timestamp) -> Result<(), ErrorKind> {
+        let (monthly, month) = match (
+            self.fee_schedule
+                .as_ref()
+                .and_then(|schedule| schedule.monthly),
+            now.month(),
+        ) {
+            (Some(monthly), Some(month)) => (monthly, month),
+            _ => return Ok(()),
+        };
+        let last = *self.maintenance_month.get_or_insert(month);
+        if month <= last {
+            return Ok(());
+        }
+        self.maintenance_month = Some(month);
+
+        let amount = monthly
+            .checked_mul(Decimal::from(month - last))
+            .ok_or(ErrorKind::InvariantViolation("fee overflow"))?;
+        if amount.is_positive() {
+            self.emit(AccountEvent::FeeCharged {
+                tx: None,
+                currency: None,
+                amount,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Accrues daily interest on positive available funds up to the day of `now`. Whatever
+    /// accrued is posted at the start of every calendar month.
+    fn accrue_interest(&mut self, now: Timestamp) -> Result<(), ErrorKind> {
+        let overflow = || ErrorKind::InvariantViolation("interest overflow");
+        let rate = match self.interest_rate {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        let today = interest::day(now);
+        let mut day = *self.accrual_day.get_or_insert(today);
+        while day < today {
+            let month_start = interest::next_month_start(day).ok_or_else(overflow)?;
+            let until = month_start.min(today);
+            let factor = interest::accrual_factor(rate, until - day).ok_or_else(overflow)?;
+            for (currency, balance) in &self.balances {
+                if balance.available.is_positive() {
+                    let accrued = self.accrued_interest.entry(currency.clone()).or_default();
+                    *accrued = balance
+                        .available
+                        .checked_mul(factor)
+                        .and_then(|interest| accrued.checked_add(interest))
+                        .ok_or_else(overflow)?;
+                }
+            }
+            if until == month_start {
+                let posted_at = interest::day_start(month_start).ok_or_else(overflow)?;
+                self.post_interest(posted_at)?;
+            }
+            day = until;
+        }
+        self.accrual_day = Some(day);
+        Ok(())
+    }
+
+    /// Credits the accrued interest rounded to the report precision, the remainder carries
+    /// over to the next posting.
+    fn post_interest(&mut self, posted_at: Timestamp) -> Result<(), ErrorKind> {
+        for (currency, accrued) in std::mem::take(&mut self.accrued_interest) {
+            let amount = accrued.round(&MoneyFormat::default());
+            if amount.is_positive() {
+                self.emit(AccountEvent::InterestPosted {
+                    currency: currency.clone(),
+                    amount,
+                    posted_at,
+                })?;
+            }
+            self.accrued_interest.insert(currency, accrued - amount);
+        }
+        Ok(())
+    }
+
+    pub fn process_pending_transaction(&mut self) -> Result<(), ErrorKind> {
+        let transaction = match self.pending_transactions.pop_front() {
+            Some(t) => t,
+            None => return Err(ErrorKind::NoTransactionToProcess),
+        };
+        if self.closed {
+            return Err(ErrorKind::AccountClosed);
+        }
+        // Refused transactions are dropped, so they can't block an unlock queued behind them
+        self.is_account_state_valid_for_transaction(&transaction.transaction_type)?;
+        if matches!(
+            transaction.transaction_type,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Representment
+                | TransactionType::Refund
+        ) {
+            self.load_history(transaction.tx)?;
+        }
+        if let Some(now) = transaction.timestamp {
+            self.expire_authorizations(now);
+            self.accrue_interest(now)?;
+            self.charge_maintenance(now)?;
+        }
+        self.check_limits(&transaction)?;
+        self.check_fraud_rules(&transaction)?;
+        // Rules learn from accepted transactions, which `apply` consumes
+        let watched = (!self.fraud_rules.is_empty()).then(|| transaction.clone());
+        let timestamp = transaction.timestamp;
+        // Disputes and the like move funds in the currency of the transaction they refer to
+        let statement = self.statement.is_some().then(|| {
+            let currency = match self.transactions_history.get(&transaction.tx) {
+                Some(entry) if !transaction.transaction_type.has_own_id() => entry.currency.clone(),
+                _ => transaction.currency.clone(),
+            };
+            (transaction.clone(), currency)
+        });
+        let withdrawn = match transaction.transaction_type {
+            TransactionType::Withdrawal => transaction.amount,
+            _ => None,
+        };
+
+        // The fee is taken up front so the transaction's own balance checks account for it,
+        // and given back when the transaction fails. It's only logged once the transaction
+        // went through, ahead of the transaction's own events.
+        let fee = self.transaction_fee(&transaction)?.map(AccountEvent::from);
+        let logged = self.events().len();
+        if let Some(fee) = &fee {
+            self.apply_event(fee)?;
+        }
+        let applied = self.apply(transaction);
+        // Entries loaded back for the transaction are only spilled again once it's applied
+        self.spill_history();
+        match applied {
+            Ok(()) => {
+                if let (Some(fee), Some(events)) = (fee, &mut self.events) {
+                    events.insert(logged, fee);
+                }
+                if let Some(now) = timestamp {
+                    self.count_for_limits(now, withdrawn);
+                }
+                if let Some((transaction, currency)) = statement {
+                    self.record_statement(&transaction, currency);
+                }
+                if let Some(transaction) = watched {
+                    let mut rules = std::mem::take(&mut self.fraud_rules);
+                    for rule in rules.iter_mut() {
+                        rule.accepted(self, &transaction);
+                    }
+                    self.fraud_rules = rules;
+                }
+                Ok(())
+            }
+            Err(error) => {
+                if let Some(fee) = &fee {
+                    self.give_back_fee(fee)?;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Rejects transactions exceeding the account's limits. Only transactions with a
+    /// timestamp count towards the daily and per minute limits.
+    fn check_limits(&mut self, transaction: &Transaction) -> Result<(), ErrorKind> {
+        if let (Some(max), Some(amount)) = (self.limits.max_amount, transaction.amount) {
+            if amount > max || -amount > max {
+                return Err(ErrorKind::TransactionLimitExceeded);
+            }
+        }
+        let now = match transaction.timestamp {
+            Some(now) => now,
+            None => return Ok(()),
+        };
+
+        if let Some(max) = self.limits.max_per_minute {
+            let window_start = now.as_millis().saturating_sub(MILLIS_PER_MINUTE);
+            while self
+                .recent_transactions
+                .front()
+                .is_some_and(|t| t.as_millis() <= window_start)
+            {
+                self.recent_transactions.pop_front();
+            }
+            let count = self.recent_transactions.len() as u32;
+            if count >= max {
+                return Err(ErrorKind::VelocityLimitExceeded(count));
+            }
+        }
+
+        if let (Some(max), TransactionType::Withdrawal, Some(amount)) = (
+            self.limits.max_daily_withdrawal,
+            &transaction.transaction_type,
+            transaction.amount,
+        ) {
+            let (withdrawn, count) = match self.daily_withdrawals {
+                Some((day, withdrawn, count)) if day == interest::day(now) => (withdrawn, count),
+                _ => (Money::ZERO, 0),
+            };
+            if withdrawn
+                .checked_add(amount)
+                .is_none_or(|total| total > max)
+            {
+                return Err(ErrorKind::DailyLimitExceeded(count));
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the transaction's hits of fraud rules, rejecting it when any of them blocks it.
+    fn check_fraud_rules(&mut self, transaction: &Transaction) -> Result<(), ErrorKind> {
+        let mut blocked = None;
+        for rule in self.fraud_rules.iter() {
+            let verdict = rule.check(self, transaction);
+            if verdict == Verdict::Pass {
+                continue;
+            }
+            if verdict == Verdict::Block && blocked.is_none() {
+                blocked = Some(rule.name());
+            }
+            self.fraud_hits
+                .push(FraudHit::new(transaction, rule.name(), verdict));
+        }
+        match blocked {
+            Some(rule) => Err(ErrorKind::FraudBlocked(rule)),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts an accepted transaction towards the daily and per minute limits.
+    fn count_for_limits(&mut self, now: Timestamp, withdrawn: Option<Money>) {
+        if self.limits.max_per_minute.is_some() {
+            self.recent_transactions.push_back(now);
+        }
+        if let Some(amount) = withdrawn {
+            self.daily_withdrawals = Some(match self.daily_withdrawals {
+                Some((day, total, count)) if day == interest::day(now) => {
+                    (day, total.checked_add(amount).unwrap_or(total), count + 1)
+                }
+                _ => (interest::day(now), amount, 1),
+            });
+        }
+    }
+
+    fn apply(&mut self, transaction: Transaction) -> Result<(), ErrorKind> {
+        match transaction.transaction_type {
+            TransactionType::Deposit => {
+                let amount = match transaction.amount {
+                    Some(a) => a,
+                    None => {
+                        return Err(ErrorKind::InvalidAmount);
+                    }
+                };
+
+                self.deposit(transaction.tx, transaction.currency.as_ref(), amount)?;
+                self.record_history(transaction);
+            }
+            TransactionType::Withdrawal => {
+                let amount = match transaction.amount {
+                    Some(a) => a,
+                    None => {
+                        return Err(ErrorKind::InvalidAmount);
+                    }
+                };
+
+                self.withdraw(transaction.tx, transaction.currency.as_ref(), amount)?;
+                self.record_history(transaction);
+            }
+            TransactionType::Convert => {
+                let amount = match transaction.amount {
+                    Some(a) => a,
+                    None => {
+                        return Err(ErrorKind::InvalidAmount);
+                    }
+                };
+                let (from, to, converted) = match (
+                    &transaction.currency,
+                    &transaction.to_currency,
+                    transaction.converted,
+                ) {
+                    (Some(from), Some(to), Some(converted)) => (from, to, converted),
+                    _ => return Err(ErrorKind::InvalidConversion),
+                };
+
+                self.convert(transaction.tx, from, to, amount, converted)?;
+                self.record_history(transaction);
+            }
+            // Transfers touch two accounts and go through Account::transfer
+            TransactionType::Transfer => {
+                return Err(ErrorKind::InvalidTransfer);
+            }
+            TransactionType::Unlock => {
+                self.unlock()?;
+            }
+            TransactionType::Representment => {
+                self.represent(transaction.tx)?;
+            }
+            TransactionType::Close => {
+                self.close()?;
+            }
+            TransactionType::Adjustment => {
+                let amount = match transaction.amount {
+                    Some(a) => a,
+                    None => {
+                        return Err(ErrorKind::InvalidAmount);
+                    }
+                };
+                if !transaction
+                    .reason
+                    .as_deref()
+                    .is_some_and(is_valid_reason_code)
+                {
+                    return Err(ErrorKind::InvalidReasonCode);
+                }
+
+                self.adjust(transaction.tx, transaction.currency.as_ref(), amount)?;
+                self.record_history(transaction);
+            }
+            TransactionType::Authorize => {
+                let amount = match transaction.amount {
+                    Some(a) => a,
+                    None => {
+                        return Err(ErrorKind::InvalidAmount);
+                    }
+                };
+
+                self.authorize(transaction.tx, transaction.currency.as_ref(), amount)?;
+                self.record_history(transaction);
+            }
+            TransactionType::Capture => {
+                self.capture(transaction.tx, transaction.amount)?;
+            }
+            TransactionType::Void => {
+                self.settle_authorization(transaction.tx, Money::ZERO, AuthorizationState::Voided)?;
+            }
+            TransactionType::Refund => {
+                self.refund(transaction.tx, transaction.amount)?;
+            }
+            TransactionType::Dispute => {
+                self.dispute(transaction.tx, transaction.amount)?;
+            }
+            TransactionType::Resolve => {
+                self.resolve(transaction.tx)?;
+            }
+            TransactionType::Chargeback => {
+                self.chargeback(transaction.tx)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Account {
+    /// State to persist the account with. Fails when spilled history can't be read back.
+    pub(crate) fn snapshot(&self) -> io::Result<AccountSnapshot> {
+        let mut history = self
+            .transactions_history
+            .iter()
+            .map(|(tx, entry)| entry.state(self.client, *tx))
+            .collect::<Vec<_>>();
+        if let Some(window) = &self.history_window {
+            for offset in self.spilled_history.values() {
+                history.push(window.spill().read(*offset)?);
+            }
+        }
+        history.sort_by_key(|entry| entry.transaction.tx);
+        Ok(self.snapshot_with(history))
+    }
+
+    /// State of the account right after it posted transaction `tx`, with only the history
+    /// entry of that transaction, if it has one.
+    #[cfg(feature = "postgres")]
+    pub(crate) fn posting_snapshot(&self, tx: u32) -> AccountSnapshot {
+        let entry = self.transactions_history.get(&tx);
+        self.snapshot_with(
+            entry
+                .map(|entry| entry.state(self.client, tx))
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    fn snapshot_with(&self, history: Vec<HistoryState>) -> AccountSnapshot {
+        AccountSnapshot {
+            client: self.client,
+            balances: self
+                .balances
+                .iter()
+                .map(|(currency, balance)| (currency.clone(), *balance))
+                .collect(),
+            locked: self.locked,
+            closed: self.closed,
+            history,
+            open_authorizations: self.open_authorizations.clone(),
+            outgoing_transfers: self
+                .outgoing_transfers
+                .iter()
+                .map(|(tx, transfer)| (*tx, transfer.clone()))
+                .collect(),
+            incoming_transfers: self
+                .incoming_transfers
+                .iter()
+                .map(|(tx, transfer)| (*tx, transfer.clone()))
+                .collect(),
+            fees: self.fee_ledger.clone(),
+            maintenance_month: self.maintenance_month,
+            accrued_interest: self
+                .accrued_interest
+                .iter()
+                .map(|(currency, amount)| (currency.clone(), *amount))
+                .collect(),
+            accrual_day: self.accrual_day,
+            interest: self.interest_ledger.clone(),
+            daily_withdrawals: self.daily_withdrawals,
+            recent_transactions: self.recent_transactions.iter().copied().collect(),
+        }
+    }
+
+    /// Takes over persisted state, keeping the account's configuration.
+    pub(crate) fn restore(mut self, state: AccountSnapshot) -> Self {
+        self.client = state.client;
+        self.balances = state.balances.into_iter().collect();
+        self.locked = state.locked;
+        self.closed = state.closed;
+        self.history_order = state
+            .history
+            .iter()
+            .map(|entry| entry.transaction.tx)
+            .collect();
+        self.transactions_history = state
+            .history
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.transaction.tx,
+                    HistoryEntry::from_state(entry, self.full_history),
+                )
+            })
+            .collect();
+        self.spilled_history.clear();
+        self.open_authorizations = state.open_authorizations;
+        self.outgoing_transfers = state.outgoing_transfers.into_iter().collect();
+        self.incoming_transfers = state.incoming_transfers.into_iter().collect();
+        self.fee_ledger = state.fees;
+        self.maintenance_month = state.maintenance_month;
+        self.accrued_interest = state.accrued_interest.into_iter().collect();
+        self.accrual_day = state.accrual_day;
+        self.interest_ledger = state.interest;
+        self.daily_withdrawals = state.daily_withdrawals;
+        self.recent_transactions = state.recent_transactions.into();
+        if self.history_window.is_none() {
+            self.history_order.clear();
+        }
+        self.spill_history();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Account, AuthorizationState, ChargebackPolicy, DisputeState, ErrorKind,
+        TransactionProcessingError,
+    };
+    use crate::currency::Currency;
+    use crate::fees::{Fee, FeeEntry, FeeSchedule};
+    use crate::fraud::{FraudRules, RepeatedDisputes, Verdict};
+    use crate::history::HistoryWindow;
+    use crate::interest::InterestPosting;
+    use crate::limits::Limits;
+    use crate::money::{Money, MoneyFormat};
+    use crate::timestamp::Timestamp;
+    use crate::transaction::{Transaction, TransactionType};
+    use rust_decimal::Decimal;
+    use std::sync::Arc;
+
+    fn prepare_acc(initial_funds: Money) -> Account {
+        let mut acc = Account::new(0);
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            0,
+            Some(initial_funds),
+        ));
+        acc.process_pending_transaction().unwrap();
+        acc
+    }
+
+    #[test]
+    fn deposit() {
+        let mut acc = prepare_acc(Money::from(5));
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.total(), Money::from(5));
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            1,
+            Some(Money::from(-5)),
+        ));
+        assert!(acc.process_pending_transaction().is_err());
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.total(), Money::from(5));
+    }
+
+    #[test]
+    fn history_keeps_timestamp() {
+        let mut acc = Account::new(0).with_full_history();
+        let timestamp = Timestamp::from_millis(1_700_000_000_000);
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            0,
+            Some(Money::from(5)),
+        ));
+        acc.add_transaction(
+            Transaction::new(TransactionType::Deposit, 0, 1, Some(Money::from(1)))
+                .with_timestamp(timestamp),
+        );
+        acc.process_pending_transaction().unwrap();
+        acc.process_pending_transaction().unwrap();
+
+        let entry = acc.history_entry(1).unwrap();
+        assert_eq!(entry.transaction().unwrap().timestamp(), Some(timestamp));
+        assert_eq!(
+            acc.history_entry(0)
+                .unwrap()
+                .transaction()
+                .unwrap()
+                .timestamp(),
+            None
+        );
+    }
+
+    #[test]
+    fn compact_history() {
+        let mut acc = prepare_acc(Money::new(15, 1));
+        acc.add_transaction(
+            Transaction::new(TransactionType::Withdrawal, 0, 1, Some(Money::new(5, 1)))
+                .with_timestamp(Timestamp::from_millis(1_700_000_000_000))
+                .with_metadata("memo", "rent"),
+        );
+        acc.process_pending_transaction().unwrap();
+
+        let entry = acc.history_entry(1).unwrap();
+        assert!(entry.transaction().is_none());
+        assert_eq!(entry.metadata()["memo"], "rent");
+        let restored = super::HistoryEntry::from_state(entry.state(0, 1), true);
+        assert_eq!(restored.transaction().unwrap().metadata()["memo"], "rent");
+        assert!(acc.history_entry(0).unwrap().metadata().is_empty());
+        assert_eq!(entry.transaction_type(), &TransactionType::Withdrawal);
+        assert_eq!(entry.amount(), Money::new(5, 1));
+        assert!(entry.dispute_lifecycle().is_empty());
+
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 0, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.held(), Money::new(15, 1));
+        let state = acc.history_entry(0).unwrap().state(0, 0);
+        let restored = super::HistoryEntry::from_state(state, false);
+        assert_eq!(restored.amount(), Money::new(15, 1));
+        assert_eq!(restored.disputed(), Money::new(15, 1));
+        assert_eq!(restored.dispute_lifecycle(), [DisputeState::Disputed]);
+    }
+
+    #[test]
+    fn currencies() {
+        let eur = "EUR".parse::<Currency>().unwrap();
+        let mut acc = prepare_acc(Money::from(5));
+        acc.add_transaction(
+            Transaction::new(TransactionType::Deposit, 0, 1, Some(Money::from(3)))
+                .with_currency(eur.clone()),
+        );
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.balance(Some(&eur)).available(), Money::from(3));
+        assert_eq!(acc.currencies().collect::<Vec<_>>(), vec![&eur]);
+
+        // Funds of one currency can't cover a withdrawal in another
+        acc.add_transaction(
+            Transaction::new(TransactionType::Withdrawal, 0, 2, Some(Money::from(4)))
+                .with_currency(eur.clone()),
+        );
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+
+        // Disputes hold funds in the currency of the disputed transaction
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.balance(Some(&eur)).held(), Money::from(3));
+        assert_eq!(acc.balance(Some(&eur)).available(), Money::ZERO);
+        assert_eq!(acc.held(), Money::ZERO);
+        assert!(acc.reconcile().is_ok());
+
+        let currencies = acc
+            .records(&MoneyFormat::default())
+            .into_iter()
+            .map(|record| record.currency)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            currencies,
+            vec![Some(String::new()), Some("EUR".to_string())]
+        );
+        assert_eq!(acc.record(&MoneyFormat::default()).currency, None);
+    }
+
+    #[test]
+    fn convert() {
+        let (eur, usd) = (
+            "EUR".parse::<Currency>().unwrap(),
+            "USD".parse::<Currency>().unwrap(),
+        );
+        let conversion = |tx, amount, converted| {
+            Transaction::new(TransactionType::Convert, 0, tx, Some(Money::from(amount)))
+                .with_currency(eur.clone())
+                .with_to_currency(usd.clone())
+                .with_converted_amount(Money::from(converted))
+        };
+        let mut acc = Account::new(0);
+        acc.add_transaction(
+            Transaction::new(TransactionType::Deposit, 0, 1, Some(Money::from(10)))
+                .with_currency(eur.clone()),
+        );
+        acc.process_pending_transaction().unwrap();
+
+        acc.add_transaction(conversion(2, 4, 5));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.balance(Some(&eur)).available(), Money::from(6));
+        assert_eq!(acc.balance(Some(&usd)).available(), Money::from(5));
+
+        // Nothing moves when the source balance can't cover the conversion
+        acc.add_transaction(conversion(3, 7, 9));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+        assert_eq!(acc.balance(Some(&eur)).available(), Money::from(6));
+        assert_eq!(acc.balance(Some(&usd)).available(), Money::from(5));
+
+        // Conversions need a quote and can't be disputed
+        acc.add_transaction(
+            Transaction::new(TransactionType::Convert, 0, 4, Some(Money::from(1)))
+                .with_currency(eur.clone())
+                .with_to_currency(usd.clone()),
+        );
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvalidConversion)
+        ));
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 2, None));
+        assert!(acc.process_pending_transaction().is_err());
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn transfer() {
+        let transfer = |tx, amount| {
+            Transaction::new(TransactionType::Transfer, 0, tx, Some(Money::from(amount)))
+                .with_to_client(1)
+        };
+        let mut source = prepare_acc(Money::from(10));
+        let mut destination = Account::new(1);
+
+        source.transfer(&mut destination, transfer(1, 4)).unwrap();
+        assert_eq!(source.available(), Money::from(6));
+        assert_eq!(destination.available(), Money::from(4));
+
+        assert!(matches!(
+            source.transfer(&mut destination, transfer(2, 7)),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+        assert!(matches!(
+            source.transfer(&mut destination, transfer(3, -1)),
+            Err(ErrorKind::NegativeAmount)
+        ));
+        assert!(matches!(
+            source.transfer(&mut Account::new(2), transfer(4, 1)),
+            Err(ErrorKind::InvalidTransfer)
+        ));
+
+        // A locked destination can't receive funds and the source keeps its money
+        destination.locked = true;
+        assert!(matches!(
+            source.transfer(&mut destination, transfer(5, 1)),
+            Err(ErrorKind::AccountLocked(_))
+        ));
+        assert_eq!(source.available(), Money::from(6));
+        assert_eq!(destination.available(), Money::from(4));
+
+        source.add_transaction(transfer(6, 1));
+        assert!(matches!(
+            source.process_pending_transaction(),
+            Err(ErrorKind::InvalidTransfer)
+        ));
+    }
+
+    #[test]
+    fn unlock() {
+        let mut acc = prepare_acc(Money::from(10));
+        assert!(matches!(acc.unlock(), Err(ErrorKind::AccountNotLocked)));
+
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 0, None));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(TransactionType::Chargeback, 0, 0, None));
+        acc.process_pending_transaction().unwrap();
+        assert!(acc.locked());
+
+        acc.add_transaction(Transaction::new(TransactionType::Unlock, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        assert!(!acc.locked());
+        assert_eq!(acc.available(), Money::ZERO);
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            2,
+            Some(Money::from(3)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(3));
+    }
+
+    #[test]
+    fn adjustment() {
+        let adjustment = |tx, amount, reason: &str| {
+            Transaction::new(
+                TransactionType::Adjustment,
+                0,
+                tx,
+                Some(Money::from(amount)),
+            )
+            .with_reason(reason)
+        };
+        let mut acc = prepare_acc(Money::from(10));
+
+        acc.add_transaction(adjustment(1, -4, "FEE_REVERSAL"));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+        assert_eq!(acc.total(), Money::from(6));
+        assert_eq!(acc.history_entry(1).unwrap().amount(), Money::from(-4));
+
+        acc.add_transaction(adjustment(2, 2, "BOOKING-ERROR"));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(8));
+
+        for (transaction, expected) in [
+            (adjustment(3, -9, "FIX"), "InsufficientAmount"),
+            (adjustment(4, 0, "FIX"), "InvalidAmount"),
+            (adjustment(5, 1, ""), "InvalidReasonCode"),
+            (adjustment(6, 1, "not a code"), "InvalidReasonCode"),
+            (
+                Transaction::new(TransactionType::Adjustment, 0, 7, Some(Money::from(1))),
+                "InvalidReasonCode",
+            ),
+        ] {
+            acc.add_transaction(transaction);
+            let error = acc.process_pending_transaction().unwrap_err();
+            assert_eq!(format!("{:?}", error), expected);
+        }
+        assert_eq!(acc.available(), Money::from(8));
+
+        // Adjustments can't be disputed
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 1, None));
+        assert!(acc.process_pending_transaction().is_err());
+    }
+
+    #[test]
+    fn refund() {
+        let refund = |amount: Option<i64>| {
+            Transaction::new(TransactionType::Refund, 0, 0, amount.map(Money::from))
+        };
+        let mut acc = prepare_acc(Money::from(10));
+
+        acc.add_transaction(refund(Some(4)));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+        assert!(!acc.locked());
+        assert_eq!(acc.history_entry(0).unwrap().refunded(), Money::from(4));
+
+        acc.add_transaction(refund(Some(7)));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::RefundExceedsDeposit)
+        ));
+
+        // Only the part that wasn't refunded can be disputed
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 0, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::ZERO);
+        assert_eq!(acc.held(), Money::from(6));
+
+        // Nor can a deposit under dispute be refunded
+        acc.add_transaction(refund(Some(1)));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvalidRefundTarget)
+        ));
+        acc.add_transaction(Transaction::new(TransactionType::Resolve, 0, 0, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+
+        // Without an amount the rest of the deposit is refunded
+        acc.add_transaction(refund(None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::ZERO);
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 0, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvalidDisputeTarget)
+        ));
+
+        acc.add_transaction(Transaction::new(TransactionType::Refund, 0, 9, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvalidRefundTarget)
+        ));
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn authorization() {
+        let authorize = |tx, amount| {
+            Transaction::new(TransactionType::Authorize, 0, tx, Some(Money::from(amount)))
+        };
+        let state = |acc: &Account, tx| acc.history_entry(tx).unwrap().authorization_state();
+        let mut acc = prepare_acc(Money::from(10));
+
+        acc.add_transaction(authorize(1, 4));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+        assert_eq!(acc.held(), Money::from(4));
+        assert_eq!(state(&acc, 1), Some(AuthorizationState::Authorized));
+
+        // Partial capture releases the rest of the hold
+        acc.add_transaction(Transaction::new(
+            TransactionType::Capture,
+            0,
+            1,
+            Some(Money::from(3)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(7));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.total(), Money::from(7));
+        assert_eq!(state(&acc, 1), Some(AuthorizationState::Captured));
+
+        acc.add_transaction(Transaction::new(TransactionType::Void, 0, 1, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvalidAuthorization)
+        ));
+
+        acc.add_transaction(authorize(2, 5));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(
+            TransactionType::Capture,
+            0,
+            2,
+            Some(Money::from(6)),
+        ));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::CaptureExceedsAuthorization)
+        ));
+        acc.add_transaction(Transaction::new(TransactionType::Void, 0, 2, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(7));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(state(&acc, 2), Some(AuthorizationState::Voided));
+
+        acc.add_transaction(authorize(3, 8));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn authorization_expiry() {
+        let at = Timestamp::from_millis;
+        let mut acc = prepare_acc(Money::from(10));
+        acc.add_transaction(
+            Transaction::new(TransactionType::Authorize, 0, 1, Some(Money::from(4)))
+                .with_timestamp(at(1_000))
+                .with_expiry(at(2_000)),
+        );
+        acc.process_pending_transaction().unwrap();
+
+        // Not expired yet
+        acc.add_transaction(
+            Transaction::new(TransactionType::Deposit, 0, 2, Some(Money::from(1)))
+                .with_timestamp(at(2_000)),
+        );
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.held(), Money::from(4));
+
+        acc.add_transaction(
+            Transaction::new(TransactionType::Capture, 0, 1, None).with_timestamp(at(2_001)),
+        );
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::AuthorizationExpired)
+        ));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.available(), Money::from(11));
+        assert_eq!(
+            acc.history_entry(1).unwrap().authorization_state(),
+            Some(AuthorizationState::Expired)
+        );
+    }
+
+    #[test]
+    fn withdraw() {
+        let mut acc = prepare_acc(Money::from(10));
+        assert_eq!(acc.available(), Money::from(10));
+        assert_eq!(acc.total(), Money::from(10));
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            1,
+            Some(Money::from(5)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.total(), Money::from(5));
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            2,
+            Some(Money::from(6)),
+        ));
+        assert!(acc.process_pending_transaction().is_err());
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.total(), Money::from(5));
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            3,
+            Some(Money::from(-1)),
+        ));
+        assert!(acc.process_pending_transaction().is_err());
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.total(), Money::from(5));
+    }
+
+    #[test]
+    fn dispute() {
+        let mut acc = prepare_acc(Money::from(10));
+        assert_eq!(acc.available(), Money::from(10));
+        assert_eq!(acc.total(), Money::from(10));
+        const TRANSACTION_TO_DISPUTE_ID: u32 = 5;
+        const INVALID_DISPUTE_ID: u32 = 999;
+        const WITHDRAW_TRANSACTION_ID: u32 = 10;
+
+        let deposit_transaction = Transaction::new(
+            TransactionType::Deposit,
+            0,
+            TRANSACTION_TO_DISPUTE_ID,
+            Some(Money::from(5)),
+        );
         acc.add_transaction(deposit_transaction);
         acc.process_pending_transaction().unwrap();
 
@@ -301,9 +2721,9 @@ mod tests {
 
         acc.add_transaction(dispute_transaction);
         acc.process_pending_transaction().unwrap();
-        assert_eq!(acc.total, 15.0);
-        assert_eq!(acc.available, 10.0);
-        assert_eq!(acc.held, 5.0);
+        assert_eq!(acc.total(), Money::from(15));
+        assert_eq!(acc.available(), Money::from(10));
+        assert_eq!(acc.held(), Money::from(5));
 
         let invalid_dispute =
             Transaction::new(TransactionType::Dispute, 0, INVALID_DISPUTE_ID, None);
@@ -314,16 +2734,756 @@ mod tests {
             TransactionType::Withdrawal,
             0,
             INVALID_DISPUTE_ID,
-            Some(1.0),
+            Some(Money::from(1)),
         );
         acc.add_transaction(withdraw_transaction);
         acc.process_pending_transaction().unwrap();
-        assert_eq!(acc.total, 14.0);
-        assert_eq!(acc.available, 9.0);
+        assert_eq!(acc.total(), Money::from(14));
+        assert_eq!(acc.available(), Money::from(9));
 
         let another_invalid_dispute =
             Transaction::new(TransactionType::Dispute, 0, WITHDRAW_TRANSACTION_ID, None);
         acc.add_transaction(another_invalid_dispute);
         assert!(acc.process_pending_transaction().is_err());
     }
+
+    #[test]
+    fn partial_dispute() {
+        let dispute = |amount: Option<i64>| {
+            Transaction::new(TransactionType::Dispute, 0, 1, amount.map(Money::from))
+        };
+        let mut acc = prepare_acc(Money::from(10));
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            1,
+            Some(Money::from(6)),
+        ));
+        acc.process_pending_transaction().unwrap();
+
+        acc.add_transaction(dispute(Some(2)));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(14));
+        assert_eq!(acc.held(), Money::from(2));
+
+        // Disputes add up to at most the transaction's amount
+        acc.add_transaction(dispute(Some(5)));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::DisputeExceedsTransaction)
+        ));
+        acc.add_transaction(dispute(Some(3)));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.held(), Money::from(5));
+        assert_eq!(acc.history_entry(1).unwrap().disputed(), Money::from(5));
+
+        acc.add_transaction(Transaction::new(TransactionType::Resolve, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(16));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.history_entry(1).unwrap().disputed(), Money::ZERO);
+
+        // Without an amount the whole transaction is disputed again
+        acc.add_transaction(dispute(None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.held(), Money::from(6));
+        acc.add_transaction(Transaction::new(TransactionType::Resolve, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+
+        acc.add_transaction(dispute(Some(4)));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(TransactionType::Chargeback, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(12));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.total(), Money::from(12));
+        assert!(acc.locked());
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn representment() {
+        let mut acc = prepare_acc(Money::from(10));
+        for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            acc.add_transaction(Transaction::new(transaction_type, 0, 0, None));
+            acc.process_pending_transaction().unwrap();
+        }
+        assert!(acc.locked());
+        assert_eq!(acc.total(), Money::ZERO);
+
+        acc.add_transaction(Transaction::new(TransactionType::Representment, 0, 0, None));
+        acc.process_pending_transaction().unwrap();
+        assert!(!acc.locked());
+        assert_eq!(acc.available(), Money::from(10));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(
+            acc.history_entry(0).unwrap().dispute_lifecycle(),
+            [
+                DisputeState::Disputed,
+                DisputeState::ChargedBack,
+                DisputeState::Represented
+            ]
+        );
+
+        // Only charged back transactions can be represented
+        acc.add_transaction(Transaction::new(TransactionType::Representment, 0, 0, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvalidRepresentmentTarget)
+        ));
+
+        // The account stays locked while another chargeback stands
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            1,
+            Some(Money::from(2)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        for tx in [0, 1] {
+            if acc.locked() {
+                acc.unlock().unwrap();
+            }
+            for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                acc.add_transaction(Transaction::new(transaction_type, 0, tx, None));
+                acc.process_pending_transaction().unwrap();
+            }
+        }
+        acc.add_transaction(Transaction::new(TransactionType::Representment, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        assert!(acc.locked());
+        assert_eq!(acc.available(), Money::from(2));
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn chargeback_policy() {
+        let chargeback = |acc: &mut Account, tx| {
+            for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                acc.add_transaction(Transaction::new(transaction_type, 0, tx, None));
+                acc.process_pending_transaction().unwrap();
+            }
+        };
+        let deposit = |tx, amount| {
+            Transaction::new(TransactionType::Deposit, 0, tx, Some(Money::from(amount)))
+        };
+
+        let mut acc = prepare_acc(Money::from(10)).with_chargeback_policy(ChargebackPolicy {
+            lock_account: false,
+            ..ChargebackPolicy::default()
+        });
+        chargeback(&mut acc, 0);
+        assert!(!acc.locked());
+        acc.add_transaction(deposit(1, 1));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(1));
+
+        // By default an already spent deposit can still be disputed
+        let mut acc = prepare_acc(Money::from(10));
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            1,
+            Some(Money::from(4)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        chargeback(&mut acc, 0);
+        assert_eq!(acc.available(), Money::from(-4));
+
+        let mut acc = prepare_acc(Money::from(10)).with_chargeback_policy(ChargebackPolicy {
+            allow_negative_available: false,
+            deposits_when_locked: true,
+            ..ChargebackPolicy::default()
+        });
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            1,
+            Some(Money::from(4)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 0, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+        acc.add_transaction(deposit(2, 4));
+        acc.process_pending_transaction().unwrap();
+        chargeback(&mut acc, 0);
+        assert!(acc.locked());
+        assert_eq!(acc.available(), Money::ZERO);
+
+        acc.add_transaction(deposit(3, 5));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(5));
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            4,
+            Some(Money::from(1)),
+        ));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::AccountLocked(_))
+        ));
+    }
+
+    #[test]
+    fn disputes_when_locked() {
+        let process = |acc: &mut Account, transaction_type, tx| {
+            acc.add_transaction(Transaction::new(transaction_type, 0, tx, None));
+            acc.process_pending_transaction()
+        };
+        for disputes_when_locked in [false, true] {
+            let mut acc = prepare_acc(Money::from(10)).with_chargeback_policy(ChargebackPolicy {
+                disputes_when_locked,
+                ..ChargebackPolicy::default()
+            });
+            acc.add_transaction(Transaction::new(
+                TransactionType::Deposit,
+                0,
+                1,
+                Some(Money::from(5)),
+            ));
+            acc.process_pending_transaction().unwrap();
+            process(&mut acc, TransactionType::Dispute, 1).unwrap();
+            process(&mut acc, TransactionType::Dispute, 0).unwrap();
+            process(&mut acc, TransactionType::Chargeback, 0).unwrap();
+            assert!(acc.locked());
+
+            // Settling the other open dispute depends on the policy
+            let resolved = process(&mut acc, TransactionType::Resolve, 1);
+            assert_eq!(resolved.is_ok(), disputes_when_locked);
+            assert_eq!(
+                acc.held(),
+                if disputes_when_locked {
+                    Money::ZERO
+                } else {
+                    Money::from(5)
+                }
+            );
+
+            acc.add_transaction(Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                2,
+                Some(Money::from(1)),
+            ));
+            assert!(matches!(
+                acc.process_pending_transaction(),
+                Err(ErrorKind::AccountLocked(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn close() {
+        let mut acc = prepare_acc(Money::from(10));
+        acc.add_transaction(Transaction::new(TransactionType::Close, 0, 1, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::AccountNotEmpty)
+        ));
+        assert!(!acc.closed());
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            2,
+            Some(Money::from(10)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(TransactionType::Close, 0, 3, None));
+        acc.process_pending_transaction().unwrap();
+        assert!(acc.closed());
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            4,
+            Some(Money::from(1)),
+        ));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::AccountClosed)
+        ));
+        assert_eq!(acc.available(), Money::ZERO);
+        assert!(matches!(
+            Account::new(1).transfer(
+                &mut acc,
+                Transaction::new(TransactionType::Transfer, 1, 5, Some(Money::from(1)))
+                    .with_to_client(0)
+            ),
+            Err(ErrorKind::AccountClosed)
+        ));
+    }
+
+    #[test]
+    fn fees() {
+        let mut schedule = FeeSchedule::new();
+        schedule
+            .insert(
+                TransactionType::Withdrawal,
+                Fee {
+                    flat: Money::from(1),
+                    ..Fee::default()
+                },
+            )
+            .unwrap();
+        schedule.monthly = Some(Money::from(2));
+        let mut acc = prepare_acc(Money::from(10)).with_fee_schedule(Arc::new(schedule));
+        let withdrawal = |tx, amount| {
+            Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                tx,
+                Some(Money::from(amount)),
+            )
+        };
+
+        acc.add_transaction(withdrawal(1, 4));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.fees(), [FeeEntry::new(Some(1), None, Money::from(1))]);
+
+        // The withdrawal has to leave enough for its fee, a failed one costs nothing
+        acc.add_transaction(withdrawal(2, 5));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+        assert_eq!(acc.available(), Money::from(5));
+        assert_eq!(acc.fees().len(), 1);
+
+        // Maintenance is charged for each month started since the first transaction
+        let at = |date: &str| date.parse::<Timestamp>().unwrap();
+        for (tx, date) in [
+            (3, "2024-01-31T00:00:00Z"),
+            (4, "2024-01-01T00:00:00Z"),
+            (5, "2024-03-01T00:00:00Z"),
+        ] {
+            acc.add_transaction(
+                Transaction::new(TransactionType::Deposit, 0, tx, Some(Money::from(1)))
+                    .with_timestamp(at(date)),
+            );
+            acc.process_pending_transaction().unwrap();
+        }
+        assert_eq!(acc.available(), Money::from(4));
+        assert_eq!(
+            acc.fees().last(),
+            Some(&FeeEntry::new(None, None, Money::from(4)))
+        );
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn interest() {
+        let at = |date: &str| date.parse::<Timestamp>().unwrap();
+        let deposit = |tx, date| {
+            Transaction::new(TransactionType::Deposit, 0, tx, Some(Money::from(1000)))
+                .with_timestamp(at(date))
+        };
+        // 0.1% a day
+        let mut acc = Account::new(0).with_interest_rate(Decimal::new(365, 3));
+        acc.add_transaction(deposit(1, "2024-01-01T12:00:00Z"));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(deposit(2, "2024-01-21T00:00:00Z"));
+        acc.process_pending_transaction().unwrap();
+        assert!(acc.interest().is_empty());
+
+        // 20 days on 1000 and 11 days on 2000 are posted on February 1st, the following
+        // 10 days on 2042 are still accruing
+        acc.add_transaction(deposit(3, "2024-02-11T08:00:00Z"));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(
+            acc.interest(),
+            [InterestPosting::new(
+                None,
+                Money::from(42),
+                at("2024-02-01T00:00:00Z")
+            )]
+        );
+        assert_eq!(acc.available(), Money::from(3042));
+        assert_eq!(acc.accrued_interest[&None], Money::new(2042, 2));
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn overdraft() {
+        let withdrawal = |tx, amount| {
+            Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                tx,
+                Some(Money::from(amount)),
+            )
+        };
+        let mut acc = prepare_acc(Money::from(10)).with_overdraft_limit(Money::from(5));
+        acc.add_transaction(withdrawal(1, 14));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(-4));
+        assert_eq!(acc.record(&MoneyFormat::default()).overdrawn, Some(true));
+
+        acc.add_transaction(withdrawal(2, 2));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::OverdraftExceeded)
+        ));
+        acc.add_transaction(withdrawal(3, 1));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(-5));
+        assert!(acc.reconcile().is_ok());
+
+        let mut acc = prepare_acc(Money::from(10));
+        acc.add_transaction(withdrawal(1, 11));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InsufficientAmount)
+        ));
+        assert_eq!(acc.record(&MoneyFormat::default()).overdrawn, None);
+    }
+
+    #[test]
+    fn limits() {
+        const DAY: i64 = 86_400_000;
+        let withdrawal = |tx, amount, at| {
+            Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                tx,
+                Some(Money::from(amount)),
+            )
+            .with_timestamp(Timestamp::from_millis(at))
+        };
+        let mut acc = prepare_acc(Money::from(100)).with_limits(Limits {
+            max_amount: Some(Money::from(20)),
+            max_daily_withdrawal: Some(Money::from(30)),
+            max_per_minute: Some(2),
+        });
+        acc.add_transaction(withdrawal(1, 21, DAY));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::TransactionLimitExceeded)
+        ));
+
+        acc.add_transaction(withdrawal(2, 20, DAY));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(withdrawal(3, 5, DAY + 1_000));
+        acc.process_pending_transaction().unwrap();
+        // Third transaction within a minute
+        acc.add_transaction(withdrawal(4, 1, DAY + 59_000));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::VelocityLimitExceeded(2))
+        ));
+        acc.add_transaction(withdrawal(5, 6, DAY + 61_000));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::DailyLimitExceeded(2))
+        ));
+        acc.add_transaction(withdrawal(6, 5, DAY + 62_000));
+        acc.process_pending_transaction().unwrap();
+
+        // The daily total starts over the next day
+        acc.add_transaction(withdrawal(7, 20, 2 * DAY));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(50));
+
+        // Transactions without a timestamp are only checked against the amount limit
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            8,
+            Some(Money::from(20)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(30));
+    }
+
+    #[test]
+    fn fraud_rules() {
+        let mut rules = FraudRules::new();
+        rules.push(RepeatedDisputes::new(1, Verdict::Block));
+        let mut acc = Account::new(0).with_fraud_rules(rules);
+        for tx in 1..=2 {
+            acc.add_transaction(Transaction::new(
+                TransactionType::Deposit,
+                0,
+                tx,
+                Some(Money::from(5)),
+            ));
+            acc.process_pending_transaction().unwrap();
+        }
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 2, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::FraudBlocked("disputes"))
+        ));
+        assert_eq!(acc.held(), Money::from(5));
+        assert_eq!(acc.fraud_hits().len(), 1);
+        assert_eq!(acc.fraud_hits()[0].tx, 2);
+        assert_eq!(acc.fraud_hits()[0].verdict, Verdict::Block);
+    }
+
+    #[test]
+    fn history_window() {
+        let path = std::env::temp_dir().join(format!("history_{}.jsonl", std::process::id()));
+        let window = HistoryWindow::new(2, &path, None).unwrap();
+        let mut acc = Account::new(0).with_history_window(window);
+        for tx in 1..=4 {
+            acc.add_transaction(Transaction::new(
+                TransactionType::Deposit,
+                0,
+                tx,
+                Some(Money::from(5)),
+            ));
+            acc.process_pending_transaction().unwrap();
+        }
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 4, None));
+        acc.process_pending_transaction().unwrap();
+        assert!(acc.history_entry(1).is_none());
+        assert!(acc.history_entry(2).is_none());
+
+        // Spilled deposits are loaded back for their dispute
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, 1, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.held(), Money::from(10));
+        assert_eq!(
+            acc.history_entry(1).unwrap().dispute_state(),
+            DisputeState::Disputed
+        );
+        // Open disputes stay in memory, the oldest undisputed entry makes room
+        assert_eq!(acc.history_entry(4).unwrap().disputed(), Money::from(5));
+        assert!(acc.history_entry(3).is_none());
+        acc.add_transaction(Transaction::new(TransactionType::Resolve, 0, 3, None));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::TransactionNotUnderDispute)
+        ));
+
+        // Entries that can't be disputed are spilled as well rather than dropped
+        acc.add_transaction(
+            Transaction::new(TransactionType::Adjustment, 0, 5, Some(Money::from(1)))
+                .with_reason("GOODWILL"),
+        );
+        acc.add_transaction(Transaction::new(
+            TransactionType::Authorize,
+            0,
+            6,
+            Some(Money::from(2)),
+        ));
+        acc.add_transaction(Transaction::new(TransactionType::Void, 0, 6, None));
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            7,
+            Some(Money::from(1)),
+        ));
+        for _ in 0..4 {
+            acc.process_pending_transaction().unwrap();
+        }
+        assert!(acc.history_entry(5).is_none());
+        assert!(acc.history_entry(6).is_none());
+
+        let state = acc.snapshot().unwrap();
+        assert_eq!(
+            state
+                .history
+                .iter()
+                .map(|entry| entry.transaction.tx)
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4, 5, 6, 7]
+        );
+        let ledger = acc.ledger().unwrap();
+        assert_eq!(ledger[4].transaction_type, TransactionType::Adjustment);
+        assert_eq!(ledger[5].transaction_type, TransactionType::Authorize);
+        drop(acc);
+        assert!(!path.exists());
+    }
+
+    fn dispute_withdrawal(acc: &mut Account, tx: u32) {
+        acc.add_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            0,
+            tx,
+            Some(Money::from(4)),
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+        assert_eq!(acc.total(), Money::from(6));
+
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, tx, None));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+        assert_eq!(acc.held(), Money::from(4));
+        assert_eq!(acc.total(), Money::from(10));
+
+        acc.add_transaction(Transaction::new(TransactionType::Dispute, 0, tx, None));
+        assert!(acc.process_pending_transaction().is_err());
+    }
+
+    #[test]
+    fn resolve_disputed_withdrawal() {
+        const WITHDRAW_TRANSACTION_ID: u32 = 1;
+        let mut acc = prepare_acc(Money::from(10));
+        dispute_withdrawal(&mut acc, WITHDRAW_TRANSACTION_ID);
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Resolve,
+            0,
+            WITHDRAW_TRANSACTION_ID,
+            None,
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(6));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.total(), Money::from(6));
+        assert!(!acc.locked);
+
+        let entry = acc.history_entry(WITHDRAW_TRANSACTION_ID).unwrap();
+        assert_eq!(entry.dispute_state(), DisputeState::Resolved);
+        assert_eq!(entry.transaction_type(), &TransactionType::Withdrawal);
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Resolve,
+            0,
+            WITHDRAW_TRANSACTION_ID,
+            None,
+        ));
+        assert!(acc.process_pending_transaction().is_err());
+    }
+
+    #[test]
+    fn chargeback_disputed_withdrawal() {
+        const WITHDRAW_TRANSACTION_ID: u32 = 1;
+        let mut acc = prepare_acc(Money::from(10));
+        dispute_withdrawal(&mut acc, WITHDRAW_TRANSACTION_ID);
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Chargeback,
+            0,
+            WITHDRAW_TRANSACTION_ID,
+            None,
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(10));
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.total(), Money::from(10));
+        assert!(acc.locked);
+    }
+
+    #[test]
+    fn resolve_and_chargeback_disputed_deposit() {
+        const DEPOSIT_TRANSACTION_ID: u32 = 0;
+        let mut acc = prepare_acc(Money::from(10));
+        acc.add_transaction(Transaction::new(
+            TransactionType::Dispute,
+            0,
+            DEPOSIT_TRANSACTION_ID,
+            None,
+        ));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(
+            TransactionType::Resolve,
+            0,
+            DEPOSIT_TRANSACTION_ID,
+            None,
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::from(10));
+        assert_eq!(acc.held(), Money::ZERO);
+
+        acc.add_transaction(Transaction::new(
+            TransactionType::Dispute,
+            0,
+            DEPOSIT_TRANSACTION_ID,
+            None,
+        ));
+        acc.process_pending_transaction().unwrap();
+        acc.add_transaction(Transaction::new(
+            TransactionType::Chargeback,
+            0,
+            DEPOSIT_TRANSACTION_ID,
+            None,
+        ));
+        acc.process_pending_transaction().unwrap();
+        assert_eq!(acc.available(), Money::ZERO);
+        assert_eq!(acc.held(), Money::ZERO);
+        assert_eq!(acc.total(), Money::ZERO);
+        assert!(acc.locked);
+
+        let entry = acc.history_entry(DEPOSIT_TRANSACTION_ID).unwrap();
+        assert_eq!(entry.dispute_state(), DisputeState::ChargedBack);
+        assert_eq!(entry.transaction_type(), &TransactionType::Deposit);
+    }
+
+    #[test]
+    fn overflow_is_an_invariant_violation() {
+        let mut acc = prepare_acc(Money::MAX);
+        acc.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            0,
+            1,
+            Some(Money::from(1)),
+        ));
+        assert!(matches!(
+            acc.process_pending_transaction(),
+            Err(ErrorKind::InvariantViolation(_))
+        ));
+        assert_eq!(acc.available(), Money::MAX);
+        assert!(acc.reconcile().is_ok());
+    }
+
+    #[test]
+    fn reconcile() {
+        let mut acc = prepare_acc(Money::from(10));
+        assert!(acc.reconcile().is_ok());
+
+        acc.balances.get_mut(&None).unwrap().total = Money::from(11);
+        assert!(matches!(
+            acc.reconcile(),
+            Err(ErrorKind::InvariantViolation(_))
+        ));
+
+        let balance = acc.balances.get_mut(&None).unwrap();
+        balance.total = Money::from(9);
+        balance.held = Money::from(-1);
+        assert!(acc.reconcile().is_err());
+    }
+
+    #[test]
+    fn error_codes() {
+        let error = ErrorKind::DuplicateTransactionId(7);
+        assert_eq!(error.code(), 200);
+        assert_eq!(error.to_string(), "Transaction id 7 was seen before");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "code": 200,
+                "reason": "duplicate_transaction_id",
+                "message": "Transaction id 7 was seen before"
+            })
+        );
+        assert_eq!(ErrorKind::InsufficientAmount.code(), 304);
+
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 3, 9, Some(Money::from(5)));
+        let error = TransactionProcessingError::new(&withdrawal, ErrorKind::InsufficientAmount);
+        assert_eq!(
+            error.to_string(),
+            "Transaction 9 of client 3 over 5 rejected: Not enough available funds"
+        );
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "client": 3,
+                "tx": 9,
+                "amount": "5",
+                "code": 304,
+                "reason": "insufficient_amount",
+                "message": "Not enough available funds"
+            })
+        );
+    }
 }
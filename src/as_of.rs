@@ -0,0 +1,139 @@
+use crate::account::Account;
+use crate::engine::{Engine, EngineConfig};
+use crate::timestamp::Timestamp;
+use crate::transaction::Transaction;
+use std::error::Error;
+use std::str::FromStr;
+
+/// Point of the input balances are reconstructed as of, see [`account_as_of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Up to and including the transaction with this 1-based sequence number
+    Sequence(u64),
+    /// Up to the first transaction later than this time. Transactions without a timestamp
+    /// before it are included, as the input is expected in chronological order.
+    Time(Timestamp),
+}
+
+impl FromStr for AsOf {
+    type Err = String;
+
+    /// A sequence number if `s` is an integer, a time if it's RFC3339.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(sequence) = s.parse() {
+            return Ok(AsOf::Sequence(sequence));
+        }
+        // Timestamps parse negative integers as epoch millis, which aren't times here
+        match s.parse() {
+            Ok(time) if s.parse::<i64>().is_err() => Ok(AsOf::Time(time)),
+            _ => Err(format!(
+                "Invalid point {}, expected a sequence number or an RFC3339 time",
+                s
+            )),
+        }
+    }
+}
+
+impl AsOf {
+    /// Whether the transaction with the 1-based `sequence` number is past this point, and
+    /// so is every one after it.
+    pub fn passed(&self, sequence: u64, transaction: &Transaction) -> bool {
+        match self {
+            AsOf::Sequence(until) => sequence > *until,
+            AsOf::Time(until) => transaction.timestamp().is_some_and(|time| time > *until),
+        }
+    }
+}
+
+/// Replays the transactions up to `as_of` with the given config and returns the client's
+/// account as it was at that point, `None` if it didn't exist yet.
+///
+/// Every client's transactions are replayed, not just this one's, as transfers depend on
+/// both accounts.
+pub async fn account_as_of(
+    transactions: impl IntoIterator<Item = Transaction>,
+    config: EngineConfig,
+    client: u16,
+    as_of: AsOf,
+) -> Result<Option<Account>, Box<dyn Error>> {
+    let mut engine = Engine::with_config(config);
+    for (i, transaction) in transactions.into_iter().enumerate() {
+        if as_of.passed(i as u64 + 1, &transaction) {
+            break;
+        }
+        engine.submit(transaction).await?;
+    }
+    engine.wait().await;
+    Ok(engine.account(client).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{account_as_of, AsOf};
+    use crate::{EngineConfig, Money, Timestamp, Transaction, TransactionType};
+
+    #[test]
+    fn parse() {
+        assert_eq!("42".parse(), Ok(AsOf::Sequence(42)));
+        assert_eq!(
+            "2024-01-01T00:00:00Z".parse(),
+            Ok(AsOf::Time(Timestamp::from_millis(1_704_067_200_000)))
+        );
+        assert!("-1".parse::<AsOf>().is_err());
+        assert!("yesterday".parse::<AsOf>().is_err());
+    }
+
+    #[tokio::test]
+    async fn as_of() {
+        let at =
+            |t: Transaction, seconds: i64| t.with_timestamp(Timestamp::from_millis(seconds * 1000));
+        let mut transfer = Transaction::new(TransactionType::Transfer, 2, 3, Some(Money::from(4)));
+        transfer.to_client = Some(1);
+        let transactions = vec![
+            at(
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+                10,
+            ),
+            at(
+                Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(5))),
+                20,
+            ),
+            at(transfer, 30),
+            at(Transaction::new(TransactionType::Dispute, 1, 1, None), 40),
+        ];
+        let balance = |as_of| {
+            let transactions = transactions.clone();
+            async move {
+                let account = account_as_of(transactions, EngineConfig::default(), 1, as_of)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                (account.available(), account.held())
+            }
+        };
+        assert_eq!(
+            balance(AsOf::Sequence(1)).await,
+            (Money::from(10), Money::ZERO)
+        );
+        assert_eq!(
+            balance(AsOf::Sequence(3)).await,
+            (Money::from(14), Money::ZERO)
+        );
+        assert_eq!(
+            balance(AsOf::Time(Timestamp::from_millis(39_999))).await,
+            (Money::from(14), Money::ZERO)
+        );
+        assert_eq!(
+            balance(AsOf::Time(Timestamp::from_millis(40_000))).await,
+            (Money::from(4), Money::from(10))
+        );
+        let before = AsOf::Time(Timestamp::from_millis(0));
+        assert!(
+            account_as_of(transactions, EngineConfig::default(), 1, before)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use apache_avro::schema::{Schema as AvroSchema, SchemaKind};
+use apache_avro::types::Value;
+use apache_avro::Reader;
+use rust_decimal::Decimal;
+use std::io::{self, BufReader, Read};
+
+/// Schema Avro inputs are validated against, a record with the csv columns as fields.
+pub const TRANSACTION_SCHEMA: &str = include_str!("transaction.avsc");
+
+fn invalid(reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid Avro input: {}", reason),
+    )
+}
+
+/// The Avro types transactions are made of. Records, arrays, maps, fixed and named type
+/// references aren't.
+#[derive(Debug, Clone, PartialEq)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    /// Bytes of a decimal's unscaled value
+    Decimal(u32),
+    /// Long of milliseconds since the unix epoch
+    TimestampMillis,
+    Enum(Vec<String>),
+    Union(Vec<Schema>),
+}
+
+impl Schema {
+    fn parse(schema: &AvroSchema) -> Result<Schema, String> {
+        Ok(match schema {
+            AvroSchema::Null => Schema::Null,
+            AvroSchema::Boolean => Schema::Boolean,
+            AvroSchema::Int => Schema::Int,
+            AvroSchema::Long => Schema::Long,
+            AvroSchema::Float => Schema::Float,
+            AvroSchema::Double => Schema::Double,
+            AvroSchema::Bytes => Schema::Bytes,
+            AvroSchema::String => Schema::String,
+            AvroSchema::Decimal(decimal) if *decimal.inner == AvroSchema::Bytes => {
+                Schema::Decimal(decimal.scale as u32)
+            }
+            AvroSchema::TimestampMillis => Schema::TimestampMillis,
+            AvroSchema::Enum(schema) => Schema::Enum(schema.symbols.clone()),
+            AvroSchema::Union(union) => Schema::Union(
+                union
+                    .variants()
+                    .iter()
+                    .map(Schema::parse)
+                    .collect::<Result<_, _>>()?,
+            ),
+            schema => {
+                return Err(format!(
+                    "Unsupported Avro type {:?}",
+                    SchemaKind::from(schema)
+                ))
+            }
+        })
+    }
+
+    /// Whether every value written with this schema reads as a value of `reader`: the same
+    /// type, an int as a long, a float as a double, enum symbols the reader knows, and
+    /// union branches that all match.
+    fn matches(&self, reader: &Schema) -> bool {
+        match (self, reader) {
+            (Schema::Union(branches), _) => branches.iter().all(|branch| branch.matches(reader)),
+            (_, Schema::Union(branches)) => branches.iter().any(|branch| self.matches(branch)),
+            (Schema::Int, Schema::Long) | (Schema::Float, Schema::Double) => true,
+            (Schema::Decimal(_), Schema::Decimal(_)) => true,
+            (Schema::Enum(symbols), Schema::Enum(known)) => {
+                symbols.iter().all(|symbol| known.contains(symbol))
+            }
+            (writer, reader) => writer == reader,
+        }
+    }
+
+    /// A value of this schema as the text of a csv field, empty for null.
+    fn text(&self, value: Value) -> io::Result<String> {
+        Ok(match (self, value) {
+            (_, Value::Null) => String::new(),
+            (_, Value::Boolean(value)) => value.to_string(),
+            (_, Value::Int(value)) => value.to_string(),
+            (_, Value::Long(value) | Value::TimestampMillis(value)) => value.to_string(),
+            (_, Value::Float(value)) => value.to_string(),
+            (_, Value::Double(value)) => value.to_string(),
+            (_, Value::String(text)) => text.trim().to_string(),
+            (_, Value::Bytes(bytes)) => String::from_utf8(bytes)
+                .map_err(|_| invalid("text isn't UTF-8"))?
+                .trim()
+                .to_string(),
+            (Schema::Decimal(scale), Value::Decimal(decimal)) => {
+                let bytes = Vec::<u8>::try_from(&decimal).map_err(invalid)?;
+                if bytes.len() > 16 {
+                    return Err(invalid("decimal out of range"));
+                }
+                // Sign extended big-endian two's complement
+                let fill = if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+                    0xff
+                } else {
+                    0
+                };
+                let mut unscaled = [fill; 16];
+                unscaled[16 - bytes.len()..].copy_from_slice(&bytes);
+                Decimal::try_from_i128_with_scale(i128::from_be_bytes(unscaled), *scale)
+                    .map_err(|_| invalid("decimal out of range"))?
+                    .to_string()
+            }
+            (_, Value::Enum(_, symbol)) => symbol,
+            (Schema::Union(branches), Value::Union(index, value)) => branches
+                .get(index as usize)
+                .ok_or_else(|| invalid("union index out of range"))?
+                .text(*value)?,
+            (schema, value) => {
+                return Err(invalid(format_args!(
+                    "{:?} value read for a {:?} field",
+                    value, schema
+                )))
+            }
+        })
+    }
+}
+
+/// Fields of a record schema, in order.
+fn record_fields(schema: &AvroSchema) -> Result<Vec<(String, Schema, bool)>, String> {
+    let AvroSchema::Record(record) = schema else {
+        return Err("Expected a record".to_string());
+    };
+    record
+        .fields
+        .iter()
+        .map(|field| {
+            let schema = Schema::parse(&field.schema)?;
+            Ok((field.name.clone(), schema, field.default.is_some()))
+        })
+        .collect()
+}
+
+/// Checks that the records of a writer's schema are transactions: every field is one of
+/// [`TRANSACTION_SCHEMA`] with a type matching it, or text, and no field without a default
+/// is left out. Returns the writer's fields.
+fn validate(writer: &AvroSchema) -> Result<Vec<(String, Schema)>, String> {
+    let transaction = AvroSchema::parse_str(TRANSACTION_SCHEMA).expect("Valid bundled schema");
+    let known = record_fields(&transaction).expect("Valid bundled schema");
+    let fields = record_fields(writer)?;
+    for (name, schema, _) in &fields {
+        match known.iter().find(|(known, _, _)| known == name) {
+            Some((_, expected, _)) if schema.matches(expected) => {}
+            Some(_) => return Err(format!("Field {} doesn't match its type", name)),
+            // Kept as metadata of the transaction
+            None if schema.matches(&Schema::Union(vec![Schema::Null, Schema::String])) => {}
+            None => return Err(format!("Unknown field {}", name)),
+        }
+    }
+    for (name, _, default) in &known {
+        if !default && !fields.iter().any(|(field, _, _)| field == name) {
+            return Err(format!("Missing field {}", name));
+        }
+    }
+    Ok(fields
+        .into_iter()
+        .map(|(name, schema, _)| (name, schema))
+        .collect())
+}
+
+/// Reader of an Avro object container file of transactions, decoded by the
+/// [`apache-avro`](https://docs.rs/apache-avro) crate, so blocks may be uncompressed,
+/// deflated, snappy or zstandard compressed.
+///
+/// The writer's schema in the file header is validated against [`TRANSACTION_SCHEMA`]
+/// before anything is read, records then come out as fields of text, the way csv rows do.
+pub struct AvroReader<R> {
+    records: Reader<'static, BufReader<R>>,
+    fields: Vec<(String, Schema)>,
+}
+
+impl<R: Read> AvroReader<R> {
+    /// Reads and validates the file header.
+    pub fn new(input: R) -> io::Result<Self> {
+        let records = Reader::new(BufReader::new(input)).map_err(invalid)?;
+        let fields = validate(records.writer_schema()).map_err(|reason| {
+            invalid(format_args!(
+                "schema isn't a transaction schema: {}",
+                reason
+            ))
+        })?;
+        Ok(Self { records, fields })
+    }
+
+    /// Names of the fields of every record, in order.
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Next record's fields, `None` at the end of the file.
+    pub fn next_record(&mut self) -> io::Result<Option<Vec<String>>> {
+        let values = match self.records.next() {
+            None => return Ok(None),
+            Some(Ok(Value::Record(values))) => values,
+            Some(Ok(value)) => return Err(invalid(format_args!("{:?} isn't a record", value))),
+            Some(Err(e)) => return Err(invalid(e)),
+        };
+        values
+            .into_iter()
+            .zip(&self.fields)
+            .map(|((_, value), (_, schema))| schema.text(value))
+            .collect::<io::Result<_>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AvroReader;
+    use apache_avro::types::Value;
+    use apache_avro::{Codec, DeflateSettings, Schema, Writer};
+
+    const SCHEMA: &str = r#"{"type": "record", "name": "Tx", "fields": [
+        {"name": "type", "type": {"type": "enum", "name": "T", "symbols": ["withdrawal", "deposit"]}},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "int"},
+        {"name": "amount", "type": ["null", {"type": "bytes", "logicalType": "decimal", "precision": 28, "scale": 2}]},
+        {"name": "timestamp", "type": {"type": "long", "logicalType": "timestamp-millis"}}
+    ]}"#;
+
+    /// Object container file of a deposit of 1.50 and a withdrawal without amount.
+    fn container(schema: &str, codec: Codec) -> Vec<u8> {
+        let schema = Schema::parse_str(schema).unwrap();
+        let mut writer = Writer::with_codec(&schema, Vec::new(), codec);
+        for (symbol, client, tx, amount) in [(1, 2, 1, Some(150)), (0, 3, 70000, None)] {
+            let amount = match amount {
+                Some(amount) => {
+                    let decimal = apache_avro::Decimal::from(vec![0, amount]);
+                    Value::Union(1, Box::new(Value::Decimal(decimal)))
+                }
+                None => Value::Union(0, Box::new(Value::Null)),
+            };
+            let names = ["withdrawal", "deposit"];
+            let record = Value::Record(vec![
+                (
+                    "type".into(),
+                    Value::Enum(symbol, names[symbol as usize].into()),
+                ),
+                ("client".into(), Value::Int(client)),
+                ("tx".into(), Value::Int(tx)),
+                ("amount".into(), amount),
+                (
+                    "timestamp".into(),
+                    Value::TimestampMillis(1_700_000_000_000),
+                ),
+            ]);
+            writer.append(record).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn read_all(file: Vec<u8>) -> std::io::Result<Vec<Vec<String>>> {
+        let mut reader = AvroReader::new(file.as_slice())?;
+        assert_eq!(
+            reader.fields().collect::<Vec<_>>(),
+            ["type", "client", "tx", "amount", "timestamp"]
+        );
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn read() {
+        let expected = [
+            ["deposit", "2", "1", "1.50", "1700000000000"],
+            ["withdrawal", "3", "70000", "", "1700000000000"],
+        ];
+        let codecs = [
+            Codec::Null,
+            Codec::Deflate(DeflateSettings::default()),
+            Codec::Snappy,
+            Codec::Zstandard(Default::default()),
+        ];
+        for codec in codecs {
+            let records = read_all(container(SCHEMA, codec)).unwrap();
+            assert_eq!(records, expected, "{:?}", codec);
+        }
+
+        // A block cut short
+        let mut file = container(SCHEMA, Codec::Null);
+        file.truncate(file.len() - 20);
+        assert!(read_all(file).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let invalid = [
+            // Client as a long may not fit
+            SCHEMA.replace(r#""client", "type": "int""#, r#""client", "type": "long""#),
+            // Unknown transaction type
+            SCHEMA.replace(r#""withdrawal""#, r#""payout""#),
+            // Amounts as doubles lose precision
+            SCHEMA.replace(
+                r#"{"type": "bytes", "logicalType": "decimal", "precision": 28, "scale": 2}"#,
+                r#""double""#,
+            ),
+            SCHEMA.replace(r#""name": "tx""#, r#""name": "id""#),
+            r#""string""#.to_string(),
+        ];
+        for schema in invalid {
+            let schema = Schema::parse_str(&schema).unwrap();
+            let file = Writer::new(&schema, Vec::new()).into_inner().unwrap();
+            let error = AvroReader::new(file.as_slice()).err().unwrap().to_string();
+            assert!(error.contains("isn't a transaction schema"), "{}", error);
+        }
+
+        // Other fields are metadata, as long as they are text
+        let memo = |schema: &str| {
+            let field = format!(
+                r#"{{"name": "memo", "type": {}}}, {{"name": "client""#,
+                schema
+            );
+            let schema = SCHEMA.replacen(r#"{"name": "client""#, &field, 1);
+            let schema = Schema::parse_str(&schema).unwrap();
+            Writer::new(&schema, Vec::new()).into_inner().unwrap()
+        };
+        assert!(AvroReader::new(memo(r#"["null", "string"]"#).as_slice()).is_ok());
+        assert!(AvroReader::new(memo(r#""long""#).as_slice()).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::dedup::mix;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Resolution fault chances are drawn with.
+const CHANCE_RESOLUTION: u64 = 1_000_000;
+
+/// Faults injected so far, see [`Chaos::injected`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Transactions a worker waited on before applying them
+    pub delays: u64,
+    /// Transactions lost on their way to a worker and sent again
+    pub drops: u64,
+    /// Transaction id checks that failed as if storage was unavailable
+    pub storage_errors: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    delays: AtomicU64,
+    drops: AtomicU64,
+    storage_errors: AtomicU64,
+}
+
+/// Faults injected into the engine at random to test that it still applies every client's
+/// transactions exactly once and in order, see [`crate::EngineConfig::chaos`].
+///
+/// Workers are delayed before applying a transaction, transactions are dropped on their way
+/// to a worker and sent again after the retry backoff, and transaction id checks fail
+/// transiently, which [`crate::retry::RetryPolicy`] is expected to recover from. Faults are drawn
+/// from the seed, so a single worker sees the same faults on every run.
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    state: Arc<AtomicU64>,
+    counters: Arc<Counters>,
+    delay_rate: Decimal,
+    max_delay: Duration,
+    drop_rate: Decimal,
+    storage_error_rate: Decimal,
+}
+
+/// Equal when the faults are drawn from the same generator with the same rates.
+impl PartialEq for Chaos {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+            && self.delay_rate == other.delay_rate
+            && self.max_delay == other.max_delay
+            && self.drop_rate == other.drop_rate
+            && self.storage_error_rate == other.storage_error_rate
+    }
+}
+
+impl Eq for Chaos {}
+
+impl Chaos {
+    /// Injects no faults until some are enabled with the `with_*` methods.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(AtomicU64::new(seed)),
+            counters: Arc::default(),
+            delay_rate: Decimal::ZERO,
+            max_delay: Duration::ZERO,
+            drop_rate: Decimal::ZERO,
+            storage_error_rate: Decimal::ZERO,
+        }
+    }
+
+    /// Delays the `rate` share of transactions by up to `max_delay` before they are applied.
+    pub fn with_delays(mut self, rate: Decimal, max_delay: Duration) -> Self {
+        self.delay_rate = rate;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Drops the `rate` share of attempts to queue a transaction on its worker.
+    pub fn with_drops(mut self, rate: Decimal) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Fails the `rate` share of transaction id checks.
+    pub fn with_storage_errors(mut self, rate: Decimal) -> Self {
+        self.storage_error_rate = rate;
+        self
+    }
+
+    pub fn injected(&self) -> Faults {
+        Faults {
+            delays: self.counters.delays.load(Ordering::Relaxed),
+            drops: self.counters.drops.load(Ordering::Relaxed),
+            storage_errors: self.counters.storage_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// SplitMix64, shared by every clone.
+    fn next(&self) -> u64 {
+        mix(self
+            .state
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15))
+    }
+
+    fn chance(&self, rate: Decimal) -> bool {
+        let threshold = (rate * Decimal::from(CHANCE_RESOLUTION))
+            .to_u64()
+            .unwrap_or(0);
+        threshold > 0 && self.next() % CHANCE_RESOLUTION < threshold
+    }
+
+    /// Waits before a worker applies a transaction, now and then.
+    pub(crate) async fn delay(&self) {
+        if !self.chance(self.delay_rate) {
+            return;
+        }
+        self.counters.delays.fetch_add(1, Ordering::Relaxed);
+        let nanos = self.max_delay.as_nanos().min(u128::from(u64::MAX)) as u64;
+        let delay = Duration::from_nanos(self.next() % nanos.saturating_add(1));
+        match delay.is_zero() {
+            true => tokio::task::yield_now().await,
+            false => tokio::time::sleep(delay).await,
+        }
+    }
+
+    /// Whether the attempt to queue a transaction is lost.
+    pub(crate) fn drop_message(&self) -> bool {
+        let dropped = self.chance(self.drop_rate);
+        if dropped {
+            self.counters.drops.fetch_add(1, Ordering::Relaxed);
+        }
+        dropped
+    }
+
+    /// Fails with a transient error now and then, otherwise runs `operation`.
+    pub(crate) fn storage<T>(&self, operation: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        if self.chance(self.storage_error_rate) {
+            self.counters.storage_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Storage error injected by chaos",
+            ));
+        }
+        operation()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chaos;
+    use crate::reader::{deserialize_file, ReadOptions};
+    use crate::retry::RetryPolicy;
+    use crate::workload::Workload;
+    use crate::{Engine, EngineConfig, OutcomeStatus, TransactionOutcome};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Report and outcomes of processing a generated workload.
+    async fn process(config: EngineConfig, name: &str) -> (String, Vec<TransactionOutcome>) {
+        let workload = Workload {
+            clients: 20,
+            transactions: 2_000,
+            dispute_rate: Decimal::new(1, 1),
+            invalid_rate: Decimal::new(2, 2),
+            seed: 3,
+        };
+        let path = std::env::temp_dir().join(format!("chaos_{}_{}.csv", name, std::process::id()));
+        workload
+            .write_csv(std::fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut engine = Engine::with_config(config);
+        let mut outcomes = engine.outcomes();
+        let (sender, mut receiver) = mpsc::channel(64);
+        let path_string = path.to_str().unwrap().to_string();
+        let read = std::thread::spawn(move || {
+            deserialize_file(path_string, ReadOptions::default(), sender)
+        });
+        while let Some(transaction) = receiver.recv().await {
+            engine.submit(transaction).await.unwrap();
+        }
+        read.join().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut report = Vec::new();
+        engine.write_report(&mut report).await.unwrap();
+        drop(engine);
+        let mut received = Vec::new();
+        while let Some(outcome) = outcomes.recv().await {
+            received.push(outcome);
+        }
+        (String::from_utf8(report).unwrap(), received)
+    }
+
+    #[tokio::test]
+    async fn guarantees_hold() {
+        let config = || EngineConfig {
+            workers: 4,
+            channel_capacity: 8,
+            ..EngineConfig::default()
+        };
+        let (expected, _) = process(config(), "calm").await;
+
+        let chaos = Chaos::new(11)
+            .with_delays(Decimal::new(2, 1), Duration::from_micros(200))
+            .with_drops(Decimal::new(1, 1))
+            .with_storage_errors(Decimal::new(1, 1));
+        let (report, outcomes) = process(
+            EngineConfig {
+                retry: RetryPolicy {
+                    attempts: 20,
+                    backoff: Duration::from_micros(10),
+                    max_backoff: Duration::from_micros(100),
+                },
+                chaos: Some(chaos.clone()),
+                ..config()
+            },
+            "chaos",
+        )
+        .await;
+
+        let faults = chaos.injected();
+        assert!(faults.delays > 0 && faults.drops > 0 && faults.storage_errors > 0);
+        assert_eq!(report, expected);
+        // Every client's transactions were applied in the order they were read
+        let mut last_rows = HashMap::new();
+        for outcome in outcomes
+            .iter()
+            .filter(|o| o.status == OutcomeStatus::Accepted)
+        {
+            let row = outcome.row.unwrap();
+            let last = last_rows.insert(outcome.client, row);
+            assert!(last < Some(row), "{:?} after row {:?}", outcome, last);
+        }
+    }
+
+    #[tokio::test]
+    async fn storage_errors_without_retries() {
+        let chaos = Chaos::new(5).with_storage_errors(Decimal::ONE);
+        let (report, outcomes) = process(
+            EngineConfig {
+                chaos: Some(chaos.clone()),
+                ..EngineConfig::default()
+            },
+            "no_retries",
+        )
+        .await;
+        // Without retries every deposit and withdrawal is rejected, nothing is credited
+        assert!(report
+            .lines()
+            .skip(1)
+            .all(|line| line.ends_with(",0.0000,0.0000,0.0000,false")));
+        assert!(outcomes.iter().all(|outcome| outcome.error.is_some()));
+        assert!(chaos.injected().storage_errors > 0);
+    }
+}
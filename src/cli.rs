@@ -0,0 +1,1799 @@
+use clap::{Args, Parser, Subcommand};
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use transaction_system::as_of::AsOf;
+use transaction_system::dedup::IdFilter;
+use transaction_system::determinism::MAX_REPLAYS;
+use transaction_system::encryption::Cipher;
+use transaction_system::history::HistoryWindow;
+use transaction_system::http::HttpUrl;
+#[cfg(feature = "kafka")]
+use transaction_system::kafka::KafkaSource;
+use transaction_system::logging::{self, Level, LogFormat};
+use transaction_system::money::{Money, RoundingMode};
+use transaction_system::partition::Partition;
+#[cfg(feature = "postgres")]
+use transaction_system::postgres::PostgresConfig;
+use transaction_system::reader::{Columns, Delimiter, InputFormat, STDIN};
+use transaction_system::retry::RetryPolicy;
+use transaction_system::webhook::Webhook;
+use transaction_system::{
+    ChargebackPolicy, DuplicatePolicy, EngineConfig, ExchangeRates, FeeSchedule, FraudRules,
+    LimitRules, ReportFormat, ReportOrder,
+};
+
+/// Payments engine turning a stream of transactions into client account balances.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Running without a subcommand is the same as `process`
+    #[command(flatten)]
+    process: ProcessArgs,
+    /// Least severe events logged to stderr, error, warn, info, debug or trace
+    /// [default: warn]
+    #[arg(long, global = true)]
+    log_level: Option<Level>,
+    /// Format of log events, json or pretty [default: pretty]
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+}
+
+impl Cli {
+    /// Sets up logging as the options ask.
+    pub fn init_logging(&self) -> Result<(), Box<dyn Error>> {
+        Ok(logging::init(
+            self.log_level.unwrap_or(Level::Warn),
+            self.log_format.unwrap_or(LogFormat::Pretty),
+        )?)
+    }
+
+    pub fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Process(self.process))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Process transactions and print the resulting account report
+    Process(ProcessArgs),
+    /// Replay the input up to a row or a time and print the account report as of then
+    Reconstruct {
+        /// 1-based sequence number of the last row to replay, or an RFC3339 time to replay
+        /// the rows up to, stopping at the first later one
+        #[arg(long)]
+        until: AsOf,
+        /// Only print this client's account
+        #[arg(long)]
+        client: Option<u16>,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Process transactions and print a client's statement: every transaction their account
+    /// accepted, in order, with the balance it left
+    Statement {
+        /// Client whose statement is printed
+        #[arg(long)]
+        client: u16,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Process transactions and print a report of the books instead of the account report
+    Report {
+        /// Print the trial balance: debit and credit totals of every account of the books,
+        /// which must net to zero
+        #[arg(long, required = true)]
+        trial_balance: bool,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Process the input files or restore `--load-state`, then read commands exploring and
+    /// changing the accounts from stdin
+    Repl(ProcessArgs),
+    /// Process every input file dropped into a folder, moving it to its `done/` subfolder
+    /// afterwards, until interrupted
+    Watch {
+        /// Folder input files are dropped into
+        dir: PathBuf,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Apply transactions arriving over the network and serve the accounts until interrupted
+    Serve {
+        /// Address the HTTP API listens on, e.g. 127.0.0.1:8080
+        #[arg(long, required_unless_present_any = ["tcp", "grpc"])]
+        http: Option<SocketAddr>,
+        /// Address the csv line protocol listens on, e.g. 127.0.0.1:9000
+        #[arg(long)]
+        tcp: Option<SocketAddr>,
+        /// Address the gRPC service listens on, e.g. 127.0.0.1:50051
+        #[arg(long)]
+        grpc: Option<SocketAddr>,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Print a file encrypted at rest, e.g. an event log, ledger or dead letter file, in
+    /// plaintext, with the key encryption is set up with
+    Decrypt {
+        /// File to decrypt
+        file: PathBuf,
+    },
+    /// Merge partition tagged account reports into a single report
+    Merge {
+        #[arg(required = true)]
+        reports: Vec<String>,
+    },
+    /// Check that every row of the input parses and is correctly signed, without processing,
+    /// or with `--expected` that processing it ends with the expected balances
+    Verify {
+        /// Csv account report the input's account report is compared with, e.g. one kept
+        /// from an earlier run
+        #[arg(long)]
+        expected: Option<PathBuf>,
+        /// Process the input this many times, with 1, 2, 4 and so on workers, and check
+        /// that every run comes out the same
+        #[arg(
+            long,
+            conflicts_with = "expected",
+            value_parser = clap::value_parser!(u32).range(2..=i64::from(MAX_REPLAYS))
+        )]
+        replays: Option<u32>,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Reprocess the input, from `--load-state` or from nothing, and compare the resulting
+    /// state with a known-good snapshot account by account, without writing any state
+    Reconcile {
+        /// Snapshot the reprocessed state is compared with, e.g. one saved by the run the
+        /// input is a backfill or correction of
+        #[arg(long)]
+        against: PathBuf,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Write random but valid transactions as csv to stdout, for load testing
+    Generate {
+        /// Number of clients transactions are spread over
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        clients: u16,
+        /// Number of rows written
+        #[arg(long)]
+        transactions: u64,
+        /// Share of rows that are disputes, resolves or chargebacks
+        #[arg(long, default_value = "0.05", value_parser = rate)]
+        dispute_rate: Decimal,
+        /// Share of rows that are malformed or rejected
+        #[arg(long, default_value = "0.01", value_parser = rate)]
+        invalid_rate: Decimal,
+        /// Seed of the random rows, the same seed writes the same rows [default: random]
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Process transactions without writing any output and report how fast it went
+    Bench {
+        /// Generate this many transactions to process instead of reading the inputs
+        #[arg(long)]
+        transactions: Option<u64>,
+        /// Number of clients generated transactions are spread over
+        #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u16).range(1..))]
+        clients: u16,
+        /// Seed of the generated transactions
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+}
+
+/// Expands glob patterns into the files they match, in alphabetical order. Plain paths
+/// are kept as they are and no inputs at all means stdin.
+pub fn expand_inputs(inputs: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    if inputs.is_empty() {
+        return Ok(vec![STDIN.to_string()]);
+    }
+    let mut paths = Vec::new();
+    for input in inputs {
+        if !input.contains(['*', '?', '[']) {
+            paths.push(input);
+            continue;
+        }
+        let matches = glob::glob(&input)
+            .map_err(|e| format!("Invalid pattern {}: {}", input, e))?
+            .map(|path| path.map(|path| path.to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(format!("No files match {}", input).into());
+        }
+        paths.extend(matches);
+    }
+    if paths.iter().filter(|path| *path == STDIN).count() > 1 {
+        return Err("Stdin can only be read once".into());
+    }
+    Ok(paths)
+}
+
+fn spread(s: &str) -> Result<Decimal, String> {
+    match s.parse::<Decimal>() {
+        Ok(spread) if spread >= Decimal::ZERO && spread < Decimal::ONE => Ok(spread),
+        _ => Err(format!("{} is not a fraction between 0 and 1", s)),
+    }
+}
+
+fn false_positive_rate(s: &str) -> Result<Decimal, String> {
+    match s.parse::<Decimal>() {
+        Ok(rate) if rate > Decimal::ZERO && rate < Decimal::ONE => Ok(rate),
+        _ => Err(format!("{} is not a rate between 0 and 1", s)),
+    }
+}
+
+fn interest_rate(s: &str) -> Result<Decimal, String> {
+    match s.parse::<Decimal>() {
+        Ok(rate) if rate >= Decimal::ZERO => Ok(rate),
+        _ => Err(format!("{} is not a non-negative annual rate", s)),
+    }
+}
+
+fn rate(s: &str) -> Result<Decimal, String> {
+    match s.parse::<Decimal>() {
+        Ok(rate) if rate >= Decimal::ZERO && rate <= Decimal::ONE => Ok(rate),
+        _ => Err(format!("{} is not a rate between 0 and 1", s)),
+    }
+}
+
+fn non_negative(s: &str) -> Result<Money, String> {
+    match s.parse::<Money>() {
+        Ok(amount) if !amount.is_negative() => Ok(amount),
+        _ => Err(format!("{} is not a non-negative amount", s)),
+    }
+}
+
+#[derive(Deserialize)]
+struct OverdraftLimitRow {
+    client: u16,
+    limit: Money,
+}
+
+/// Reads per client overdraft limits from `client,limit` rows.
+fn load_overdraft_limits(path: &Path) -> Result<HashMap<u16, Money>, Box<dyn Error>> {
+    let mut limits = HashMap::new();
+    for row in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?
+        .deserialize()
+    {
+        let row: OverdraftLimitRow = row?;
+        if row.limit.is_negative() {
+            return Err(format!("Negative overdraft limit of client {}", row.client).into());
+        }
+        limits.insert(row.client, row.limit);
+    }
+    Ok(limits)
+}
+
+/// Where the transactions of a run come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// The input files, stdin or a Unix socket
+    File,
+    #[cfg(feature = "kafka")]
+    Kafka,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(Source::File),
+            #[cfg(feature = "kafka")]
+            "kafka" => Ok(Source::Kafka),
+            #[cfg(not(feature = "kafka"))]
+            "kafka" => Err("Consuming Kafka needs the kafka feature".to_string()),
+            _ => Err(format!("Unknown source: {}", s)),
+        }
+    }
+}
+
+fn positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} is not a positive number", s)),
+    }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ProcessArgs {
+    /// Files or glob patterns with transactions, read one after another; `-` or nothing
+    /// for stdin, `unix:<path>` for a Unix socket producers connect to
+    inputs: Vec<String>,
+    /// Format of the input, csv, json, jsonl, avro, xlsx or iso20022 [default: from the file
+    /// extension]
+    #[arg(long)]
+    input_format: Option<InputFormat>,
+    /// Field delimiter of csv inputs, a single character or `tab` [default: detected from
+    /// the header, one of `,`, `;`, tab and `|`]
+    #[arg(long)]
+    delimiter: Option<Delimiter>,
+    /// Comma separated columns of csv inputs without header, e.g. type,client,tx,amount
+    #[arg(long)]
+    schema: Option<Columns>,
+    /// Where transactions come from, file for the inputs or kafka for a Kafka topic
+    /// [default: file]
+    #[arg(long)]
+    source: Option<Source>,
+    /// Broker the Kafka cluster is discovered from, e.g. 127.0.0.1:9092
+    #[cfg(feature = "kafka")]
+    #[arg(long)]
+    kafka_broker: Option<String>,
+    /// Kafka topic consumed
+    #[cfg(feature = "kafka")]
+    #[arg(long)]
+    kafka_topic: Option<String>,
+    /// Consumer group the offsets of checkpoints are committed to
+    /// [default: transaction_system]
+    #[cfg(feature = "kafka")]
+    #[arg(long)]
+    kafka_group: Option<String>,
+    /// TOML file providing defaults for any of the options below
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Write the account report to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Layout of the account report, csv, json, jsonl or parquet [default: csv]
+    #[arg(long)]
+    output_format: Option<ReportFormat>,
+    /// Order of the account report's rows, client (then currency) [default: client]
+    #[arg(long)]
+    sort_output: Option<ReportOrder>,
+    /// Where rejected transactions are written [default: errors.csv next to --output]
+    #[arg(long)]
+    errors: Option<PathBuf>,
+    /// Where transactions flagged or blocked by fraud rules are written
+    #[arg(long)]
+    fraud_report: Option<PathBuf>,
+    /// Where the processed ledger, every accepted and rejected transaction, is written
+    #[arg(long)]
+    ledger: Option<PathBuf>,
+    /// Layout of the ledger, csv, json, jsonl or parquet [default: csv]
+    #[arg(long)]
+    ledger_format: Option<ReportFormat>,
+    /// Snapshot of an earlier run to continue from
+    #[arg(long)]
+    load_state: Option<PathBuf>,
+    /// Where a snapshot of the state after the run is written
+    #[arg(long)]
+    save_state: Option<PathBuf>,
+    /// Where the checkpoint of a run interrupted by SIGINT or SIGTERM is written
+    /// [default: checkpoint.json]
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Write-ahead log a crashed run is recovered from, removed once the run finishes
+    #[arg(long)]
+    wal: Option<PathBuf>,
+    /// NATS server every processed transaction is published to as an account event,
+    /// e.g. 127.0.0.1:4222
+    #[arg(long)]
+    nats: Option<String>,
+    /// Subject account events are published to [default: accounts]
+    #[arg(long)]
+    nats_subject: Option<String>,
+    /// Kafka topic every processed transaction is published to as an account event, on
+    /// the cluster of --kafka-broker
+    #[cfg(feature = "kafka")]
+    #[arg(long)]
+    kafka_events: Option<String>,
+    /// Redis server the balances of every account are mirrored to, a redis:// URL or
+    /// just host:port, e.g. redis://:password@127.0.0.1:6379/2
+    #[arg(long)]
+    redis: Option<String>,
+    /// Prefix of the keys of the mirrored balances [default: account]
+    #[arg(long)]
+    redis_prefix: Option<String>,
+    /// http:// URL notified of opened disputes, chargebacks and locked accounts
+    #[arg(long)]
+    webhook: Option<HttpUrl>,
+    /// Attempts to deliver a webhook notification before giving up on it [default: 5]
+    #[arg(long)]
+    webhook_attempts: Option<u32>,
+    /// Where webhook notifications that couldn't be delivered are written
+    /// [default: webhooks-failed.jsonl]
+    #[arg(long)]
+    webhook_failed: Option<PathBuf>,
+    /// File every rejected transaction is written to as a JSON line with its error, to be
+    /// fixed and processed again
+    #[arg(long)]
+    dead_letters: Option<PathBuf>,
+    /// Where statistics of the run are written as JSON: accounts, transactions by type,
+    /// amounts moved, rejections by reason and throughput
+    #[arg(long)]
+    summary: Option<PathBuf>,
+    /// Print statistics of the run to stderr once it finishes
+    #[arg(long)]
+    print_summary: bool,
+    /// Redraw a live dashboard on stderr every second while processing: throughput, queued
+    /// transactions, locked accounts, the busiest accounts and the latest rejections
+    #[arg(long)]
+    dashboard: bool,
+    /// Where every account's events (deposits, withdrawals, disputes, locks) are written as
+    /// JSON lines
+    #[arg(long)]
+    event_log: Option<PathBuf>,
+    /// Where the double-entry postings of those events are written as csv
+    #[arg(long)]
+    postings: Option<PathBuf>,
+    /// OTLP/HTTP endpoint spans are exported to, e.g. http://localhost:4318/v1/traces
+    #[arg(long)]
+    otlp_endpoint: Option<HttpUrl>,
+    /// Fraction of transactions traced [default: 1]
+    #[arg(long, value_parser = rate)]
+    trace_sample_rate: Option<Decimal>,
+    /// Attempts at a transaction failing on storage, or a connection to NATS or Redis,
+    /// before giving up on it [default: 3]
+    #[arg(long)]
+    retry_attempts: Option<u32>,
+    /// Wait before the first retry in milliseconds, doubling with every further one
+    /// [default: 100]
+    #[arg(long)]
+    retry_backoff_ms: Option<u64>,
+    /// Directory account state is loaded from and saved to, so it carries over between runs
+    #[cfg(feature = "persistence")]
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+    /// SQLite database account state is loaded from and saved to, in tables of accounts,
+    /// balances and history
+    #[cfg(feature = "sqlite")]
+    #[arg(long, conflicts_with = "state_dir")]
+    state_db: Option<PathBuf>,
+    /// PostgreSQL database account state is loaded from and every posted transaction is
+    /// written to, e.g. postgres://engine@localhost/accounts
+    #[cfg(feature = "postgres")]
+    #[arg(long, conflicts_with = "state_dir")]
+    state_postgres: Option<String>,
+    /// Connections to the PostgreSQL database open at most [default: 16]
+    #[cfg(feature = "postgres")]
+    #[arg(long, value_parser = positive)]
+    postgres_pool_size: Option<usize>,
+    /// Longest wait for a free PostgreSQL connection in milliseconds [default: unbounded]
+    #[cfg(feature = "postgres")]
+    #[arg(long)]
+    postgres_wait_timeout_ms: Option<u64>,
+    /// Longest wait for a new PostgreSQL connection in milliseconds [default: unbounded]
+    #[cfg(feature = "postgres")]
+    #[arg(long)]
+    postgres_connect_timeout_ms: Option<u64>,
+    /// Abort on malformed rows instead of skipping them
+    #[arg(long)]
+    strict: bool,
+    /// Read all inputs at once and interleave them by their timestamp column
+    #[arg(long)]
+    merge_by_timestamp: bool,
+    /// Keep the input file open at its end and process rows as they are appended, until
+    /// interrupted
+    #[arg(long)]
+    follow: bool,
+    /// Seconds between checkpoints written while following an input [default: 60]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    snapshot_interval: Option<u64>,
+    /// Check every transaction against the invariants of money and fail the run with a
+    /// report of every violation
+    #[arg(long)]
+    check_invariants: bool,
+    /// Accept administrative transactions, unlock and adjustment
+    #[arg(long)]
+    allow_admin_ops: bool,
+    /// Leave accounts unlocked after a chargeback
+    #[arg(long)]
+    chargeback_no_lock: bool,
+    /// Reject disputes holding more than the available funds of the account
+    #[arg(long)]
+    no_negative_available: bool,
+    /// Keep accepting deposits on accounts locked by a chargeback
+    #[arg(long)]
+    deposits_when_locked: bool,
+    /// Keep processing disputes, resolves and chargebacks on locked accounts
+    #[arg(long)]
+    disputes_when_locked: bool,
+    /// Only accept clients from this inclusive id range, e.g. 0-999
+    #[arg(long)]
+    partition: Option<Partition>,
+    /// Settle transfers with clients of other partitions, writing the messages for those
+    /// partitions to this file as JSON lines
+    #[arg(long)]
+    transfer_outbox: Option<PathBuf>,
+    /// Transfer messages written by other partitions, settled before the input is processed
+    #[arg(long)]
+    transfer_inbox: Vec<PathBuf>,
+    /// Decimal places of balances in the report [default: 4]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=28))]
+    precision: Option<u32>,
+    /// Rounding of midpoints, half-up or bankers [default: half-up]
+    #[arg(long)]
+    rounding: Option<RoundingMode>,
+    /// Number of worker tasks, bench compares a comma separated list of counts
+    /// [default: number of cores]
+    #[arg(long, value_parser = positive, value_delimiter = ',')]
+    workers: Vec<usize>,
+    /// Capacity of the channels between reader and workers [default: 1024]
+    #[arg(long, value_parser = positive)]
+    channel_capacity: Option<usize>,
+    /// Handling of reused transaction ids, first-wins or error [default: first-wins]
+    #[arg(long)]
+    duplicates: Option<DuplicatePolicy>,
+    /// Detect reused transaction ids with a Bloom filter of this false positive rate,
+    /// e.g. 0.001, keeping the exact ids on disk rather than in memory
+    #[arg(long, value_parser = false_positive_rate)]
+    id_filter_rate: Option<Decimal>,
+    /// Number of transaction ids the Bloom filter is sized for [default: 100000000]
+    #[arg(long, value_parser = positive)]
+    expected_ids: Option<usize>,
+    /// Csv or TOML file with the exchange rates used by convert transactions
+    #[arg(long)]
+    rates: Option<PathBuf>,
+    /// TOML file with the fees charged per transaction type and monthly
+    #[arg(long)]
+    fees: Option<PathBuf>,
+    /// TOML file with limits on transaction amounts, daily withdrawals and transactions per minute
+    #[arg(long)]
+    limits: Option<PathBuf>,
+    /// TOML file configuring the built-in fraud rules
+    #[arg(long)]
+    fraud_rules: Option<PathBuf>,
+    /// Keep whole transactions in account history rather than just what disputes need
+    #[arg(long)]
+    full_history: bool,
+    /// History entries each account keeps in memory, older ones are spilled to disk
+    #[arg(long, value_parser = positive)]
+    history_window: Option<usize>,
+    /// File history beyond the window is spilled to, removed once the run finishes
+    /// [default: history-spill-<pid>.jsonl in the temp directory]
+    #[arg(long)]
+    history_spill: Option<PathBuf>,
+    /// How far withdrawals may take available funds below zero [default: 0]
+    #[arg(long, value_parser = non_negative)]
+    overdraft_limit: Option<Money>,
+    /// Csv file with client,limit rows overriding the overdraft limit of single clients
+    #[arg(long)]
+    overdraft_limits: Option<PathBuf>,
+    /// Annual rate of the interest accrued daily on available funds, e.g. 0.05
+    #[arg(long, value_parser = interest_rate)]
+    interest_rate: Option<Decimal>,
+    /// Fraction of converted amounts kept as a fee, e.g. 0.01 [default: 0]
+    #[arg(long, value_parser = spread)]
+    spread: Option<Decimal>,
+    /// Decimal places converted amounts are rounded to [default: 4]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=28))]
+    conversion_precision: Option<u32>,
+    /// Rounding of converted amounts, half-up or bankers [default: half-up]
+    #[arg(long)]
+    conversion_rounding: Option<RoundingMode>,
+}
+
+fn from_str<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(d)?;
+    T::from_str(&s).map(Some).map_err(de::Error::custom)
+}
+
+/// Contents of the `--config` file. Options given on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    #[serde(deserialize_with = "from_str")]
+    input_format: Option<InputFormat>,
+    #[serde(deserialize_with = "from_str")]
+    delimiter: Option<Delimiter>,
+    #[serde(deserialize_with = "from_str")]
+    schema: Option<Columns>,
+    #[serde(deserialize_with = "from_str")]
+    source: Option<Source>,
+    #[cfg(feature = "kafka")]
+    kafka_broker: Option<String>,
+    #[cfg(feature = "kafka")]
+    kafka_topic: Option<String>,
+    #[cfg(feature = "kafka")]
+    kafka_group: Option<String>,
+    output: Option<PathBuf>,
+    #[serde(deserialize_with = "from_str")]
+    output_format: Option<ReportFormat>,
+    #[serde(deserialize_with = "from_str")]
+    sort_output: Option<ReportOrder>,
+    errors: Option<PathBuf>,
+    fraud_report: Option<PathBuf>,
+    ledger: Option<PathBuf>,
+    #[serde(deserialize_with = "from_str")]
+    ledger_format: Option<ReportFormat>,
+    load_state: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    wal: Option<PathBuf>,
+    nats: Option<String>,
+    nats_subject: Option<String>,
+    #[cfg(feature = "kafka")]
+    kafka_events: Option<String>,
+    redis: Option<String>,
+    redis_prefix: Option<String>,
+    #[serde(deserialize_with = "from_str")]
+    webhook: Option<HttpUrl>,
+    webhook_attempts: Option<u32>,
+    webhook_failed: Option<PathBuf>,
+    dead_letters: Option<PathBuf>,
+    summary: Option<PathBuf>,
+    print_summary: Option<bool>,
+    dashboard: Option<bool>,
+    event_log: Option<PathBuf>,
+    postings: Option<PathBuf>,
+    #[serde(deserialize_with = "from_str")]
+    otlp_endpoint: Option<HttpUrl>,
+    #[serde(deserialize_with = "from_str")]
+    trace_sample_rate: Option<Decimal>,
+    retry_attempts: Option<u32>,
+    retry_backoff_ms: Option<u64>,
+    #[cfg(feature = "persistence")]
+    state_dir: Option<PathBuf>,
+    #[cfg(feature = "sqlite")]
+    state_db: Option<PathBuf>,
+    #[cfg(feature = "postgres")]
+    state_postgres: Option<String>,
+    #[cfg(feature = "postgres")]
+    postgres_pool_size: Option<usize>,
+    #[cfg(feature = "postgres")]
+    postgres_wait_timeout_ms: Option<u64>,
+    #[cfg(feature = "postgres")]
+    postgres_connect_timeout_ms: Option<u64>,
+    strict: Option<bool>,
+    merge_by_timestamp: Option<bool>,
+    follow: Option<bool>,
+    snapshot_interval: Option<u64>,
+    check_invariants: Option<bool>,
+    allow_admin_ops: Option<bool>,
+    chargeback_no_lock: Option<bool>,
+    no_negative_available: Option<bool>,
+    deposits_when_locked: Option<bool>,
+    disputes_when_locked: Option<bool>,
+    #[serde(deserialize_with = "from_str")]
+    partition: Option<Partition>,
+    transfer_outbox: Option<PathBuf>,
+    transfer_inbox: Option<Vec<PathBuf>>,
+    precision: Option<u32>,
+    #[serde(deserialize_with = "from_str")]
+    rounding: Option<RoundingMode>,
+    workers: Option<usize>,
+    channel_capacity: Option<usize>,
+    #[serde(deserialize_with = "from_str")]
+    duplicates: Option<DuplicatePolicy>,
+    #[serde(deserialize_with = "from_str")]
+    id_filter_rate: Option<Decimal>,
+    expected_ids: Option<usize>,
+    rates: Option<PathBuf>,
+    fees: Option<PathBuf>,
+    limits: Option<PathBuf>,
+    fraud_rules: Option<PathBuf>,
+    full_history: Option<bool>,
+    history_window: Option<usize>,
+    history_spill: Option<PathBuf>,
+    #[serde(deserialize_with = "from_str")]
+    overdraft_limit: Option<Money>,
+    overdraft_limits: Option<PathBuf>,
+    #[serde(deserialize_with = "from_str")]
+    interest_rate: Option<Decimal>,
+    #[serde(deserialize_with = "from_str")]
+    spread: Option<Decimal>,
+    conversion_precision: Option<u32>,
+    #[serde(deserialize_with = "from_str")]
+    conversion_rounding: Option<RoundingMode>,
+}
+
+fn read_config(path: Option<&Path>) -> Result<ConfigFile, Box<dyn Error>> {
+    Ok(match path {
+        Some(path) => toml::from_str::<ConfigFile>(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?,
+        None => ConfigFile::default(),
+    })
+}
+
+/// Options naming the fees, overdraft limits, limits and fraud rules of a run, those of the
+/// command line taking precedence over the config file's.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RuleSources {
+    config: Option<PathBuf>,
+    overdraft_limit: Option<Money>,
+    overdraft_limits: Option<PathBuf>,
+    fees: Option<PathBuf>,
+    limits: Option<PathBuf>,
+    fraud_rules: Option<PathBuf>,
+}
+
+impl RuleSources {
+    /// Default engine config with the rules loaded, those neither given nor in `file` left
+    /// at their defaults.
+    fn load(&self, file: &ConfigFile) -> Result<EngineConfig, Box<dyn Error>> {
+        let mut engine = EngineConfig::default();
+        let overdraft_limit = self.overdraft_limit.or(file.overdraft_limit);
+        if let Some(limit) = overdraft_limit.filter(|limit| limit.is_negative()) {
+            return Err(format!("Invalid overdraft limit: {}", limit).into());
+        }
+        engine.overdraft_limit = overdraft_limit.unwrap_or_default();
+        engine.overdraft_limits = match self
+            .overdraft_limits
+            .as_ref()
+            .or(file.overdraft_limits.as_ref())
+        {
+            Some(path) => load_overdraft_limits(path)
+                .map_err(|e| format!("Invalid overdraft limits {}: {}", path.display(), e))?,
+            None => HashMap::new(),
+        };
+        engine.fees = match self.fees.as_ref().or(file.fees.as_ref()) {
+            Some(path) => {
+                Some(Arc::new(FeeSchedule::load(path).map_err(|e| {
+                    format!("Invalid fees file {}: {}", path.display(), e)
+                })?))
+            }
+            None => None,
+        };
+        engine.limits = match self.limits.as_ref().or(file.limits.as_ref()) {
+            Some(path) => LimitRules::load(path)
+                .map_err(|e| format!("Invalid limits file {}: {}", path.display(), e))?,
+            None => LimitRules::default(),
+        };
+        engine.fraud_rules = match self.fraud_rules.as_ref().or(file.fraud_rules.as_ref()) {
+            Some(path) => FraudRules::load(path)
+                .map_err(|e| format!("Invalid fraud rules {}: {}", path.display(), e))?,
+            None => FraudRules::default(),
+        };
+        Ok(engine)
+    }
+
+    /// Reads the config file and the rule files again, for [`Engine::reconfigure`].
+    ///
+    /// [`Engine::reconfigure`]: transaction_system::Engine::reconfigure
+    pub fn reload(&self) -> Result<EngineConfig, Box<dyn Error>> {
+        self.load(&read_config(self.config.as_deref())?)
+    }
+}
+
+/// Fully resolved options of a processing run.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub inputs: Vec<String>,
+    pub input_format: Option<InputFormat>,
+    /// Field delimiter of csv inputs, detected when not given
+    pub delimiter: Option<u8>,
+    /// Columns of csv inputs without header
+    pub schema: Option<Columns>,
+    pub output: Option<PathBuf>,
+    /// Where rejected transactions are written, nowhere when neither it nor the report's
+    /// file is given
+    pub errors: Option<PathBuf>,
+    pub fraud_report: Option<PathBuf>,
+    /// Where and in which format the processed ledger is written
+    pub ledger: Option<(PathBuf, ReportFormat)>,
+    pub load_state: Option<PathBuf>,
+    pub save_state: Option<PathBuf>,
+    pub checkpoint: PathBuf,
+    pub wal: Option<PathBuf>,
+    /// Where fees, overdraft limits, limits and fraud rules were loaded from, to load them
+    /// again while serving
+    pub rules: RuleSources,
+    /// Cipher snapshots, the write-ahead log, state directories and spilled history are
+    /// encrypted with, from the environment
+    pub cipher: Option<Cipher>,
+    /// NATS server and subject account events are published to
+    pub nats: Option<(String, String)>,
+    /// Kafka broker and topic account events are published to
+    #[cfg(feature = "kafka")]
+    pub kafka_events: Option<(String, String)>,
+    /// Redis server and key prefix balances are mirrored to
+    pub redis: Option<(String, String)>,
+    pub webhook: Option<Webhook>,
+    pub dead_letters: Option<PathBuf>,
+    /// Where messages to other partitions settling transfers are written
+    pub transfer_outbox: Option<PathBuf>,
+    /// Messages of other partitions settling transfers
+    pub transfer_inbox: Vec<PathBuf>,
+    pub summary: Option<PathBuf>,
+    pub print_summary: bool,
+    pub dashboard: bool,
+    pub event_log: Option<PathBuf>,
+    pub postings: Option<PathBuf>,
+    /// OTLP endpoint and sample rate of tracing
+    pub tracing: Option<(HttpUrl, Decimal)>,
+    #[cfg(feature = "persistence")]
+    pub state_dir: Option<PathBuf>,
+    #[cfg(feature = "sqlite")]
+    pub state_db: Option<PathBuf>,
+    #[cfg(feature = "postgres")]
+    pub state_postgres: Option<PostgresConfig>,
+    pub strict: bool,
+    pub merge_by_timestamp: bool,
+    /// Time between checkpoints when the input is followed, as a Kafka topic always is
+    pub follow: Option<Duration>,
+    /// Topic consumed instead of the inputs
+    #[cfg(feature = "kafka")]
+    pub kafka: Option<KafkaSource>,
+    pub engine: EngineConfig,
+}
+
+impl Settings {
+    /// The settings, unless they consume a Kafka topic, which only processing runs do.
+    pub fn reading_files(self) -> Result<Self, Box<dyn Error>> {
+        #[cfg(feature = "kafka")]
+        if self.kafka.is_some() {
+            return Err("Only process and reconstruct consume a Kafka topic".into());
+        }
+        Ok(self)
+    }
+}
+
+impl ProcessArgs {
+    /// Worker counts `bench` compares, taken out so the remaining arguments resolve into
+    /// the settings of one run. Empty unless several counts were given.
+    pub fn take_worker_counts(&mut self) -> Vec<usize> {
+        match self.workers.len() {
+            0 | 1 => Vec::new(),
+            _ => std::mem::take(&mut self.workers),
+        }
+    }
+
+    pub fn settings(self) -> Result<Settings, Box<dyn Error>> {
+        let file = read_config(self.config.as_deref())?;
+        let rules = RuleSources {
+            config: self.config.clone(),
+            overdraft_limit: self.overdraft_limit,
+            overdraft_limits: self.overdraft_limits,
+            fees: self.fees,
+            limits: self.limits,
+            fraud_rules: self.fraud_rules,
+        };
+        let with_rules = rules.load(&file)?;
+
+        let partition = self.partition.or(file.partition);
+        let transfer_outbox = self.transfer_outbox.or(file.transfer_outbox);
+        let transfer_inbox = match self.transfer_inbox.is_empty() {
+            true => file.transfer_inbox.unwrap_or_default(),
+            false => self.transfer_inbox,
+        };
+        if transfer_outbox.is_some() && partition.is_none() {
+            return Err("--transfer-outbox needs a --partition".into());
+        }
+        if !transfer_inbox.is_empty() && transfer_outbox.is_none() {
+            return Err("--transfer-inbox needs a --transfer-outbox for the answers".into());
+        }
+
+        let mut engine = EngineConfig {
+            partition,
+            cross_partition_transfers: transfer_outbox.is_some(),
+            allow_admin_ops: self.allow_admin_ops || file.allow_admin_ops.unwrap_or(false),
+            check_invariants: self.check_invariants || file.check_invariants.unwrap_or(false),
+            event_log: [
+                &self.event_log,
+                &file.event_log,
+                &self.postings,
+                &file.postings,
+            ]
+            .iter()
+            .any(|path| path.is_some()),
+            chargeback_policy: ChargebackPolicy {
+                lock_account: !(self.chargeback_no_lock
+                    || file.chargeback_no_lock.unwrap_or(false)),
+                allow_negative_available: !(self.no_negative_available
+                    || file.no_negative_available.unwrap_or(false)),
+                deposits_when_locked: self.deposits_when_locked
+                    || file.deposits_when_locked.unwrap_or(false),
+                disputes_when_locked: self.disputes_when_locked
+                    || file.disputes_when_locked.unwrap_or(false),
+            },
+            ..with_rules
+        };
+        if let Some(precision) = self.precision.or(file.precision) {
+            if precision > 28 {
+                return Err(format!("Invalid precision: {}", precision).into());
+            }
+            engine.output_format.decimal_places = precision;
+        }
+        if let Some(rounding) = self.rounding.or(file.rounding) {
+            engine.output_format.rounding = rounding;
+        }
+        let workers = match self.workers[..] {
+            [] => file.workers,
+            [workers] => Some(workers),
+            _ => return Err("Only bench compares several worker counts".into()),
+        };
+        if let Some(workers) = workers {
+            engine.workers = workers.max(1);
+        }
+        if let Some(capacity) = self.channel_capacity.or(file.channel_capacity) {
+            engine.channel_capacity = capacity.max(1);
+        }
+        if let Some(report_format) = self.output_format.or(file.output_format) {
+            engine.report_format = report_format;
+        }
+        if let Some(report_order) = self.sort_output.or(file.sort_output) {
+            engine.report_order = report_order;
+        }
+        if let Some(duplicates) = self.duplicates.or(file.duplicates) {
+            engine.duplicate_policy = duplicates;
+        }
+        engine.retry = RetryPolicy {
+            attempts: self
+                .retry_attempts
+                .or(file.retry_attempts)
+                .unwrap_or(3)
+                .max(1),
+            backoff: Duration::from_millis(
+                self.retry_backoff_ms
+                    .or(file.retry_backoff_ms)
+                    .unwrap_or(100),
+            ),
+            max_backoff: Duration::from_secs(10),
+        };
+        if let Some(rate) = self.id_filter_rate.or(file.id_filter_rate) {
+            if rate <= Decimal::ZERO || rate >= Decimal::ONE {
+                return Err(format!("Invalid id filter rate: {}", rate).into());
+            }
+            engine.id_filter = Some(IdFilter {
+                expected_ids: self
+                    .expected_ids
+                    .or(file.expected_ids)
+                    .unwrap_or(100_000_000),
+                false_positive_rate: rate,
+                bitmap_dir: std::env::temp_dir(),
+            });
+        }
+        engine.exchange_rates = match self.rates.or(file.rates) {
+            Some(path) => {
+                let mut rates = ExchangeRates::load(&path)
+                    .map_err(|e| format!("Invalid rates file {}: {}", path.display(), e))?;
+                if let Some(spread) = self.spread.or(file.spread) {
+                    if spread < Decimal::ZERO || spread >= Decimal::ONE {
+                        return Err(format!("Invalid spread: {}", spread).into());
+                    }
+                    rates.spread = spread;
+                }
+                if let Some(precision) = self.conversion_precision.or(file.conversion_precision) {
+                    if precision > 28 {
+                        return Err(format!("Invalid conversion precision: {}", precision).into());
+                    }
+                    rates.rounding.decimal_places = precision;
+                }
+                if let Some(rounding) = self.conversion_rounding.or(file.conversion_rounding) {
+                    rates.rounding.rounding = rounding;
+                }
+                Some(rates)
+            }
+            None => None,
+        };
+
+        if let Some(rate) = self.interest_rate.or(file.interest_rate) {
+            if rate < Decimal::ZERO {
+                return Err(format!("Invalid interest rate: {}", rate).into());
+            }
+            engine.interest_rate = Some(rate);
+        }
+        engine.full_history = self.full_history || file.full_history.unwrap_or(false);
+        let cipher = Cipher::from_env()?;
+        if let Some(size) = self.history_window.or(file.history_window) {
+            let path = self
+                .history_spill
+                .or(file.history_spill)
+                .unwrap_or_else(|| {
+                    std::env::temp_dir().join(format!("history-spill-{}.jsonl", std::process::id()))
+                });
+            engine.history_window = Some(
+                HistoryWindow::new(size, &path, cipher.clone())
+                    .map_err(|e| format!("Invalid history spill {}: {}", path.display(), e))?,
+            );
+        }
+
+        let merge_by_timestamp =
+            self.merge_by_timestamp || file.merge_by_timestamp.unwrap_or(false);
+        let follow_input = self.follow || file.follow.unwrap_or(false);
+        let wal = self.wal.or(file.wal);
+        let source = self.source.or(file.source).unwrap_or(Source::File);
+        #[cfg(feature = "kafka")]
+        let kafka_broker = self.kafka_broker.or(file.kafka_broker);
+        #[cfg(feature = "kafka")]
+        let kafka_events = match self.kafka_events.or(file.kafka_events) {
+            Some(topic) => Some((
+                kafka_broker
+                    .clone()
+                    .ok_or("--kafka-events needs a --kafka-broker")?,
+                topic,
+            )),
+            None => None,
+        };
+        #[cfg(feature = "kafka")]
+        let kafka = match source {
+            Source::Kafka => {
+                if !self.inputs.is_empty() || follow_input || merge_by_timestamp {
+                    return Err("--source kafka reads no input files".into());
+                }
+                // Replaying the log would apply what the topic delivers again from the
+                // checkpoint's offsets
+                if wal.is_some() {
+                    return Err("--source kafka continues from checkpoints, not --wal".into());
+                }
+                Some(KafkaSource {
+                    broker: kafka_broker.ok_or("--source kafka needs a --kafka-broker")?,
+                    topic: self
+                        .kafka_topic
+                        .or(file.kafka_topic)
+                        .ok_or("--source kafka needs a --kafka-topic")?,
+                    group: self
+                        .kafka_group
+                        .or(file.kafka_group)
+                        .unwrap_or_else(|| "transaction_system".to_string()),
+                })
+            }
+            Source::File => None,
+        };
+        let topic = source != Source::File;
+        let inputs = match topic {
+            true => Vec::new(),
+            false => expand_inputs(self.inputs)?,
+        };
+        let follow = (follow_input || topic).then(|| {
+            let interval = self.snapshot_interval.or(file.snapshot_interval);
+            Duration::from_secs(interval.unwrap_or(60))
+        });
+        if follow_input && (inputs.len() != 1 || inputs[0] == STDIN || merge_by_timestamp) {
+            return Err("--follow takes a single input file".into());
+        }
+
+        #[cfg(feature = "persistence")]
+        let state_dir = self.state_dir.or(file.state_dir);
+        #[cfg(feature = "sqlite")]
+        let state_db = self.state_db.or(file.state_db);
+        #[cfg(feature = "sqlite")]
+        if state_db.is_some() && (state_dir.is_some() || cipher.is_some()) {
+            return Err("--state-db takes neither a --state-dir nor encryption".into());
+        }
+        #[cfg(feature = "postgres")]
+        let state_postgres = self.state_postgres.or(file.state_postgres).map(|url| {
+            let millis = |ms: Option<u64>| ms.map(Duration::from_millis);
+            PostgresConfig {
+                pool_size: self
+                    .postgres_pool_size
+                    .or(file.postgres_pool_size)
+                    .unwrap_or(16),
+                wait_timeout: millis(
+                    self.postgres_wait_timeout_ms
+                        .or(file.postgres_wait_timeout_ms),
+                ),
+                connect_timeout: millis(
+                    self.postgres_connect_timeout_ms
+                        .or(file.postgres_connect_timeout_ms),
+                ),
+                ..PostgresConfig::new(url)
+            }
+        });
+        #[cfg(feature = "postgres")]
+        if state_postgres.is_some() && (state_dir.is_some() || cipher.is_some()) {
+            return Err("--state-postgres takes neither a --state-dir nor encryption".into());
+        }
+        #[cfg(all(feature = "postgres", feature = "sqlite"))]
+        if state_postgres.is_some() && state_db.is_some() {
+            return Err("--state-postgres can't be combined with --state-db".into());
+        }
+
+        let output = self.output.or(file.output);
+        Ok(Settings {
+            inputs,
+            input_format: self.input_format.or(file.input_format),
+            delimiter: self.delimiter.or(file.delimiter).map(|d| d.0),
+            schema: self.schema.or(file.schema),
+            errors: self
+                .errors
+                .or(file.errors)
+                .or_else(|| Some(output.as_ref()?.with_file_name("errors.csv"))),
+            output,
+            fraud_report: self.fraud_report.or(file.fraud_report),
+            ledger: self.ledger.or(file.ledger).map(|path| {
+                let format = self.ledger_format.or(file.ledger_format);
+                (path, format.unwrap_or_default())
+            }),
+            load_state: self.load_state.or(file.load_state),
+            save_state: self.save_state.or(file.save_state),
+            checkpoint: self
+                .checkpoint
+                .or(file.checkpoint)
+                .unwrap_or_else(|| PathBuf::from("checkpoint.json")),
+            wal,
+            rules,
+            cipher,
+            nats: self.nats.or(file.nats).map(|address| {
+                let subject = self.nats_subject.or(file.nats_subject);
+                (address, subject.unwrap_or_else(|| "accounts".to_string()))
+            }),
+            #[cfg(feature = "kafka")]
+            kafka_events,
+            redis: self.redis.or(file.redis).map(|address| {
+                let prefix = self.redis_prefix.or(file.redis_prefix);
+                (address, prefix.unwrap_or_else(|| "account".to_string()))
+            }),
+            webhook: self.webhook.or(file.webhook).map(|url| Webhook {
+                url,
+                retry: RetryPolicy {
+                    attempts: self.webhook_attempts.or(file.webhook_attempts).unwrap_or(5),
+                    backoff: Duration::from_millis(500),
+                    max_backoff: Duration::from_secs(30),
+                },
+                failed: self
+                    .webhook_failed
+                    .or(file.webhook_failed)
+                    .unwrap_or_else(|| PathBuf::from("webhooks-failed.jsonl")),
+            }),
+            dead_letters: self.dead_letters.or(file.dead_letters),
+            transfer_outbox,
+            transfer_inbox,
+            summary: self.summary.or(file.summary),
+            print_summary: self.print_summary || file.print_summary.unwrap_or(false),
+            dashboard: self.dashboard || file.dashboard.unwrap_or(false),
+            event_log: self.event_log.or(file.event_log),
+            postings: self.postings.or(file.postings),
+            tracing: self.otlp_endpoint.or(file.otlp_endpoint).map(|endpoint| {
+                let rate = self.trace_sample_rate.or(file.trace_sample_rate);
+                (endpoint, rate.unwrap_or(Decimal::ONE))
+            }),
+            #[cfg(feature = "persistence")]
+            state_dir,
+            #[cfg(feature = "sqlite")]
+            state_db,
+            #[cfg(feature = "postgres")]
+            state_postgres,
+            strict: self.strict || file.strict.unwrap_or(false),
+            merge_by_timestamp,
+            follow,
+            #[cfg(feature = "kafka")]
+            kafka,
+            engine,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_inputs, Cli, Command};
+    use clap::{CommandFactory, Parser};
+    use rust_decimal::Decimal;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use transaction_system::as_of::AsOf;
+    use transaction_system::money::RoundingMode;
+    use transaction_system::partition::Partition;
+    use transaction_system::reader::InputFormat;
+    use transaction_system::Money;
+    use transaction_system::{
+        ChargebackPolicy, DuplicatePolicy, ReportFormat, ReportOrder, Timestamp,
+    };
+
+    fn parse(args: &[&str]) -> Result<Command, clap::Error> {
+        Cli::try_parse_from(std::iter::once("transaction_system").chain(args.iter().copied()))
+            .map(Cli::into_command)
+    }
+
+    #[test]
+    fn cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn errors_default_next_to_output() {
+        let settings = match parse(&["transactions.csv", "--output", "out/accounts.csv"]).unwrap() {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        assert_eq!(settings.errors, Some(PathBuf::from("out/errors.csv")));
+    }
+
+    #[test]
+    fn bare_filename_processes() {
+        let settings = match parse(&["transactions.csv"]).unwrap() {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        assert_eq!(settings.inputs, vec!["transactions.csv"]);
+        assert_eq!(settings.errors, None);
+        assert_eq!(settings.input_format, None);
+        assert_eq!(settings.delimiter, None);
+        assert_eq!(settings.schema, None);
+        assert_eq!(settings.output, None);
+        assert_eq!(settings.ledger, None);
+        assert!(!settings.engine.event_log);
+        assert!(!settings.strict);
+        assert!(!settings.merge_by_timestamp);
+        assert!(!settings.engine.allow_admin_ops);
+        assert_eq!(
+            settings.engine.chargeback_policy,
+            ChargebackPolicy::default()
+        );
+
+        match parse(&[]).unwrap() {
+            Command::Process(args) => assert_eq!(args.settings().unwrap().inputs, vec!["-"]),
+            _ => panic!("Expected process command"),
+        }
+    }
+
+    #[test]
+    fn process_options() {
+        let command = parse(&[
+            "process",
+            "--partition",
+            "0-99",
+            "--precision",
+            "2",
+            "--rounding",
+            "bankers",
+            "--workers",
+            "3",
+            "--channel-capacity",
+            "16",
+            "--duplicates",
+            "error",
+            "--errors",
+            "rejected.csv",
+            "--output",
+            "accounts.csv",
+            "--output-format",
+            "jsonl",
+            "--sort-output",
+            "client",
+            "--summary",
+            "run.json",
+            "--print-summary",
+            "--dashboard",
+            "--event-log",
+            "events.jsonl",
+            "--postings",
+            "postings.csv",
+            "--ledger",
+            "ledger.jsonl",
+            "--ledger-format",
+            "jsonl",
+            "--strict",
+            "--merge-by-timestamp",
+            "--check-invariants",
+            "--allow-admin-ops",
+            "--chargeback-no-lock",
+            "--no-negative-available",
+            "--deposits-when-locked",
+            "--disputes-when-locked",
+            "--input-format",
+            "jsonl",
+            "--delimiter",
+            "tab",
+            "--schema",
+            "type,client,tx,amount",
+            "transactions.csv",
+        ])
+        .unwrap();
+        let settings = match command {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        assert_eq!(settings.engine.partition, Partition::new(0, 99));
+        assert_eq!(settings.engine.output_format.decimal_places, 2);
+        assert_eq!(
+            settings.engine.output_format.rounding,
+            RoundingMode::Bankers
+        );
+        assert_eq!(settings.engine.workers, 3);
+        assert_eq!(settings.engine.channel_capacity, 16);
+        assert_eq!(settings.engine.duplicate_policy, DuplicatePolicy::Abort);
+        assert_eq!(settings.errors, Some(PathBuf::from("rejected.csv")));
+        assert_eq!(settings.output, Some(PathBuf::from("accounts.csv")));
+        assert_eq!(settings.engine.report_format, ReportFormat::Jsonl);
+        assert_eq!(settings.engine.report_order, ReportOrder::Client);
+        assert_eq!(settings.summary, Some(PathBuf::from("run.json")));
+        assert!(settings.print_summary);
+        assert!(settings.dashboard);
+        assert_eq!(settings.event_log, Some(PathBuf::from("events.jsonl")));
+        assert_eq!(settings.postings, Some(PathBuf::from("postings.csv")));
+        assert!(settings.engine.event_log);
+        assert_eq!(
+            settings.ledger,
+            Some((PathBuf::from("ledger.jsonl"), ReportFormat::Jsonl))
+        );
+        assert!(settings.strict);
+        assert_eq!(settings.input_format, Some(InputFormat::Jsonl));
+        assert_eq!(settings.delimiter, Some(b'\t'));
+        assert_eq!(settings.schema, "type,client,tx,amount".parse().ok());
+        assert!(settings.merge_by_timestamp);
+        assert!(settings.engine.check_invariants);
+        assert!(settings.engine.allow_admin_ops);
+        assert_eq!(
+            settings.engine.chargeback_policy,
+            ChargebackPolicy {
+                lock_account: false,
+                allow_negative_available: false,
+                deposits_when_locked: true,
+                disputes_when_locked: true,
+            }
+        );
+    }
+
+    #[test]
+    fn bench_compares_worker_counts() {
+        let mut args = match parse(&["bench", "--workers", "1,4,8", "big.csv"]).unwrap() {
+            Command::Bench { process, .. } => process,
+            _ => panic!("Expected bench command"),
+        };
+        assert_eq!(args.take_worker_counts(), vec![1, 4, 8]);
+        assert_eq!(args.settings().unwrap().inputs, vec!["big.csv"]);
+
+        let settings = match parse(&["--workers", "1,4", "transactions.csv"]).unwrap() {
+            Command::Process(args) => args.settings(),
+            _ => panic!("Expected process command"),
+        };
+        assert!(settings.is_err());
+    }
+
+    #[test]
+    fn reload_rules() {
+        let dir = std::env::temp_dir();
+        let config = dir.join(format!("cli_rules_{}.toml", std::process::id()));
+        let limits = dir.join(format!("cli_limits_{}.toml", std::process::id()));
+        std::fs::write(&config, "overdraft-limit = \"5\"\n").unwrap();
+        std::fs::write(&limits, "max-amount = \"100\"\n").unwrap();
+        let settings = match parse(&[
+            "--config",
+            &config.to_string_lossy(),
+            "--limits",
+            &limits.to_string_lossy(),
+            "transactions.csv",
+        ])
+        .unwrap()
+        {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        assert_eq!(settings.engine.overdraft_limit, Money::from(5));
+        assert_eq!(
+            settings.engine.limits.default.max_amount,
+            Some(Money::from(100))
+        );
+
+        std::fs::write(&config, "").unwrap();
+        std::fs::write(&limits, "max-amount = \"10\"\n").unwrap();
+        let reloaded = settings.rules.reload().unwrap();
+        assert_eq!(reloaded.overdraft_limit, Money::ZERO);
+        assert_eq!(reloaded.limits.default.max_amount, Some(Money::from(10)));
+
+        std::fs::write(&limits, "max-amount = \"-1\"\n").unwrap();
+        assert!(settings.rules.reload().is_err());
+        std::fs::remove_file(config).unwrap();
+        std::fs::remove_file(limits).unwrap();
+    }
+
+    #[test]
+    fn transfer_messages() {
+        let settings = |args: &[&str]| match parse(args).unwrap() {
+            Command::Process(args) => args.settings(),
+            _ => panic!("Expected process command"),
+        };
+        let resolved = settings(&[
+            "--partition",
+            "0-99",
+            "--transfer-outbox",
+            "out.jsonl",
+            "--transfer-inbox",
+            "a.jsonl",
+            "--transfer-inbox",
+            "b.jsonl",
+            "transactions.csv",
+        ])
+        .unwrap();
+        assert!(resolved.engine.cross_partition_transfers);
+        assert_eq!(resolved.transfer_outbox, Some(PathBuf::from("out.jsonl")));
+        assert_eq!(
+            resolved.transfer_inbox,
+            vec![PathBuf::from("a.jsonl"), PathBuf::from("b.jsonl")]
+        );
+
+        assert!(settings(&["--transfer-outbox", "out.jsonl", "transactions.csv"]).is_err());
+        assert!(settings(&[
+            "--partition",
+            "0-99",
+            "--transfer-inbox",
+            "a.jsonl",
+            "transactions.csv"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(parse(&["--precision", "29", "transactions.csv"]).is_err());
+        assert!(parse(&["--rounding", "up", "transactions.csv"]).is_err());
+        assert!(parse(&["--sort-output", "total", "transactions.csv"]).is_err());
+        assert!(parse(&["--workers", "0", "transactions.csv"]).is_err());
+        assert!(parse(&["--channel-capacity", "0", "transactions.csv"]).is_err());
+        assert!(parse(&["--duplicates", "last-wins", "transactions.csv"]).is_err());
+        assert!(parse(&["--partition", "99-0", "transactions.csv"]).is_err());
+        assert!(parse(&["--unknown", "transactions.csv"]).is_err());
+        assert!(parse(&["--input-format", "xml", "transactions.csv"]).is_err());
+        assert!(parse(&["--output-format", "xml", "transactions.csv"]).is_err());
+        assert!(parse(&["--trace-sample-rate", "1.5", "transactions.csv"]).is_err());
+        assert!(parse(&["--log-level", "verbose", "transactions.csv"]).is_err());
+        assert!(parse(&["--log-format", "yaml", "transactions.csv"]).is_err());
+        assert!(parse(&["--otlp-endpoint", "https://otel:4318", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn reconstruct() {
+        match parse(&["reconstruct", "--until", "42", "transactions.csv"]).unwrap() {
+            Command::Reconstruct {
+                until,
+                client,
+                process,
+            } => {
+                assert_eq!(until, AsOf::Sequence(42));
+                assert_eq!(client, None);
+                assert_eq!(process.settings().unwrap().inputs, vec!["transactions.csv"]);
+            }
+            _ => panic!("Expected reconstruct command"),
+        }
+        assert!(parse(&["reconstruct", "--until", "ts", "transactions.csv"]).is_err());
+        let args = [
+            "reconstruct",
+            "--until",
+            "2024-01-01T00:00:00Z",
+            "--client",
+            "7",
+            "transactions.csv",
+        ];
+        match parse(&args).unwrap() {
+            Command::Reconstruct { until, client, .. } => {
+                let time = Timestamp::from_millis(1_704_067_200_000);
+                assert_eq!(until, AsOf::Time(time));
+                assert_eq!(client, Some(7));
+            }
+            _ => panic!("Expected reconstruct command"),
+        }
+        assert!(parse(&["reconstruct", "--at", "1", "transactions.csv"]).is_err());
+        assert!(parse(&["reconstruct", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn statement() {
+        match parse(&["statement", "--client", "7", "transactions.csv"]).unwrap() {
+            Command::Statement { client, process } => {
+                assert_eq!(client, 7);
+                assert_eq!(process.settings().unwrap().inputs, vec!["transactions.csv"]);
+            }
+            _ => panic!("Expected statement command"),
+        }
+        assert!(parse(&["statement", "transactions.csv"]).is_err());
+        assert!(parse(&["statement", "--client", "70000", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn follow() {
+        let settings = |args: &[&str]| match parse(args).unwrap() {
+            Command::Process(process) => process.settings(),
+            _ => panic!("Expected process command"),
+        };
+        let follow = settings(&["--follow", "transactions.csv"]).unwrap().follow;
+        assert_eq!(follow, Some(Duration::from_secs(60)));
+        let args = ["--follow", "--snapshot-interval", "5", "transactions.csv"];
+        assert_eq!(
+            settings(&args).unwrap().follow,
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(settings(&["transactions.csv"]).unwrap().follow, None);
+        assert!(settings(&["--follow"]).is_err());
+        assert!(settings(&["--follow", "a.csv", "b.csv"]).is_err());
+        assert!(parse(&["--follow", "--snapshot-interval", "0", "a.csv"]).is_err());
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn kafka_source() {
+        use transaction_system::kafka::KafkaSource;
+
+        let settings = |args: &[&str]| match parse(args).unwrap() {
+            Command::Process(process) => process.settings(),
+            _ => panic!("Expected process command"),
+        };
+        let args = [
+            "--source",
+            "kafka",
+            "--kafka-broker",
+            "127.0.0.1:9092",
+            "--kafka-topic",
+            "transactions",
+        ];
+        let kafka = settings(&args).unwrap();
+        assert_eq!(
+            kafka.kafka,
+            Some(KafkaSource {
+                broker: "127.0.0.1:9092".to_string(),
+                topic: "transactions".to_string(),
+                group: "transaction_system".to_string(),
+            })
+        );
+        // A topic is followed, with checkpoints as it goes
+        assert!(kafka.inputs.is_empty());
+        assert_eq!(kafka.follow, Some(Duration::from_secs(60)));
+        assert!(kafka.reading_files().is_err());
+        assert_eq!(settings(&["transactions.csv"]).unwrap().kafka, None);
+        assert!(settings(&args[..4]).is_err());
+        assert!(settings(&[&args[..], &["transactions.csv"]].concat()).is_err());
+        assert!(settings(&[&args[..], &["--wal", "wal.log"]].concat()).is_err());
+        assert!(parse(&["--source", "s3", "transactions.csv"]).is_err());
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn kafka_events() {
+        let settings = |args: &[&str]| match parse(args).unwrap() {
+            Command::Process(process) => process.settings(),
+            _ => panic!("Expected process command"),
+        };
+        let args = ["--kafka-events", "accounts", "transactions.csv"];
+        assert!(settings(&args).is_err());
+        let publishing = settings(&[&args[..], &["--kafka-broker", "127.0.0.1:9092"]].concat());
+        assert_eq!(
+            publishing.unwrap().kafka_events,
+            Some(("127.0.0.1:9092".to_string(), "accounts".to_string()))
+        );
+    }
+
+    #[test]
+    fn watch() {
+        match parse(&["watch", "incoming", "--save-state", "state.json"]).unwrap() {
+            Command::Watch { dir, process } => {
+                assert_eq!(dir, PathBuf::from("incoming"));
+                assert_eq!(process.settings().unwrap().inputs, ["-"]);
+            }
+            _ => panic!("Expected watch command"),
+        }
+        assert!(parse(&["watch"]).is_err());
+    }
+
+    #[test]
+    fn report() {
+        match parse(&["report", "--trial-balance", "transactions.csv"]).unwrap() {
+            Command::Report {
+                trial_balance,
+                process,
+            } => {
+                assert!(trial_balance);
+                assert_eq!(process.settings().unwrap().inputs, vec!["transactions.csv"]);
+            }
+            _ => panic!("Expected report command"),
+        }
+        assert!(parse(&["report", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn repl() {
+        match parse(&["repl", "--load-state", "state.json"]).unwrap() {
+            Command::Repl(process) => {
+                assert_eq!(
+                    process.settings().unwrap().load_state,
+                    Some(PathBuf::from("state.json"))
+                );
+            }
+            _ => panic!("Expected repl command"),
+        }
+    }
+
+    #[test]
+    fn merge_and_verify() {
+        match parse(&["merge", "low.csv", "high.csv"]).unwrap() {
+            Command::Merge { reports } => assert_eq!(reports, vec!["low.csv", "high.csv"]),
+            _ => panic!("Expected merge command"),
+        }
+        assert!(parse(&["merge"]).is_err());
+
+        match parse(&["verify", "transactions.csv"]).unwrap() {
+            Command::Verify {
+                expected,
+                replays,
+                process,
+            } => {
+                assert_eq!(expected, None);
+                assert_eq!(replays, None);
+                assert_eq!(process.inputs, vec!["transactions.csv"]);
+            }
+            _ => panic!("Expected verify command"),
+        }
+        match parse(&["verify", "--expected", "expected.csv", "transactions.csv"]).unwrap() {
+            Command::Verify { expected, .. } => {
+                assert_eq!(expected, Some(PathBuf::from("expected.csv")))
+            }
+            _ => panic!("Expected verify command"),
+        }
+        match parse(&["verify", "--replays", "3", "transactions.csv"]).unwrap() {
+            Command::Verify { replays, .. } => assert_eq!(replays, Some(3)),
+            _ => panic!("Expected verify command"),
+        }
+        assert!(parse(&["verify", "--replays", "1", "transactions.csv"]).is_err());
+        assert!(parse(&["verify", "--replays", "9", "transactions.csv"]).is_err());
+        assert!(parse(&["verify", "--replays", "2", "--expected", "e.csv", "t.csv"]).is_err());
+    }
+
+    #[test]
+    fn reconcile() {
+        match parse(&[
+            "reconcile",
+            "--against",
+            "known-good.snap",
+            "--load-state",
+            "base.snap",
+            "backfill.csv",
+        ])
+        .unwrap()
+        {
+            Command::Reconcile { against, process } => {
+                assert_eq!(against, PathBuf::from("known-good.snap"));
+                let settings = process.settings().unwrap();
+                assert_eq!(settings.load_state, Some(PathBuf::from("base.snap")));
+                assert_eq!(settings.inputs, vec!["backfill.csv"]);
+            }
+            _ => panic!("Expected reconcile command"),
+        }
+        assert!(parse(&["reconcile", "backfill.csv"]).is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let path = std::env::temp_dir().join(format!("cli_config_{}.toml", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(
+            b"workers = 2\nrounding = \"bankers\"\npartition = \"10-19\"\nstrict = true\ninput-format = \"json\"\ndelimiter = \";\"\n",
+        )
+        .unwrap();
+        let config = path.to_string_lossy().into_owned();
+
+        let settings =
+            match parse(&["--config", &config, "--workers", "5", "transactions.csv"]).unwrap() {
+                Command::Process(args) => args.settings().unwrap(),
+                _ => panic!("Expected process command"),
+            };
+        assert_eq!(settings.engine.workers, 5);
+        assert_eq!(
+            settings.engine.output_format.rounding,
+            RoundingMode::Bankers
+        );
+        assert_eq!(settings.engine.partition, Partition::new(10, 19));
+        assert_eq!(settings.input_format, Some(InputFormat::Json));
+        assert_eq!(settings.delimiter, Some(b';'));
+        assert!(settings.strict);
+
+        std::fs::write(&path, "threads = 2\n").unwrap();
+        match parse(&["--config", &config, "transactions.csv"]).unwrap() {
+            Command::Process(args) => assert!(args.settings().is_err()),
+            _ => panic!("Expected process command"),
+        }
+    }
+
+    #[test]
+    fn multiple_inputs() {
+        let dir = std::env::temp_dir().join(format!("cli_inputs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.csv", "a.csv", "c.json"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let pattern = dir.join("*.csv").to_string_lossy().into_owned();
+
+        let settings = match parse(&["first.csv", &pattern, "-"]).unwrap() {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        assert_eq!(
+            settings.inputs,
+            vec![
+                "first.csv".to_string(),
+                dir.join("a.csv").to_string_lossy().into_owned(),
+                dir.join("b.csv").to_string_lossy().into_owned(),
+                "-".to_string(),
+            ]
+        );
+
+        let missing = dir.join("*.jsonl").to_string_lossy().into_owned();
+        assert!(expand_inputs(vec![missing]).is_err());
+        assert!(expand_inputs(vec!["-".to_string(), "-".to_string()]).is_err());
+        assert_eq!(expand_inputs(vec![]).unwrap(), vec!["-"]);
+    }
+
+    #[test]
+    fn exchange_rates() {
+        let path = std::env::temp_dir().join(format!("cli_rates_{}.csv", std::process::id()));
+        std::fs::write(&path, "from,to,rate\nEUR,USD,1.25\n").unwrap();
+        let rates = path.to_string_lossy().into_owned();
+
+        let settings = match parse(&[
+            "--rates",
+            &rates,
+            "--spread",
+            "0.01",
+            "--conversion-precision",
+            "2",
+            "--conversion-rounding",
+            "bankers",
+            "transactions.csv",
+        ])
+        .unwrap()
+        {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        let rates = settings.engine.exchange_rates.unwrap();
+        assert_eq!(rates.spread, Decimal::new(1, 2));
+        assert_eq!(rates.rounding.decimal_places, 2);
+        assert_eq!(rates.rounding.rounding, RoundingMode::Bankers);
+
+        assert!(parse(&["--spread", "1", "transactions.csv"]).is_err());
+        assert!(parse(&["--interest-rate", "-0.05", "transactions.csv"]).is_err());
+        match parse(&["--interest-rate", "0.05", "transactions.csv"]).unwrap() {
+            Command::Process(args) => assert_eq!(
+                args.settings().unwrap().engine.interest_rate,
+                Some(Decimal::new(5, 2))
+            ),
+            _ => panic!("Expected process command"),
+        }
+        assert!(parse(&["--spread", "-0.1", "transactions.csv"]).is_err());
+        match parse(&["--rates", "missing.csv", "transactions.csv"]).unwrap() {
+            Command::Process(args) => assert!(args.settings().is_err()),
+            _ => panic!("Expected process command"),
+        }
+        match parse(&["--limits", "missing.toml", "transactions.csv"]).unwrap() {
+            Command::Process(args) => assert!(args.settings().is_err()),
+            _ => panic!("Expected process command"),
+        }
+        match parse(&["--fraud-rules", "missing.toml", "transactions.csv"]).unwrap() {
+            Command::Process(args) => assert!(args.settings().is_err()),
+            _ => panic!("Expected process command"),
+        }
+    }
+
+    #[test]
+    fn overdraft_limits() {
+        let path = std::env::temp_dir().join(format!("cli_overdraft_{}.csv", std::process::id()));
+        std::fs::write(&path, "client,limit\n7,250\n").unwrap();
+        let limits = path.to_string_lossy().into_owned();
+
+        let settings = match parse(&[
+            "--overdraft-limit",
+            "100",
+            "--overdraft-limits",
+            &limits,
+            "transactions.csv",
+        ])
+        .unwrap()
+        {
+            Command::Process(args) => args.settings().unwrap(),
+            _ => panic!("Expected process command"),
+        };
+        assert_eq!(settings.engine.overdraft_limit, Money::from(100));
+        assert_eq!(settings.engine.overdraft_limits[&7], Money::from(250));
+
+        assert!(parse(&["--overdraft-limit", "-1", "transactions.csv"]).is_err());
+        std::fs::write(&path, "client,limit\n7,-1\n").unwrap();
+        match parse(&["--overdraft-limits", &limits, "transactions.csv"]).unwrap() {
+            Command::Process(args) => assert!(args.settings().is_err()),
+            _ => panic!("Expected process command"),
+        }
+    }
+}
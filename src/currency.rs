@@ -0,0 +1,67 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Code of the currency an amount is denominated in, e.g. `EUR`.
+///
+/// Codes are case insensitive and stored upper case. Transactions without a currency use the
+/// account's default currency, which is represented as `None` wherever a currency is optional.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency(String);
+
+impl Currency {
+    pub fn code(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s.len() > 8 || !s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid currency code {}", s));
+        }
+        Ok(Self(s.to_ascii_uppercase()))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        Currency::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Currency;
+
+    #[test]
+    fn parse() {
+        assert_eq!("eur".parse::<Currency>().unwrap().code(), "EUR");
+        assert_eq!(" USDC ".parse::<Currency>().unwrap().to_string(), "USDC");
+        assert!("".parse::<Currency>().is_err());
+        assert!("E-UR".parse::<Currency>().is_err());
+        assert!("TOOLONGCODE".parse::<Currency>().is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::engine::{Engine, OutcomeStatus, TransactionOutcome};
+use ratatui::backend::Backend;
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal, TerminalOptions, Viewport};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Accounts listed under the top accounts.
+const TOP_ACCOUNTS: usize = 5;
+/// Rejections listed under the recent rejections.
+const RECENT_REJECTIONS: usize = 5;
+
+/// Lines the dashboard takes up below the cursor: the boxed counters on top, the boxed top
+/// accounts, under a header, and recent rejections side by side below them.
+pub const HEIGHT: u16 = 7 + TOP_ACCOUNTS as u16 + 3;
+
+/// Live view of a long running engine, redrawn with [ratatui](https://ratatui.rs) below
+/// the cursor of a terminal, see [`Dashboard::draw`].
+///
+/// Keeps what it needs of the engine's outcomes: counts, transactions by client and the
+/// latest rejections. Queue depth and locked accounts are read from the engine per frame.
+/// Once dropped the cursor is put below the last frame.
+pub struct Dashboard<B: Backend> {
+    terminal: Terminal<B>,
+    outcomes: mpsc::UnboundedReceiver<TransactionOutcome>,
+    started: Instant,
+    accepted: u64,
+    rejected: u64,
+    /// Transactions by client, accepted or not
+    activity: HashMap<u16, u64>,
+    recent: VecDeque<TransactionOutcome>,
+    /// Time and processed transactions of the last frame, for the throughput
+    last_frame: (Instant, u64),
+}
+
+/// What a frame shows.
+struct View {
+    up: u64,
+    counters: [(&'static str, String); 5],
+    top: Vec<(u16, u64)>,
+    recent: Vec<String>,
+}
+
+impl<B: Backend> Dashboard<B> {
+    /// Dashboard of the engine from now on, drawn on `backend`, e.g. a
+    /// `CrosstermBackend` of stderr. Takes over the engine's outcomes, see
+    /// [`Engine::outcomes`]. Fails when the backend isn't a terminal.
+    pub fn new(engine: &mut Engine, backend: B) -> io::Result<Self> {
+        let terminal = Terminal::with_options(
+            backend,
+            TerminalOptions {
+                viewport: Viewport::Inline(HEIGHT),
+            },
+        )?;
+        let now = Instant::now();
+        Ok(Self {
+            terminal,
+            outcomes: engine.outcomes(),
+            started: now,
+            accepted: 0,
+            rejected: 0,
+            activity: HashMap::new(),
+            recent: VecDeque::with_capacity(RECENT_REJECTIONS),
+            last_frame: (now, 0),
+        })
+    }
+
+    /// Redraws the dashboard with the outcomes since the last frame.
+    pub async fn draw(&mut self, engine: &Engine) -> io::Result<()> {
+        while let Ok(outcome) = self.outcomes.try_recv() {
+            self.observe(outcome);
+        }
+        let view = self.view(engine.queue_depth(), engine.locked_count().await);
+        self.terminal.draw(|frame| view.render(frame))?;
+        Ok(())
+    }
+
+    fn observe(&mut self, outcome: TransactionOutcome) {
+        *self.activity.entry(outcome.client).or_default() += 1;
+        match outcome.status {
+            OutcomeStatus::Accepted => self.accepted += 1,
+            OutcomeStatus::Rejected => {
+                self.rejected += 1;
+                if self.recent.len() == RECENT_REJECTIONS {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(outcome);
+            }
+        }
+    }
+
+    fn view(&mut self, queued: usize, locked: usize) -> View {
+        let now = Instant::now();
+        let processed = self.accepted + self.rejected;
+        let (last_time, last_processed) = self.last_frame;
+        let elapsed = now.duration_since(last_time).max(Duration::from_millis(1));
+        let throughput = (processed - last_processed) as f64 / elapsed.as_secs_f64();
+        self.last_frame = (now, processed);
+
+        let mut top = self
+            .activity
+            .iter()
+            .map(|(&client, &count)| (client, count))
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(TOP_ACCOUNTS);
+        let recent = self
+            .recent
+            .iter()
+            .rev()
+            .map(|outcome| {
+                let error = outcome.error.as_ref();
+                format!(
+                    "client {} tx {}: {}",
+                    outcome.client,
+                    outcome.tx,
+                    error.map_or(String::new(), ToString::to_string)
+                )
+            })
+            .collect();
+        View {
+            up: now.duration_since(self.started).as_secs(),
+            counters: [
+                ("throughput", format!("{:.0} tx/s", throughput)),
+                ("accepted", self.accepted.to_string()),
+                ("rejected", self.rejected.to_string()),
+                ("queued", queued.to_string()),
+                ("locked accounts", locked.to_string()),
+            ],
+            top,
+            recent,
+        }
+    }
+}
+
+impl View {
+    fn render(&self, frame: &mut Frame) {
+        let [counters, lists] =
+            Layout::vertical([Constraint::Length(7), Constraint::Fill(1)]).areas(frame.area());
+        let [top, recent] =
+            Layout::horizontal([Constraint::Length(32), Constraint::Fill(1)]).areas(lists);
+
+        let lines = self
+            .counters
+            .iter()
+            .map(|(name, value)| Line::from(format!("{:<16}{}", name, value)));
+        let title = format!("transaction_system, up {}s", self.up);
+        frame.render_widget(
+            Paragraph::new(lines.collect::<Vec<_>>()).block(Block::bordered().title(title)),
+            counters,
+        );
+        let rows = self
+            .top
+            .iter()
+            .map(|(client, count)| Row::new([client.to_string(), count.to_string()]));
+        frame.render_widget(
+            Table::new(rows, [Constraint::Length(8), Constraint::Fill(1)])
+                .header(Row::new(["client", "transactions"]))
+                .block(Block::bordered().title("top accounts")),
+            top,
+        );
+        frame.render_widget(
+            List::new(self.recent.iter().map(String::as_str))
+                .block(Block::bordered().title("recent rejections")),
+            recent,
+        );
+    }
+}
+
+impl<B: Backend> Drop for Dashboard<B> {
+    fn drop(&mut self) {
+        let bottom = self.terminal.get_frame().area().bottom();
+        let _ = self.terminal.set_cursor_position(Position::new(0, bottom));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dashboard, HEIGHT};
+    use crate::{Engine, Money, Transaction, TransactionType};
+    use ratatui::backend::TestBackend;
+
+    #[tokio::test]
+    async fn draw() {
+        let mut engine = Engine::new();
+        let backend = TestBackend::new(80, HEIGHT);
+        let mut dashboard = Dashboard::new(&mut engine, backend).unwrap();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 2, 3, Some(Money::from(9))),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Chargeback, 2, 2, None),
+        ];
+        for transaction in transactions {
+            let _ = engine.process(transaction).await;
+        }
+        dashboard.draw(&engine).await.unwrap();
+        let buffer = dashboard.terminal.backend().buffer();
+        let lines = (0..buffer.area.height)
+            .map(|y| {
+                let line = (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert!(lines[0].starts_with("┌transaction_system, up 0s"));
+        assert_eq!(
+            lines[2..],
+            [
+                "│accepted        4                                                             │",
+                "│rejected        1                                                             │",
+                "│queued          0                                                             │",
+                "│locked accounts 1                                                             │",
+                "└──────────────────────────────────────────────────────────────────────────────┘",
+                "┌top accounts──────────────────┐┌recent rejections─────────────────────────────┐",
+                "│client   transactions         ││client 2 tx 3: Not enough available funds     │",
+                "│2        4                    ││                                              │",
+                "│1        1                    ││                                              │",
+                "│                              ││                                              │",
+                "│                              ││                                              │",
+                "│                              ││                                              │",
+                "└──────────────────────────────┘└──────────────────────────────────────────────┘",
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use flate2::bufread::MultiGzDecoder;
+use std::io::{self, BufRead, BufReader, Read};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Extensions of compressed inputs, left out when the input format is detected.
+pub const COMPRESSED_EXTENSIONS: [&str; 2] = ["gz", "zst"];
+
+/// Input decompressed on the fly if it starts with the magic bytes of gzip or zstd, as it
+/// is otherwise. Concatenated gzip members and zstd frames make up a single input.
+pub fn decompressed(input: impl Read + Send + 'static) -> io::Result<Box<dyn Read>> {
+    let mut input = BufReader::new(input);
+    let start = input.fill_buf()?;
+    if start.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(input)))
+    } else if start.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::with_buffer(input)?))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompressed;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Read, Write};
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn decompress(input: Vec<u8>) -> std::io::Result<String> {
+        let mut output = String::new();
+        decompressed(std::io::Cursor::new(input))?.read_to_string(&mut output)?;
+        Ok(output)
+    }
+
+    const ROWS: &str = "type,client,tx,amount\ndeposit,2,1,1.5\ndeposit,3,2,2.5\n\
+        deposit,1,3,3.5\ndeposit,2,4,4.5\ndeposit,3,5,5.5\ndeposit,1,6,6.5\ndeposit,2,7,7.5\n\
+        deposit,3,8,8.5\n";
+    /// `ROWS` compressed into a block with dynamic codes
+    const DYNAMIC: &str = "1f8b080000000000020355ca4b0a80300c45d1b96b79084dbfdb11cda050db\
+        821174f77624cdf05caebc9db197cc55200fb6b3dd5596837bbbb280606056ffdb8e4293cd287632c1c1a9df\
+        c3ab3f20a83f22aa3f210d7f92e75b9c96000000";
+    const ROW: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+    /// `ROW` compressed into a block with fixed codes
+    const FIXED: &str = "1f8b08000000000002032ba92c48d549cec94ccd2bd129a9d049cccd2fcd2be14a\
+        492dc82fce2cd13104413d032e00b1c3c1d326000000";
+    /// `ROW` stored as it is
+    const STORED: &str = "1f8b0800000000000403012600d9ff747970652c636c69656e742c74782c616d\
+        6f756e740a6465706f7369742c312c312c312e300ab1c3c1d326000000";
+
+    #[test]
+    fn gzip() {
+        assert_eq!(decompress(bytes(DYNAMIC)).unwrap(), ROWS);
+        let fixed = bytes(FIXED);
+        assert_eq!(decompress(fixed.clone()).unwrap(), ROW);
+        assert_eq!(decompress(bytes(STORED)).unwrap(), ROW);
+        // Members one after another make up a single input
+        let members = [fixed.clone(), fixed.clone()].concat();
+        assert_eq!(decompress(members).unwrap(), [ROW, ROW].concat());
+        // Plain input passes as it is
+        assert_eq!(decompress(ROW.as_bytes().to_vec()).unwrap(), ROW);
+
+        let mut corrupt = fixed.clone();
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 1;
+        assert!(decompress(corrupt).is_err());
+        assert!(decompress(fixed[..fixed.len() - 10].to_vec()).is_err());
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(ROWS.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress(compressed.clone()).unwrap(), ROWS);
+        let members = [compressed.clone(), compressed].concat();
+        assert_eq!(decompress(members).unwrap(), [ROWS, ROWS].concat());
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        let compressed = zstd::encode_all(ROWS.as_bytes(), 3).unwrap();
+        assert_eq!(decompress(compressed.clone()).unwrap(), ROWS);
+        let frames = [compressed.clone(), compressed.clone()].concat();
+        assert_eq!(decompress(frames).unwrap(), [ROWS, ROWS].concat());
+
+        let mut corrupt = compressed.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        assert!(decompress(corrupt).is_err());
+        assert!(decompress(compressed[..compressed.len() - 4].to_vec()).is_err());
+    }
+}
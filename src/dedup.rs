@@ -0,0 +1,297 @@
+use memmap2::MmapMut;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use std::f64::consts::LN_2;
+use std::io;
+use std::path::PathBuf;
+
+/// Duplicate detection for streams with too many transaction ids to keep in memory.
+///
+/// Ids go through a Bloom filter sized for `expected_ids` at the given false positive rate.
+/// Ids the filter hasn't seen are new for sure and are only added to the exact set of ids,
+/// a bitmap of every possible id in a file mapped into memory, in sorted batches. Only ids
+/// the filter has probably seen are looked up in the bitmap, so its pages only have to be
+/// resident for probable hits and while a batch is added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdFilter {
+    pub expected_ids: usize,
+    pub false_positive_rate: Decimal,
+    /// Directory of the sparse 512 MiB files holding the exact sets of ids, unnamed
+    /// temporary files of every worker's own that are gone once they are dropped
+    pub bitmap_dir: PathBuf,
+}
+
+/// Bloom filter over transaction ids.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Filter sized for `expected` ids with at most the given false positive rate.
+    pub fn new(expected: usize, false_positive_rate: f64) -> Self {
+        let expected = expected.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-12, 0.5);
+        let bits = (-expected * rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as usize;
+        let hashes = (bits as f64 / expected * LN_2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+        }
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        positions(self.bits.len(), self.hashes, id)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Adds the id, returning whether it was probably added before.
+    pub fn insert(&mut self, id: u32) -> bool {
+        if self.contains(id) {
+            return true;
+        }
+        for bit in positions(self.bits.len(), self.hashes, id) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        false
+    }
+}
+
+/// Bits of a filter of `words` 64 bit words the id sets, by double hashing.
+fn positions(words: usize, hashes: u32, id: u32) -> impl Iterator<Item = usize> {
+    let len = words as u64 * 64;
+    let first = mix(u64::from(id));
+    let step = mix(u64::from(id) ^ 0x9e37_79b9_7f4a_7c15) | 1;
+    (0..u64::from(hashes)).map(move |i| (first.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+}
+
+/// SplitMix64 finalizer, spreading consecutive ids over the whole filter.
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Bytes of a bitmap with a bit for every possible id, 512 MiB.
+const BITMAP_BYTES: usize = 1 << 29;
+
+/// Bytes of the blocks of the bitmap [`IdBitmap::ids`] skips unless an id was set in them.
+const BLOCK_BYTES: usize = 4096;
+
+/// New ids held back before they are added to the bitmap together, in ascending order.
+const PENDING_IDS: usize = 1 << 16;
+
+/// Exact set of ids, a bit for every possible id in a sparse temporary file in `dir` mapped
+/// into memory and created on the first insert. Ids are looked up and added in place, and the OS only keeps
+/// the pages of the bitmap in use resident, writing them back to the file under memory
+/// pressure.
+#[derive(Debug)]
+pub(crate) struct IdBitmap {
+    dir: PathBuf,
+    map: Option<MmapMut>,
+    /// Bit of every block holding at least one id
+    blocks: Vec<u64>,
+}
+
+impl IdBitmap {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            map: None,
+            blocks: vec![0; (BITMAP_BYTES / BLOCK_BYTES).div_ceil(64)],
+        }
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        let (byte, bit) = (id as usize / 8, id % 8);
+        self.map
+            .as_ref()
+            .is_some_and(|map| map[byte] & (1 << bit) != 0)
+    }
+
+    fn insert(&mut self, id: u32) -> io::Result<()> {
+        let map = match &mut self.map {
+            Some(map) => map,
+            None => {
+                let file = tempfile::tempfile_in(&self.dir)?;
+                file.set_len(BITMAP_BYTES as u64)?;
+                // SAFETY: the file is unlinked as it is created, so no other file handle
+                // truncates or writes it while it is mapped; only another process of the
+                // same user going through /proc could
+                self.map.insert(unsafe { MmapMut::map_mut(&file)? })
+            }
+        };
+        let (byte, bit) = (id as usize / 8, id % 8);
+        map[byte] |= 1 << bit;
+        let block = byte / BLOCK_BYTES;
+        self.blocks[block / 64] |= 1 << (block % 64);
+        Ok(())
+    }
+
+    /// Every id in the bitmap, in ascending order, reading only the blocks holding any.
+    fn ids(&self) -> Vec<u32> {
+        let Some(map) = &self.map else {
+            return Vec::new();
+        };
+        let mut ids = Vec::new();
+        for block in bits(&self.blocks) {
+            let start = block as usize * BLOCK_BYTES;
+            for (offset, &byte) in map[start..start + BLOCK_BYTES].iter().enumerate() {
+                let first = ((start + offset) * 8) as u32;
+                ids.extend(
+                    (0..8)
+                        .filter(|bit| byte & (1 << bit) != 0)
+                        .map(|bit| first + bit),
+                );
+            }
+        }
+        ids
+    }
+}
+
+/// Positions of the set bits of `words`, in ascending order.
+fn bits(words: &[u64]) -> impl Iterator<Item = u32> + '_ {
+    words.iter().enumerate().flat_map(|(index, &word)| {
+        (0..64)
+            .filter(move |bit| word & (1 << bit) != 0)
+            .map(move |bit| index as u32 * 64 + bit)
+    })
+}
+
+/// Transaction ids the engine has seen, exactly in memory or behind an [`IdFilter`].
+#[derive(Debug)]
+pub(crate) enum TransactionIds {
+    Exact(HashSet<u32>),
+    Filtered {
+        filter: BloomFilter,
+        /// New ids not yet in the bitmap
+        pending: HashSet<u32>,
+        bitmap: IdBitmap,
+    },
+}
+
+impl Default for TransactionIds {
+    fn default() -> Self {
+        Self::Exact(HashSet::new())
+    }
+}
+
+impl TransactionIds {
+    pub(crate) fn new(filter: Option<&IdFilter>) -> Self {
+        match filter {
+            Some(filter) => Self::Filtered {
+                filter: BloomFilter::new(
+                    filter.expected_ids,
+                    filter.false_positive_rate.to_f64().unwrap_or(0.01),
+                ),
+                pending: HashSet::new(),
+                bitmap: IdBitmap::new(filter.bitmap_dir.clone()),
+            },
+            None => Self::default(),
+        }
+    }
+
+    /// Adds the id, returning whether it is new.
+    pub(crate) fn insert(&mut self, id: u32) -> io::Result<bool> {
+        match self {
+            Self::Exact(ids) => Ok(ids.insert(id)),
+            Self::Filtered {
+                filter,
+                pending,
+                bitmap,
+            } => {
+                if filter.insert(id) && (pending.contains(&id) || bitmap.contains(id)) {
+                    return Ok(false);
+                }
+                pending.insert(id);
+                if pending.len() >= PENDING_IDS {
+                    let mut ids = pending.drain().collect::<Vec<_>>();
+                    ids.sort_unstable();
+                    for id in ids {
+                        bitmap.insert(id)?;
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    pub(crate) fn extend(&mut self, ids: Vec<u32>) -> io::Result<()> {
+        for id in ids {
+            self.insert(id)?;
+        }
+        Ok(())
+    }
+
+    /// Every id seen so far, in ascending order.
+    pub(crate) fn sorted(&self) -> Vec<u32> {
+        match self {
+            Self::Exact(ids) => {
+                let mut ids = ids.iter().copied().collect::<Vec<_>>();
+                ids.sort_unstable();
+                ids
+            }
+            Self::Filtered {
+                pending, bitmap, ..
+            } => {
+                let mut ids = bitmap.ids();
+                ids.extend(pending);
+                ids.sort_unstable();
+                ids
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, IdFilter, TransactionIds, PENDING_IDS};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn bloom_filter() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for id in 0..10_000 {
+            filter.insert(id * 7);
+        }
+        assert!((0..10_000).all(|id| filter.contains(id * 7)));
+        let false_positives = (0..10_000).filter(|id| filter.contains(id * 7 + 1)).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn filtered_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        // A filter far too small for its ids, so nearly every id is a probable hit
+        let filter = IdFilter {
+            expected_ids: 1,
+            false_positive_rate: Decimal::new(5, 1),
+            bitmap_dir: dir.path().to_path_buf(),
+        };
+        let mut ids = TransactionIds::new(Some(&filter));
+        for id in [5, 1, 9] {
+            assert!(ids.insert(id).unwrap());
+        }
+        assert!(!ids.insert(1).unwrap());
+        assert!(ids.insert(2).unwrap());
+        ids.extend(vec![9, 12, u32::MAX, 70_000]).unwrap();
+        assert!(!ids.insert(u32::MAX).unwrap());
+        assert_eq!(ids.sorted(), [1, 2, 5, 9, 12, 70_000, u32::MAX]);
+
+        // Enough ids for the pending ones to go into the bitmap, one of its own per worker
+        let mut other = TransactionIds::new(Some(&filter));
+        assert!(other.insert(1).unwrap());
+        for id in 100..100 + PENDING_IDS as u32 {
+            assert!(ids.insert(id).unwrap());
+        }
+        assert!(!ids.insert(5).unwrap());
+        assert!(!ids.insert(100).unwrap());
+        assert!(!other.insert(1).unwrap());
+        assert!(other.insert(5).unwrap());
+        assert_eq!(ids.sorted().len(), 7 + PENDING_IDS);
+        // The bitmaps leave nothing behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
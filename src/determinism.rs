@@ -0,0 +1,234 @@
+use crate::diff::{diff_reports, Discrepancy};
+use crate::engine::{Engine, EngineConfig, OutcomeStatus, TransactionOutcome};
+use crate::output::ReportFormat;
+use crate::transaction::Transaction;
+use std::error::Error;
+use std::fmt;
+
+/// Most runs [`check_determinism`] makes, the last of them with 128 workers.
+pub const MAX_REPLAYS: u32 = 8;
+
+/// First difference between two runs over the same transactions with different numbers of
+/// workers, see [`check_determinism`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub client: u16,
+    /// Transaction decided differently, none when only the balances differ
+    pub tx: Option<u32>,
+    /// Line of the input the transaction was read from
+    pub row: Option<u64>,
+    /// Workers of the first run and of the run that diverged from it
+    pub workers: (usize, usize),
+    /// What each of the two runs made of the transaction or balance
+    pub first: String,
+    pub second: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "client {}", self.client)?;
+        if let Some(tx) = self.tx {
+            write!(f, ", tx {}", tx)?;
+        }
+        if let Some(row) = self.row {
+            write!(f, ", row {}", row)?;
+        }
+        let workers = |n: usize| match n {
+            1 => "1 worker".to_string(),
+            n => format!("{} workers", n),
+        };
+        write!(
+            f,
+            ": {} with {} but {} with {}",
+            self.first,
+            workers(self.workers.0),
+            self.second,
+            workers(self.workers.1)
+        )
+    }
+}
+
+fn describe(outcome: Option<&TransactionOutcome>) -> String {
+    match outcome {
+        None => "no outcome".to_string(),
+        Some(TransactionOutcome {
+            error: Some(error), ..
+        }) => format!("rejected ({})", error),
+        Some(outcome) => match outcome.status {
+            OutcomeStatus::Accepted => "accepted".to_string(),
+            OutcomeStatus::Rejected => "rejected".to_string(),
+        },
+    }
+}
+
+/// Outcomes in input order, as workers of different clients decide in any order.
+fn sort(outcomes: &mut [TransactionOutcome]) {
+    outcomes.sort_by_key(|outcome| {
+        (
+            outcome.row,
+            outcome.client,
+            outcome.tx,
+            outcome.status == OutcomeStatus::Accepted,
+            outcome.error.as_ref().map(|error| error.code()),
+        )
+    });
+}
+
+/// Account report and outcomes of processing the transactions with `workers` workers.
+async fn run(
+    transactions: &[Transaction],
+    config: &EngineConfig,
+    workers: usize,
+) -> Result<(Vec<u8>, Vec<TransactionOutcome>), Box<dyn Error>> {
+    let mut engine = Engine::with_config(EngineConfig {
+        workers,
+        report_format: ReportFormat::Csv,
+        ..config.clone()
+    });
+    let mut receiver = engine.outcomes();
+    for transaction in transactions {
+        engine.submit(transaction.clone()).await?;
+    }
+    let mut report = Vec::new();
+    engine.write_report(&mut report).await?;
+    drop(engine);
+    let mut outcomes = Vec::new();
+    while let Some(outcome) = receiver.recv().await {
+        outcomes.push(outcome);
+    }
+    sort(&mut outcomes);
+    Ok((report, outcomes))
+}
+
+/// Processes the transactions `runs` times, at most [`MAX_REPLAYS`], with 1, 2, 4 and so on
+/// workers, and compares every run with the first: each transaction must be decided the
+/// same way and the account reports must match. Returns the first transaction, in input order, or else the
+/// first account the runs disagree on.
+///
+/// Every client is pinned to a worker, so however many there are the result must be the
+/// same; a divergence means the order transactions are applied in leaks between workers.
+pub async fn check_determinism(
+    transactions: &[Transaction],
+    config: &EngineConfig,
+    runs: u32,
+) -> Result<Option<Divergence>, Box<dyn Error>> {
+    let (report, outcomes) = run(transactions, config, 1).await?;
+    for doublings in 1..runs.min(MAX_REPLAYS) {
+        let workers = 1 << doublings;
+        let (other_report, other_outcomes) = run(transactions, config, workers).await?;
+        let len = outcomes.len().max(other_outcomes.len());
+        for i in 0..len {
+            let (first, second) = (outcomes.get(i), other_outcomes.get(i));
+            if first == second {
+                continue;
+            }
+            let outcome = first.or(second).expect("Index within the longer run");
+            return Ok(Some(Divergence {
+                client: outcome.client,
+                tx: Some(outcome.tx),
+                row: outcome.row,
+                workers: (1, workers),
+                first: describe(first),
+                second: describe(second),
+            }));
+        }
+
+        let discrepancies = diff_reports(report.as_slice(), other_report.as_slice())?;
+        if let Some(discrepancy) = discrepancies.into_iter().next() {
+            let (client, first, second) = match discrepancy {
+                Discrepancy::Missing { client, .. } => {
+                    (client, "reported".to_string(), "missing".to_string())
+                }
+                Discrepancy::Unexpected { client, .. } => {
+                    (client, "missing".to_string(), "reported".to_string())
+                }
+                Discrepancy::Mismatch {
+                    client,
+                    column,
+                    expected,
+                    actual,
+                    ..
+                } => (
+                    client,
+                    format!("{} {}", column, expected),
+                    format!("{} {}", column, actual),
+                ),
+            };
+            return Ok(Some(Divergence {
+                client,
+                tx: None,
+                row: None,
+                workers: (1, workers),
+                first,
+                second,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_determinism, Divergence};
+    use crate::reader::{deserialize_csv, ReadOptions};
+    use crate::workload::Workload;
+    use crate::{EngineConfig, Money, Transaction, TransactionType};
+    use rust_decimal::Decimal;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn deterministic() {
+        let workload = Workload {
+            clients: 50,
+            transactions: 3_000,
+            dispute_rate: Decimal::new(1, 1),
+            invalid_rate: Decimal::new(5, 2),
+            seed: 9,
+        };
+        let mut csv = Vec::new();
+        workload.write_csv(&mut csv).unwrap();
+        let (sender, mut receiver) = mpsc::channel(64);
+        let read = std::thread::spawn(move || {
+            deserialize_csv(csv.as_slice(), ReadOptions::default(), sender)
+        });
+        let mut transactions = Vec::new();
+        while let Some(transaction) = receiver.recv().await {
+            transactions.push(transaction);
+        }
+        read.join().unwrap().unwrap();
+        let config = EngineConfig::default();
+        assert_eq!(
+            check_determinism(&transactions, &config, 4).await.unwrap(),
+            None
+        );
+
+        // Transfers see every earlier transaction of both clients, whichever workers they
+        // are on
+        let deposit = Transaction::new(TransactionType::Deposit, 2, 1, Some(Money::from(5)));
+        let mut transfer = Transaction::new(TransactionType::Transfer, 2, 2, Some(Money::from(5)));
+        transfer.to_client = Some(1);
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(5)));
+        let transactions = [deposit, transfer, withdrawal];
+        assert_eq!(
+            check_determinism(&transactions, &config, 3).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn display() {
+        let divergence = Divergence {
+            client: 3,
+            tx: Some(17),
+            row: Some(18),
+            workers: (1, 4),
+            first: "accepted".to_string(),
+            second: "rejected (Insufficient funds)".to_string(),
+        };
+        assert_eq!(
+            divergence.to_string(),
+            "client 3, tx 17, row 18: accepted with 1 worker but rejected (Insufficient \
+             funds) with 4 workers"
+        );
+    }
+}
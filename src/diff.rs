@@ -0,0 +1,196 @@
+use crate::partition::PARTITION_COLUMN;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+const CLIENT_COLUMN: &str = "client";
+const CURRENCY_COLUMN: &str = "currency";
+
+/// Difference between an account report and the balances it was expected to show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// Expected account, or currency of an account, the report doesn't show
+    Missing {
+        client: u16,
+        currency: Option<String>,
+    },
+    /// Account, or currency of an account, the report shows but wasn't expected
+    Unexpected {
+        client: u16,
+        currency: Option<String>,
+    },
+    /// Column of an account whose value isn't the expected one
+    Mismatch {
+        client: u16,
+        currency: Option<String>,
+        column: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+fn write_account(f: &mut fmt::Formatter, client: u16, currency: &Option<String>) -> fmt::Result {
+    write!(f, "client {}", client)?;
+    match currency {
+        Some(currency) if !currency.is_empty() => write!(f, " ({})", currency),
+        _ => Ok(()),
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::Missing { client, currency } => {
+                write_account(f, *client, currency)?;
+                write!(f, ": expected but not in the report")
+            }
+            Discrepancy::Unexpected { client, currency } => {
+                write_account(f, *client, currency)?;
+                write!(f, ": in the report but not expected")
+            }
+            Discrepancy::Mismatch {
+                client,
+                currency,
+                column,
+                expected,
+                actual,
+            } => {
+                write_account(f, *client, currency)?;
+                write!(f, ": {} expected {} but was {}", column, expected, actual)
+            }
+        }
+    }
+}
+
+/// Rows of a csv account report by client and currency, each row as its columns' values.
+type Rows = BTreeMap<(u16, Option<String>), BTreeMap<String, String>>;
+
+fn read_rows(reader: impl io::Read, name: &str) -> Result<(Vec<String>, Rows), Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader
+        .headers()?
+        .iter()
+        .filter(|header| *header != PARTITION_COLUMN)
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if !headers.iter().any(|header| header == CLIENT_COLUMN) {
+        return Err(format!("{} has no client column", name).into());
+    }
+    let mut rows = Rows::new();
+    for record in reader.deserialize::<BTreeMap<String, String>>() {
+        let mut row = record?;
+        row.remove(PARTITION_COLUMN);
+        let client = row.remove(CLIENT_COLUMN).unwrap_or_default();
+        let client = client
+            .parse::<u16>()
+            .map_err(|_| format!("{} has an invalid client {}", name, client))?;
+        // Reports without a currency column only hold the default currency
+        let currency = row
+            .remove(CURRENCY_COLUMN)
+            .filter(|currency| !currency.is_empty());
+        if rows.insert((client, currency), row).is_some() {
+            return Err(format!("{} lists client {} more than once", name, client).into());
+        }
+    }
+    Ok((headers, rows))
+}
+
+/// Equal numbers, whatever their precision, or equal text, ignoring case.
+fn same_value(expected: &str, actual: &str) -> bool {
+    match (expected.parse::<Decimal>(), actual.parse::<Decimal>()) {
+        (Ok(expected), Ok(actual)) => expected == actual,
+        _ => expected.eq_ignore_ascii_case(actual),
+    }
+}
+
+/// Compares a csv account report with a csv of the balances it should show, account by
+/// account. Only the columns of the expected balances are compared, so they may leave out
+/// any but `client`, and a `partition` column in either is ignored.
+///
+/// Fails when either isn't an account report or the expected balances have a column the
+/// report lacks.
+pub fn diff_reports(
+    expected: impl io::Read,
+    actual: impl io::Read,
+) -> Result<Vec<Discrepancy>, Box<dyn Error>> {
+    let (columns, expected) = read_rows(expected, "Expected balances")?;
+    let (report_columns, mut actual) = read_rows(actual, "Account report")?;
+    if let Some(column) = columns.iter().find(|c| !report_columns.contains(c)) {
+        return Err(format!("The account report has no {} column", column).into());
+    }
+
+    let mut discrepancies = Vec::new();
+    for ((client, currency), expected) in expected {
+        let Some(mut actual) = actual.remove(&(client, currency.clone())) else {
+            discrepancies.push(Discrepancy::Missing { client, currency });
+            continue;
+        };
+        for (column, expected) in expected {
+            let actual = actual.remove(&column).unwrap_or_default();
+            if !same_value(&expected, &actual) {
+                discrepancies.push(Discrepancy::Mismatch {
+                    client,
+                    currency: currency.clone(),
+                    column,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    discrepancies.extend(
+        actual
+            .into_keys()
+            .map(|(client, currency)| Discrepancy::Unexpected { client, currency }),
+    );
+    Ok(discrepancies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_reports, Discrepancy};
+
+    #[test]
+    fn discrepancies() {
+        let report = "client,available,held,total,locked\n\
+                      1,1.5000,0.0000,1.5000,false\n\
+                      2,0.0000,2.0000,2.0000,true\n\
+                      3,1.0000,0.0000,1.0000,false\n";
+        let expected = "client,available,locked\n1,1.5,false\n2,0,false\n4,0,false\n";
+        let discrepancies = diff_reports(expected.as_bytes(), report.as_bytes()).unwrap();
+        assert_eq!(
+            discrepancies
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "client 2: locked expected false but was true",
+                "client 4: expected but not in the report",
+                "client 3: in the report but not expected",
+            ]
+        );
+        assert!(diff_reports(report.as_bytes(), report.as_bytes())
+            .unwrap()
+            .is_empty());
+
+        let multi_currency = "partition,client,currency,total\n0-9,1,,1.5\n0-9,1,EUR,2\n";
+        let expected = "client,currency,total\n1,,1.5\n1,EUR,3\n";
+        assert_eq!(
+            diff_reports(expected.as_bytes(), multi_currency.as_bytes()).unwrap(),
+            vec![Discrepancy::Mismatch {
+                client: 1,
+                currency: Some("EUR".into()),
+                column: "total".into(),
+                expected: "3".into(),
+                actual: "2".into(),
+            }]
+        );
+
+        assert!(diff_reports("client,frozen\n1,no\n".as_bytes(), report.as_bytes()).is_err());
+        assert!(diff_reports("total\n1\n".as_bytes(), report.as_bytes()).is_err());
+    }
+}
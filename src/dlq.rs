@@ -0,0 +1,188 @@
+use crate::account::ErrorKind;
+use crate::currency::Currency;
+use crate::encryption::{new_stream, seal_line, Cipher, StreamId};
+use crate::money::Money;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionType};
+use serde::{Serialize, Serializer};
+use std::io;
+use std::path::Path;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+/// Rejected transaction as it was read, together with the error it was rejected for.
+///
+/// Serialized as a JSON input row with `error_code` and `error` fields added, so a fixed
+/// dead letter file can be processed again like any other JSON lines input.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub transaction: Transaction,
+    pub error: ErrorKind,
+}
+
+/// Columns of an input row, named the way inputs name them.
+#[derive(Serialize)]
+struct Row<'a> {
+    #[serde(rename = "type")]
+    transaction_type: &'a TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<Money>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_client: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_currency: Option<&'a Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
+    /// Line of the input the transaction was read from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row: Option<u64>,
+    error_code: u16,
+    error: String,
+}
+
+impl Serialize for DeadLetter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let t = &self.transaction;
+        Row {
+            transaction_type: &t.transaction_type,
+            client: t.client,
+            tx: t.tx,
+            amount: t.amount,
+            currency: t.currency.as_ref(),
+            to_client: t.to_client,
+            to_currency: t.to_currency.as_ref(),
+            reason: t.reason.as_deref(),
+            expires_at: t.expires_at,
+            timestamp: t.timestamp,
+            row: t.row,
+            error_code: self.error.code(),
+            error: self.error.reason().to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// File dead letters are appended to, one JSON line each, encrypted if there is a cipher.
+pub struct DeadLetterFile {
+    writer: BufWriter<tokio::fs::File>,
+    cipher: Option<Cipher>,
+    stream: StreamId,
+}
+
+impl DeadLetterFile {
+    /// Creates the file at `path`, replacing the dead letters of an earlier run.
+    pub async fn create(path: impl AsRef<Path>, cipher: Option<Cipher>) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(tokio::fs::File::create(path).await?),
+            cipher,
+            stream: new_stream(),
+        })
+    }
+
+    /// Writes dead letters until the [`Engine`](crate::Engine) sending them is dropped.
+    pub async fn run(mut self, mut letters: mpsc::UnboundedReceiver<DeadLetter>) -> io::Result<()> {
+        let mut sequence = 0;
+        while let Some(letter) = letters.recv().await {
+            let entry = serde_json::to_vec(&letter)?;
+            let mut line = seal_line(self.cipher.as_ref(), &self.stream, sequence, entry);
+            sequence += 1;
+            line.push(b'\n');
+            self.writer.write_all(&line).await?;
+            if letters.is_empty() {
+                self.writer.flush().await?;
+            }
+        }
+        self.writer.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeadLetterFile;
+    use crate::encryption::{decrypt_file, Cipher};
+    use crate::reader::{deserialize_file, ReadOptions};
+    use crate::{Engine, Money, Transaction, TransactionType};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn resubmit() {
+        let path = std::env::temp_dir().join(format!("dead_letters_{}.jsonl", std::process::id()));
+        let mut engine = Engine::new();
+        let written = tokio::spawn(
+            DeadLetterFile::create(&path, None)
+                .await
+                .unwrap()
+                .run(engine.dead_letters()),
+        );
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(9))).with_row(3),
+            Transaction::new(TransactionType::Dispute, 1, 7, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        engine.wait().await;
+        drop(engine);
+        written.await.unwrap().unwrap();
+
+        let letters = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            letters.lines().collect::<Vec<_>>(),
+            [
+                r#"{"type":"withdrawal","client":1,"tx":2,"amount":"9","row":3,"error_code":304,"error":"insufficient_amount"}"#,
+                r#"{"type":"dispute","client":1,"tx":7,"error_code":400,"error":"invalid_dispute_target"}"#,
+            ]
+        );
+
+        // Dead letters read back as the transactions they were
+        let (sender, mut receiver) = mpsc::channel(8);
+        let path_string = path.to_str().unwrap().to_string();
+        let read = std::thread::spawn(move || {
+            deserialize_file(path_string, ReadOptions::default(), sender)
+        });
+        let mut resubmitted = Vec::new();
+        while let Some(transaction) = receiver.recv().await {
+            resubmitted.push((transaction.tx, transaction.amount));
+        }
+        assert_eq!(read.join().unwrap().unwrap().rows, 2);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resubmitted, [(2, Some(Money::from(9))), (7, None)]);
+    }
+
+    #[tokio::test]
+    async fn encrypted() {
+        let path = std::env::temp_dir().join(format!("dead_letters_{}.enc", std::process::id()));
+        let cipher = Cipher::new(&[3; 32]);
+        let mut engine = Engine::new();
+        let written = tokio::spawn(
+            DeadLetterFile::create(&path, Some(cipher.clone()))
+                .await
+                .unwrap()
+                .run(engine.dead_letters()),
+        );
+        let transaction = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(9)));
+        engine.submit(transaction).await.unwrap();
+        engine.wait().await;
+        drop(engine);
+        written.await.unwrap().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!file.windows(10).any(|window| window == b"withdrawal"));
+        let letters = String::from_utf8(decrypt_file(&cipher, file).unwrap()).unwrap();
+        assert!(letters.starts_with(r#"{"type":"withdrawal","client":1,"tx":2,"#));
+        assert_eq!(letters.lines().count(), 1);
+    }
+}
@@ -0,0 +1,687 @@
+use crate::account::Account;
+use crate::currency::Currency;
+use crate::events::AccountEvent;
+use crate::money::{Money, MoneyFormat};
+use crate::transaction::TransactionType;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Account of the books postings go to. Client funds are what the bank owes its clients,
+/// the clearing account the money that came in and went out through payment networks. The
+/// other accounts are the offsets of money the bank itself moves in and out of client funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAccount {
+    /// Funds the client can use
+    Available(u16),
+    /// Funds of the client on hold while a transaction is disputed or authorized
+    Held(u16),
+    Clearing,
+    /// Withdrawals disputed or charged back, which the bank has to make good
+    ChargebackLoss,
+    /// Fees charged to clients
+    Fees,
+    /// Interest paid to clients
+    Interest,
+    /// Currencies bought and sold by conversions
+    Fx,
+    /// Transfers between clients, netting to zero once both sides are posted
+    Transfer,
+    /// Manual corrections of client funds
+    Adjustments,
+}
+
+impl LedgerAccount {
+    pub fn client(&self) -> Option<u16> {
+        match self {
+            LedgerAccount::Available(client) | LedgerAccount::Held(client) => Some(*client),
+            _ => None,
+        }
+    }
+
+    /// Name of the account, leaving out the client.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LedgerAccount::Available(_) => "available",
+            LedgerAccount::Held(_) => "held",
+            LedgerAccount::Clearing => "clearing",
+            LedgerAccount::ChargebackLoss => "chargeback_loss",
+            LedgerAccount::Fees => "fees",
+            LedgerAccount::Interest => "interest",
+            LedgerAccount::Fx => "fx",
+            LedgerAccount::Transfer => "transfer",
+            LedgerAccount::Adjustments => "adjustments",
+        }
+    }
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.client() {
+            Some(client) => write!(f, "{}:{}", self.name(), client),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+/// Line of the books. Credits increase client funds and debits decrease them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    /// `None` for maintenance fees and interest, which no transaction caused
+    pub tx: Option<u32>,
+    pub account: LedgerAccount,
+    pub side: Side,
+    /// `None` for the default currency
+    pub currency: Option<Currency>,
+    pub amount: Money,
+}
+
+impl Posting {
+    /// Change the posting makes to the funds of a client account.
+    pub fn signed_amount(&self) -> Money {
+        match self.side {
+            Side::Credit => self.amount,
+            Side::Debit => -self.amount,
+        }
+    }
+
+    /// Posting taking this one back, on the other side of the same account.
+    pub fn reversed(self) -> Self {
+        let side = match self.side {
+            Side::Debit => Side::Credit,
+            Side::Credit => Side::Debit,
+        };
+        Self { side, ..self }
+    }
+}
+
+/// Postings of an event of the client's account, each debit followed by its credit. Every
+/// event moving funds debits one account and credits another by the same amount, locking and
+/// closing move none.
+pub fn postings(client: u16, event: &AccountEvent) -> Vec<Posting> {
+    use LedgerAccount::{
+        Adjustments, Available, ChargebackLoss, Clearing, Fees, Fx, Held, Interest, Transfer,
+    };
+    // Amount moved from the first account to the second, in the currency
+    let transfers: Vec<(LedgerAccount, LedgerAccount, Option<Currency>, Money)> = match event {
+        AccountEvent::Deposited {
+            currency, amount, ..
+        } => vec![(Clearing, Available(client), currency.clone(), *amount)],
+        AccountEvent::Withdrawn {
+            currency, amount, ..
+        } => vec![(Available(client), Clearing, currency.clone(), *amount)],
+        AccountEvent::DisputeOpened {
+            disputed,
+            currency,
+            amount,
+            ..
+        } => match disputed {
+            TransactionType::Deposit => {
+                vec![(Available(client), Held(client), currency.clone(), *amount)]
+            }
+            _ => vec![(ChargebackLoss, Held(client), currency.clone(), *amount)],
+        },
+        AccountEvent::Resolved {
+            disputed,
+            currency,
+            amount,
+            ..
+        } => match disputed {
+            TransactionType::Deposit => {
+                vec![(Held(client), Available(client), currency.clone(), *amount)]
+            }
+            _ => vec![(Held(client), ChargebackLoss, currency.clone(), *amount)],
+        },
+        AccountEvent::ChargedBack {
+            disputed,
+            currency,
+            amount,
+            ..
+        } => match disputed {
+            TransactionType::Deposit => vec![(Held(client), Clearing, currency.clone(), *amount)],
+            _ => vec![(Held(client), Available(client), currency.clone(), *amount)],
+        },
+        AccountEvent::Represented {
+            disputed,
+            currency,
+            amount,
+            ..
+        } => match disputed {
+            TransactionType::Deposit => {
+                vec![(Clearing, Available(client), currency.clone(), *amount)]
+            }
+            _ => vec![(Available(client), ChargebackLoss, currency.clone(), *amount)],
+        },
+        AccountEvent::Refunded {
+            currency, amount, ..
+        } => vec![(Available(client), Clearing, currency.clone(), *amount)],
+        AccountEvent::Converted {
+            from,
+            to,
+            amount,
+            converted,
+            ..
+        } => vec![
+            (Available(client), Fx, Some(from.clone()), *amount),
+            (Fx, Available(client), Some(to.clone()), *converted),
+        ],
+        AccountEvent::TransferredOut {
+            currency, amount, ..
+        } => vec![(Available(client), Transfer, currency.clone(), *amount)],
+        AccountEvent::TransferredIn {
+            currency, amount, ..
+        }
+        | AccountEvent::TransferReturned {
+            currency, amount, ..
+        } => vec![(Transfer, Available(client), currency.clone(), *amount)],
+        AccountEvent::Authorized {
+            currency, amount, ..
+        } => vec![(Available(client), Held(client), currency.clone(), *amount)],
+        AccountEvent::Captured {
+            currency,
+            amount,
+            released,
+            ..
+        } => vec![
+            (Held(client), Clearing, currency.clone(), *amount),
+            (Held(client), Available(client), currency.clone(), *released),
+        ],
+        AccountEvent::Voided {
+            currency, amount, ..
+        }
+        | AccountEvent::AuthorizationExpired {
+            currency, amount, ..
+        } => vec![(Held(client), Available(client), currency.clone(), *amount)],
+        AccountEvent::Adjusted {
+            currency, amount, ..
+        } => match amount.is_negative() {
+            true => vec![(Available(client), Adjustments, currency.clone(), -*amount)],
+            false => vec![(Adjustments, Available(client), currency.clone(), *amount)],
+        },
+        AccountEvent::FeeCharged {
+            currency, amount, ..
+        } => vec![(Available(client), Fees, currency.clone(), *amount)],
+        AccountEvent::InterestPosted {
+            currency, amount, ..
+        } => vec![(Interest, Available(client), currency.clone(), *amount)],
+        AccountEvent::Locked | AccountEvent::Unlocked | AccountEvent::Closed => Vec::new(),
+    };
+    transfers
+        .into_iter()
+        .flat_map(|(debited, credited, currency, amount)| {
+            pair(event.tx(), debited, credited, &currency, amount)
+        })
+        .collect()
+}
+
+/// Debit of `debited` and credit of `credited` by the amount, none for a zero amount.
+fn pair(
+    tx: Option<u32>,
+    debited: LedgerAccount,
+    credited: LedgerAccount,
+    currency: &Option<Currency>,
+    amount: Money,
+) -> Vec<Posting> {
+    if amount == Money::ZERO {
+        return Vec::new();
+    }
+    let posting = |account, side| Posting {
+        tx,
+        account,
+        side,
+        currency: currency.clone(),
+        amount,
+    };
+    vec![
+        posting(debited, Side::Debit),
+        posting(credited, Side::Credit),
+    ]
+}
+
+/// Postings whose sum doesn't fit into [`Money`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Postings in {} add up to more than can be represented", match .currency {
+    Some(currency) => currency.to_string(),
+    None => "the default currency".to_string(),
+})]
+pub struct Overflow {
+    /// `None` for the default currency
+    pub currency: Option<Currency>,
+}
+
+/// Adds `amount` to `sum`, failing when the sum of the postings in `currency` overflows.
+fn add(sum: &mut Money, amount: Money, currency: &Option<Currency>) -> Result<(), Overflow> {
+    *sum = sum.checked_add(amount).ok_or_else(|| Overflow {
+        currency: currency.clone(),
+    })?;
+    Ok(())
+}
+
+/// Whether debits and credits of every currency add up to the same amount.
+pub fn is_balanced<'a>(postings: impl IntoIterator<Item = &'a Posting>) -> Result<bool, Overflow> {
+    let mut net = BTreeMap::<&Option<Currency>, Money>::new();
+    for posting in postings {
+        let sum = net.entry(&posting.currency).or_default();
+        add(sum, posting.signed_amount(), &posting.currency)?;
+    }
+    Ok(net.values().all(|net| *net == Money::ZERO))
+}
+
+/// Debit and credit totals of an account of the books in one currency, see [`trial_balance`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrialBalanceLine {
+    pub account: LedgerAccount,
+    /// `None` for the default currency
+    pub currency: Option<Currency>,
+    pub debit: Money,
+    pub credit: Money,
+}
+
+impl TrialBalanceLine {
+    /// Credits less debits, what the account holds for client accounts.
+    pub fn balance(&self) -> Money {
+        self.credit - self.debit
+    }
+}
+
+/// Totals the postings of every account of the books, by currency and then account.
+pub fn trial_balance<'a>(
+    postings: impl IntoIterator<Item = &'a Posting>,
+) -> Result<Vec<TrialBalanceLine>, Overflow> {
+    let mut totals = BTreeMap::<(Option<Currency>, LedgerAccount), (Money, Money)>::new();
+    for posting in postings {
+        let (debit, credit) = totals
+            .entry((posting.currency.clone(), posting.account))
+            .or_default();
+        let total = match posting.side {
+            Side::Debit => debit,
+            Side::Credit => credit,
+        };
+        add(total, posting.amount, &posting.currency)?;
+    }
+    Ok(totals
+        .into_iter()
+        .map(|((currency, account), (debit, credit))| TrialBalanceLine {
+            account,
+            currency,
+            debit,
+            credit,
+        })
+        .collect())
+}
+
+#[derive(Serialize)]
+struct TrialBalanceRecord {
+    account: &'static str,
+    client: Option<u16>,
+    currency: Option<String>,
+    debit: String,
+    credit: String,
+    balance: String,
+}
+
+/// Writes a trial balance as csv, each currency's accounts followed by a `total` line of
+/// its debits and credits. Fails once written if the debits and credits of a currency
+/// don't add up to the same amount, and before its total if they don't fit into [`Money`].
+pub fn write_trial_balance<W: io::Write>(
+    writer: W,
+    lines: &[TrialBalanceLine],
+    format: &MoneyFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record([
+        "account", "client", "currency", "debit", "credit", "balance",
+    ])?;
+    let mut unbalanced = Vec::new();
+    for currency_lines in lines.chunk_by(|a, b| a.currency == b.currency) {
+        let code = &currency_lines[0].currency;
+        let currency = code.as_ref().map(ToString::to_string);
+        let mut debits = Money::ZERO;
+        let mut credits = Money::ZERO;
+        for line in currency_lines {
+            add(&mut debits, line.debit, code)?;
+            add(&mut credits, line.credit, code)?;
+            writer.serialize(TrialBalanceRecord {
+                account: line.account.name(),
+                client: line.account.client(),
+                currency: currency.clone(),
+                debit: line.debit.format(format),
+                credit: line.credit.format(format),
+                balance: line.balance().format(format),
+            })?;
+        }
+        writer.serialize(TrialBalanceRecord {
+            account: "total",
+            client: None,
+            currency: currency.clone(),
+            debit: debits.format(format),
+            credit: credits.format(format),
+            balance: (credits - debits).format(format),
+        })?;
+        if debits != credits {
+            unbalanced.push(currency.unwrap_or_else(|| "default currency".to_string()));
+        }
+    }
+    writer.flush()?;
+    if !unbalanced.is_empty() {
+        return Err(format!(
+            "Trial balance doesn't net to zero in {}",
+            unbalanced.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks the client lines of a trial balance against the balances of the accounts, failing
+/// with every client and currency whose available or held funds differ from its postings.
+pub fn reconcile<'a>(
+    lines: &[TrialBalanceLine],
+    accounts: impl IntoIterator<Item = &'a Account>,
+) -> Result<(), String> {
+    let mut posted = BTreeMap::<(u16, Option<Currency>), (Money, Money)>::new();
+    for line in lines {
+        let (available, held) = match line.account {
+            LedgerAccount::Available(client) | LedgerAccount::Held(client) => {
+                posted.entry((client, line.currency.clone())).or_default()
+            }
+            _ => continue,
+        };
+        let funds = match line.account {
+            LedgerAccount::Available(_) => available,
+            _ => held,
+        };
+        add(funds, line.balance(), &line.currency).map_err(|e| e.to_string())?;
+    }
+    let mut mismatches = Vec::new();
+    for account in accounts {
+        for (currency, balance) in account.balances() {
+            let funds = posted
+                .remove(&(account.client(), currency.clone()))
+                .unwrap_or_default();
+            if funds != (balance.available(), balance.held()) {
+                mismatches.push((account.client(), currency.clone()));
+            }
+        }
+    }
+    // Postings of funds no account holds
+    mismatches.extend(
+        posted
+            .into_iter()
+            .filter(|(_, funds)| *funds != (Money::ZERO, Money::ZERO))
+            .map(|(key, _)| key),
+    );
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    mismatches.sort();
+    let mismatches = mismatches
+        .into_iter()
+        .map(|(client, currency)| match currency {
+            Some(currency) => format!("client {} in {}", client, currency),
+            None => format!("client {}", client),
+        })
+        .collect::<Vec<_>>();
+    Err(format!(
+        "Trial balance differs from the account balances of {}",
+        mismatches.join(", ")
+    ))
+}
+
+#[derive(Serialize)]
+struct PostingRecord {
+    tx: Option<u32>,
+    account: &'static str,
+    client: Option<u16>,
+    currency: Option<String>,
+    debit: Option<String>,
+    credit: Option<String>,
+}
+
+/// Writes postings as csv, one line each, with the amount in the debit or credit column.
+pub fn write_postings<'a, W: io::Write>(
+    writer: W,
+    postings: impl IntoIterator<Item = &'a Posting>,
+    format: &MoneyFormat,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record(["tx", "account", "client", "currency", "debit", "credit"])?;
+    for posting in postings {
+        let amount = Some(posting.amount.format(format));
+        let (debit, credit) = match posting.side {
+            Side::Debit => (amount, None),
+            Side::Credit => (None, amount),
+        };
+        writer.serialize(PostingRecord {
+            tx: posting.tx,
+            account: posting.account.name(),
+            client: posting.account.client(),
+            currency: posting.currency.as_ref().map(ToString::to_string),
+            debit,
+            credit,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_balanced, reconcile, trial_balance, write_postings, write_trial_balance, LedgerAccount,
+        Overflow,
+    };
+    use crate::fees::{Fee, FeeSchedule};
+    use crate::{
+        Currency, Engine, EngineConfig, ExchangeRates, Money, Timestamp, Transaction,
+        TransactionType,
+    };
+    use rust_decimal::Decimal;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn balanced_books() {
+        let mut engine = Engine::with_config(EngineConfig {
+            event_log: true,
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(4))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+            Transaction::new(TransactionType::Deposit, 2, 3, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 2, 4, Some(Money::from(2))),
+            Transaction::new(TransactionType::Dispute, 2, 4, None),
+            Transaction::new(TransactionType::Resolve, 2, 4, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let postings = engine.postings().await;
+        assert_eq!(is_balanced(&postings), Ok(true));
+        // Every client's funds are what their postings add up to
+        for client in [1, 2] {
+            let account = engine.account(client).await.unwrap();
+            let funds = postings
+                .iter()
+                .filter(|posting| posting.account.client() == Some(client))
+                .try_fold(Money::ZERO, |funds, posting| {
+                    funds.checked_add(posting.signed_amount())
+                })
+                .unwrap();
+            assert_eq!(funds, account.total());
+        }
+
+        let mut csv = Vec::new();
+        write_postings(&mut csv, &postings[..4], &Default::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "tx,account,client,currency,debit,credit\n\
+             1,clearing,,,10.0000,\n\
+             1,available,1,,,10.0000\n\
+             2,available,1,,4.0000,\n\
+             2,clearing,,,,4.0000\n"
+        );
+
+        let mut csv = Vec::new();
+        let lines = trial_balance(&postings).unwrap();
+        write_trial_balance(&mut csv, &lines, &Default::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "account,client,currency,debit,credit,balance\n\
+             available,1,,14.0000,10.0000,-4.0000\n\
+             available,2,,2.0000,5.0000,3.0000\n\
+             held,1,,10.0000,10.0000,0.0000\n\
+             held,2,,2.0000,2.0000,0.0000\n\
+             clearing,,,15.0000,16.0000,1.0000\n\
+             chargeback_loss,,,2.0000,2.0000,0.0000\n\
+             total,,,45.0000,45.0000,0.0000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn postings_match_balances() {
+        let (eur, usd) = (
+            "EUR".parse::<Currency>().unwrap(),
+            "USD".parse::<Currency>().unwrap(),
+        );
+        let mut rates = ExchangeRates::new();
+        rates
+            .insert(eur.clone(), usd.clone(), Money::new(125, 2).into())
+            .unwrap();
+        let mut fees = FeeSchedule::new();
+        fees.insert(
+            TransactionType::Transfer,
+            Fee {
+                flat: Money::from(1),
+                ..Fee::default()
+            },
+        )
+        .unwrap();
+        fees.monthly = Some(Money::from(2));
+        let mut engine = Engine::with_config(EngineConfig {
+            event_log: true,
+            allow_admin_ops: true,
+            exchange_rates: Some(rates),
+            fees: Some(Arc::new(fees)),
+            interest_rate: Some(Decimal::new(5, 2)),
+            ..EngineConfig::default()
+        });
+        let january = Timestamp::from_millis(1_705_276_800_000);
+        let march = Timestamp::from_millis(1_709_337_600_000);
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(100)))
+                .with_timestamp(january),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from(20)))
+                .with_currency(eur.clone()),
+            Transaction::new(TransactionType::Convert, 1, 3, Some(Money::from(8)))
+                .with_currency(eur.clone())
+                .with_to_currency(usd.clone()),
+            Transaction::new(TransactionType::Transfer, 1, 4, Some(Money::from(30)))
+                .with_to_client(2),
+            Transaction::new(TransactionType::Refund, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Authorize, 1, 5, Some(Money::from(20))),
+            Transaction::new(TransactionType::Capture, 1, 5, Some(Money::from(15))),
+            Transaction::new(TransactionType::Authorize, 1, 6, Some(Money::from(5))),
+            Transaction::new(TransactionType::Adjustment, 2, 7, Some(Money::from(-4)))
+                .with_reason("FEE_CORRECTION"),
+            Transaction::new(TransactionType::Withdrawal, 2, 8, Some(Money::from(6))),
+            Transaction::new(TransactionType::Dispute, 2, 8, None),
+            Transaction::new(TransactionType::Chargeback, 2, 8, None),
+            Transaction::new(TransactionType::Representment, 2, 8, None),
+            Transaction::new(TransactionType::Deposit, 1, 9, Some(Money::from(1)))
+                .with_timestamp(march),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        assert!(engine.wait().await.is_empty());
+
+        let postings = engine.postings().await;
+        assert_eq!(is_balanced(&postings), Ok(true));
+        for client in [1, 2] {
+            let account = engine.account(client).await.unwrap();
+            let mut funds = BTreeMap::new();
+            for posting in &postings {
+                let (available, held) = funds
+                    .entry(posting.currency.clone())
+                    .or_insert((Money::ZERO, Money::ZERO));
+                match posting.account {
+                    LedgerAccount::Available(c) if c == client => {
+                        *available = available.checked_add(posting.signed_amount()).unwrap()
+                    }
+                    LedgerAccount::Held(c) if c == client => {
+                        *held = held.checked_add(posting.signed_amount()).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+            for (currency, balance) in account.balances() {
+                assert_eq!(funds[currency], (balance.available(), balance.held()));
+            }
+        }
+        let offsets = trial_balance(&postings)
+            .unwrap()
+            .into_iter()
+            .filter(|line| line.account.client().is_none())
+            .map(|line| line.account)
+            .collect::<Vec<_>>();
+        for account in [
+            LedgerAccount::Fees,
+            LedgerAccount::Interest,
+            LedgerAccount::Fx,
+            LedgerAccount::Transfer,
+            LedgerAccount::Adjustments,
+        ] {
+            assert!(offsets.contains(&account), "{} has no postings", account);
+        }
+
+        let lines = trial_balance(&postings).unwrap();
+        let accounts = engine.accounts().await;
+        assert_eq!(reconcile(&lines, &accounts), Ok(()));
+        // Postings missing from the books show as a mismatch of their client
+        let partial = trial_balance(postings.iter().filter(|p| p.tx != Some(4))).unwrap();
+        assert_eq!(
+            reconcile(&partial, &accounts),
+            Err("Trial balance differs from the account balances of client 1, client 2".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn overflowing_books() {
+        let amount = Some("40000000000000000000000000000".parse::<Money>().unwrap());
+        let mut engine = Engine::with_config(EngineConfig {
+            event_log: true,
+            ..EngineConfig::default()
+        });
+        for client in [1, 2] {
+            let deposit = Transaction::new(TransactionType::Deposit, client, client.into(), amount);
+            engine.submit(deposit).await.unwrap();
+        }
+        assert!(engine.wait().await.is_empty());
+        assert_eq!(
+            engine.trial_balance().await,
+            Err(Overflow { currency: None })
+        );
+        let error = engine.write_trial_balance(Vec::new()).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Postings in the default currency add up to more than can be represented"
+        );
+    }
+}
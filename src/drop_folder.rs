@@ -0,0 +1,83 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Subfolder processed files are moved to.
+const DONE: &str = "done";
+
+/// Folder other systems drop input files into, for `watch`.
+///
+/// Files with an input extension (csv, json, jsonl or ndjson) are picked up in name order,
+/// others are left alone: producers write files under another name, e.g. `.part` or a
+/// leading dot, and rename them once complete, so a file is never read half written.
+#[derive(Debug, Clone)]
+pub struct DropFolder {
+    dir: PathBuf,
+    done: PathBuf,
+}
+
+impl DropFolder {
+    /// Opens the folder, creating its `done/` subfolder if needed.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let done = dir.join(DONE);
+        std::fs::create_dir_all(&done)?;
+        Ok(Self { dir, done })
+    }
+
+    /// Files waiting to be processed, in name order.
+    pub fn pending(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let input = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| matches!(extension, "csv" | "json" | "jsonl" | "ndjson"));
+            if input && !hidden {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Moves a processed file to `done/`, replacing a file of the same name there, and
+    /// returns where it went.
+    pub fn done(&self, path: &Path) -> io::Result<PathBuf> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file"))?;
+        let target = self.done.join(name);
+        std::fs::rename(path, &target)?;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DropFolder;
+
+    #[test]
+    fn pending_and_done() {
+        let dir = std::env::temp_dir().join(format!("drop_folder_{}", std::process::id()));
+        let folder = DropFolder::open(&dir).unwrap();
+        for name in ["b.csv", "a.jsonl", ".c.csv", "d.csv.part", "e.txt"] {
+            std::fs::write(dir.join(name), "type,client,tx,amount\n").unwrap();
+        }
+        assert_eq!(
+            folder.pending().unwrap(),
+            [dir.join("a.jsonl"), dir.join("b.csv")]
+        );
+
+        let done = folder.done(&dir.join("a.jsonl")).unwrap();
+        assert_eq!(done, dir.join("done").join("a.jsonl"));
+        assert!(done.exists());
+        assert_eq!(folder.pending().unwrap(), [dir.join("b.csv")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
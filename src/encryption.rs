@@ -0,0 +1,380 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Hex encoded 256 bit key state is encrypted with, see [`Cipher::from_env`].
+pub const ENCRYPTION_KEY_ENV: &str = "TRANSACTION_ENCRYPTION_KEY";
+/// File holding the hex encoded key instead, e.g. one a KMS agent keeps up to date.
+pub const ENCRYPTION_KEY_FILE_ENV: &str = "TRANSACTION_ENCRYPTION_KEY_FILE";
+
+/// First bytes of every encrypted file, telling it apart from plaintext JSON.
+pub(crate) const MAGIC: &[u8] = b"TSENC1";
+
+const NONCE_LEN: usize = 12;
+
+/// Random id of an append-only log, sealed along with every encrypted line of it, see
+/// [`seal_line`].
+pub(crate) type StreamId = [u8; 16];
+
+/// Id of a new log.
+pub(crate) fn new_stream() -> StreamId {
+    let mut id = StreamId::default();
+    OsRng.fill_bytes(&mut id);
+    id
+}
+
+/// AES-256-GCM encryption of state written to disk: snapshots, write-ahead log entries,
+/// account state directories and spilled history, and of audit outputs: event logs,
+/// ledgers, postings and dead letters.
+///
+/// Every message is sealed with a fresh random nonce, stored in front of the ciphertext,
+/// so the same key can encrypt any number of them. Decrypting checks the authentication
+/// tag, a tampered message or one sealed with another key fails.
+#[derive(Clone)]
+pub struct Cipher {
+    key: [u8; 32],
+    cipher: Aes256Gcm,
+}
+
+impl PartialEq for Cipher {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Cipher {}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Cipher")
+    }
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            key: *key,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    /// Cipher from a hex encoded 256 bit key, surrounding whitespace aside.
+    pub fn from_hex(key: &str) -> Result<Self, Box<dyn Error>> {
+        let key = hex::decode(key.trim()).map_err(|_| "The encryption key isn't hex")?;
+        let key = <[u8; 32]>::try_from(key.as_slice())
+            .map_err(|_| "The encryption key has to be 32 bytes (64 hex digits)")?;
+        Ok(Self::new(&key))
+    }
+
+    /// Cipher with the key of `TRANSACTION_ENCRYPTION_KEY` or, when that isn't set, of the
+    /// file `TRANSACTION_ENCRYPTION_KEY_FILE` names. `None` when neither is set, state is
+    /// then persisted in plaintext.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        match std::env::var(ENCRYPTION_KEY_ENV) {
+            Ok(key) if !key.is_empty() => return Self::from_hex(&key).map(Some),
+            _ => {}
+        }
+        match std::env::var(ENCRYPTION_KEY_FILE_ENV) {
+            Ok(path) if !path.is_empty() => {
+                let key = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Can't read the encryption key {}: {}", path, e))?;
+                Self::from_hex(&key).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Nonce followed by the ciphertext and its tag.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        self.encrypt_with(plaintext, &[])
+    }
+
+    pub fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.decrypt_with(sealed, &[])
+    }
+
+    /// [`Cipher::encrypt`] authenticating `associated` data along with the plaintext,
+    /// without storing it: decrypting needs the same data.
+    fn encrypt_with(&self, plaintext: &[u8], associated: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: associated,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .expect("AES-GCM encrypts messages of any size held in memory");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        sealed
+    }
+
+    fn decrypt_with(&self, sealed: &[u8], associated: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if sealed.len() < NONCE_LEN {
+            return Err("Encrypted data is cut short".into());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: associated,
+        };
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| "Can't decrypt, wrong key or tampered data".into())
+    }
+
+    /// [`Cipher::encrypt`] behind [`MAGIC`], as encrypted files are written.
+    pub(crate) fn seal_file(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend(self.encrypt(plaintext));
+        file
+    }
+}
+
+fn missing_key() -> Box<dyn Error> {
+    format!(
+        "Encrypted, set {} or {} to read it",
+        ENCRYPTION_KEY_ENV, ENCRYPTION_KEY_FILE_ENV
+    )
+    .into()
+}
+
+/// Data a line is authenticated with: the log it belongs to and its position in it.
+fn line_position(stream: &StreamId, sequence: u64) -> [u8; 24] {
+    let mut position = [0; 24];
+    position[..16].copy_from_slice(stream);
+    position[16..].copy_from_slice(&sequence.to_be_bytes());
+    position
+}
+
+/// Line number `sequence` of the append-only log `stream`: the JSON `entry` as it is, or
+/// encrypted and hex encoded so lines stay apart. Without the trailing newline.
+///
+/// Encrypted lines start with the log's id, and the id and sequence number are
+/// authenticated with the entry, so a line moved to another position or log fails to
+/// open.
+pub(crate) fn seal_line(
+    cipher: Option<&Cipher>,
+    stream: &StreamId,
+    sequence: u64,
+    entry: Vec<u8>,
+) -> Vec<u8> {
+    match cipher {
+        Some(cipher) => {
+            let mut sealed = stream.to_vec();
+            sealed.extend(cipher.encrypt_with(&entry, &line_position(stream, sequence)));
+            hex::encode(sealed).into_bytes()
+        }
+        None => entry,
+    }
+}
+
+fn not_encrypted() -> Box<dyn Error> {
+    "Not encrypted although encryption is on, it may have been forged".into()
+}
+
+/// JSON entry of the line written by [`seal_line`] as number `sequence` of the log
+/// `stream`, or of the log the line names when `stream` is `None`, which it is then set to,
+/// e.g. for the first line of a log. Plaintext lines are objects, anything else is
+/// encrypted. With a cipher only encrypted lines are read, a plaintext one could have been
+/// put in place of an authenticated one.
+pub(crate) fn open_line(
+    cipher: Option<&Cipher>,
+    stream: &mut Option<StreamId>,
+    sequence: u64,
+    line: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let line = line.trim_end();
+    if line.starts_with('{') {
+        return match cipher {
+            Some(_) => Err(not_encrypted()),
+            None => Ok(line.as_bytes().to_vec()),
+        };
+    }
+    let sealed = hex::decode(line).map_err(|_| "Neither JSON nor encrypted")?;
+    let Some(cipher) = cipher else {
+        return Err(missing_key());
+    };
+    let Some((id, sealed)) = sealed.split_first_chunk::<16>() else {
+        return Err("Encrypted data is cut short".into());
+    };
+    match stream {
+        Some(stream) if stream != id => return Err("Line of another log".into()),
+        Some(_) => {}
+        None => *stream = Some(*id),
+    }
+    cipher.decrypt_with(sealed, &line_position(id, sequence))
+}
+
+/// Contents of a file written either in plaintext or by [`Cipher::seal_file`]. Encrypted
+/// files need the cipher, and with a cipher plaintext files are refused like plaintext
+/// lines.
+pub(crate) fn open_file(cipher: Option<&Cipher>, file: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    match (file.strip_prefix(MAGIC), cipher) {
+        (Some(sealed), Some(cipher)) => cipher.decrypt(sealed),
+        (Some(_), None) => Err(missing_key()),
+        (None, Some(_)) => Err(not_encrypted()),
+        (None, None) => Ok(file),
+    }
+}
+
+/// Plaintext of a file encrypted at rest, either sealed whole by [`Cipher::seal_file`] or
+/// line by line by [`seal_line`], e.g. to read an encrypted ledger or dead letter file.
+pub fn decrypt_file(cipher: &Cipher, file: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    if file.starts_with(MAGIC) {
+        return open_file(Some(cipher), file);
+    }
+    let mut plaintext = Vec::new();
+    let mut stream = None;
+    for (sequence, line) in std::str::from_utf8(&file)?.lines().enumerate() {
+        plaintext.extend(open_line(Some(cipher), &mut stream, sequence as u64, line)?);
+        plaintext.push(b'\n');
+    }
+    Ok(plaintext)
+}
+
+/// Output written for audits, e.g. the event log or the ledger. Without a cipher it is
+/// written to its file as it goes, with one it is kept in memory and sealed into its file
+/// by [`AuditFile::finish`], like a snapshot.
+pub struct AuditFile {
+    writer: BufWriter<File>,
+    /// Cipher and the plaintext written so far, when encrypted
+    sealing: Option<(Box<Cipher>, Vec<u8>)>,
+}
+
+impl AuditFile {
+    /// Creates the file at `path`, replacing any earlier one.
+    pub fn create(path: impl AsRef<Path>, cipher: Option<Cipher>) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            sealing: cipher.map(|cipher| (Box::new(cipher), Vec::new())),
+        })
+    }
+
+    /// Writes what is still buffered, sealing the whole output when it is encrypted.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some((cipher, contents)) = self.sealing.take() {
+            self.writer.write_all(&cipher.seal_file(&contents))?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl Write for AuditFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.sealing {
+            Some((_, contents)) => contents.write(buf),
+            None => self.writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sealing {
+            Some(_) => Ok(()),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt_file, new_stream, open_file, open_line, seal_line, AuditFile, Cipher};
+    use std::io::Write;
+
+    #[test]
+    fn round_trip() {
+        let cipher = Cipher::new(&[7; 32]);
+        let sealed = cipher.encrypt(b"client,available\n1,10\n");
+        assert_ne!(cipher.encrypt(b"client,available\n1,10\n"), sealed);
+        assert_eq!(
+            cipher.decrypt(&sealed).unwrap(),
+            b"client,available\n1,10\n"
+        );
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.decrypt(&tampered).is_err());
+        assert!(Cipher::new(&[8; 32]).decrypt(&sealed).is_err());
+        assert!(cipher.decrypt(&sealed[..5]).is_err());
+
+        let file = cipher.seal_file(b"{}");
+        assert_eq!(open_file(Some(&cipher), file.clone()).unwrap(), b"{}");
+        assert!(open_file(None, file).is_err());
+        // Plaintext can't stand in for encrypted state
+        assert!(open_file(Some(&cipher), b"{}".to_vec()).is_err());
+        assert_eq!(open_file(None, b"{}".to_vec()).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn lines() {
+        let cipher = Cipher::new(&[7; 32]);
+        let stream = new_stream();
+        let line = seal_line(Some(&cipher), &stream, 3, b"{\"tx\":1}".to_vec());
+        assert!(!line.contains(&b'\n'));
+        let line = String::from_utf8(line).unwrap();
+        let mut read = None;
+        assert_eq!(
+            open_line(Some(&cipher), &mut read, 3, &line).unwrap(),
+            b"{\"tx\":1}"
+        );
+        assert_eq!(read, Some(stream));
+        assert!(open_line(None, &mut None, 3, &line).is_err());
+        assert!(open_line(Some(&cipher), &mut None, 3, &line[..line.len() - 2]).is_err());
+        // Lines only open at their position in their own log
+        assert!(open_line(Some(&cipher), &mut Some(stream), 4, &line).is_err());
+        assert!(open_line(Some(&cipher), &mut Some(new_stream()), 3, &line).is_err());
+        let mut forged = line.clone();
+        forged.replace_range(..32, &hex::encode(new_stream()));
+        assert!(open_line(Some(&cipher), &mut None, 3, &forged).is_err());
+
+        let line = seal_line(None, &stream, 0, b"{\"tx\":1}".to_vec());
+        let line = String::from_utf8(line).unwrap();
+        assert_eq!(open_line(None, &mut None, 0, &line).unwrap(), b"{\"tx\":1}");
+        assert!(open_line(Some(&cipher), &mut None, 0, &line).is_err());
+    }
+
+    #[test]
+    fn audit_files() {
+        let cipher = Cipher::new(&[7; 32]);
+        let path = std::env::temp_dir().join(format!("audit_{}.csv", std::process::id()));
+        let mut file = AuditFile::create(&path, Some(cipher.clone())).unwrap();
+        file.write_all(b"client,tx\n1,1\n").unwrap();
+        file.finish().unwrap();
+        let sealed = std::fs::read(&path).unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"client"));
+        assert_eq!(decrypt_file(&cipher, sealed).unwrap(), b"client,tx\n1,1\n");
+
+        let mut file = AuditFile::create(&path, None).unwrap();
+        file.write_all(b"client,tx\n").unwrap();
+        file.finish().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"client,tx\n");
+        std::fs::remove_file(path).unwrap();
+
+        let stream = new_stream();
+        let lines = [
+            seal_line(Some(&cipher), &stream, 0, b"{\"tx\":1}".to_vec()),
+            seal_line(Some(&cipher), &stream, 1, b"{\"tx\":2}".to_vec()),
+        ];
+        assert_eq!(
+            decrypt_file(&cipher, lines.join(&b'\n')).unwrap(),
+            b"{\"tx\":1}\n{\"tx\":2}\n"
+        );
+        // Reordered lines
+        let swapped = [lines[1].clone(), lines[0].clone()];
+        assert!(decrypt_file(&cipher, swapped.join(&b'\n')).is_err());
+    }
+
+    #[test]
+    fn keys() {
+        assert!(Cipher::from_hex(&"ab".repeat(32)).is_ok());
+        assert!(Cipher::from_hex(&format!("{}\n", "ab".repeat(32))).is_ok());
+        assert!(Cipher::from_hex(&"ab".repeat(16)).is_err());
+        assert!(Cipher::from_hex(&"zz".repeat(32)).is_err());
+    }
+}
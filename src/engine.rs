@@ -0,0 +1,2988 @@
+use crate::account::{
+    Account, AccountState, ChargebackPolicy, ErrorKind, TransactionProcessingError,
+};
+#[cfg(any(test, feature = "chaos"))]
+use crate::chaos::Chaos;
+use crate::currency::Currency;
+use crate::dedup::{IdFilter, TransactionIds};
+use crate::dlq::DeadLetter;
+use crate::double_entry::{self, Overflow, Posting, TrialBalanceLine};
+use crate::events::{self, AccountEvent};
+use crate::fees::FeeSchedule;
+use crate::fraud::{FraudHit, FraudRules};
+use crate::history::HistoryWindow;
+use crate::invariants::{Before, InvariantChecker, Violation};
+use crate::latency::Latencies;
+use crate::ledger::{self, LedgerEntry};
+use crate::limits::LimitRules;
+use crate::money::{Money, MoneyFormat};
+use crate::output::{self, ReportFormat, ReportOrder, StatusColumns};
+use crate::partition::{Partition, TransferMessage, TransferStep};
+use crate::rates::ExchangeRates;
+use crate::retry::{RetryPolicy, Transient};
+use crate::snapshot::{Snapshot, SNAPSHOT_VERSION};
+use crate::store::{MemoryStore, StateStore};
+use crate::summary::{Summary, Tally};
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionType};
+#[cfg(feature = "persistence")]
+use crate::{
+    snapshot::AccountSnapshot,
+    state::{self, KeyValueStore},
+};
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, watch, Mutex, OwnedMutexGuard};
+use tokio::task::JoinSet;
+use tracing::{field, Span};
+
+/// What happens when a deposit or withdrawal reuses an already seen transaction id.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// The first transaction with the id is kept, later ones are rejected
+    #[default]
+    FirstWins,
+    /// Processing stops with an error
+    Abort,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-wins" => Ok(DuplicatePolicy::FirstWins),
+            "error" => Ok(DuplicatePolicy::Abort),
+            _ => Err(format!(
+                "Invalid duplicate policy {}, expected first-wins or error",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// Only accept clients from this range and tag the report with it
+    pub partition: Option<Partition>,
+    /// Settle transfers to clients outside the partition with the partition handling them,
+    /// see [`Engine::settle`], instead of rejecting them
+    pub cross_partition_transfers: bool,
+    /// Precision and rounding of balances in the account report
+    pub output_format: MoneyFormat,
+    /// Csv or JSON layout of the account report
+    pub report_format: ReportFormat,
+    /// Order of the account report's rows and of [`Engine::accounts`]
+    pub report_order: ReportOrder,
+    /// Number of worker tasks submitted transactions are sharded across
+    pub workers: usize,
+    /// How many transactions may queue up in front of each worker before submitting blocks
+    pub channel_capacity: usize,
+    /// Handling of deposits and withdrawals reusing a transaction id
+    pub duplicate_policy: DuplicatePolicy,
+    /// Rates quoting `convert` transactions, which are rejected without them
+    pub exchange_rates: Option<ExchangeRates>,
+    /// Accept administrative transactions, `unlock` and `adjustment`
+    pub allow_admin_ops: bool,
+    /// Whether chargebacks lock accounts and what locked accounts still accept
+    pub chargeback_policy: ChargebackPolicy,
+    /// Fees posted to accounts as their transactions are processed
+    pub fees: Option<Arc<FeeSchedule>>,
+    /// Annual rate of the interest accrued on available funds, e.g. 0.05 for 5%
+    pub interest_rate: Option<Decimal>,
+    /// How far withdrawals may take available funds below zero
+    pub overdraft_limit: Money,
+    /// Overdraft limits of individual clients, overriding `overdraft_limit`
+    pub overdraft_limits: HashMap<u16, Money>,
+    /// Limits on amounts and frequency of transactions, none by default
+    pub limits: LimitRules,
+    /// Rules flagging or blocking suspicious transactions, each account gets its own copy
+    pub fraud_rules: FraudRules,
+    /// Keep whole transactions in account history rather than just what disputes need
+    pub full_history: bool,
+    /// Bound on the history each account keeps in memory, unbounded by default
+    pub history_window: Option<HistoryWindow>,
+    /// Probabilistic duplicate detection for more ids than fit in memory, exact by default
+    pub id_filter: Option<IdFilter>,
+    /// Retries of transactions failing on storage that may recover, none by default
+    pub retry: RetryPolicy,
+    /// Check every transaction against the invariants of money, see [`InvariantChecker`]
+    pub check_invariants: bool,
+    /// Clients whose accounts keep a statement, see [`Account::statement`]
+    pub statements: HashSet<u16>,
+    /// Every account keeps a log of its events, see [`Account::events`]
+    pub event_log: bool,
+    /// Faults injected to test the engine's guarantees, none by default
+    #[cfg(any(test, feature = "chaos"))]
+    pub chaos: Option<Chaos>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            partition: None,
+            cross_partition_transfers: false,
+            output_format: MoneyFormat::default(),
+            report_format: ReportFormat::default(),
+            report_order: ReportOrder::default(),
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            channel_capacity: 1024,
+            duplicate_policy: DuplicatePolicy::default(),
+            exchange_rates: None,
+            allow_admin_ops: false,
+            chargeback_policy: ChargebackPolicy::default(),
+            fees: None,
+            interest_rate: None,
+            overdraft_limit: Money::ZERO,
+            overdraft_limits: HashMap::new(),
+            limits: LimitRules::default(),
+            fraud_rules: FraudRules::default(),
+            full_history: false,
+            history_window: None,
+            id_filter: None,
+            retry: RetryPolicy::default(),
+            check_invariants: false,
+            statements: HashSet::new(),
+            event_log: false,
+            #[cfg(any(test, feature = "chaos"))]
+            chaos: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Status columns of the account report: `closed` once administrative transactions,
+    /// which close accounts, are allowed and `overdrawn` once there are overdrafts.
+    pub fn status_columns(&self) -> StatusColumns {
+        StatusColumns {
+            closed: self.allow_admin_ops,
+            overdrawn: self.overdraft_limit.is_positive()
+                || self
+                    .overdraft_limits
+                    .values()
+                    .any(|limit| limit.is_positive()),
+        }
+    }
+}
+
+/// Transaction the engine refused to apply: where it was read from and what it was,
+/// together with the error, which carries its client, id and amount.
+#[derive(Debug)]
+pub struct Rejection {
+    pub row: Option<u64>,
+    pub timestamp: Option<Timestamp>,
+    pub transaction_type: TransactionType,
+    pub currency: Option<Currency>,
+    pub error: TransactionProcessingError,
+}
+
+impl Rejection {
+    fn new(transaction: &Transaction, kind: ErrorKind) -> Self {
+        Self {
+            row: transaction.row,
+            timestamp: transaction.timestamp,
+            transaction_type: transaction.transaction_type.clone(),
+            currency: transaction.currency.clone(),
+            error: TransactionProcessingError::new(transaction, kind),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", self.error, self.error.code())
+    }
+}
+
+impl Error for Rejection {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A row of the rejections report: the error is flattened into its context, `code` and
+/// `reason`.
+impl Serialize for Rejection {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut rejection = serializer.serialize_struct("Rejection", 7)?;
+        rejection.serialize_field("row", &self.row)?;
+        rejection.serialize_field("client", &self.error.client)?;
+        rejection.serialize_field("tx", &self.error.tx)?;
+        rejection.serialize_field("timestamp", &self.timestamp)?;
+        rejection.serialize_field("amount", &self.error.amount)?;
+        rejection.serialize_field("code", &self.error.code())?;
+        rejection.serialize_field("reason", self.error.reason())?;
+        rejection.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeStatus {
+    Accepted,
+    Rejected,
+}
+
+/// How busy an engine was, see [`Engine::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineStats {
+    /// Transactions queued on workers
+    pub queued: u64,
+    /// Most transactions waiting in front of a single worker at once
+    pub peak_queue: usize,
+    /// Times submitting waited for room in a full worker queue
+    pub queue_full: u64,
+    /// Time spent applying transactions to accounts, summed over all workers
+    pub applying: Duration,
+}
+
+/// What became of a transaction handed to the engine, see [`Engine::outcomes`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransactionOutcome {
+    /// Line of the input the transaction was read from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row: Option<u64>,
+    pub client: u16,
+    pub tx: u32,
+    pub status: OutcomeStatus,
+    /// Why the transaction was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorKind>,
+}
+
+impl TransactionOutcome {
+    fn accepted(row: Option<u64>, client: u16, tx: u32) -> Self {
+        Self {
+            row,
+            client,
+            tx,
+            status: OutcomeStatus::Accepted,
+            error: None,
+        }
+    }
+
+    fn rejected(rejection: &Rejection) -> Self {
+        Self {
+            row: rejection.row,
+            client: rejection.error.client,
+            tx: rejection.error.tx,
+            status: OutcomeStatus::Rejected,
+            error: Some(rejection.error.kind.clone()),
+        }
+    }
+}
+
+/// Streams told what became of each transaction, see [`Engine::outcomes`],
+/// [`Engine::dead_letters`] and [`Engine::watch`].
+#[derive(Clone, Default)]
+struct Listeners {
+    outcomes: Option<mpsc::UnboundedSender<TransactionOutcome>>,
+    dead_letters: Option<mpsc::UnboundedSender<DeadLetter>>,
+    /// State of every watched client, shared with the workers
+    watched: Arc<RwLock<HashMap<u16, watch::Sender<AccountState>>>>,
+}
+
+impl Listeners {
+    /// Copy of the transaction as it was read, if a rejection would need it.
+    fn keep(&self, transaction: &Transaction) -> Option<Transaction> {
+        self.dead_letters.as_ref().map(|_| transaction.clone())
+    }
+
+    /// Tells the watchers of the account about its state after a transaction.
+    fn changed(&self, account: &Account) {
+        let watched = self.watched.read().expect("Watched accounts poisoned");
+        if let Some(state) = watched.get(&account.client()) {
+            state.send_replace(account.state());
+        }
+    }
+
+    fn accepted(&self, row: Option<u64>, client: u16, tx: u32) {
+        if let Some(outcomes) = &self.outcomes {
+            let _ = outcomes.send(TransactionOutcome::accepted(row, client, tx));
+        }
+    }
+
+    fn rejected(&self, rejection: &Rejection, original: Option<Transaction>) {
+        if let Some(outcomes) = &self.outcomes {
+            let _ = outcomes.send(TransactionOutcome::rejected(rejection));
+        }
+        if let (Some(dead_letters), Some(transaction)) = (&self.dead_letters, original) {
+            let _ = dead_letters.send(DeadLetter {
+                transaction,
+                error: rejection.error.kind.clone(),
+            });
+        }
+    }
+}
+
+/// What a worker is asked to do, in the order of its queue.
+enum Task {
+    Job(Box<Job>),
+    /// Locks the account and hands it to the worker of a transfer to it, which gives it
+    /// back by dropping it
+    Lend(
+        Arc<Mutex<Account>>,
+        oneshot::Sender<OwnedMutexGuard<Account>>,
+    ),
+}
+
+/// Transaction queued on a worker.
+struct Job {
+    account: Arc<Mutex<Account>>,
+    transaction: Transaction,
+    work: Work,
+    /// Span of the whole transaction
+    span: Span,
+    /// Span of its wait for the worker
+    queued: Span,
+    /// When it was submitted
+    submitted: Instant,
+}
+
+/// How a worker applies a job's transaction.
+enum Work {
+    /// To the job's account
+    Apply,
+    /// As a transfer from the job's account to another one
+    Transfer(Destination),
+    /// As a transfer from the job's account to a client of another partition
+    TransferOut,
+}
+
+/// Account a transfer goes to.
+enum Destination {
+    /// Account of a client of the same worker
+    Local(Arc<Mutex<Account>>),
+    /// Account of a client of another worker, lent once that worker caught up with the
+    /// transfer
+    Lent(oneshot::Receiver<OwnedMutexGuard<Account>>),
+}
+
+/// Span of a transaction's way through the engine, the first of a trace of its own.
+fn transaction_span(transaction: &Transaction) -> Span {
+    tracing::info_span!(
+        parent: None,
+        "transaction",
+        tx = transaction.tx,
+        client = transaction.client,
+        "type" = ?transaction.transaction_type,
+        error = field::Empty,
+    )
+}
+
+/// Tells the store about a rejected transaction and logs it.
+fn record_rejection(store: &dyn StateStore, rejection: &Rejection, account: Option<&Account>) {
+    tracing::info!(
+        tx = rejection.error.tx,
+        client = rejection.error.client,
+        row = rejection.row,
+        code = rejection.error.code(),
+        error = rejection.error.reason(),
+        "transaction rejected"
+    );
+    store.reject(rejection, account);
+}
+
+/// Logs the account getting locked or unlocked by transaction `tx`.
+fn log_lock_change(was_locked: bool, account: &Account, tx: u32) {
+    match (was_locked, account.locked()) {
+        (false, true) => tracing::warn!(client = account.client(), tx, "account locked"),
+        (true, false) => tracing::info!(client = account.client(), tx, "account unlocked"),
+        _ => {}
+    }
+}
+
+/// Records why a transaction was rejected on its span.
+fn reject_span(span: &Span, error: &TransactionProcessingError) {
+    if !span.is_disabled() {
+        span.record("error", error.reason());
+    }
+}
+
+/// Fresh account of `client` set up as the config asks.
+fn new_account(config: &EngineConfig, client: u16) -> Account {
+    let mut account = Account::new(client).with_chargeback_policy(config.chargeback_policy);
+    if let Some(fees) = &config.fees {
+        account = account.with_fee_schedule(fees.clone());
+    }
+    if let Some(rate) = config.interest_rate {
+        account = account.with_interest_rate(rate);
+    }
+    if config.full_history {
+        account = account.with_full_history();
+    }
+    if let Some(window) = &config.history_window {
+        account = account.with_history_window(window.clone());
+    }
+    if config.statements.contains(&client) {
+        account = account.with_statement();
+    }
+    if config.event_log {
+        account = account.with_event_log();
+    }
+    let overdraft_limit = config.overdraft_limits.get(&client);
+    account
+        .with_overdraft_limit(*overdraft_limit.unwrap_or(&config.overdraft_limit))
+        .with_limits(config.limits.for_client(client))
+        .with_fraud_rules(config.fraud_rules.clone())
+}
+
+fn invariant_checker(config: &EngineConfig) -> Option<InvariantChecker> {
+    config
+        .check_invariants
+        .then(|| InvariantChecker::new(config.chargeback_policy))
+}
+
+/// Applies the transaction to the account, trying again after a backoff while it fails
+/// transiently.
+async fn apply(
+    account: &mut Account,
+    mut transaction: Transaction,
+    retry: RetryPolicy,
+) -> Result<(), ErrorKind> {
+    let mut attempt = 1;
+    loop {
+        let again = retry.retries(attempt).then(|| transaction.clone());
+        account.add_transaction(transaction);
+        match (account.process_pending_transaction(), again) {
+            (Err(e), Some(again)) if e.is_transient() => {
+                tokio::time::sleep(retry.backoff(attempt)).await;
+                attempt += 1;
+                transaction = again;
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+/// Moves the amount of a transfer between the two locked accounts, telling the invariant
+/// checker, the store, the listeners and the tally.
+fn apply_transfer(
+    source: &mut Account,
+    destination: &mut Account,
+    transaction: Transaction,
+    store: &dyn StateStore,
+    listeners: &Listeners,
+    invariants: Option<&InvariantChecker>,
+    tally: &mut Tally,
+) -> Result<(), ErrorKind> {
+    let (tx, amount, currency) = (
+        transaction.tx,
+        transaction.amount,
+        transaction.currency.clone(),
+    );
+    let checked = invariants.map(|_| {
+        let before = (Before::of(source), Before::of(destination));
+        (before, transaction.clone())
+    });
+    let result = source.transfer(destination, transaction);
+    if let (Some(invariants), Some(((from, to), transaction))) = (invariants, checked) {
+        let accounts = [(from, &*source), (to, &*destination)];
+        invariants.check(&transaction, result.is_ok(), &accounts);
+    }
+    result?;
+    store.append_history(source, tx);
+    store.append_history(destination, tx);
+    listeners.changed(source);
+    listeners.changed(destination);
+    tally.accept(source.client(), TransactionType::Transfer, amount, currency);
+    tally.clients.insert(destination.client());
+    Ok(())
+}
+
+/// Takes the amount of a transfer to a client of another partition from the locked
+/// sender, handing back the prepare asking that partition to credit it.
+fn apply_transfer_out(
+    source: &mut Account,
+    transaction: Transaction,
+    store: &dyn StateStore,
+    listeners: &Listeners,
+    invariants: Option<&InvariantChecker>,
+    tally: &mut Tally,
+) -> Result<Option<TransferMessage>, ErrorKind> {
+    let prepare = TransferMessage::prepare(&transaction);
+    let (tx, amount, currency) = (
+        transaction.tx,
+        transaction.amount,
+        transaction.currency.clone(),
+    );
+    let checked = invariants.map(|_| (Before::of(source), transaction.clone()));
+    let result = source.transfer_out(transaction);
+    if let (Some(invariants), Some((before, transaction))) = (invariants, checked) {
+        invariants.check(&transaction, result.is_ok(), &[(before, &*source)]);
+    }
+    result?;
+    store.append_history(source, tx);
+    listeners.changed(source);
+    tally.accept(source.client(), TransactionType::Transfer, amount, currency);
+    Ok(prepare)
+}
+
+/// Sender's account of a transfer and the recipient's with its client.
+type TransferAccounts = (Arc<Mutex<Account>>, Option<(u16, Arc<Mutex<Account>>)>);
+
+/// What a worker hands back once its queue is closed.
+type Finished = (Vec<Rejection>, Tally, Latencies, Vec<TransferMessage>);
+
+/// Applies the shard's transactions strictly in the order they were submitted.
+///
+/// A transfer is queued on the sender's worker. When the recipient belongs to another
+/// worker, that worker is queued a [`Task::Lend`] at the same time and the transfer waits
+/// for the account, so it sees every earlier transaction of both clients and none of the
+/// later ones, while the other clients of both workers go on.
+async fn worker(
+    mut receiver: mpsc::Receiver<Task>,
+    store: Arc<dyn StateStore>,
+    listeners: Listeners,
+    retry: RetryPolicy,
+    apply_time: Arc<AtomicU64>,
+    invariants: Option<InvariantChecker>,
+    #[cfg(any(test, feature = "chaos"))] chaos: Option<Chaos>,
+) -> Finished {
+    let mut rejections = Vec::new();
+    let mut tally = Tally::default();
+    let mut latencies = Latencies::new();
+    let mut transfer_messages = Vec::new();
+    while let Some(task) = receiver.recv().await {
+        let job = match task {
+            Task::Job(job) => *job,
+            Task::Lend(account, borrower) => {
+                let _ = borrower.send(account.lock_owned().await);
+                continue;
+            }
+        };
+        let Job {
+            account,
+            transaction,
+            work,
+            span,
+            queued,
+            submitted,
+        } = job;
+        drop(queued);
+        let applying = tracing::info_span!(parent: &span, "apply");
+        let (row, client, tx, timestamp, amount) = (
+            transaction.row,
+            transaction.client,
+            transaction.tx,
+            transaction.timestamp,
+            transaction.amount,
+        );
+        #[cfg(any(test, feature = "chaos"))]
+        if let Some(chaos) = &chaos {
+            chaos.delay().await;
+        }
+        let original = listeners.keep(&transaction);
+        let (transaction_type, currency) = (
+            transaction.transaction_type.clone(),
+            transaction.currency.clone(),
+        );
+        let (account, result) = match work {
+            Work::Apply => {
+                let mut account = account.lock_owned().await;
+                let was_locked = account.locked();
+                let checked = invariants
+                    .as_ref()
+                    .map(|_| (Before::of(&account), transaction.clone()));
+                let started = Instant::now();
+                let result = apply(&mut account, transaction, retry).await;
+                apply_time.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                if let (Some(invariants), Some((before, transaction))) = (&invariants, checked) {
+                    invariants.check(&transaction, result.is_ok(), &[(before, &account)]);
+                }
+                if result.is_ok() {
+                    log_lock_change(was_locked, &account, tx);
+                    store.append_history(&account, tx);
+                    listeners.changed(&account);
+                    tally.accept(client, transaction_type.clone(), amount, currency.clone());
+                }
+                (account, result)
+            }
+            Work::Transfer(destination) => {
+                let accounts = match destination {
+                    Destination::Local(destination) => {
+                        let source_first = transaction.to_client.is_some_and(|to| client < to);
+                        Ok(lock_pair(account, destination, source_first).await)
+                    }
+                    Destination::Lent(lent) => {
+                        let source = account.lock_owned().await;
+                        // The loan is only dropped unsent when the recipient's worker is gone
+                        match lent.await {
+                            Ok(destination) => Ok((source, destination)),
+                            Err(_) => Err(source),
+                        }
+                    }
+                };
+                match accounts {
+                    Ok((mut source, mut destination)) => {
+                        let started = Instant::now();
+                        let result = apply_transfer(
+                            &mut source,
+                            &mut destination,
+                            transaction,
+                            store.as_ref(),
+                            &listeners,
+                            invariants.as_ref(),
+                            &mut tally,
+                        );
+                        apply_time
+                            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                        (source, result)
+                    }
+                    Err(source) => (source, Err(ErrorKind::RecipientUnavailable)),
+                }
+            }
+            Work::TransferOut => {
+                let mut source = account.lock_owned().await;
+                let started = Instant::now();
+                let result = apply_transfer_out(
+                    &mut source,
+                    transaction,
+                    store.as_ref(),
+                    &listeners,
+                    invariants.as_ref(),
+                    &mut tally,
+                );
+                apply_time.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                (
+                    source,
+                    result.map(|prepare| transfer_messages.extend(prepare)),
+                )
+            }
+        };
+        match result {
+            Ok(()) => listeners.accepted(row, client, tx),
+            Err(kind) => {
+                let rejection = Rejection {
+                    row,
+                    timestamp,
+                    transaction_type,
+                    currency,
+                    error: TransactionProcessingError {
+                        client,
+                        tx,
+                        amount,
+                        kind,
+                    },
+                };
+                reject_span(&span, &rejection.error);
+                record_rejection(store.as_ref(), &rejection, Some(&*account));
+                listeners.rejected(&rejection, original);
+                rejections.push(rejection);
+            }
+        }
+        drop(account);
+        latencies.record(submitted.elapsed());
+        drop(applying);
+    }
+    (rejections, tally, latencies, transfer_messages)
+}
+
+/// Locks the accounts of a transfer in client id order, so concurrent transfers between
+/// the same clients can't deadlock.
+async fn lock_pair(
+    source: Arc<Mutex<Account>>,
+    destination: Arc<Mutex<Account>>,
+    source_first: bool,
+) -> (OwnedMutexGuard<Account>, OwnedMutexGuard<Account>) {
+    if source_first {
+        let source = source.lock_owned().await;
+        (source, destination.lock_owned().await)
+    } else {
+        let destination = destination.lock_owned().await;
+        (source.lock_owned().await, destination)
+    }
+}
+
+/// Payments engine owning every client account.
+///
+/// Transactions can either be awaited one by one with [`Engine::process`] or handed off
+/// to worker tasks with [`Engine::submit`]. Every client is pinned to one worker, so its
+/// transactions are applied in submission order while different clients are processed
+/// in parallel. A transfer holds up the workers of its two clients only. Submitted
+/// transactions are tracked until [`Engine::wait`] collects them, which the report does
+/// before it is written.
+pub struct Engine {
+    accounts: Arc<dyn StateStore>,
+    config: EngineConfig,
+    shards: Vec<mpsc::Sender<Task>>,
+    workers: JoinSet<Finished>,
+    rejections: Vec<Rejection>,
+    tally: Tally,
+    latencies: Latencies,
+    /// Messages to other partitions settling transfers, see [`Engine::settle`]
+    transfer_messages: Vec<TransferMessage>,
+    transaction_ids: TransactionIds,
+    /// Highest transaction id of restored state, new transactions have to go above it
+    restored_id: Option<u32>,
+    listeners: Listeners,
+    stats: EngineStats,
+    /// Nanoseconds the workers spent applying transactions
+    apply_time: Arc<AtomicU64>,
+    invariants: Option<InvariantChecker>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            accounts: Arc::new(MemoryStore::new()),
+            config: EngineConfig::default(),
+            shards: Vec::new(),
+            workers: JoinSet::new(),
+            rejections: Vec::new(),
+            tally: Tally::default(),
+            latencies: Latencies::new(),
+            transfer_messages: Vec::new(),
+            transaction_ids: TransactionIds::default(),
+            restored_id: None,
+            listeners: Listeners::default(),
+            stats: EngineStats::default(),
+            apply_time: Arc::new(AtomicU64::new(0)),
+            invariants: None,
+        }
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Engine keeping its accounts in `store` instead of memory only.
+    pub fn with_store(config: EngineConfig, store: Arc<dyn StateStore>) -> Self {
+        Self {
+            accounts: store,
+            transaction_ids: TransactionIds::new(config.id_filter.as_ref()),
+            invariants: invariant_checker(&config),
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            transaction_ids: TransactionIds::new(config.id_filter.as_ref()),
+            invariants: invariant_checker(&config),
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Stream of the outcome of every transaction handed to the engine from now on, in
+    /// the order they are decided: accepted, or rejected with the reason. Transactions of
+    /// one client keep their order, those of different clients may interleave.
+    ///
+    /// Replaces the stream of an earlier call. Workers started before keep sending to the
+    /// old stream until [`Engine::wait`], so ask for outcomes before submitting.
+    pub fn outcomes(&mut self) -> mpsc::UnboundedReceiver<TransactionOutcome> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.listeners.outcomes = Some(sender);
+        receiver
+    }
+
+    /// Stream of every transaction rejected from now on, as it was read and with the
+    /// error it was rejected for, to be fixed and submitted again. Like
+    /// [`Engine::outcomes`], it replaces the stream of an earlier call.
+    pub fn dead_letters(&mut self) -> mpsc::UnboundedReceiver<DeadLetter> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.listeners.dead_letters = Some(sender);
+        receiver
+    }
+
+    /// Current state of the client's account, updated whenever a transaction changed it,
+    /// so applications can follow clients without polling. Clients without an account yet
+    /// start out empty.
+    ///
+    /// Unlike [`Engine::outcomes`], watching works for transactions of workers started
+    /// before, and every call adds another receiver of the same client.
+    pub async fn watch(&self, client: u16) -> watch::Receiver<AccountState> {
+        // Locked before the watchers, like workers do, so no change slips in between
+        let account = self.accounts.get(client);
+        let account = match &account {
+            Some(account) => Some(account.lock().await),
+            None => None,
+        };
+        let mut watched = self
+            .listeners
+            .watched
+            .write()
+            .expect("Watched accounts poisoned");
+        watched
+            .entry(client)
+            .or_insert_with(|| {
+                let state = match &account {
+                    Some(account) => account.state(),
+                    None => AccountState {
+                        client,
+                        ..AccountState::default()
+                    },
+                };
+                watch::channel(state).0
+            })
+            .subscribe()
+    }
+
+    async fn account_for(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<Arc<Mutex<Account>>, ErrorKind> {
+        let client = transaction.client;
+        if self.config.partition.is_some_and(|p| !p.contains(client)) {
+            return Err(ErrorKind::ClientOutsidePartition(client));
+        }
+        if transaction.transaction_type.is_admin() && !self.config.allow_admin_ops {
+            return Err(ErrorKind::AdminOperationNotAllowed);
+        }
+        if transaction.transaction_type.has_own_id() {
+            let ids = &mut self.transaction_ids;
+            #[cfg(any(test, feature = "chaos"))]
+            let chaos = self.config.chaos.as_ref();
+            let inserted = self
+                .config
+                .retry
+                .run(|| {
+                    #[cfg(any(test, feature = "chaos"))]
+                    if let Some(chaos) = chaos {
+                        return std::future::ready(chaos.storage(|| ids.insert(transaction.tx)));
+                    }
+                    std::future::ready(ids.insert(transaction.tx))
+                })
+                .await;
+            match inserted {
+                Ok(true) => {}
+                Ok(false) => return Err(ErrorKind::DuplicateTransactionId(transaction.tx)),
+                Err(_) => return Err(ErrorKind::DuplicateCheckFailed(transaction.tx)),
+            }
+        }
+        if transaction.transaction_type.has_own_id()
+            && self.restored_id.is_some_and(|id| transaction.tx <= id)
+        {
+            return Err(ErrorKind::TransactionIdRegression(transaction.tx));
+        }
+
+        Ok(self.account_entry(client))
+    }
+
+    fn account_entry(&mut self, client: u16) -> Arc<Mutex<Account>> {
+        if let Some(account) = self.accounts.get(client) {
+            return account;
+        }
+        let account = Arc::new(Mutex::new(new_account(&self.config, client)));
+        self.accounts.put(client, account.clone());
+        account
+    }
+
+    /// Applies a transfer to both of its accounts, or takes its amount from the sender when
+    /// the recipient is a client of another partition and asks that partition to credit it.
+    async fn transfer(&mut self, transaction: Transaction) -> Result<(), ErrorKind> {
+        let (source, destination) = self.transfer_accounts(&transaction).await?;
+        let Some((to, destination)) = destination else {
+            let mut source = source.lock().await;
+            let prepare = apply_transfer_out(
+                &mut source,
+                transaction,
+                self.accounts.as_ref(),
+                &self.listeners,
+                self.invariants.as_ref(),
+                &mut self.tally,
+            )?;
+            self.transfer_messages.extend(prepare);
+            return Ok(());
+        };
+        let source_first = transaction.client < to;
+        let (mut source, mut destination) = lock_pair(source, destination, source_first).await;
+        apply_transfer(
+            &mut source,
+            &mut destination,
+            transaction,
+            self.accounts.as_ref(),
+            &self.listeners,
+            self.invariants.as_ref(),
+            &mut self.tally,
+        )
+    }
+
+    /// Accounts of the sender and the recipient of a transfer, no recipient when it is a
+    /// client of another partition.
+    async fn transfer_accounts(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<TransferAccounts, ErrorKind> {
+        let source = self.account_for(transaction).await?;
+        let to = match transaction.to_client {
+            Some(to) if to != transaction.client => to,
+            _ => return Err(ErrorKind::InvalidTransfer),
+        };
+        if self.config.partition.is_some_and(|p| !p.contains(to)) {
+            if !self.config.cross_partition_transfers {
+                return Err(ErrorKind::ClientOutsidePartition(to));
+            }
+            return Ok((source, None));
+        }
+        Ok((source, Some((to, self.account_entry(to)))))
+    }
+
+    /// Settles a transfer between clients of different partitions with a message of the
+    /// other partition, see [`TransferStep`]: a prepare credits the destination client and
+    /// is answered with a commit, or with an abort when the account refuses it, a commit
+    /// completes the sender's transfer and an abort gives the sender the amount back.
+    ///
+    /// The client the message is for has to be in the engine's partition. Every prepare is
+    /// answered, so the sender's amount never stays in transit: a prepare that can't be
+    /// credited, e.g. because its transaction id was seen before, with an abort. A prepare
+    /// that was credited already is delivered again, not credited twice, and answered with
+    /// a commit again. Answers are collected by [`Engine::take_transfer_messages`].
+    pub async fn settle(
+        &mut self,
+        message: TransferMessage,
+    ) -> Result<(), TransactionProcessingError> {
+        let transaction = message.transaction();
+        let span = transaction_span(&transaction);
+        let account = match self.settling_account(&message) {
+            Ok(account) => account,
+            Err(ErrorKind::DuplicateTransactionId(_)) if self.credited(&message).await => {
+                self.transfer_messages
+                    .push(message.reply(TransferStep::Commit, None));
+                return Ok(());
+            }
+            Err(error) => {
+                if message.step == TransferStep::Prepare {
+                    let reason = Some(error.to_string());
+                    self.transfer_messages
+                        .push(message.reply(TransferStep::Abort, reason));
+                }
+                let rejection = Rejection::new(&transaction, error);
+                return Err(self.reject(rejection, None, None, &span));
+            }
+        };
+        let mut account = account.lock().await;
+        let before = self.invariants.as_ref().map(|_| Before::of(&account));
+        let result = match message.step {
+            TransferStep::Prepare => account.transfer_in(&transaction),
+            TransferStep::Commit => account.complete_transfer(message.tx),
+            TransferStep::Abort => account.return_transfer(message.tx),
+        };
+        if let (Some(invariants), Some(before)) = (&self.invariants, before) {
+            invariants.check(&transaction, result.is_ok(), &[(before, &account)]);
+        }
+        if message.step == TransferStep::Prepare {
+            self.transfer_messages.push(match &result {
+                Ok(()) => message.reply(TransferStep::Commit, None),
+                Err(error) => message.reply(TransferStep::Abort, Some(error.to_string())),
+            });
+        }
+        match result {
+            Ok(()) => {
+                self.accounts.append_history(&account, message.tx);
+                self.listeners.changed(&account);
+                self.listeners
+                    .accepted(None, transaction.client, transaction.tx);
+                self.tally.clients.insert(account.client());
+                Ok(())
+            }
+            Err(error) => {
+                let rejection = Rejection::new(&transaction, error);
+                Err(self.reject(rejection, Some(&account), None, &span))
+            }
+        }
+    }
+
+    /// Account of the client a transfer message is for.
+    fn settling_account(
+        &mut self,
+        message: &TransferMessage,
+    ) -> Result<Arc<Mutex<Account>>, ErrorKind> {
+        let client = message.recipient();
+        if self.config.partition.is_some_and(|p| !p.contains(client)) {
+            return Err(ErrorKind::ClientOutsidePartition(client));
+        }
+        if message.step != TransferStep::Prepare {
+            return self.accounts.get(client).ok_or(ErrorKind::InvalidTransfer);
+        }
+        match self.transaction_ids.insert(message.tx) {
+            Ok(true) => Ok(self.account_entry(client)),
+            Ok(false) => Err(ErrorKind::DuplicateTransactionId(message.tx)),
+            Err(_) => Err(ErrorKind::DuplicateCheckFailed(message.tx)),
+        }
+    }
+
+    /// Whether the prepare is one the destination account was credited with before.
+    async fn credited(&self, message: &TransferMessage) -> bool {
+        let Some(account) = self.accounts.get(message.to_client) else {
+            return false;
+        };
+        let account = account.lock().await;
+        account
+            .incoming_transfer(message.tx)
+            .is_some_and(|transfer| {
+                transfer.from_client == message.from_client
+                    && transfer.currency == message.currency
+                    && transfer.amount == message.amount
+            })
+    }
+
+    /// Messages to other partitions settling transfers since the last call: prepares of
+    /// transfers to their clients and answers to their prepares. Prepares of submitted
+    /// transfers are here once [`Engine::wait`] collected them.
+    pub fn take_transfer_messages(&mut self) -> Vec<TransferMessage> {
+        std::mem::take(&mut self.transfer_messages)
+    }
+
+    /// Attaches the credited amount to `convert` transactions, so workers don't need the rates.
+    fn quote(&self, transaction: &mut Transaction) -> Result<(), ErrorKind> {
+        if transaction.transaction_type != TransactionType::Convert {
+            return Ok(());
+        }
+        let (from, to) = match (&transaction.currency, &transaction.to_currency) {
+            (Some(from), Some(to)) if from != to => (from, to),
+            _ => return Err(ErrorKind::InvalidConversion),
+        };
+        let amount = transaction.amount.ok_or(ErrorKind::InvalidAmount)?;
+        let rates = self
+            .config
+            .exchange_rates
+            .as_ref()
+            .ok_or(ErrorKind::MissingExchangeRate)?;
+        transaction.converted = Some(rates.convert(from, to, amount)?);
+        Ok(())
+    }
+
+    /// Applies the transaction to its client's account and waits for the result.
+    pub async fn process(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let (row, client, tx, timestamp, amount) = (
+            transaction.row,
+            transaction.client,
+            transaction.tx,
+            transaction.timestamp,
+            transaction.amount,
+        );
+        let span = transaction_span(&transaction);
+        let original = self.listeners.keep(&transaction);
+        let (transaction_type, currency) = (
+            transaction.transaction_type.clone(),
+            transaction.currency.clone(),
+        );
+        let rejection = |kind| Rejection {
+            row,
+            timestamp,
+            transaction_type: transaction_type.clone(),
+            currency: currency.clone(),
+            error: TransactionProcessingError {
+                client,
+                tx,
+                amount,
+                kind,
+            },
+        };
+        if transaction.transaction_type == TransactionType::Transfer {
+            return match self.transfer(transaction).await {
+                Ok(()) => {
+                    self.listeners.accepted(row, client, tx);
+                    Ok(())
+                }
+                Err(error) => Err(self.reject(rejection(error), None, original, &span)),
+            };
+        }
+        let account = match self
+            .account_for(&transaction)
+            .await
+            .and_then(|account| self.quote(&mut transaction).map(|_| account))
+        {
+            Ok(account) => account,
+            Err(error) => return Err(self.reject(rejection(error), None, original, &span)),
+        };
+        let mut account = account.lock().await;
+        let was_locked = account.locked();
+        let checked = self
+            .invariants
+            .as_ref()
+            .map(|_| (Before::of(&account), transaction.clone()));
+        let applying = tracing::info_span!(parent: &span, "apply");
+        let started = Instant::now();
+        let result = apply(&mut account, transaction, self.config.retry).await;
+        let elapsed = started.elapsed().as_nanos() as u64;
+        self.apply_time.fetch_add(elapsed, Ordering::Relaxed);
+        drop(applying);
+        if let (Some(invariants), Some((before, transaction))) = (&self.invariants, checked) {
+            invariants.check(&transaction, result.is_ok(), &[(before, &account)]);
+        }
+        match result {
+            Ok(()) => {
+                log_lock_change(was_locked, &account, tx);
+                self.accounts.append_history(&account, tx);
+                self.listeners.changed(&account);
+                self.listeners.accepted(row, client, tx);
+                self.tally
+                    .accept(client, transaction_type, amount, currency);
+                Ok(())
+            }
+            Err(error) => Err(self.reject(rejection(error), Some(&account), original, &span)),
+        }
+    }
+
+    /// Tells the store, the log, the listeners and the span about the rejection and keeps
+    /// it for the report, handing back its error.
+    fn reject(
+        &mut self,
+        rejection: Rejection,
+        account: Option<&Account>,
+        original: Option<Transaction>,
+        span: &Span,
+    ) -> TransactionProcessingError {
+        reject_span(span, &rejection.error);
+        record_rejection(self.accounts.as_ref(), &rejection, account);
+        self.listeners.rejected(&rejection, original);
+        let error = rejection.error.clone();
+        self.rejections.push(rejection);
+        error
+    }
+
+    /// Whether the [`DuplicatePolicy`] stops processing on this error.
+    fn aborts_on(&self, error: &ErrorKind) -> bool {
+        matches!(error, ErrorKind::DuplicateTransactionId(_))
+            && self.config.duplicate_policy == DuplicatePolicy::Abort
+    }
+
+    fn shard_for(&mut self, client: u16) -> &mpsc::Sender<Task> {
+        if self.shards.is_empty() {
+            for _ in 0..self.config.workers.max(1) {
+                let (sender, receiver) = mpsc::channel(self.config.channel_capacity.max(1));
+                self.workers.spawn(worker(
+                    receiver,
+                    self.accounts.clone(),
+                    self.listeners.clone(),
+                    self.config.retry,
+                    self.apply_time.clone(),
+                    self.invariants.clone(),
+                    #[cfg(any(test, feature = "chaos"))]
+                    self.config.chaos.clone(),
+                ));
+                self.shards.push(sender);
+            }
+        }
+
+        let shard = client as usize % self.shards.len();
+        &self.shards[shard]
+    }
+
+    /// Queues the transaction on its client's worker, waiting for room when the worker's
+    /// queue is full. A transfer is queued on the sender's worker, which waits for the
+    /// recipient's worker to catch up with it.
+    ///
+    /// Rejections are collected by [`Engine::wait`]; an error is only returned when the
+    /// [`DuplicatePolicy`] requires processing to stop.
+    pub async fn submit(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let submitted = Instant::now();
+        let span = transaction_span(&transaction);
+        let transfer = transaction.transaction_type == TransactionType::Transfer;
+        let accounts = if transfer {
+            self.transfer_accounts(&transaction).await
+        } else {
+            self.account_for(&transaction)
+                .await
+                .and_then(|account| self.quote(&mut transaction).map(|_| (account, None)))
+        };
+        let (account, destination) = match accounts {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                self.latencies.record(submitted.elapsed());
+                let abort = self.aborts_on(&e);
+                let rejection = Rejection::new(&transaction, e);
+                let e = self.reject(rejection, None, Some(transaction), &span);
+                return if abort { Err(e) } else { Ok(()) };
+            }
+        };
+
+        let shard = self.shard_for(transaction.client).clone();
+        // The recipient's worker lends its account once it caught up, see `worker`
+        let mut lend = None;
+        let work = match destination {
+            _ if !transfer => Work::Apply,
+            None => Work::TransferOut,
+            Some((to, destination)) => {
+                let lender = self.shard_for(to).clone();
+                if lender.same_channel(&shard) {
+                    Work::Transfer(Destination::Local(destination))
+                } else {
+                    let (sender, receiver) = oneshot::channel();
+                    lend = Some((lender, Task::Lend(destination, sender)));
+                    Work::Transfer(Destination::Lent(receiver))
+                }
+            }
+        };
+        let queued = tracing::info_span!(parent: &span, "queue");
+        let job = Task::Job(Box::new(Job {
+            account,
+            transaction,
+            work,
+            span,
+            queued,
+            submitted,
+        }));
+        #[cfg(any(test, feature = "chaos"))]
+        if let Some(chaos) = &self.config.chaos {
+            // A lost transaction is noticed and sent again, later ones of the client wait
+            let mut attempt = 1;
+            while chaos.drop_message() {
+                tokio::time::sleep(self.config.retry.backoff(attempt)).await;
+                attempt += 1;
+            }
+        }
+        let sent = match shard.try_send(job) {
+            Err(TrySendError::Full(job)) => {
+                self.stats.queue_full += 1;
+                shard.send(job).await.is_ok()
+            }
+            sent => sent.is_ok(),
+        };
+        if sent {
+            self.stats.queued += 1;
+            let waiting = shard.max_capacity() - shard.capacity();
+            self.stats.peak_queue = self.stats.peak_queue.max(waiting);
+        }
+        if let Some((lender, lend)) = lend {
+            let _ = lender.send(lend).await;
+        }
+        Ok(())
+    }
+
+    /// Whether a transaction broke an invariant so far, always false unless
+    /// [`EngineConfig::check_invariants`] is set.
+    pub fn invariants_broken(&self) -> bool {
+        self.invariants
+            .as_ref()
+            .is_some_and(InvariantChecker::broken)
+    }
+
+    /// Waits for all submitted transactions, checks that the accounts together hold what
+    /// flowed into them and returns every invariant violation of the run.
+    pub async fn check_invariants(&mut self) -> Vec<Violation> {
+        self.wait().await;
+        match self.invariants.clone() {
+            Some(invariants) => invariants.finish(&self.accounts().await),
+            None => Vec::new(),
+        }
+    }
+
+    /// Transactions accepted so far. Those still queued count once [`Engine::wait`]
+    /// collected them.
+    pub fn tally(&self) -> &Tally {
+        &self.tally
+    }
+
+    /// Statistics of everything submitted so far, waiting for it first, for a run that
+    /// took `elapsed`.
+    pub async fn summary(&mut self, elapsed: Duration) -> Summary {
+        self.wait().await;
+        let mut accounts = Vec::new();
+        for account in self.stored_accounts() {
+            accounts.push(account.lock_owned().await);
+        }
+        let accounts = accounts.iter().map(|account| &**account);
+        Summary::new(&self.tally, accounts, &self.rejections, elapsed)
+    }
+
+    /// Time from submitting each transaction until it was decided, for benchmarks.
+    /// Transactions still queued count once [`Engine::wait`] collected them.
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// How busy the engine was so far, for benchmarks. Transactions still queued count
+    /// towards `applying` once a worker got to them.
+    pub fn stats(&self) -> EngineStats {
+        EngineStats {
+            applying: Duration::from_nanos(self.apply_time.load(Ordering::Relaxed)),
+            ..self.stats
+        }
+    }
+
+    /// Transactions waiting in the worker queues right now.
+    pub fn queue_depth(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.max_capacity() - shard.capacity())
+            .sum()
+    }
+
+    /// Accounts locked so far, without waiting for queued transactions.
+    pub async fn locked_count(&self) -> usize {
+        let mut locked = 0;
+        for account in self.stored_accounts() {
+            if account.lock().await.locked() {
+                locked += 1;
+            }
+        }
+        locked
+    }
+
+    /// Waits until every submitted transaction has been processed and returns all
+    /// rejections so far, ordered by input row.
+    pub async fn wait(&mut self) -> &[Rejection] {
+        self.shards.clear();
+
+        while let Some(result) = self.workers.join_next().await {
+            match result {
+                Ok((rejections, tally, latencies, transfer_messages)) => {
+                    self.rejections.extend(rejections);
+                    self.transfer_messages.extend(transfer_messages);
+                    self.tally.merge(tally);
+                    self.latencies.merge(&latencies);
+                }
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(_) => {}
+            }
+        }
+        self.rejections
+            .sort_by_key(|r| (r.row.is_none(), r.row, r.error.tx));
+        &self.rejections
+    }
+
+    /// Takes over the fees, overdraft limits, limits and fraud rules of `config`, e.g. once
+    /// their files changed, for every account and the transactions submitted from now on.
+    /// Transactions already submitted are applied under the previous ones first. Accounts
+    /// keep their balances and history and the rest of the config stays as it was.
+    pub async fn reconfigure(&mut self, config: EngineConfig) {
+        self.wait().await;
+        self.config.fees = config.fees;
+        self.config.overdraft_limit = config.overdraft_limit;
+        self.config.overdraft_limits = config.overdraft_limits;
+        self.config.limits = config.limits;
+        self.config.fraud_rules = config.fraud_rules;
+        for account in self.stored_accounts() {
+            let mut account = account.lock().await;
+            let client = account.client();
+            let overdraft_limit = self.config.overdraft_limits.get(&client);
+            account.reconfigure(
+                self.config.fees.clone(),
+                *overdraft_limit.unwrap_or(&self.config.overdraft_limit),
+                self.config.limits.for_client(client),
+                self.config.fraud_rules.clone(),
+            );
+        }
+    }
+
+    /// Writes every rejected submitted transaction as csv.
+    pub async fn write_rejections(&mut self, writer: impl io::Write) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(writer);
+        writer.write_record([
+            "row",
+            "client",
+            "tx",
+            "timestamp",
+            "amount",
+            "code",
+            "reason",
+        ])?;
+        for rejection in self.wait().await {
+            writer.serialize(rejection)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Waits for all submitted transactions and captures the state of every account and
+    /// the transaction ids seen so far. Fails when spilled history can't be read.
+    pub async fn snapshot(&mut self) -> io::Result<Snapshot> {
+        self.wait().await;
+        let mut accounts = Vec::new();
+        for account in self.stored_accounts() {
+            accounts.push(account.lock().await.snapshot()?);
+        }
+        let transaction_ids = self.transaction_ids.sorted();
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts,
+            transaction_ids,
+            cursor: None,
+            topic: None,
+        })
+    }
+
+    /// Takes over the accounts and transaction ids of a snapshot. Accounts are set up from
+    /// this engine's config and only take over the state of their balances and history.
+    /// Transactions with an id of their own are rejected from then on unless their id is
+    /// above all of the snapshot's, except after checkpoints, which continue the same
+    /// inputs. Has to be called before any transaction is submitted.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Snapshot version {} isn't supported, expected {}",
+                snapshot.version, SNAPSHOT_VERSION
+            ));
+        }
+        for account_state in snapshot.accounts {
+            let client = account_state.client;
+            let account = new_account(&self.config, client).restore(account_state);
+            self.accounts.put(client, Arc::new(Mutex::new(account)));
+        }
+        match snapshot.cursor {
+            Some(_) => self.transaction_ids.extend(snapshot.transaction_ids),
+            None => self.restore_transaction_ids(snapshot.transaction_ids),
+        }
+        .map_err(|e| format!("Failed to restore transaction ids: {}", e))
+    }
+
+    fn restore_transaction_ids(&mut self, ids: Vec<u32>) -> io::Result<()> {
+        self.restored_id = self.restored_id.max(ids.iter().max().copied());
+        self.transaction_ids.extend(ids)
+    }
+
+    /// Loads accounts and seen transaction ids persisted by an earlier run. Accounts are set
+    /// up from this engine's config and only take over the state of their balances and
+    /// history. Has to be called before any transaction is submitted.
+    #[cfg(feature = "persistence")]
+    pub fn load_state(&mut self, store: &dyn KeyValueStore) -> Result<(), Box<dyn Error>> {
+        for key in store.keys()? {
+            let Some(client) = state::account_client(&key) else {
+                continue;
+            };
+            if let Some(value) = store.get(&key)? {
+                let account_state = serde_json::from_slice::<AccountSnapshot>(&value)
+                    .map_err(|e| format!("Invalid state of {}: {}", key, e))?;
+                let account = new_account(&self.config, client).restore(account_state);
+                self.accounts.put(client, Arc::new(Mutex::new(account)));
+            }
+        }
+        if let Some(value) = store.get(state::TRANSACTION_IDS_KEY)? {
+            self.restore_transaction_ids(serde_json::from_slice::<Vec<u32>>(&value)?)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for all submitted transactions and persists every account and the transaction
+    /// ids seen so far.
+    #[cfg(feature = "persistence")]
+    pub async fn save_state(&mut self, store: &dyn KeyValueStore) -> Result<(), Box<dyn Error>> {
+        self.wait().await;
+        for account in self.stored_accounts() {
+            let account_state = account.lock().await.snapshot()?;
+            let value = serde_json::to_vec(&account_state)?;
+            store.put(&state::account_key(account_state.client), &value)?;
+        }
+        let transaction_ids = self.transaction_ids.sorted();
+        store.put(
+            state::TRANSACTION_IDS_KEY,
+            &serde_json::to_vec(&transaction_ids)?,
+        )?;
+        store.flush()?;
+        Ok(())
+    }
+
+    /// Waits for all submitted transactions and returns the hits of fraud rules, ordered by
+    /// input row.
+    pub async fn fraud_hits(&mut self) -> Vec<FraudHit> {
+        self.wait().await;
+        let mut hits = Vec::new();
+        for account in self.stored_accounts() {
+            hits.extend_from_slice(account.lock().await.fraud_hits());
+        }
+        hits.sort_by_key(|h| (h.row.is_none(), h.row, h.tx));
+        hits
+    }
+
+    /// Waits for all submitted transactions and returns the processed ledger: every
+    /// transaction in the accounts' history with its dispute state, and every rejected
+    /// transaction, ordered by client and transaction id. Fails when spilled history can't
+    /// be read back.
+    pub async fn ledger(&mut self) -> io::Result<Vec<LedgerEntry>> {
+        self.wait().await;
+        let mut entries = self
+            .rejections
+            .iter()
+            .map(LedgerEntry::rejected)
+            .collect::<Vec<_>>();
+        for account in self.stored_accounts() {
+            entries.extend(account.lock().await.ledger()?);
+        }
+        // Rejections are sorted after an accepted transaction with the same id
+        entries.sort_by_key(|entry| (entry.client, entry.tx, entry.error.is_some()));
+        Ok(entries)
+    }
+
+    /// Writes the processed ledger, see [`Engine::ledger`], in the given format.
+    pub async fn write_ledger(
+        &mut self,
+        writer: impl io::Write,
+        format: ReportFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        let entries = self.ledger().await?;
+        ledger::write_ledger(writer, &entries, &self.config.output_format, format)
+    }
+
+    /// Waits for all submitted transactions and returns the events of every account, by
+    /// client and in the order each account emitted them.
+    pub async fn events(&mut self) -> Vec<(u16, AccountEvent)> {
+        self.wait().await;
+        let mut events = Vec::new();
+        for account in self.stored_accounts() {
+            let account = account.lock().await;
+            let client = account.client();
+            events.extend(account.events().iter().map(|event| (client, event.clone())));
+        }
+        events
+    }
+
+    /// Waits for all submitted transactions and returns the postings of every account's
+    /// events, see [`double_entry::postings`]. Empty unless accounts keep an event log.
+    pub async fn postings(&mut self) -> Vec<Posting> {
+        self.events()
+            .await
+            .iter()
+            .flat_map(|(client, event)| double_entry::postings(*client, event))
+            .collect()
+    }
+
+    /// Waits for all submitted transactions and returns the trial balance of the postings of
+    /// every account's events, see [`double_entry::trial_balance`].
+    pub async fn trial_balance(&mut self) -> Result<Vec<TrialBalanceLine>, Overflow> {
+        double_entry::trial_balance(&self.postings().await)
+    }
+
+    /// Writes the trial balance of every account's postings as csv, failing if it doesn't
+    /// net to zero or its client lines differ from the accounts' balances.
+    pub async fn write_trial_balance(
+        &mut self,
+        writer: impl io::Write,
+    ) -> Result<(), Box<dyn Error>> {
+        let lines = self.trial_balance().await?;
+        double_entry::write_trial_balance(writer, &lines, &self.config.output_format)?;
+        double_entry::reconcile(&lines, &self.accounts().await)?;
+        Ok(())
+    }
+
+    /// Writes the postings of every account's events as csv.
+    pub async fn write_postings(&mut self, writer: impl io::Write) -> Result<(), Box<dyn Error>> {
+        let postings = self.postings().await;
+        double_entry::write_postings(writer, &postings, &self.config.output_format)?;
+        Ok(())
+    }
+
+    /// Writes the events of every account as JSON lines, each tagged with its client.
+    pub async fn write_events(&mut self, writer: impl io::Write) -> Result<(), Box<dyn Error>> {
+        let events = self.events().await;
+        events::write_events(writer, &events)?;
+        Ok(())
+    }
+
+    /// Writes every hit of a fraud rule as csv.
+    pub async fn write_fraud_hits(&mut self, writer: impl io::Write) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(writer);
+        writer.write_record(["row", "client", "tx", "timestamp", "rule", "verdict"])?;
+        for hit in self.fraud_hits().await {
+            writer.serialize(hit)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Every stored account, in client order.
+    fn stored_accounts(&self) -> Vec<Arc<Mutex<Account>>> {
+        self.accounts
+            .clients()
+            .into_iter()
+            .filter_map(|client| self.accounts.get(client))
+            .collect()
+    }
+
+    pub async fn account(&self, client: u16) -> Option<Account> {
+        match self.accounts.get(client) {
+            Some(account) => Some(account.lock().await.to_owned()),
+            None => None,
+        }
+    }
+
+    /// Waits for all submitted transactions and iterates over copies of all accounts, in the
+    /// configured [`ReportOrder`].
+    pub async fn accounts_iter(&mut self) -> impl Iterator<Item = Account> {
+        self.wait().await;
+        self.accounts().await.into_iter()
+    }
+
+    /// Waits for all submitted transactions and returns copies of the locked accounts, in
+    /// the configured [`ReportOrder`].
+    pub async fn locked_accounts(&mut self) -> Vec<Account> {
+        self.accounts_iter().await.filter(Account::locked).collect()
+    }
+
+    /// Waits for all submitted transactions and returns the transactions under dispute,
+    /// ordered by client and transaction id.
+    pub async fn disputed_transactions(&mut self) -> Vec<LedgerEntry> {
+        self.wait().await;
+        let mut entries = Vec::new();
+        for account in self.stored_accounts() {
+            entries.extend(account.lock().await.disputed_transactions());
+        }
+        entries
+    }
+
+    /// Copies of all accounts, in the configured [`ReportOrder`].
+    pub async fn accounts(&self) -> Vec<Account> {
+        let mut accounts = Vec::new();
+        for account in self.stored_accounts() {
+            accounts.push(account.lock().await.to_owned());
+        }
+        match self.config.report_order {
+            ReportOrder::Client => accounts.sort_by_key(Account::client),
+        }
+        accounts
+    }
+
+    /// Writes the account report in the configured format, tagged with the partition if there
+    /// is one.
+    ///
+    /// Waits for all submitted transactions first, so the report never shows partial balances.
+    pub async fn write_report(&mut self, writer: impl io::Write) -> Result<(), Box<dyn Error>> {
+        self.wait().await;
+
+        let _span = tracing::info_span!(parent: None, "report");
+        let accounts = self.accounts().await;
+        self.write_accounts(writer, &accounts)
+    }
+
+    /// Same as [`Engine::write_report`], with the client's account only. The report is
+    /// empty when the client has no account.
+    pub async fn write_client_report(
+        &mut self,
+        writer: impl io::Write,
+        client: u16,
+    ) -> Result<(), Box<dyn Error>> {
+        self.wait().await;
+        let accounts = Vec::from_iter(self.account(client).await);
+        self.write_accounts(writer, &accounts)
+    }
+
+    fn write_accounts(
+        &self,
+        writer: impl io::Write,
+        accounts: &[Account],
+    ) -> Result<(), Box<dyn Error>> {
+        let format = &self.config.output_format;
+        let status = self.config.status_columns();
+        let partition = self.config.partition;
+        match (self.config.report_format, partition) {
+            (ReportFormat::Csv, Some(partition)) => {
+                output::write_partitioned_accounts(writer, accounts, format, status, partition)?
+            }
+            (ReportFormat::Csv, None) => output::write_accounts(writer, accounts, format, status)?,
+            (ReportFormat::Json, _) => {
+                output::write_json_accounts(writer, accounts, format, status, partition)?
+            }
+            (ReportFormat::Jsonl, _) => {
+                output::write_jsonl_accounts(writer, accounts, format, status, partition)?
+            }
+            (ReportFormat::Parquet, _) => {
+                output::write_parquet_accounts(writer, accounts, format, status, partition)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        new_account, worker, Destination, DuplicatePolicy, Engine, EngineConfig, Job, Listeners,
+        OutcomeStatus, Rejection, Task, Work,
+    };
+    use crate::account::TransactionProcessingError;
+    use crate::dedup::IdFilter;
+    use crate::encryption::Cipher;
+    use crate::fraud::{DepositWithdrawal, FraudRules, RepeatedDisputes, Verdict};
+    use crate::limits::{LimitRules, Limits};
+    use crate::money::{MoneyFormat, RoundingMode};
+    use crate::partition::{Partition, TransferMessage, TransferStep};
+    use crate::retry::RetryPolicy;
+    use crate::snapshot::Snapshot;
+    use crate::store::{MemoryStore, StateStore};
+    use crate::{
+        Account, AccountState, Currency, ErrorKind, ExchangeRates, Money, ReportFormat, Timestamp,
+        Transaction, TransactionType,
+    };
+    use rust_decimal::Decimal;
+    use std::error::Error;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::sync::{mpsc, oneshot, Mutex};
+    use tracing::Span;
+
+    #[tokio::test]
+    async fn process() {
+        let mut engine = Engine::new();
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from(10)),
+            ))
+            .await
+            .unwrap();
+        engine
+            .process(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(Money::from(4)),
+            ))
+            .await
+            .unwrap();
+        assert!(engine
+            .process(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Some(Money::from(7))
+            ))
+            .await
+            .is_err());
+
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.available(), Money::from(6));
+        assert_eq!(account.total(), Money::from(6));
+        assert!(engine.account(2).await.is_none());
+        assert!(matches!(
+            engine.wait().await,
+            [Rejection {
+                error: TransactionProcessingError {
+                    tx: 3,
+                    kind: ErrorKind::InsufficientAmount,
+                    ..
+                },
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn submit() {
+        let mut engine = Engine::new();
+        for tx in 0..100 {
+            engine
+                .submit(Transaction::new(
+                    TransactionType::Deposit,
+                    (tx % 4) as u16,
+                    tx,
+                    Some(Money::from(1)),
+                ))
+                .await
+                .unwrap();
+        }
+        engine
+            .submit(Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                100,
+                Some(Money::from(1000)),
+            ))
+            .await
+            .unwrap();
+
+        let errors = engine.wait().await;
+        assert!(matches!(
+            errors,
+            [Rejection {
+                error: TransactionProcessingError {
+                    client: 0,
+                    tx: 100,
+                    kind: ErrorKind::InsufficientAmount,
+                    ..
+                },
+                ..
+            }]
+        ));
+        assert_eq!(engine.latencies().count(), 101);
+        for client in 0..4 {
+            assert_eq!(
+                engine.account(client).await.unwrap().available(),
+                Money::from(25)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn outcomes() {
+        let mut engine = Engine::new();
+        let mut outcomes = engine.outcomes();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(9))),
+            // Rejected before it gets to a worker
+            Transaction::new(TransactionType::Deposit, 2, 1, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        engine.wait().await;
+        engine
+            .process(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Some(Money::from(1)),
+            ))
+            .await
+            .unwrap();
+        drop(engine);
+
+        let mut received = Vec::new();
+        while let Some(outcome) = outcomes.recv().await {
+            received.push((outcome.tx, outcome.status, outcome.error));
+        }
+        received.sort_by_key(|(tx, status, _)| (*tx, *status as u8));
+        assert_eq!(
+            received,
+            [
+                (1, OutcomeStatus::Accepted, None),
+                (
+                    1,
+                    OutcomeStatus::Rejected,
+                    Some(ErrorKind::DuplicateTransactionId(1))
+                ),
+                (
+                    2,
+                    OutcomeStatus::Rejected,
+                    Some(ErrorKind::InsufficientAmount)
+                ),
+                (3, OutcomeStatus::Accepted, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn partition() {
+        let mut engine = Engine::with_config(EngineConfig {
+            partition: Partition::new(0, 9),
+            ..EngineConfig::default()
+        });
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from(1)),
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine
+                .process(Transaction::new(
+                    TransactionType::Deposit,
+                    10,
+                    2,
+                    Some(Money::from(1))
+                ))
+                .await,
+            Err(TransactionProcessingError {
+                kind: ErrorKind::ClientOutsidePartition(10),
+                ..
+            })
+        ));
+
+        let mut report = Vec::new();
+        engine.write_report(&mut report).await.unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "partition,client,available,held,total,locked\n0-9,1,1.0000,0.0000,1.0000,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn report_format() {
+        let mut engine = Engine::with_config(EngineConfig {
+            output_format: MoneyFormat {
+                decimal_places: 2,
+                rounding: RoundingMode::Bankers,
+            },
+            ..EngineConfig::default()
+        });
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::new(1125, 3)),
+            ))
+            .await
+            .unwrap();
+
+        let mut report = Vec::new();
+        engine.write_report(&mut report).await.unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n1,1.12,0.00,1.12,false\n"
+        );
+
+        let mut engine = Engine::with_config(EngineConfig {
+            partition: Partition::new(0, 9),
+            report_format: ReportFormat::Json,
+            ..EngineConfig::default()
+        });
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::new(1125, 3)),
+            ))
+            .await
+            .unwrap();
+
+        let mut report = Vec::new();
+        engine.write_report(&mut report).await.unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "[{\"partition\":\"0-9\",\"client\":1,\"available\":\"1.1250\",\"held\":\"0.0000\",\"total\":\"1.1250\",\"locked\":false}]\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let mut engine = Engine::new();
+        let mut source = engine.watch(1).await;
+        assert_eq!(
+            *source.borrow(),
+            AccountState {
+                client: 1,
+                ..AccountState::default()
+            }
+        );
+        engine
+            .submit(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from(10)),
+            ))
+            .await
+            .unwrap();
+        source.changed().await.unwrap();
+        assert_eq!(
+            source.borrow_and_update().balances[&None].available(),
+            Money::from(10)
+        );
+
+        let destination = engine.watch(2).await;
+        engine
+            .submit(
+                Transaction::new(TransactionType::Transfer, 1, 2, Some(Money::from(4)))
+                    .with_to_client(2),
+            )
+            .await
+            .unwrap();
+        engine
+            .submit(Transaction::new(
+                TransactionType::Withdrawal,
+                2,
+                3,
+                Some(Money::from(5)),
+            ))
+            .await
+            .unwrap();
+        engine.wait().await;
+        assert!(source.has_changed().unwrap());
+        assert_eq!(source.borrow().balances[&None].available(), Money::from(6));
+        assert!(destination.has_changed().unwrap());
+        assert_eq!(
+            destination.borrow().balances[&None].available(),
+            Money::from(4)
+        );
+        assert_eq!(
+            engine.watch(1).await.borrow().balances,
+            source.borrow().balances
+        );
+    }
+
+    #[tokio::test]
+    async fn submit_keeps_client_order() {
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 4,
+            ..EngineConfig::default()
+        });
+        for tx in 0..1000 {
+            let client = (tx % 8) as u16;
+            let transaction_type = if tx % 16 < 8 {
+                TransactionType::Deposit
+            } else {
+                TransactionType::Withdrawal
+            };
+            engine
+                .submit(Transaction::new(
+                    transaction_type,
+                    client,
+                    tx,
+                    Some(Money::from(1)),
+                ))
+                .await
+                .unwrap();
+        }
+
+        assert!(engine.wait().await.is_empty());
+        for client in 0..8 {
+            let account = engine.account(client).await.unwrap();
+            assert_eq!(account.total(), Money::from(1));
+        }
+    }
+
+    #[tokio::test]
+    async fn submit_with_small_capacity() {
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 2,
+            channel_capacity: 1,
+            ..EngineConfig::default()
+        });
+        for tx in 0..100 {
+            engine
+                .submit(Transaction::new(
+                    TransactionType::Deposit,
+                    (tx % 3) as u16,
+                    tx,
+                    Some(Money::from(1)),
+                ))
+                .await
+                .unwrap();
+        }
+
+        assert!(engine.wait().await.is_empty());
+        assert_eq!(
+            engine.account(0).await.unwrap().available(),
+            Money::from(34)
+        );
+        let stats = engine.stats();
+        assert_eq!(stats.queued, 100);
+        assert_eq!(stats.peak_queue, 1);
+        assert!(stats.applying > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn duplicate_transaction_ids() {
+        let filtered = EngineConfig {
+            id_filter: Some(IdFilter {
+                expected_ids: 100,
+                false_positive_rate: Decimal::new(1, 2),
+                bitmap_dir: std::env::temp_dir(),
+            }),
+            ..EngineConfig::default()
+        };
+        for config in [EngineConfig::default(), filtered] {
+            let mut engine = Engine::with_config(config);
+            engine
+                .process(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Money::from(5)),
+                ))
+                .await
+                .unwrap();
+            assert!(matches!(
+                engine
+                    .process(Transaction::new(
+                        TransactionType::Deposit,
+                        2,
+                        1,
+                        Some(Money::from(7))
+                    ))
+                    .await,
+                Err(TransactionProcessingError {
+                    kind: ErrorKind::DuplicateTransactionId(1),
+                    ..
+                })
+            ));
+            engine
+                .process(Transaction::new(TransactionType::Dispute, 1, 1, None))
+                .await
+                .unwrap();
+            assert_eq!(engine.account(1).await.unwrap().held(), Money::from(5));
+
+            engine
+                .submit(Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    1,
+                    Some(Money::from(1)),
+                ))
+                .await
+                .unwrap();
+            assert!(matches!(
+                engine.wait().await,
+                [
+                    Rejection {
+                        error: TransactionProcessingError {
+                            client: 2,
+                            kind: ErrorKind::DuplicateTransactionId(1),
+                            ..
+                        },
+                        ..
+                    },
+                    Rejection {
+                        error: TransactionProcessingError {
+                            client: 1,
+                            kind: ErrorKind::DuplicateTransactionId(1),
+                            ..
+                        },
+                        ..
+                    }
+                ]
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_on_duplicate_transaction_id() {
+        let mut engine = Engine::with_config(EngineConfig {
+            duplicate_policy: DuplicatePolicy::Abort,
+            ..EngineConfig::default()
+        });
+        engine
+            .submit(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from(5)),
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine
+                .submit(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Money::from(5))
+                ))
+                .await,
+            Err(TransactionProcessingError {
+                kind: ErrorKind::DuplicateTransactionId(1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            engine.wait().await,
+            [Rejection {
+                error: TransactionProcessingError {
+                    kind: ErrorKind::DuplicateTransactionId(1),
+                    ..
+                },
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejections_report() {
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))).with_row(2),
+            Transaction::new(TransactionType::Withdrawal, 2, 2, Some(Money::from(5))).with_row(3),
+            Transaction::new(TransactionType::Dispute, 1, 9, None)
+                .with_row(4)
+                .with_timestamp(Timestamp::from_millis(1_700_000_000_000)),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(1))).with_row(5),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+
+        let mut report = Vec::new();
+        engine.write_rejections(&mut report).await.unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "row,client,tx,timestamp,amount,code,reason\n3,2,2,,5,304,insufficient_amount\n\
+             4,1,9,2023-11-14T22:13:20.000Z,,400,invalid_dispute_target\n"
+        );
+        let rejection = &engine.wait().await[0];
+        assert_eq!(
+            rejection.to_string(),
+            "Transaction 2 of client 2 over 5 rejected: Not enough available funds (code 304)"
+        );
+        assert!(rejection.source().is_some());
+    }
+
+    #[tokio::test]
+    async fn fraud_report() {
+        let mut fraud_rules = FraudRules::new();
+        fraud_rules.push(DepositWithdrawal::new(
+            Money::from(1000),
+            600_000,
+            Verdict::Block,
+        ));
+        fraud_rules.push(RepeatedDisputes::new(0, Verdict::Flag));
+        let mut engine = Engine::with_config(EngineConfig {
+            fraud_rules,
+            ..EngineConfig::default()
+        });
+        let at = |seconds: i64| Timestamp::from_millis(1_700_000_000_000 + seconds * 1000);
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(1000)))
+                .with_row(2)
+                .with_timestamp(at(0)),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(1000)))
+                .with_row(3)
+                .with_timestamp(at(60)),
+            Transaction::new(TransactionType::Dispute, 1, 1, None).with_row(4),
+            // The other client's rules have seen no deposit
+            Transaction::new(TransactionType::Deposit, 2, 3, Some(Money::from(5))).with_row(5),
+            Transaction::new(TransactionType::Withdrawal, 2, 4, Some(Money::from(5)))
+                .with_row(6)
+                .with_timestamp(at(60)),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+
+        let mut report = Vec::new();
+        engine.write_fraud_hits(&mut report).await.unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "row,client,tx,timestamp,rule,verdict\n\
+             3,1,2,2023-11-14T22:14:20.000Z,deposit-withdrawal,block\n\
+             4,1,1,,disputes,flag\n"
+        );
+        let rejections = engine.wait().await;
+        assert_eq!(rejections.len(), 1);
+        assert!(matches!(
+            rejections[0].error.kind,
+            ErrorKind::FraudBlocked("deposit-withdrawal")
+        ));
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.held(), Money::from(1000));
+    }
+
+    /// Memory store recording which transactions it was told about.
+    #[derive(Default)]
+    struct RecordingStore {
+        accounts: MemoryStore,
+        history: std::sync::Mutex<Vec<(u16, u32)>>,
+    }
+
+    impl StateStore for RecordingStore {
+        fn get(&self, client: u16) -> Option<Arc<Mutex<Account>>> {
+            self.accounts.get(client)
+        }
+
+        fn put(&self, client: u16, account: Arc<Mutex<Account>>) {
+            self.accounts.put(client, account)
+        }
+
+        fn clients(&self) -> Vec<u16> {
+            self.accounts.clients()
+        }
+
+        fn append_history(&self, account: &Account, tx: u32) {
+            self.history.lock().unwrap().push((account.client(), tx));
+        }
+    }
+
+    #[tokio::test]
+    async fn injected_store() {
+        let store = Arc::new(RecordingStore::default());
+        let mut engine = Engine::with_store(EngineConfig::default(), store.clone());
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(9))),
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Money::from(1)))
+                .with_to_client(2),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        engine.wait().await;
+
+        // Rejected transactions aren't recorded
+        assert_eq!(
+            *store.history.lock().unwrap(),
+            [(1, 1), (1, 3), (2, 3), (1, 1)]
+        );
+        assert_eq!(store.clients(), [1, 2]);
+        assert_eq!(store.get(1).unwrap().lock().await.held(), Money::from(5));
+    }
+
+    #[tokio::test]
+    async fn snapshot() {
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(3))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Chargeback, 2, 2, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("snapshot_{}.json", std::process::id()));
+        let cipher = Cipher::new(&[3; 32]);
+        engine
+            .snapshot()
+            .await
+            .unwrap()
+            .save(&path, Some(&cipher))
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&std::fs::read(&path).unwrap()).contains("client"));
+        assert!(Snapshot::load(&path, None).is_err());
+
+        let mut engine = Engine::new();
+        engine
+            .restore(Snapshot::load(&path, Some(&cipher)).unwrap())
+            .unwrap();
+        let transactions = [
+            Transaction::new(TransactionType::Chargeback, 1, 1, None).with_row(2),
+            Transaction::new(TransactionType::Deposit, 3, 2, Some(Money::from(1))).with_row(3),
+            Transaction::new(TransactionType::Deposit, 3, 0, Some(Money::from(1))).with_row(4),
+            Transaction::new(TransactionType::Deposit, 3, 3, Some(Money::from(1))).with_row(5),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let rejections = engine.wait().await;
+        assert_eq!(rejections.len(), 2);
+        assert!(matches!(
+            rejections[0].error.kind,
+            ErrorKind::DuplicateTransactionId(2)
+        ));
+        // Ids of a new day's file have to continue after the ones already processed
+        assert!(matches!(
+            rejections[1].error.kind,
+            ErrorKind::TransactionIdRegression(0)
+        ));
+        assert_eq!(engine.account(3).await.unwrap().total(), Money::from(1));
+        let first = engine.account(1).await.unwrap();
+        assert_eq!(first.total(), Money::ZERO);
+        assert!(first.locked());
+        assert!(engine.account(2).await.unwrap().locked());
+
+        // Checkpoints continue the same inputs, whose ids needn't be in order
+        let mut resumed = Engine::new();
+        resumed
+            .restore(engine.snapshot().await.unwrap().with_cursor(5))
+            .unwrap();
+        resumed
+            .submit(Transaction::new(
+                TransactionType::Deposit,
+                3,
+                4,
+                Some(Money::from(1)),
+            ))
+            .await
+            .unwrap();
+        assert!(resumed.wait().await.is_empty());
+
+        let mut snapshot = engine.snapshot().await.unwrap();
+        snapshot.version += 1;
+        assert!(Engine::new().restore(snapshot).is_err());
+    }
+
+    #[cfg(feature = "persistence")]
+    #[tokio::test]
+    async fn persisted_state() {
+        use crate::state::SledStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledStore::open(dir.path(), None).unwrap();
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(3))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        engine.save_state(&store).await.unwrap();
+
+        let mut engine = Engine::new();
+        engine.load_state(&store).unwrap();
+        let transactions = [
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from(1))).with_row(3),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let rejections = engine.wait().await;
+        assert_eq!(rejections.len(), 1);
+        assert!(matches!(
+            rejections[0].error.kind,
+            ErrorKind::DuplicateTransactionId(2)
+        ));
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.available(), Money::from(7));
+        assert_eq!(account.held(), Money::ZERO);
+    }
+
+    #[tokio::test]
+    async fn convert() {
+        let (eur, usd) = (
+            "EUR".parse::<Currency>().unwrap(),
+            "USD".parse::<Currency>().unwrap(),
+        );
+        let conversion = Transaction::new(TransactionType::Convert, 1, 2, Some(Money::from(4)))
+            .with_currency(eur.clone())
+            .with_to_currency(usd.clone());
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10)))
+            .with_currency(eur.clone());
+
+        let mut engine = Engine::new();
+        engine.process(deposit).await.unwrap();
+        assert!(matches!(
+            engine.process(conversion).await,
+            Err(TransactionProcessingError {
+                kind: ErrorKind::MissingExchangeRate,
+                ..
+            })
+        ));
+
+        let mut rates = ExchangeRates::new();
+        rates
+            .insert(eur.clone(), usd.clone(), Money::new(125, 2).into())
+            .unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            exchange_rates: Some(rates),
+            ..EngineConfig::default()
+        });
+        engine
+            .submit(
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10)))
+                    .with_currency(eur.clone()),
+            )
+            .await
+            .unwrap();
+        engine
+            .submit(
+                Transaction::new(TransactionType::Convert, 1, 2, Some(Money::from(4)))
+                    .with_currency(eur.clone())
+                    .with_to_currency(usd.clone()),
+            )
+            .await
+            .unwrap();
+        engine
+            .submit(
+                Transaction::new(TransactionType::Convert, 1, 3, Some(Money::from(1)))
+                    .with_currency(eur.clone())
+                    .with_to_currency(eur.clone()),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.wait().await,
+            [Rejection {
+                error: TransactionProcessingError {
+                    tx: 3,
+                    kind: ErrorKind::InvalidConversion,
+                    ..
+                },
+                ..
+            }]
+        ));
+
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.balance(Some(&eur)).available(), Money::from(6));
+        assert_eq!(account.balance(Some(&usd)).available(), Money::from(5));
+    }
+
+    #[tokio::test]
+    async fn check_invariants() {
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 2,
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(7))),
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Money::from(4)))
+                .with_to_client(2),
+            Transaction::new(TransactionType::Withdrawal, 2, 4, Some(Money::from(3))),
+            Transaction::new(TransactionType::Withdrawal, 1, 5, Some(Money::from(50))),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Chargeback, 2, 2, None),
+            Transaction::new(TransactionType::Deposit, 2, 6, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        assert_eq!(engine.check_invariants().await, []);
+        assert!(!engine.invariants_broken());
+        assert_eq!(engine.wait().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn check_invariants_overflowing_flows() {
+        let amount = Some("40000000000000000000000000000".parse::<Money>().unwrap());
+        // A single worker, so the deposits of client 1 overflow first
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 1,
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, amount),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, amount),
+            Transaction::new(TransactionType::Deposit, 1, 3, amount),
+            Transaction::new(TransactionType::Deposit, 2, 4, amount),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let violations = engine.check_invariants().await;
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].to_string(),
+            "client 1, tx 3: flows of the default currency overflowed, leaving them unchecked"
+        );
+        assert!(engine.wait().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 4,
+            partition: Partition::new(0, 9),
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))).with_row(2),
+            Transaction::new(TransactionType::Transfer, 1, 2, Some(Money::from(4)))
+                .with_to_client(2)
+                .with_row(3),
+            Transaction::new(TransactionType::Withdrawal, 2, 3, Some(Money::from(3))).with_row(4),
+            Transaction::new(TransactionType::Transfer, 2, 4, Some(Money::from(5)))
+                .with_to_client(1)
+                .with_row(5),
+            Transaction::new(TransactionType::Transfer, 1, 5, Some(Money::from(1)))
+                .with_to_client(42)
+                .with_row(6),
+            Transaction::new(TransactionType::Transfer, 1, 6, Some(Money::from(1))).with_row(7),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+
+        assert!(matches!(
+            engine.wait().await,
+            [
+                Rejection {
+                    error: TransactionProcessingError {
+                        tx: 4,
+                        kind: ErrorKind::InsufficientAmount,
+                        ..
+                    },
+                    ..
+                },
+                Rejection {
+                    error: TransactionProcessingError {
+                        tx: 5,
+                        kind: ErrorKind::ClientOutsidePartition(42),
+                        ..
+                    },
+                    ..
+                },
+                Rejection {
+                    error: TransactionProcessingError {
+                        tx: 6,
+                        kind: ErrorKind::InvalidTransfer,
+                        ..
+                    },
+                    ..
+                },
+            ]
+        ));
+        assert_eq!(engine.account(1).await.unwrap().available(), Money::from(6));
+        assert_eq!(engine.account(2).await.unwrap().available(), Money::from(1));
+
+        engine
+            .process(
+                Transaction::new(TransactionType::Transfer, 2, 7, Some(Money::from(1)))
+                    .with_to_client(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(engine.account(1).await.unwrap().available(), Money::from(7));
+        assert_eq!(engine.account(2).await.unwrap().available(), Money::ZERO);
+    }
+
+    #[tokio::test]
+    async fn transfers_between_workers() {
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 3,
+            check_invariants: true,
+            ..EngineConfig::default()
+        });
+        let mut tx = 0;
+        for client in 0..6 {
+            tx += 1;
+            let deposit = Transaction::new(TransactionType::Deposit, client, tx, Some(100.into()));
+            engine.submit(deposit).await.unwrap();
+        }
+        // Transfers crossing each other between every pair of workers, each client's
+        // deposits and withdrawals in between
+        for round in 0..50 {
+            for client in 0..6 {
+                tx += 1;
+                let to = (client + 1 + round % 5) % 6;
+                let transfer =
+                    Transaction::new(TransactionType::Transfer, client, tx, Some(3.into()))
+                        .with_to_client(to);
+                engine.submit(transfer).await.unwrap();
+                tx += 1;
+                let (transaction_type, amount) = if round % 2 == 0 {
+                    (TransactionType::Deposit, 2)
+                } else {
+                    (TransactionType::Withdrawal, 2)
+                };
+                let transaction = Transaction::new(transaction_type, to, tx, Some(amount.into()));
+                engine.submit(transaction).await.unwrap();
+            }
+        }
+        assert_eq!(engine.workers.len(), 3);
+
+        let rejections = tokio::time::timeout(Duration::from_secs(10), engine.wait())
+            .await
+            .expect("Workers deadlocked");
+        assert!(rejections.is_empty());
+        assert_eq!(engine.latencies().count(), 6 + 50 * 6 * 2);
+        for client in 0..6 {
+            assert_eq!(
+                engine.account(client).await.unwrap().available(),
+                Money::from(100)
+            );
+        }
+        assert_eq!(engine.check_invariants().await, []);
+    }
+
+    #[tokio::test]
+    async fn transfer_to_stopped_worker() {
+        let (sender, receiver) = mpsc::channel(1);
+        // The recipient's worker dropped the loan, as it does once it panicked
+        let (_, lent) = oneshot::channel();
+        let job = Job {
+            account: Arc::new(Mutex::new(new_account(&EngineConfig::default(), 1))),
+            transaction: Transaction::new(TransactionType::Transfer, 1, 2, Some(3.into()))
+                .with_to_client(2),
+            work: Work::Transfer(Destination::Lent(lent)),
+            span: Span::none(),
+            queued: Span::none(),
+            submitted: Instant::now(),
+        };
+        sender.send(Task::Job(Box::new(job))).await.unwrap();
+        drop(sender);
+        let (rejections, ..) = worker(
+            receiver,
+            Arc::new(MemoryStore::new()),
+            Listeners::default(),
+            RetryPolicy::default(),
+            Arc::default(),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].error.tx, 2);
+        assert_eq!(rejections[0].error.kind, ErrorKind::RecipientUnavailable);
+    }
+
+    #[tokio::test]
+    async fn reconfigure() {
+        let mut engine = Engine::with_config(EngineConfig {
+            workers: 2,
+            ..EngineConfig::default()
+        });
+        for client in [1, 2] {
+            let deposit = Transaction::new(
+                TransactionType::Deposit,
+                client,
+                client.into(),
+                Some(Money::from(100)),
+            );
+            engine.submit(deposit).await.unwrap();
+        }
+        let mut limits = LimitRules::default();
+        limits.default.max_amount = Some(Money::from(10));
+        limits.clients.insert(
+            2,
+            Limits {
+                max_amount: Some(Money::from(50)),
+                ..Limits::default()
+            },
+        );
+        engine
+            .reconfigure(EngineConfig {
+                limits,
+                overdraft_limit: Money::from(5),
+                workers: 8,
+                ..EngineConfig::default()
+            })
+            .await;
+        assert_eq!(engine.config().workers, 2);
+
+        for (client, tx, amount) in [(1, 3, 20), (2, 4, 20), (3, 5, 20), (3, 6, 5)] {
+            let transaction = Transaction::new(
+                TransactionType::Withdrawal,
+                client,
+                tx,
+                Some(Money::from(amount)),
+            );
+            engine.submit(transaction).await.unwrap();
+        }
+        assert_eq!(
+            engine
+                .wait()
+                .await
+                .iter()
+                .map(|rejection| (rejection.error.tx, rejection.error.kind.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (3, ErrorKind::TransactionLimitExceeded),
+                (5, ErrorKind::TransactionLimitExceeded)
+            ]
+        );
+        assert_eq!(
+            engine.account(1).await.unwrap().available(),
+            Money::from(100)
+        );
+        assert_eq!(
+            engine.account(2).await.unwrap().available(),
+            Money::from(80)
+        );
+        assert_eq!(
+            engine.account(3).await.unwrap().available(),
+            Money::from(-5)
+        );
+    }
+
+    #[tokio::test]
+    async fn cross_partition_transfer() {
+        let partition = |first, last| EngineConfig {
+            partition: Partition::new(first, last),
+            cross_partition_transfers: true,
+            check_invariants: true,
+            ..EngineConfig::default()
+        };
+        let (mut sender, mut receiver) = (
+            Engine::with_config(partition(0, 99)),
+            Engine::with_config(partition(100, 199)),
+        );
+        for transaction in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Transfer, 1, 2, Some(Money::from(4)))
+                .with_to_client(150),
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Money::from(2)))
+                .with_to_client(160),
+            Transaction::new(TransactionType::Transfer, 1, 4, Some(Money::from(9)))
+                .with_to_client(150),
+            // Its id was taken by a deposit of the receiving partition
+            Transaction::new(TransactionType::Transfer, 1, 10, Some(Money::from(1)))
+                .with_to_client(150),
+        ] {
+            sender.submit(transaction).await.unwrap();
+        }
+        for transaction in [
+            Transaction::new(TransactionType::Deposit, 160, 10, Some(Money::from(5))),
+            Transaction::new(TransactionType::Dispute, 160, 10, None),
+            Transaction::new(TransactionType::Chargeback, 160, 10, None),
+        ] {
+            receiver.submit(transaction).await.unwrap();
+        }
+        sender.wait().await;
+        receiver.wait().await;
+        let account = sender.account(1).await.unwrap();
+        assert_eq!(account.available(), Money::from(3));
+        assert_eq!(account.outgoing_transfers().len(), 3);
+
+        let prepares = sender.take_transfer_messages();
+        assert_eq!(prepares.len(), 3);
+        for message in prepares.clone() {
+            let _ = receiver.settle(message).await;
+        }
+        // Delivered again, the credited prepare is answered again and not credited twice
+        assert!(receiver.settle(prepares[0].clone()).await.is_ok());
+        let outside = TransferMessage {
+            to_client: 50,
+            ..prepares[0].clone()
+        };
+        assert!(matches!(
+            receiver.settle(outside).await,
+            Err(TransactionProcessingError {
+                kind: ErrorKind::ClientOutsidePartition(50),
+                ..
+            })
+        ));
+        assert_eq!(
+            receiver.account(150).await.unwrap().available(),
+            Money::from(4)
+        );
+        assert_eq!(
+            receiver.account(160).await.unwrap().available(),
+            Money::ZERO
+        );
+
+        let answers = receiver.take_transfer_messages();
+        assert_eq!(
+            answers.iter().map(|m| (m.tx, m.step)).collect::<Vec<_>>(),
+            [
+                (2, TransferStep::Commit),
+                (3, TransferStep::Abort),
+                (10, TransferStep::Abort),
+                (2, TransferStep::Commit),
+                (2, TransferStep::Abort),
+            ]
+        );
+        for message in &answers[..3] {
+            sender.settle(message.clone()).await.unwrap();
+        }
+        for message in &answers[3..] {
+            assert!(matches!(
+                sender.settle(message.clone()).await,
+                Err(TransactionProcessingError {
+                    kind: ErrorKind::InvalidTransfer,
+                    ..
+                })
+            ));
+        }
+        let account = sender.account(1).await.unwrap();
+        assert_eq!(account.available(), Money::from(6));
+        assert!(account.outgoing_transfers().is_empty());
+        assert!(sender.take_transfer_messages().is_empty());
+        assert_eq!(sender.check_invariants().await, Vec::new());
+        assert_eq!(receiver.check_invariants().await, Vec::new());
+
+        // Credited prepares are still recognized after a restart
+        let mut restarted = Engine::with_config(partition(100, 199));
+        restarted
+            .restore(receiver.snapshot().await.unwrap())
+            .unwrap();
+        assert!(restarted.settle(prepares[0].clone()).await.is_ok());
+        assert_eq!(
+            restarted.take_transfer_messages()[0].step,
+            TransferStep::Commit
+        );
+        assert_eq!(
+            restarted.account(150).await.unwrap().available(),
+            Money::from(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_ops() {
+        let transactions = || {
+            [
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                Transaction::new(TransactionType::Chargeback, 1, 1, None),
+                Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(1))),
+                Transaction::new(TransactionType::Unlock, 1, 2, None),
+            ]
+        };
+
+        let mut engine = Engine::new();
+        for transaction in transactions() {
+            engine.submit(transaction).await.unwrap();
+        }
+        assert!(matches!(
+            engine.wait().await,
+            [
+                Rejection {
+                    error: TransactionProcessingError {
+                        kind: ErrorKind::AdminOperationNotAllowed,
+                        ..
+                    },
+                    ..
+                },
+                Rejection {
+                    error: TransactionProcessingError {
+                        kind: ErrorKind::AccountLocked(_),
+                        ..
+                    },
+                    ..
+                }
+            ]
+        ));
+        assert!(engine.account(1).await.unwrap().locked());
+
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        });
+        for transaction in transactions() {
+            engine.submit(transaction).await.unwrap();
+        }
+        // The withdrawal refused while locked doesn't hold up the unlock
+        assert!(matches!(
+            engine.wait().await,
+            [Rejection {
+                error: TransactionProcessingError {
+                    kind: ErrorKind::AccountLocked(_),
+                    ..
+                },
+                ..
+            }]
+        ));
+        assert!(!engine.account(1).await.unwrap().locked());
+    }
+
+    #[tokio::test]
+    async fn queries() {
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 3, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from(2))),
+            Transaction::new(TransactionType::Deposit, 1, 3, Some(Money::from(4))),
+            Transaction::new(TransactionType::Deposit, 2, 4, Some(Money::from(1))),
+            Transaction::new(TransactionType::Dispute, 1, 3, None),
+            Transaction::new(TransactionType::Dispute, 3, 1, None),
+            Transaction::new(TransactionType::Dispute, 2, 4, None),
+            Transaction::new(TransactionType::Chargeback, 2, 4, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let clients = engine
+            .accounts_iter()
+            .await
+            .map(|account| account.client())
+            .collect::<Vec<_>>();
+        assert_eq!(clients, [1, 2, 3]);
+        let locked = engine.locked_accounts().await;
+        assert_eq!(locked.len(), 1);
+        assert_eq!(locked[0].client(), 2);
+        let disputed = engine
+            .disputed_transactions()
+            .await
+            .into_iter()
+            .map(|entry| (entry.client, entry.tx, entry.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            disputed,
+            [(1, 3, Some(Money::from(4))), (3, 1, Some(Money::from(5)))]
+        );
+    }
+}
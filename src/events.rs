@@ -0,0 +1,390 @@
+use crate::currency::Currency;
+use crate::fees::FeeEntry;
+use crate::money::Money;
+use crate::timestamp::Timestamp;
+use crate::transaction::TransactionType;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Change of an account's state, kept in its event log, see [`crate::Account::events`].
+///
+/// Every change of an account's funds, lock or closing goes through events: the account
+/// applies the event it emits, so folding the events of an account in order with
+/// [`crate::Account::from_events`] rebuilds its balances, lock, fees and interest. Events
+/// refer to earlier transactions by id only, the history of those transactions isn't part
+/// of the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AccountEvent {
+    Deposited {
+        tx: u32,
+        /// `None` for the account's default currency
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    Withdrawn {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Funds of transaction `tx` put on hold: taken from the available funds for a
+    /// deposit, added on top of them for a withdrawal
+    DisputeOpened {
+        tx: u32,
+        disputed: TransactionType,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Dispute dismissed, the held funds are released
+    Resolved {
+        tx: u32,
+        disputed: TransactionType,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Disputed transaction reversed, the held funds are taken out
+    ChargedBack {
+        tx: u32,
+        disputed: TransactionType,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Chargeback of transaction `tx` reversed, the charged back funds are moved again
+    Represented {
+        tx: u32,
+        disputed: TransactionType,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Part of deposit `tx` returned to the payer
+    Refunded {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// `amount` of one currency exchanged for `converted` of another
+    Converted {
+        tx: u32,
+        from: Currency,
+        to: Currency,
+        amount: Money,
+        converted: Money,
+    },
+    TransferredOut {
+        tx: u32,
+        to_client: u16,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    TransferredIn {
+        tx: u32,
+        from_client: u16,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Transfer `tx` to a client of another partition refused there and given back
+    TransferReturned {
+        tx: u32,
+        to_client: u16,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Funds put on hold until authorization `tx` is settled
+    Authorized {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// `amount` of authorization `tx` taken, what's `released` goes back to available funds
+    Captured {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+        released: Money,
+    },
+    Voided {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    AuthorizationExpired {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Available funds credited, or debited for a negative amount
+    Adjusted {
+        tx: u32,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    /// Fee charged for transaction `tx`, `None` for the monthly maintenance fee
+    FeeCharged {
+        tx: Option<u32>,
+        currency: Option<Currency>,
+        amount: Money,
+    },
+    InterestPosted {
+        currency: Option<Currency>,
+        amount: Money,
+        posted_at: Timestamp,
+    },
+    Locked,
+    Unlocked,
+    Closed,
+}
+
+impl AccountEvent {
+    /// Transaction the event came from, `None` for changes no transaction caused directly.
+    pub fn tx(&self) -> Option<u32> {
+        use AccountEvent::*;
+        match self {
+            Deposited { tx, .. }
+            | Withdrawn { tx, .. }
+            | DisputeOpened { tx, .. }
+            | Resolved { tx, .. }
+            | ChargedBack { tx, .. }
+            | Represented { tx, .. }
+            | Refunded { tx, .. }
+            | Converted { tx, .. }
+            | TransferredOut { tx, .. }
+            | TransferredIn { tx, .. }
+            | TransferReturned { tx, .. }
+            | Authorized { tx, .. }
+            | Captured { tx, .. }
+            | Voided { tx, .. }
+            | AuthorizationExpired { tx, .. }
+            | Adjusted { tx, .. } => Some(*tx),
+            FeeCharged { tx, .. } => *tx,
+            InterestPosted { .. } | Locked | Unlocked | Closed => None,
+        }
+    }
+}
+
+impl From<FeeEntry> for AccountEvent {
+    fn from(fee: FeeEntry) -> Self {
+        AccountEvent::FeeCharged {
+            tx: fee.tx(),
+            currency: fee.currency().cloned(),
+            amount: fee.amount(),
+        }
+    }
+}
+
+/// Line of the event log, the event tagged with its client.
+#[derive(Serialize)]
+struct ClientEvent<'a> {
+    client: u16,
+    #[serde(flatten)]
+    event: &'a AccountEvent,
+}
+
+/// Writes events as JSON lines, each tagged with its client, e.g.
+/// `{"client":1,"event":"deposited","tx":1,"currency":null,"amount":"5"}`.
+pub fn write_events<'a, W: io::Write>(
+    mut writer: W,
+    events: impl IntoIterator<Item = &'a (u16, AccountEvent)>,
+) -> Result<(), serde_json::Error> {
+    for (client, event) in events {
+        let client = *client;
+        serde_json::to_writer(&mut writer, &ClientEvent { client, event })?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+    }
+    writer.flush().map_err(serde_json::Error::io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_events, AccountEvent};
+    use crate::engine::Rejection;
+    use crate::fees::{Fee, FeeSchedule};
+    use crate::store::{MemoryStore, StateStore};
+    use crate::{
+        Account, Currency, Engine, EngineConfig, ErrorKind, ExchangeRates, Money, Timestamp,
+        Transaction, TransactionProcessingError, TransactionType,
+    };
+    use rust_decimal::Decimal;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn fold() {
+        let mut engine = Engine::with_config(EngineConfig {
+            event_log: true,
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(3))),
+            Transaction::new(TransactionType::Withdrawal, 1, 4, Some(Money::from(30))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 2, None),
+            Transaction::new(TransactionType::Deposit, 2, 5, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let events = engine.events().await;
+        let names = events
+            .iter()
+            .map(|(client, event)| {
+                let json = serde_json::to_value(event).unwrap();
+                format!("{} {}", client, json["event"].as_str().unwrap())
+            })
+            .collect::<Vec<_>>();
+        // The rejected withdrawal emitted nothing
+        assert_eq!(
+            names,
+            [
+                "1 deposited",
+                "1 deposited",
+                "1 withdrawn",
+                "1 dispute_opened",
+                "1 dispute_opened",
+                "1 resolved",
+                "1 charged_back",
+                "1 locked",
+                "2 deposited",
+            ]
+        );
+
+        let account = engine.account(1).await.unwrap();
+        let client_events = events
+            .iter()
+            .filter(|(client, _)| *client == 1)
+            .map(|(_, event)| event.clone());
+        let folded = Account::from_events(1, client_events).unwrap();
+        assert_eq!(folded.balance(None), account.balance(None));
+        assert_eq!(folded.available(), Money::from(7));
+        assert!(folded.locked());
+        assert_eq!(folded.events(), account.events());
+
+        let mut log = Vec::new();
+        write_events(&mut log, &events[..1]).unwrap();
+        assert_eq!(
+            String::from_utf8(log).unwrap(),
+            "{\"client\":1,\"event\":\"deposited\",\"tx\":1,\"currency\":null,\"amount\":\"10\"}\n"
+        );
+        let line = r#"{"event":"withdrawn","tx":3,"currency":null,"amount":"3"}"#;
+        assert_eq!(
+            serde_json::from_str::<AccountEvent>(line).unwrap(),
+            AccountEvent::Withdrawn {
+                tx: 3,
+                currency: None,
+                amount: Money::from(3),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn fold_every_change() {
+        let (eur, usd) = (
+            "EUR".parse::<Currency>().unwrap(),
+            "USD".parse::<Currency>().unwrap(),
+        );
+        let mut rates = ExchangeRates::new();
+        rates
+            .insert(eur.clone(), usd.clone(), Money::new(125, 2).into())
+            .unwrap();
+        let mut fees = FeeSchedule::new();
+        fees.insert(
+            TransactionType::Withdrawal,
+            Fee {
+                flat: Money::from(1),
+                ..Fee::default()
+            },
+        )
+        .unwrap();
+        fees.monthly = Some(Money::from(2));
+        let config = EngineConfig {
+            event_log: true,
+            allow_admin_ops: true,
+            exchange_rates: Some(rates),
+            fees: Some(Arc::new(fees)),
+            interest_rate: Some(Decimal::new(5, 2)),
+            ..EngineConfig::default()
+        };
+        // Copies of accounts leave out their fees and interest, the store has the originals
+        let store = Arc::new(MemoryStore::new());
+        let mut engine = Engine::with_store(config, store.clone());
+        let january = Timestamp::from_millis(1_705_276_800_000);
+        let march = Timestamp::from_millis(1_709_337_600_000);
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(100)))
+                .with_timestamp(january),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 1, 3, Some(Money::from(20)))
+                .with_currency(eur.clone()),
+            Transaction::new(TransactionType::Convert, 1, 4, Some(Money::from(8)))
+                .with_currency(eur.clone())
+                .with_to_currency(usd.clone()),
+            Transaction::new(TransactionType::Authorize, 1, 5, Some(Money::from(30))),
+            Transaction::new(TransactionType::Capture, 1, 5, Some(Money::from(20))),
+            Transaction::new(TransactionType::Authorize, 1, 6, Some(Money::from(5))),
+            Transaction::new(TransactionType::Void, 1, 6, None),
+            Transaction::new(TransactionType::Authorize, 1, 7, Some(Money::from(3)))
+                .with_expiry(january),
+            Transaction::new(TransactionType::Refund, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Adjustment, 1, 8, Some(Money::from(5)))
+                .with_reason("GOODWILL"),
+            Transaction::new(TransactionType::Transfer, 1, 9, Some(Money::from(15)))
+                .with_to_client(2),
+            Transaction::new(TransactionType::Deposit, 1, 10, Some(Money::from(7))),
+            Transaction::new(TransactionType::Dispute, 1, 10, None),
+            Transaction::new(TransactionType::Chargeback, 1, 10, None),
+            Transaction::new(TransactionType::Representment, 1, 10, None),
+            Transaction::new(TransactionType::Deposit, 1, 11, Some(Money::from(1)))
+                .with_timestamp(march),
+            // The fee taken up front is given back once the withdrawal fails
+            Transaction::new(TransactionType::Withdrawal, 2, 12, Some(Money::from(15))),
+            Transaction::new(TransactionType::Withdrawal, 2, 13, Some(Money::from(14))),
+            Transaction::new(TransactionType::Close, 2, 2, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        assert!(matches!(
+            engine.wait().await,
+            [Rejection {
+                error: TransactionProcessingError {
+                    tx: 12,
+                    kind: ErrorKind::InsufficientAmount,
+                    ..
+                },
+                ..
+            }]
+        ));
+
+        let events = engine.events().await;
+        let names = events
+            .iter()
+            .map(|(_, event)| serde_json::to_value(event).unwrap()["event"].to_string())
+            .collect::<BTreeSet<_>>();
+        // Every kind of event but a resolve, which `fold` covers
+        assert_eq!(names.len(), 19);
+        for client in [1, 2] {
+            let account = store.get(client).unwrap();
+            let account = account.lock().await;
+            let client_events = events
+                .iter()
+                .filter(|(c, _)| *c == client)
+                .map(|(_, event)| event.clone());
+            let folded = Account::from_events(client, client_events).unwrap();
+            assert_eq!(folded.balances(), account.balances());
+            assert_eq!(folded.fees(), account.fees());
+            assert_eq!(folded.interest(), account.interest());
+            assert_eq!(folded.locked(), account.locked());
+            assert_eq!(folded.closed(), account.closed());
+        }
+        let first = store.get(1).unwrap();
+        let first = first.lock().await;
+        assert_eq!(first.fees().len(), 2);
+        // Interest of February and March in each of the three currencies
+        assert_eq!(first.interest().len(), 6);
+        assert!(!first.locked());
+        assert!(engine.account(2).await.unwrap().closed());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::currency::Currency;
+use crate::money::{Money, MoneyFormat};
+use crate::transaction::TransactionType;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Fee of a single transaction: a flat part plus a percentage of its amount, kept within
+/// an optional minimum and cap.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Fee {
+    pub flat: Money,
+    /// Fraction of the transaction's amount, e.g. 0.01 for 1%
+    pub percentage: Decimal,
+    pub minimum: Option<Money>,
+    pub cap: Option<Money>,
+}
+
+impl Fee {
+    /// Fee of a transaction moving `amount`, rounded to the default report precision.
+    /// `None` when it overflows.
+    pub fn charge(&self, amount: Money) -> Option<Money> {
+        let amount = if amount.is_negative() {
+            -amount
+        } else {
+            amount
+        };
+        let mut fee = amount
+            .checked_mul(self.percentage)?
+            .checked_add(self.flat)?;
+        if let Some(minimum) = self.minimum {
+            fee = fee.max(minimum);
+        }
+        if let Some(cap) = self.cap {
+            fee = fee.min(cap);
+        }
+        Some(fee.round(&MoneyFormat::default()))
+    }
+}
+
+/// Fees charged to accounts, per transaction type and monthly.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FeeSchedule {
+    fees: HashMap<TransactionType, Fee>,
+    /// Maintenance fee charged in the default currency for every calendar month an account
+    /// is active in after its first one
+    pub monthly: Option<Money>,
+}
+
+/// Fee posted to an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeEntry {
+    tx: Option<u32>,
+    currency: Option<Currency>,
+    amount: Money,
+}
+
+impl FeeEntry {
+    pub(crate) fn new(tx: Option<u32>, currency: Option<Currency>, amount: Money) -> Self {
+        Self {
+            tx,
+            currency,
+            amount,
+        }
+    }
+
+    /// Transaction the fee was charged for, `None` for maintenance fees.
+    pub fn tx(&self) -> Option<u32> {
+        self.tx
+    }
+
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeeRow {
+    flat: Option<Money>,
+    percentage: Option<String>,
+    minimum: Option<Money>,
+    cap: Option<Money>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeesFile {
+    monthly: Option<Money>,
+    #[serde(default)]
+    transactions: HashMap<TransactionType, FeeRow>,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a TOML file with an optional `monthly` fee and a `transactions` table of fees
+    /// keyed by transaction type, e.g. `[transactions.withdrawal]`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = toml::from_str::<FeesFile>(&std::fs::read_to_string(path)?)?;
+
+        let mut schedule = Self::new();
+        if let Some(monthly) = file.monthly {
+            if monthly.is_negative() {
+                return Err(format!("Monthly fee {} is negative", monthly).into());
+            }
+            schedule.monthly = Some(monthly);
+        }
+        for (transaction_type, row) in file.transactions {
+            let percentage = match row.percentage {
+                Some(percentage) => Decimal::from_str(percentage.trim())
+                    .map_err(|e| format!("Invalid percentage {}: {}", percentage, e))?,
+                None => Decimal::ZERO,
+            };
+            let fee = Fee {
+                flat: row.flat.unwrap_or(Money::ZERO),
+                percentage,
+                minimum: row.minimum,
+                cap: row.cap,
+            };
+            schedule.insert(transaction_type, fee)?;
+        }
+        Ok(schedule)
+    }
+
+    pub fn insert(&mut self, transaction_type: TransactionType, fee: Fee) -> Result<(), String> {
+        let negative = [Some(fee.flat), fee.minimum, fee.cap]
+            .into_iter()
+            .flatten()
+            .any(|amount| amount.is_negative());
+        if negative || fee.percentage < Decimal::ZERO || fee.percentage > Decimal::ONE {
+            return Err(format!(
+                "Invalid fee of {:?} transactions",
+                transaction_type
+            ));
+        }
+        if let (Some(minimum), Some(cap)) = (fee.minimum, fee.cap) {
+            if minimum > cap {
+                return Err(format!(
+                    "Minimum fee {} of {:?} transactions exceeds its cap {}",
+                    minimum, transaction_type, cap
+                ));
+            }
+        }
+        self.fees.insert(transaction_type, fee);
+        Ok(())
+    }
+
+    pub fn fee(&self, transaction_type: &TransactionType) -> Option<&Fee> {
+        self.fees.get(transaction_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fee, FeeSchedule};
+    use crate::money::Money;
+    use crate::transaction::TransactionType;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn charge() {
+        let fee = Fee {
+            flat: Money::new(5, 1),
+            percentage: Decimal::new(1, 2),
+            minimum: Some(Money::from(1)),
+            cap: Some(Money::from(3)),
+        };
+        // 0.5 + 1% of 10 is below the minimum
+        assert_eq!(fee.charge(Money::from(10)), Some(Money::from(1)));
+        assert_eq!(fee.charge(Money::from(150)), Some(Money::from(2)));
+        assert_eq!(fee.charge(Money::from(-150)), Some(Money::from(2)));
+        assert_eq!(fee.charge(Money::from(1000)), Some(Money::from(3)));
+        assert_eq!(Fee::default().charge(Money::from(10)), Some(Money::ZERO));
+    }
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join(format!("fees_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "monthly = \"2.5\"\n\
+             [transactions.withdrawal]\n\
+             flat = \"0.5\"\n\
+             percentage = \"0.01\"\n\
+             cap = \"10\"\n",
+        )
+        .unwrap();
+        let schedule = FeeSchedule::load(&path).unwrap();
+        assert_eq!(schedule.monthly, Some(Money::new(25, 1)));
+        assert_eq!(
+            schedule.fee(&TransactionType::Withdrawal),
+            Some(&Fee {
+                flat: Money::new(5, 1),
+                percentage: Decimal::new(1, 2),
+                minimum: None,
+                cap: Some(Money::from(10)),
+            })
+        );
+        assert_eq!(schedule.fee(&TransactionType::Deposit), None);
+
+        std::fs::write(
+            &path,
+            "[transactions.withdrawal]\nminimum = \"2\"\ncap = \"1\"\n",
+        )
+        .unwrap();
+        assert!(FeeSchedule::load(&path).is_err());
+        std::fs::write(&path, "[transactions.withdrawal]\npercentage = \"2\"\n").unwrap();
+        assert!(FeeSchedule::load(&path).is_err());
+        std::fs::write(&path, "[transactions.teleport]\nflat = \"1\"\n").unwrap();
+        assert!(FeeSchedule::load(&path).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::account::Account;
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Outcome of checking a transaction against a fraud rule, from least to most severe.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    #[default]
+    Pass,
+    /// The transaction is applied but reported
+    Flag,
+    /// The transaction is rejected
+    Block,
+}
+
+/// Check run on every transaction of an account before it is applied.
+///
+/// Every account gets its own copy of each rule from [`FraudRule::fresh`], so rules can
+/// keep state about the account's past transactions in `accepted`.
+pub trait FraudRule: fmt::Debug + Send + Sync {
+    /// Name the rule's hits are reported under
+    fn name(&self) -> &'static str;
+
+    /// Checks a transaction about to be applied to `account`.
+    fn check(&self, account: &Account, transaction: &Transaction) -> Verdict;
+
+    /// Called once `transaction` has been applied to `account`.
+    fn accepted(&mut self, _account: &Account, _transaction: &Transaction) {}
+
+    /// Copy of the rule's configuration without any state, for a new account.
+    fn fresh(&self) -> Box<dyn FraudRule>;
+}
+
+/// Transaction flagged or blocked by a fraud rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FraudHit {
+    pub row: Option<u64>,
+    pub client: u16,
+    pub tx: u32,
+    pub timestamp: Option<Timestamp>,
+    pub rule: &'static str,
+    pub verdict: Verdict,
+}
+
+impl FraudHit {
+    pub(crate) fn new(transaction: &Transaction, rule: &'static str, verdict: Verdict) -> Self {
+        Self {
+            row: transaction.row,
+            client: transaction.client,
+            tx: transaction.tx,
+            timestamp: transaction.timestamp,
+            rule,
+            verdict,
+        }
+    }
+}
+
+/// Set of fraud rules. Cloning it clones the rules without their state, and two sets are
+/// equal when they hold rules of the same names.
+#[derive(Debug, Default)]
+pub struct FraudRules(Vec<Box<dyn FraudRule>>);
+
+impl Clone for FraudRules {
+    fn clone(&self) -> Self {
+        Self(self.0.iter().map(|rule| rule.fresh()).collect())
+    }
+}
+
+impl PartialEq for FraudRules {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .map(|rule| rule.name())
+            .eq(other.0.iter().map(|rule| rule.name()))
+    }
+}
+
+impl Eq for FraudRules {}
+
+impl FraudRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, rule: impl FraudRule + 'static) {
+        self.0.push(Box::new(rule));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn FraudRule> {
+        self.0.iter().map(|rule| rule.as_ref())
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn FraudRule>> {
+        self.0.iter_mut()
+    }
+
+    /// Loads the built-in rules configured in a TOML file, one table per rule:
+    /// `[deposit-withdrawal]` and `[disputes]`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = toml::from_str::<RulesFile>(&std::fs::read_to_string(path)?)?;
+        let mut rules = Self::new();
+        if let Some(row) = file.deposit_withdrawal {
+            if row.min_deposit.is_negative() || row.within_seconds < 0 {
+                return Err("Invalid deposit-withdrawal rule".into());
+            }
+            rules.push(DepositWithdrawal {
+                min_deposit: row.min_deposit,
+                within_millis: row.within_seconds.saturating_mul(1000),
+                action: row.action.action()?,
+                last_deposit: None,
+            });
+        }
+        if let Some(row) = file.disputes {
+            rules.push(RepeatedDisputes {
+                max: row.max,
+                action: row.action.action()?,
+                disputes: 0,
+            });
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RulesFile {
+    deposit_withdrawal: Option<DepositWithdrawalRow>,
+    disputes: Option<DisputesRow>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DepositWithdrawalRow {
+    min_deposit: Money,
+    within_seconds: i64,
+    #[serde(default)]
+    action: Action,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DisputesRow {
+    max: u32,
+    #[serde(default)]
+    action: Action,
+}
+
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct Action(Verdict);
+
+impl Default for Action {
+    fn default() -> Self {
+        Self(Verdict::Flag)
+    }
+}
+
+impl Action {
+    fn action(&self) -> Result<Verdict, String> {
+        match self.0 {
+            Verdict::Pass => Err("Rules can only flag or block".to_string()),
+            verdict => Ok(verdict),
+        }
+    }
+}
+
+/// Catches a large deposit withdrawn again in full shortly after.
+#[derive(Debug, Clone)]
+pub struct DepositWithdrawal {
+    /// Smallest deposit the rule watches
+    pub min_deposit: Money,
+    /// How soon after the deposit a withdrawal of at least its amount is a hit
+    pub within_millis: i64,
+    pub action: Verdict,
+    /// Currency, amount and time of the last large deposit
+    last_deposit: Option<(Option<Currency>, Money, Timestamp)>,
+}
+
+impl DepositWithdrawal {
+    pub fn new(min_deposit: Money, within_millis: i64, action: Verdict) -> Self {
+        Self {
+            min_deposit,
+            within_millis,
+            action,
+            last_deposit: None,
+        }
+    }
+}
+
+impl FraudRule for DepositWithdrawal {
+    fn name(&self) -> &'static str {
+        "deposit-withdrawal"
+    }
+
+    fn check(&self, _account: &Account, transaction: &Transaction) -> Verdict {
+        let (Some((currency, deposit, at)), Some(amount), Some(now)) = (
+            &self.last_deposit,
+            transaction.amount(),
+            transaction.timestamp(),
+        ) else {
+            return Verdict::Pass;
+        };
+        let withdrawn_in_full = transaction.transaction_type() == &TransactionType::Withdrawal
+            && transaction.currency() == currency.as_ref()
+            && amount >= *deposit;
+        if withdrawn_in_full && now.as_millis().saturating_sub(at.as_millis()) <= self.within_millis
+        {
+            self.action
+        } else {
+            Verdict::Pass
+        }
+    }
+
+    fn accepted(&mut self, _account: &Account, transaction: &Transaction) {
+        if let (TransactionType::Deposit, Some(amount), Some(at)) = (
+            transaction.transaction_type(),
+            transaction.amount(),
+            transaction.timestamp(),
+        ) {
+            if amount >= self.min_deposit {
+                self.last_deposit = Some((transaction.currency().cloned(), amount, at));
+            }
+        }
+    }
+
+    fn fresh(&self) -> Box<dyn FraudRule> {
+        Box::new(Self::new(self.min_deposit, self.within_millis, self.action))
+    }
+}
+
+/// Catches clients disputing more than `max` transactions.
+#[derive(Debug, Clone)]
+pub struct RepeatedDisputes {
+    pub max: u32,
+    pub action: Verdict,
+    disputes: u32,
+}
+
+impl RepeatedDisputes {
+    pub fn new(max: u32, action: Verdict) -> Self {
+        Self {
+            max,
+            action,
+            disputes: 0,
+        }
+    }
+}
+
+impl FraudRule for RepeatedDisputes {
+    fn name(&self) -> &'static str {
+        "disputes"
+    }
+
+    fn check(&self, _account: &Account, transaction: &Transaction) -> Verdict {
+        if transaction.transaction_type() == &TransactionType::Dispute && self.disputes >= self.max
+        {
+            self.action
+        } else {
+            Verdict::Pass
+        }
+    }
+
+    fn accepted(&mut self, _account: &Account, transaction: &Transaction) {
+        if transaction.transaction_type() == &TransactionType::Dispute {
+            self.disputes += 1;
+        }
+    }
+
+    fn fresh(&self) -> Box<dyn FraudRule> {
+        Box::new(Self::new(self.max, self.action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FraudRules, Verdict};
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join(format!("fraud_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[deposit-withdrawal]\n\
+             min-deposit = \"1000\"\n\
+             within-seconds = 600\n\
+             action = \"block\"\n\
+             [disputes]\n\
+             max = 3\n",
+        )
+        .unwrap();
+        let rules = FraudRules::load(&path).unwrap();
+        assert_eq!(
+            rules.iter().map(|rule| rule.name()).collect::<Vec<_>>(),
+            ["deposit-withdrawal", "disputes"]
+        );
+        assert_eq!(rules.clone(), rules);
+        assert!(Verdict::Block > Verdict::Flag);
+
+        std::fs::write(&path, "[disputes]\nmax = 3\naction = \"pass\"\n").unwrap();
+        assert!(FraudRules::load(&path).is_err());
+        std::fs::write(&path, "[velocity]\nmax = 3\n").unwrap();
+        assert!(FraudRules::load(&path).is_err());
+    }
+}
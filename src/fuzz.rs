@@ -0,0 +1,200 @@
+use crate::account::ChargebackPolicy;
+use crate::currency::Currency;
+use crate::engine::{Engine, EngineConfig};
+use crate::money::Money;
+use crate::reader::{deserialize_csv, ReadOptions};
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionType};
+use arbitrary::{Arbitrary, Result, Unstructured};
+use rust_decimal::Decimal;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Most transactions decoded from a single input, so every run stays fast.
+const MAX_TRANSACTIONS: usize = 1024;
+
+/// `Some` about every other time.
+fn option<'a, T>(
+    u: &mut Unstructured<'a>,
+    value: impl FnOnce(&mut Unstructured<'a>) -> Result<T>,
+) -> Result<Option<T>> {
+    match u.arbitrary::<bool>()? {
+        true => value(u).map(Some),
+        false => Ok(None),
+    }
+}
+
+impl<'a> Arbitrary<'a> for TransactionType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=14)? {
+            0 => TransactionType::Deposit,
+            1 => TransactionType::Withdrawal,
+            2 => TransactionType::Dispute,
+            3 => TransactionType::Resolve,
+            4 => TransactionType::Chargeback,
+            5 => TransactionType::Convert,
+            6 => TransactionType::Transfer,
+            7 => TransactionType::Unlock,
+            8 => TransactionType::Representment,
+            9 => TransactionType::Close,
+            10 => TransactionType::Refund,
+            11 => TransactionType::Authorize,
+            12 => TransactionType::Capture,
+            13 => TransactionType::Void,
+            _ => TransactionType::Adjustment,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Money {
+    /// Mostly small amounts, so disputes and withdrawals find funds, now and then extremes.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let scale = u.int_in_range(0..=28)?;
+        Ok(match u.int_in_range(0..=7)? {
+            0 => Money::new(u.arbitrary()?, scale),
+            1 => Money::from(Decimal::MAX),
+            2 => Money::from(Decimal::MIN),
+            _ => Money::new(i64::from(u.arbitrary::<u16>()?) - 1000, scale.min(4)),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Currency {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let code = u.choose(&["EUR", "USD", "GBP"])?;
+        Ok(code.parse().expect("Currency code is valid"))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Timestamp {
+    /// Mostly within a few years of 2024, so interest and fees span months, now and then
+    /// anywhere from 1970 to 2100. Interest accrues month by month, so wider gaps only
+    /// slow fuzzing down.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=15)? {
+            0 => Timestamp::from_millis(u.int_in_range(0..=4_102_444_799_999)?),
+            _ => Timestamp::from_millis(
+                1_704_067_200_000 + i64::from(u.arbitrary::<u32>()?) * 100_000,
+            ),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Transaction {
+    /// Few clients and transaction ids, so transactions keep referring to each other.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let transaction_type = TransactionType::arbitrary(u)?;
+        let client = u.int_in_range(0..=3)?;
+        let tx = u.int_in_range(0..=15)?;
+        let amount = option(u, Money::arbitrary)?;
+        let mut transaction = Transaction::new(transaction_type, client, tx, amount);
+        transaction.currency = option(u, Currency::arbitrary)?;
+        transaction.to_client = option(u, |u| u.int_in_range(0..=3))?;
+        transaction.to_currency = option(u, Currency::arbitrary)?;
+        transaction.converted = option(u, Money::arbitrary)?;
+        transaction.reason = option(u, |u| Ok((*u.choose(&["ERR01", "fix", ""])?).into()))?;
+        transaction.expires_at = option(u, Timestamp::arbitrary)?;
+        transaction.timestamp = option(u, Timestamp::arbitrary)?;
+        Ok(transaction)
+    }
+}
+
+/// Runs transactions through an engine, one by one with [`Engine::process`] or sharded
+/// across workers with [`Engine::submit`], then sums up the run, checks the invariants and
+/// the books, and checks that every account stays consistent.
+async fn process_all(
+    transactions: impl IntoIterator<Item = Transaction>,
+    config: EngineConfig,
+    sharded: bool,
+) {
+    let mut engine = Engine::with_config(config);
+    for transaction in transactions.into_iter().take(MAX_TRANSACTIONS) {
+        let _ = match sharded {
+            true => engine.submit(transaction).await,
+            false => engine.process(transaction).await,
+        };
+    }
+    engine.summary(Duration::ZERO).await;
+    engine.check_invariants().await;
+    let _ = engine.trial_balance().await;
+    for account in engine.accounts().await {
+        if let Err(e) = account.reconcile() {
+            panic!("Account {} inconsistent: {:?}", account.client(), e);
+        }
+    }
+}
+
+/// Entry point for fuzzers: feeds `data` through the csv parser, or decodes it into
+/// transactions, depending on its first byte, and runs what comes out through an engine.
+/// Panics only on a bug, whatever the input.
+pub fn process_bytes(data: &[u8]) {
+    let mut u = Unstructured::new(data);
+    let mode = u.arbitrary::<u8>().unwrap_or_default();
+    let mut config = EngineConfig {
+        workers: 2,
+        allow_admin_ops: true,
+        chargeback_policy: ChargebackPolicy {
+            lock_account: mode & 2 == 0,
+            allow_negative_available: mode & 4 == 0,
+            deposits_when_locked: mode & 8 != 0,
+            disputes_when_locked: mode & 16 != 0,
+        },
+        full_history: mode & 32 != 0,
+        check_invariants: true,
+        event_log: true,
+        ..EngineConfig::default()
+    };
+    if mode & 64 != 0 {
+        config.interest_rate = Some(Decimal::new(5, 2));
+        config.overdraft_limit = Money::from(100);
+    }
+    let sharded = mode & 128 != 0;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Fuzzing runtime can't be built");
+
+    if mode & 1 == 0 {
+        let csv = data.get(1..).unwrap_or_default();
+        let (sender, mut receiver) = mpsc::channel(csv.len() + 1);
+        let _ = deserialize_csv(csv, ReadOptions::default(), sender);
+        let transactions = std::iter::from_fn(|| receiver.try_recv().ok());
+        runtime.block_on(process_all(transactions, config, sharded));
+    } else {
+        let transactions = std::iter::from_fn(|| match u.is_empty() {
+            true => None,
+            false => Transaction::arbitrary(&mut u).ok(),
+        });
+        runtime.block_on(process_all(transactions, config, sharded));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::process_bytes;
+    use crate::dedup::mix;
+
+    /// A short fuzzing run with deterministic inputs, csv and structured alike.
+    #[test]
+    fn random_inputs() {
+        for seed in 0..300u64 {
+            let len = (mix(seed) % 512) as usize;
+            let data = (0..len as u64)
+                .map(|i| mix(seed << 16 | i) as u8)
+                .collect::<Vec<_>>();
+            process_bytes(&data);
+        }
+        let csv = b"\x00type,client,tx,amount\ndeposit,1,1,5\ndispute,1,1,\nchargeback,1,1,\n\
+            withdrawal,1,2,79228162514264337593543950335\ndeposit,1,3,-1\n";
+        process_bytes(csv);
+        // Totals of deposits too large to be represented, on one worker and across two
+        for mode in [b'\x00', b'\x80'] {
+            let mut csv = vec![mode];
+            csv.extend_from_slice(
+                b"type,client,tx,amount\ndeposit,1,1,4e28\nwithdrawal,1,2,4e28\n\
+                  deposit,1,3,4e28\ndeposit,2,4,4e28\n",
+            );
+            process_bytes(&csv);
+        }
+    }
+}
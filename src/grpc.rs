@@ -0,0 +1,340 @@
+use crate::account::Account;
+use crate::money::MoneyFormat;
+use crate::reader;
+use crate::server::Server;
+use serde_json::{Map, Value};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+/// Messages and service stubs generated from [`SERVICE_PROTO`].
+pub mod proto {
+    tonic::include_proto!("transaction_system");
+}
+
+use proto::engine_server::{self, EngineServer};
+use proto::{ack, AccountRequest, AccountsRequest, Ack, Transaction};
+
+/// Protobuf definition of the service `serve --grpc` offers, to generate clients from.
+pub const SERVICE_PROTO: &str = include_str!("transaction_system.proto");
+
+/// Fields of a `Transaction` message that are strings, named like the columns of an input.
+const STRING_FIELDS: [&str; 8] = [
+    "amount",
+    "currency",
+    "to_currency",
+    "reason",
+    "timestamp",
+    "expires_at",
+    "signature",
+    "type",
+];
+
+/// Service of `serve --grpc`, applying transactions to the engine of a [`Server`].
+struct EngineService {
+    server: Arc<Server>,
+}
+
+type Acks = Pin<Box<dyn Stream<Item = Result<Ack, Status>> + Send>>;
+type Accounts = Pin<Box<dyn Stream<Item = Result<proto::Account, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl engine_server::Engine for EngineService {
+    type SubmitStream = Acks;
+    type ListAccountsStream = Accounts;
+
+    async fn submit(
+        &self,
+        request: Request<Streaming<Transaction>>,
+    ) -> Result<Response<Acks>, Status> {
+        let mut transactions = request.into_inner();
+        let server = self.server.clone();
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(transaction) = transactions.next().await {
+                let ack = match transaction {
+                    Ok(transaction) => Ok(submit(&server, transaction).await),
+                    Err(status) => Err(status),
+                };
+                if sender.send(ack).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<AccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = request.into_inner().client;
+        let client = u16::try_from(client)
+            .map_err(|_| Status::invalid_argument(format!("Invalid client {}", client)))?;
+        let engine = self.server.engine().await;
+        match engine.account(client).await {
+            Some(account) => Ok(Response::new(account_message(
+                &account,
+                &engine.config().output_format,
+            ))),
+            None => Err(Status::not_found(format!(
+                "No account of client {}",
+                client
+            ))),
+        }
+    }
+
+    async fn list_accounts(
+        &self,
+        _: Request<AccountsRequest>,
+    ) -> Result<Response<Accounts>, Status> {
+        let engine = self.server.engine().await;
+        let format = &engine.config().output_format;
+        let accounts = engine
+            .accounts()
+            .await
+            .iter()
+            .map(|account| Ok(account_message(account, format)))
+            .collect::<Vec<_>>();
+        Ok(Response::new(Box::pin(tokio_stream::iter(accounts))))
+    }
+}
+
+/// Serves the gRPC service over HTTP/2 without TLS on connections accepted from
+/// `listener`, until it fails.
+pub(crate) async fn serve(server: Arc<Server>, listener: TcpListener) -> std::io::Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(EngineServer::new(EngineService { server }))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .map_err(std::io::Error::other)
+}
+
+/// Applies the transaction of a `Transaction` message, returning the `Ack` of it.
+async fn submit(server: &Server, transaction: Transaction) -> Ack {
+    let tx = transaction.tx;
+    let ack = |status: ack::Status, code: u16, reason: String| Ack {
+        tx,
+        status: status.into(),
+        code: code.into(),
+        reason,
+    };
+    let value = match transaction_value(transaction) {
+        Ok(value) => value,
+        Err(reason) => return ack(ack::Status::Invalid, 0, reason),
+    };
+    let row = server.next_row();
+    let transaction = match reader::parse_json_transaction(value, row, server.read_options()) {
+        Ok(transaction) => transaction,
+        Err(reason) => return ack(ack::Status::Invalid, 0, reason),
+    };
+    match server.engine().await.process(transaction).await {
+        Ok(()) => ack(ack::Status::Accepted, 0, String::new()),
+        Err(e) => ack(ack::Status::Rejected, e.code(), e.kind.to_string()),
+    }
+}
+
+/// JSON object of a `Transaction` message, the way a row of a JSON input has it: fields in
+/// the order of their numbers, left out when empty, followed by the metadata in key order.
+fn transaction_value(transaction: Transaction) -> Result<Value, String> {
+    let Transaction {
+        r#type,
+        client,
+        tx,
+        amount,
+        currency,
+        to_client,
+        to_currency,
+        reason,
+        timestamp,
+        expires_at,
+        signature,
+        metadata,
+    } = transaction;
+    let mut object = Map::new();
+    let string = |object: &mut Map<String, Value>, name: &str, value: String| {
+        if !value.is_empty() {
+            object.insert(name.to_string(), Value::String(value));
+        }
+    };
+    string(&mut object, "type", r#type);
+    object.insert("client".to_string(), client.into());
+    object.insert("tx".to_string(), tx.into());
+    string(&mut object, "amount", amount);
+    string(&mut object, "currency", currency);
+    if let Some(to_client) = to_client {
+        object.insert("to_client".to_string(), to_client.into());
+    }
+    string(&mut object, "to_currency", to_currency);
+    string(&mut object, "reason", reason);
+    string(&mut object, "timestamp", timestamp);
+    string(&mut object, "expires_at", expires_at);
+    string(&mut object, "signature", signature);
+    for (key, value) in metadata {
+        if STRING_FIELDS.contains(&key.as_str()) || ["client", "tx", "to_client"].contains(&&*key) {
+            return Err(format!("Metadata can't be named {}", key));
+        }
+        object.insert(key, Value::String(value));
+    }
+    Ok(Value::Object(object))
+}
+
+/// `Account` message of the account, amounts formatted like the account report.
+fn account_message(account: &Account, format: &MoneyFormat) -> proto::Account {
+    let state = account.state();
+    proto::Account {
+        client: state.client.into(),
+        balances: state
+            .balances
+            .iter()
+            .map(|(currency, balance)| proto::account::Balance {
+                currency: currency
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+                available: balance.available().format(format),
+                held: balance.held().format(format),
+                total: balance.total().format(format),
+            })
+            .collect(),
+        locked: state.locked,
+        closed: state.closed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::engine_client::EngineClient;
+    use super::proto::{self, ack, AccountRequest, AccountsRequest, Ack, Transaction};
+    use super::{serve, transaction_value};
+    use crate::reader::ReadOptions;
+    use crate::server::Server;
+    use crate::Engine;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_stream::StreamExt;
+    use tonic::Code;
+
+    fn transaction(kind: &str, client: u32, tx: u32, amount: &str) -> Transaction {
+        Transaction {
+            r#type: kind.to_string(),
+            client,
+            tx,
+            amount: amount.to_string(),
+            ..Transaction::default()
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let mut message = transaction("deposit", 1, 0, "2.5");
+        message.metadata = BTreeMap::from([
+            ("merchant".to_string(), "shop".to_string()),
+            ("channel".to_string(), "card".to_string()),
+        ]);
+        // Metadata follows the fields in key order, which signatures cover
+        assert_eq!(
+            serde_json::to_string(&transaction_value(message.clone()).unwrap()).unwrap(),
+            r#"{"type":"deposit","client":1,"tx":0,"amount":"2.5","channel":"card","merchant":"shop"}"#
+        );
+        let mut transfer = transaction("transfer", 1, 2, "1");
+        transfer.to_client = Some(0);
+        assert_eq!(
+            transaction_value(transfer).unwrap(),
+            json!({ "type": "transfer", "client": 1, "tx": 2, "amount": "1", "to_client": 0 })
+        );
+
+        for name in ["amount", "to_client"] {
+            let mut reserved = message.clone();
+            reserved.metadata.insert(name.to_string(), "1".to_string());
+            assert_eq!(
+                transaction_value(reserved).unwrap_err(),
+                format!("Metadata can't be named {}", name)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn calls() {
+        let server = Arc::new(Server::new(Engine::new(), ReadOptions::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(server, listener));
+        let mut client = EngineClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+
+        let transactions = tokio_stream::iter([
+            transaction("deposit", 1, 1, "5"),
+            transaction("withdrawal", 1, 2, "9"),
+            transaction("deposit", 1, 3, "x"),
+        ]);
+        let acks = client
+            .submit(transactions)
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Result<Vec<_>, _>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            acks[..2],
+            [
+                Ack {
+                    tx: 1,
+                    status: ack::Status::Accepted.into(),
+                    code: 0,
+                    reason: String::new(),
+                },
+                Ack {
+                    tx: 2,
+                    status: ack::Status::Rejected.into(),
+                    code: 304,
+                    reason: "Not enough available funds".to_string(),
+                },
+            ]
+        );
+        assert_eq!((acks[2].tx, acks[2].status()), (3, ack::Status::Invalid));
+
+        let account = proto::Account {
+            client: 1,
+            balances: vec![proto::account::Balance {
+                currency: String::new(),
+                available: "5.0000".to_string(),
+                held: "0.0000".to_string(),
+                total: "5.0000".to_string(),
+            }],
+            locked: false,
+            closed: false,
+        };
+        let found = client.get_account(AccountRequest { client: 1 }).await;
+        assert_eq!(found.unwrap().into_inner(), account);
+        let missing = client
+            .get_account(AccountRequest { client: 2 })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+        assert_eq!(missing.message(), "No account of client 2");
+        let invalid = client
+            .get_account(AccountRequest { client: 70_000 })
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+
+        let listed = client
+            .list_accounts(AccountsRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Result<Vec<_>, _>>()
+            .await
+            .unwrap();
+        assert_eq!(listed, [account]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::encryption::{self, Cipher, StreamId};
+use crate::snapshot::HistoryState;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Bound on the history entries an account keeps in memory.
+///
+/// Once an account holds more, its oldest entries are spilled to a file shared by all
+/// accounts and loaded back when a later transaction refers to them, or read for the ledger
+/// and snapshots. Entries under dispute, charged back or holding an open authorization stay
+/// in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryWindow {
+    /// Entries kept in memory per account
+    pub size: usize,
+    spill: Arc<HistorySpill>,
+}
+
+impl HistoryWindow {
+    /// Window of `size` entries spilling to a new file at `path`, which is removed again
+    /// once the last account using it is gone. With a cipher entries are spilled encrypted.
+    pub fn new(size: usize, path: impl AsRef<Path>, cipher: Option<Cipher>) -> io::Result<Self> {
+        Ok(Self {
+            size: size.max(1),
+            spill: Arc::new(HistorySpill::create(path, cipher)?),
+        })
+    }
+
+    pub(crate) fn spill(&self) -> &HistorySpill {
+        &self.spill
+    }
+}
+
+/// Append-only file of spilled history entries, one JSON line each.
+#[derive(Debug)]
+pub(crate) struct HistorySpill {
+    path: PathBuf,
+    file: Mutex<File>,
+    cipher: Option<Cipher>,
+    stream: StreamId,
+}
+
+impl PartialEq for HistorySpill {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for HistorySpill {}
+
+impl HistorySpill {
+    fn create(path: impl AsRef<Path>, cipher: Option<Cipher>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            cipher,
+            stream: encryption::new_stream(),
+        })
+    }
+
+    /// Appends the entry, returning the offset it can be read back from. Encrypted entries
+    /// are sealed with their offset as their sequence number.
+    pub(crate) fn write(&self, entry: &HistoryState) -> io::Result<u64> {
+        let entry = serde_json::to_vec(entry)?;
+        let mut file = self.file.lock().expect("History spill poisoned");
+        let offset = file.seek(io::SeekFrom::End(0))?;
+        let mut line = encryption::seal_line(self.cipher.as_ref(), &self.stream, offset, entry);
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(offset)
+    }
+
+    pub(crate) fn read(&self, offset: u64) -> io::Result<HistoryState> {
+        let mut file = self.file.lock().expect("History spill poisoned");
+        file.seek(io::SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        io::BufReader::new(&mut *file).read_line(&mut line)?;
+        let entry =
+            encryption::open_line(self.cipher.as_ref(), &mut Some(self.stream), offset, &line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(serde_json::from_slice(&entry)?)
+    }
+}
+
+impl Drop for HistorySpill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,97 @@
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{header, Request};
+use hyper_util::rt::TokioIo;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use tokio::net::TcpStream;
+
+/// Plain `http://` URL JSON is posted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported URL {}, only http:// is", s))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Invalid port in URL {}", s))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in URL {}", s));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+impl HttpUrl {
+    /// Posts the JSON body on a connection of its own, succeeding on a 2xx response.
+    pub(crate) async fn post_json(&self, body: &[u8]) -> io::Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(io::Error::other)?;
+        let connection = tokio::spawn(connection);
+        let request = Request::post(&self.path)
+            .header(header::HOST, format!("{}:{}", self.host, self.port))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::copy_from_slice(body)))
+            .map_err(io::Error::other)?;
+        let response = sender.send_request(request).await;
+        // The body of the response doesn't matter
+        connection.abort();
+        let status = response.map_err(io::Error::other)?.status();
+        match status.is_success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("Server answered {}", status))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpUrl;
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            "http://localhost:8080/hooks/payments".parse(),
+            Ok(HttpUrl {
+                host: "localhost".to_string(),
+                port: 8080,
+                path: "/hooks/payments".to_string(),
+            })
+        );
+        let url = "http://example.com".parse::<HttpUrl>().unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/"));
+        assert_eq!(url.to_string(), "http://example.com:80/");
+        assert!("https://example.com".parse::<HttpUrl>().is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::timestamp::Timestamp;
+use chrono::{DateTime, Datelike, Months, NaiveDate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Days interest is accrued over in a year.
+const DAYS_PER_YEAR: i64 = 365;
+
+/// Interest credited to an account at the start of a calendar month.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterestPosting {
+    currency: Option<Currency>,
+    amount: Money,
+    timestamp: Timestamp,
+}
+
+impl InterestPosting {
+    pub(crate) fn new(currency: Option<Currency>, amount: Money, timestamp: Timestamp) -> Self {
+        Self {
+            currency,
+            amount,
+            timestamp,
+        }
+    }
+
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+
+    /// Start of the month the interest was posted at.
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+}
+
+/// Days since the unix epoch, in UTC.
+pub(crate) fn day(timestamp: Timestamp) -> i64 {
+    timestamp.as_millis().div_euclid(MILLIS_PER_DAY)
+}
+
+pub(crate) fn day_start(day: i64) -> Option<Timestamp> {
+    day.checked_mul(MILLIS_PER_DAY).map(Timestamp::from_millis)
+}
+
+/// First day of the calendar month following `day`.
+pub(crate) fn next_month_start(day: i64) -> Option<i64> {
+    let date = DateTime::from_timestamp_millis(day.checked_mul(MILLIS_PER_DAY)?)?.date_naive();
+    let next = NaiveDate::from_ymd_opt(date.year(), date.month(), 1)?
+        .checked_add_months(Months::new(1))?;
+    Some(
+        next.signed_duration_since(NaiveDate::from_ymd_opt(1970, 1, 1)?)
+            .num_days(),
+    )
+}
+
+/// Fraction of a balance accrued over `days` at the annual rate `apr`.
+pub(crate) fn accrual_factor(apr: Decimal, days: i64) -> Option<Decimal> {
+    apr.checked_mul(Decimal::from(days))?
+        .checked_div(Decimal::from(DAYS_PER_YEAR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accrual_factor, day, day_start, next_month_start};
+    use crate::timestamp::Timestamp;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn calendar() {
+        let at = |date: &str| date.parse::<Timestamp>().unwrap();
+        let jan_31 = day(at("2024-01-31T23:59:59Z"));
+        assert_eq!(jan_31, day(at("2024-01-31T00:00:00Z")));
+        assert_eq!(
+            next_month_start(jan_31),
+            Some(day(at("2024-02-01T00:00:00Z")))
+        );
+        assert_eq!(
+            next_month_start(day(at("2024-12-15T00:00:00Z"))),
+            Some(day(at("2025-01-01T00:00:00Z")))
+        );
+        assert_eq!(day(Timestamp::from_millis(-1)), -1);
+        assert_eq!(day_start(jan_31), Some(at("2024-01-31T00:00:00Z")));
+    }
+
+    #[test]
+    fn factor() {
+        assert_eq!(
+            accrual_factor(Decimal::new(365, 3), 10),
+            Some(Decimal::new(1, 2))
+        );
+        assert_eq!(accrual_factor(Decimal::ZERO, 10), Some(Decimal::ZERO));
+    }
+}
@@ -0,0 +1,440 @@
+use crate::account::{Account, Balance, ChargebackPolicy};
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::transaction::{Transaction, TransactionType};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Broken invariant, with the transaction that broke it unless it was the global balance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    /// Line of the input the transaction was read from
+    pub row: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Some(client), Some(tx)) = (self.client, self.tx) {
+            write!(f, "client {}, tx {}", client, tx)?;
+            if let Some(row) = self.row {
+                write!(f, ", row {}", row)?;
+            }
+            write!(f, ": ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Balances of an account before a transaction, to be compared with those after it.
+pub(crate) struct Before {
+    client: u16,
+    balances: BTreeMap<Option<Currency>, Balance>,
+    fees: usize,
+    interest: usize,
+    locked: bool,
+}
+
+impl Before {
+    pub(crate) fn of(account: &Account) -> Self {
+        Self {
+            client: account.client(),
+            balances: account.balances().clone(),
+            fees: account.fees().len(),
+            interest: account.interest().len(),
+            locked: account.locked(),
+        }
+    }
+}
+
+/// Money that entered or left all accounts together in a single currency, by cause.
+#[derive(Debug, Default)]
+struct Flows {
+    /// Totals of accounts as they were when first seen, e.g. restored from a snapshot
+    opening: Money,
+    deposits: Money,
+    withdrawals: Money,
+    chargebacks: Money,
+    fees: Money,
+    interest: Money,
+    /// Net change of every other transaction: withdrawal disputes, refunds, captures,
+    /// adjustments, conversions, representments and transfers to or from other partitions
+    other: Money,
+    /// Whether a flow no longer fits into [`Money`], which leaves the flows unchecked
+    overflowed: bool,
+}
+
+impl Flows {
+    /// What the accounts should hold, `None` if that doesn't fit into [`Money`].
+    fn expected(&self) -> Option<Money> {
+        self.opening
+            .checked_add(self.deposits)?
+            .checked_sub(self.withdrawals)?
+            .checked_sub(self.chargebacks)?
+            .checked_sub(self.fees)?
+            .checked_add(self.interest)?
+            .checked_add(self.other)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    flows: BTreeMap<Option<Currency>, Flows>,
+    /// Clients whose opening balances are in the flows
+    seen: BTreeSet<u16>,
+    violations: Vec<Violation>,
+}
+
+impl Ledger {
+    /// Adds `amount` to the flow of `currency` that `cause` picks. Fails the first time a
+    /// flow of the currency overflows, which stops adding to them.
+    fn add(
+        &mut self,
+        currency: &Option<Currency>,
+        cause: fn(&mut Flows) -> &mut Money,
+        amount: Money,
+    ) -> Result<(), String> {
+        let flows = self.flows.entry(currency.clone()).or_default();
+        if flows.overflowed {
+            return Ok(());
+        }
+        let flow = cause(flows);
+        match flow.checked_add(amount) {
+            Some(sum) => *flow = sum,
+            None => {
+                flows.overflowed = true;
+                return Err(format!(
+                    "flows of {} overflowed, leaving them unchecked",
+                    currency_name(currency)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds `amount` to the change of `currency` a transaction caused.
+fn change(
+    changes: &mut BTreeMap<Option<Currency>, Money>,
+    currency: &Option<Currency>,
+    amount: Option<Money>,
+) -> Result<(), String> {
+    let change = changes.entry(currency.clone()).or_default();
+    *change = amount
+        .and_then(|amount| change.checked_add(amount))
+        .ok_or_else(|| format!("change of {} overflowed", currency_name(currency)))?;
+    Ok(())
+}
+
+/// Checks every processed transaction against the invariants of money:
+///
+/// - every balance's total is its available plus held funds,
+/// - deposits add exactly their amount, withdrawals take exactly their amount, transfers
+///   within the partition move money without creating any, and authorizations, voids,
+///   unlocks, closes and rejected transactions change no total, besides the fees and
+///   interest they post,
+/// - locked accounts' balances don't change, except through the transactions the
+///   chargeback policy still accepts,
+///
+/// and, with [`InvariantChecker::finish`], that the accounts together hold what was
+/// deposited, minus what was withdrawn and charged back, give or take fees, interest and
+/// the other transactions.
+#[derive(Debug, Clone)]
+pub struct InvariantChecker {
+    ledger: Arc<Mutex<Ledger>>,
+    broken: Arc<AtomicBool>,
+    policy: ChargebackPolicy,
+}
+
+fn currency_name(currency: &Option<Currency>) -> String {
+    match currency {
+        Some(currency) => currency.to_string(),
+        None => "the default currency".to_string(),
+    }
+}
+
+impl InvariantChecker {
+    pub fn new(policy: ChargebackPolicy) -> Self {
+        Self {
+            ledger: Arc::default(),
+            broken: Arc::default(),
+            policy,
+        }
+    }
+
+    /// Whether an invariant was broken so far, cheap enough to ask after every transaction.
+    pub fn broken(&self) -> bool {
+        self.broken.load(Ordering::Relaxed)
+    }
+
+    /// Compares `accounts` after `transaction` with how they were before it: the account of
+    /// the transaction and, for transfers, the destination account. Transfers with one
+    /// account only are the leg of a transfer between partitions on this side.
+    pub(crate) fn check(
+        &self,
+        transaction: &Transaction,
+        accepted: bool,
+        accounts: &[(Before, &Account)],
+    ) {
+        let mut ledger = self.ledger.lock().expect("Invariant ledger poisoned");
+        let mut violations = Vec::new();
+        // Change of the totals caused by the transaction itself, fees and interest aside
+        let mut changes = BTreeMap::<Option<Currency>, Money>::new();
+        for (before, account) in accounts {
+            if ledger.seen.insert(before.client) {
+                for (currency, balance) in &before.balances {
+                    let opening = ledger.add(currency, |f| &mut f.opening, balance.total());
+                    violations.extend(opening.err());
+                }
+            }
+            if let Err(e) = account.reconcile() {
+                violations.push(format!("client {}: {}", before.client, e));
+            }
+            let mut own = BTreeMap::<Option<Currency>, Money>::new();
+            for fee in &account.fees()[before.fees.min(account.fees().len())..] {
+                let currency = fee.currency().cloned();
+                violations.extend(ledger.add(&currency, |f| &mut f.fees, fee.amount()).err());
+                violations.extend(change(&mut own, &currency, Some(fee.amount())).err());
+            }
+            for posting in &account.interest()[before.interest.min(account.interest().len())..] {
+                let currency = posting.currency().cloned();
+                let interest = ledger.add(&currency, |f| &mut f.interest, posting.amount());
+                violations.extend(interest.err());
+                violations.extend(change(&mut own, &currency, Some(-posting.amount())).err());
+            }
+            let currencies = before.balances.keys().chain(account.balances().keys());
+            for currency in currencies.collect::<BTreeSet<_>>() {
+                let total = |balances: &BTreeMap<Option<Currency>, Balance>| {
+                    balances
+                        .get(currency)
+                        .map(Balance::total)
+                        .unwrap_or_default()
+                };
+                let difference = total(account.balances()).checked_sub(total(&before.balances));
+                violations.extend(change(&mut own, currency, difference).err());
+            }
+
+            let exempt = match transaction.transaction_type {
+                TransactionType::Unlock | TransactionType::Representment => true,
+                // Returned transfers to other partitions come back to locked accounts too
+                TransactionType::Transfer => accounts.len() == 1,
+                TransactionType::Deposit => self.policy.deposits_when_locked,
+                TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback => self.policy.disputes_when_locked,
+                _ => false,
+            };
+            if accepted && before.locked && !exempt && own.values().any(|c| *c != Money::ZERO) {
+                violations.push(format!(
+                    "balance of locked client {} changed",
+                    before.client
+                ));
+            }
+            for (currency, amount) in own {
+                violations.extend(change(&mut changes, &currency, Some(amount)).err());
+            }
+        }
+        changes.retain(|_, change| *change != Money::ZERO);
+
+        let currency = transaction.currency.clone();
+        let amount = transaction.amount.unwrap_or(Money::ZERO);
+        let expected = match (&transaction.transaction_type, accepted) {
+            (TransactionType::Transfer, true) if accounts.len() == 1 => None,
+            (_, false)
+            | (
+                TransactionType::Transfer
+                | TransactionType::Authorize
+                | TransactionType::Void
+                | TransactionType::Unlock
+                | TransactionType::Close,
+                true,
+            ) => Some(BTreeMap::new()),
+            (TransactionType::Deposit, true) => Some(BTreeMap::from([(currency, amount)])),
+            (TransactionType::Withdrawal, true) => Some(BTreeMap::from([(currency, -amount)])),
+            _ => None,
+        };
+        match expected {
+            Some(mut expected) => {
+                expected.retain(|_, change| *change != Money::ZERO);
+                if expected != changes {
+                    let describe = |changes: &BTreeMap<Option<Currency>, Money>| {
+                        let changes = changes
+                            .iter()
+                            .map(|(currency, change)| {
+                                format!("{} in {}", change, currency_name(currency))
+                            })
+                            .collect::<Vec<_>>();
+                        match changes.is_empty() {
+                            true => "nothing".to_string(),
+                            false => changes.join(", "),
+                        }
+                    };
+                    violations.push(format!(
+                        "{} {:?} should change totals by {} but changed them by {}",
+                        if accepted { "accepted" } else { "rejected" },
+                        transaction.transaction_type,
+                        describe(&expected),
+                        describe(&changes)
+                    ));
+                }
+                for (currency, change) in changes {
+                    let flow = match transaction.transaction_type {
+                        TransactionType::Deposit if accepted => {
+                            ledger.add(&currency, |f| &mut f.deposits, change)
+                        }
+                        TransactionType::Withdrawal if accepted => {
+                            ledger.add(&currency, |f| &mut f.withdrawals, -change)
+                        }
+                        _ => ledger.add(&currency, |f| &mut f.other, change),
+                    };
+                    violations.extend(flow.err());
+                }
+            }
+            None => {
+                for (currency, change) in changes {
+                    let flow = match transaction.transaction_type {
+                        TransactionType::Chargeback => {
+                            ledger.add(&currency, |f| &mut f.chargebacks, -change)
+                        }
+                        _ => ledger.add(&currency, |f| &mut f.other, change),
+                    };
+                    violations.extend(flow.err());
+                }
+            }
+        }
+
+        if !violations.is_empty() {
+            self.broken.store(true, Ordering::Relaxed);
+        }
+        ledger
+            .violations
+            .extend(violations.into_iter().map(|message| Violation {
+                client: Some(transaction.client),
+                tx: Some(transaction.tx),
+                row: transaction.row,
+                message,
+            }));
+    }
+
+    /// Checks that the accounts together hold what flowed into them, once every
+    /// transaction is processed, and returns every violation found so far.
+    pub fn finish(&self, accounts: &[Account]) -> Vec<Violation> {
+        let mut ledger = self.ledger.lock().expect("Invariant ledger poisoned");
+        // Totals by currency, `None` once they overflowed
+        let mut held = BTreeMap::<Option<Currency>, Option<Money>>::new();
+        for account in accounts
+            .iter()
+            .filter(|a| ledger.seen.contains(&a.client()))
+        {
+            for (currency, balance) in account.balances() {
+                let total = held.entry(currency.clone()).or_insert(Some(Money::ZERO));
+                *total = total.and_then(|total| total.checked_add(balance.total()));
+            }
+        }
+        let mut violations = Vec::new();
+        for (currency, flows) in &ledger.flows {
+            let total = held.remove(currency).unwrap_or(Some(Money::ZERO));
+            if flows.overflowed {
+                // Reported when the flow overflowed
+                continue;
+            }
+            let (Some(total), Some(expected)) = (total, flows.expected()) else {
+                violations.push(format!(
+                    "accounts or flows of {} overflowed, leaving them unchecked",
+                    currency_name(currency)
+                ));
+                continue;
+            };
+            if total != expected {
+                violations.push(format!(
+                    "accounts hold {} in {} but {} opening + {} deposits - {} withdrawals \
+                     - {} chargebacks - {} fees + {} interest + {} other = {}",
+                    total,
+                    currency_name(currency),
+                    flows.opening,
+                    flows.deposits,
+                    flows.withdrawals,
+                    flows.chargebacks,
+                    flows.fees,
+                    flows.interest,
+                    flows.other,
+                    expected
+                ));
+            }
+        }
+        for (currency, total) in held {
+            match total {
+                Some(total) if total == Money::ZERO => {}
+                Some(total) => violations.push(format!(
+                    "accounts hold {} in {} that never flowed into them",
+                    total,
+                    currency_name(&currency)
+                )),
+                None => violations.push(format!(
+                    "accounts hold more {} than fits, none of which flowed into them",
+                    currency_name(&currency)
+                )),
+            }
+        }
+        if !violations.is_empty() {
+            self.broken.store(true, Ordering::Relaxed);
+        }
+        ledger
+            .violations
+            .extend(violations.into_iter().map(|message| Violation {
+                client: None,
+                tx: None,
+                row: None,
+                message,
+            }));
+        ledger.violations.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Before, InvariantChecker};
+    use crate::{Account, ChargebackPolicy, Money, Transaction, TransactionType};
+
+    fn deposit(tx: u32, amount: i64) -> Transaction {
+        Transaction::new(TransactionType::Deposit, 1, tx, Some(Money::from(amount)))
+    }
+
+    #[test]
+    fn violations() {
+        let checker = InvariantChecker::new(ChargebackPolicy::default());
+        let mut account = Account::new(1);
+        let before = Before::of(&account);
+        account.add_transaction(deposit(1, 5));
+        account.process_pending_transaction().unwrap();
+        checker.check(&deposit(1, 5), true, &[(before, &account)]);
+        assert!(!checker.broken());
+
+        // Claiming the deposit was for less than it credited
+        let before = Before::of(&account);
+        account.add_transaction(deposit(2, 5));
+        account.process_pending_transaction().unwrap();
+        checker.check(&deposit(2, 4), true, &[(before, &account)]);
+        assert!(checker.broken());
+
+        // Funds credited without the checker seeing it
+        account.add_transaction(deposit(3, 1));
+        account.process_pending_transaction().unwrap();
+        let violations = checker.finish(&[account]);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            violations[0].to_string(),
+            "client 1, tx 2: accepted Deposit should change totals by 4 in the default \
+             currency but changed them by 5 in the default currency"
+        );
+        assert_eq!(
+            violations[1].to_string(),
+            "accounts hold 11 in the default currency but 0 opening + 10 deposits - 0 \
+             withdrawals - 0 chargebacks - 0 fees + 0 interest + 0 other = 10"
+        );
+    }
+}
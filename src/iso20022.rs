@@ -0,0 +1,448 @@
+use crate::timestamp::Timestamp;
+use chrono::{NaiveDate, NaiveDateTime};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Decoder, Reader};
+use std::io::{self, Read};
+
+fn invalid(reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid ISO 20022 input: {}", reason),
+    )
+}
+
+/// Columns of the records read from a message, the ones after `timestamp` end up in the
+/// metadata of their transaction.
+pub const COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "timestamp",
+    "message_id",
+    "end_to_end_id",
+    "counterparty_name",
+    "memo",
+];
+
+/// Kind of a message, told by the element under `Document`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// pain.001 customer credit transfer initiation, `CstmrCdtTrfInitn`
+    PaymentInitiation,
+    /// camt.053 bank to customer statement, `BkToCstmrStmt`
+    Statement,
+}
+
+/// Transfer of a pain.001 message or entry of a camt.053 statement being read.
+#[derive(Debug, Default)]
+struct Payment {
+    credit_debit: Option<String>,
+    status: Option<String>,
+    amount: Option<String>,
+    currency: Option<String>,
+    date: Option<String>,
+    /// `InstrId` and `NtryRef`, then `AcctSvcrRef`, then `EndToEndId`: the first of them
+    /// that is set is the transaction id
+    references: [Option<String>; 3],
+    end_to_end_id: Option<String>,
+    debtor: Option<String>,
+    creditor: Option<String>,
+    memo: Option<String>,
+}
+
+/// Message-wide fields a payment falls back on.
+#[derive(Debug, Default)]
+struct Context {
+    message_id: Option<String>,
+    created: Option<String>,
+    /// Debtor account of the payment information block, or account of the statement
+    account: Option<String>,
+    /// Requested execution date of the payment information block
+    execution_date: Option<String>,
+}
+
+/// Local name of an element or attribute, without namespace prefix.
+fn local_name(name: &[u8]) -> io::Result<&str> {
+    std::str::from_utf8(name).map_err(invalid)
+}
+
+/// Value of the element's attribute with this local name, unescaped.
+fn attribute(element: &BytesStart, name: &str, decoder: Decoder) -> io::Result<Option<String>> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(invalid)?;
+        if local_name(attribute.key.local_name().as_ref())? == name {
+            return Ok(Some(
+                attribute
+                    .decode_and_unescape_value(decoder)
+                    .map_err(invalid)?
+                    .into_owned(),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+/// Dates and local date times of a message as epoch millis, taken to be UTC. Values that
+/// aren't dates are kept for validation to reject.
+fn timestamp(value: &str) -> String {
+    let millis = value
+        .parse::<Timestamp>()
+        .map(|t| t.as_millis())
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|date| date.and_utc().timestamp_millis())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| date.and_utc().timestamp_millis())
+        });
+    millis.map_or_else(|| value.to_string(), |millis| millis.to_string())
+}
+
+impl Payment {
+    /// Record of [`COLUMNS`], `None` for statement entries that aren't booked yet.
+    fn record(self, message: Message, context: &Context) -> Option<Vec<String>> {
+        let (transaction_type, counterparty) = match message {
+            Message::PaymentInitiation => ("withdrawal".to_string(), self.creditor),
+            Message::Statement => {
+                if self
+                    .status
+                    .as_deref()
+                    .is_some_and(|status| status != "BOOK")
+                {
+                    return None;
+                }
+                match self.credit_debit.as_deref() {
+                    Some("CRDT") => ("deposit".to_string(), self.debtor),
+                    Some("DBIT") => ("withdrawal".to_string(), self.creditor),
+                    other => (other.unwrap_or_default().to_string(), None),
+                }
+            }
+        };
+        let date = match message {
+            Message::PaymentInitiation => context.execution_date.as_ref(),
+            Message::Statement => self.date.as_ref(),
+        };
+        let [first, second, third] = self.references;
+        Some(vec![
+            transaction_type,
+            context.account.clone().unwrap_or_default(),
+            first.or(second).or(third).unwrap_or_default(),
+            self.amount.unwrap_or_default(),
+            self.currency.unwrap_or_default(),
+            date.or(context.created.as_ref())
+                .map(|date| timestamp(date))
+                .unwrap_or_default(),
+            context.message_id.clone().unwrap_or_default(),
+            self.end_to_end_id.unwrap_or_default(),
+            counterparty.unwrap_or_default(),
+            self.memo.unwrap_or_default(),
+        ])
+    }
+
+    fn set(&mut self, path: &[&str], value: String) {
+        let field = match path {
+            [.., "CdtTrfTxInf", "Amt", "InstdAmt"] | [.., "Ntry", "Amt"] => &mut self.amount,
+            [.., "Ntry", "CdtDbtInd"] => &mut self.credit_debit,
+            [.., "Ntry", "Sts"] | [.., "Ntry", "Sts", "Cd"] => &mut self.status,
+            [.., "Ntry", "BookgDt", "Dt" | "DtTm"] => &mut self.date,
+            [.., "InstrId"] | [.., "Ntry", "NtryRef"] => &mut self.references[0],
+            [.., "AcctSvcrRef"] => &mut self.references[1],
+            [.., "EndToEndId"] if value == "NOTPROVIDED" => return,
+            [.., "EndToEndId"] => {
+                self.references[2].get_or_insert_with(|| value.clone());
+                &mut self.end_to_end_id
+            }
+            [.., "RmtInf", "Ustrd"] => &mut self.memo,
+            [.., "Nm"] => {
+                // Name of the closest party, ultimate ones and agents aside
+                match path.iter().rev().find(|e| matches!(**e, "Dbtr" | "Cdtr")) {
+                    Some(&"Dbtr") => &mut self.debtor,
+                    Some(_) => &mut self.creditor,
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        // Batched entries repeat their details, the first ones count
+        field.get_or_insert(value);
+    }
+}
+
+impl Context {
+    fn set(&mut self, path: &[&str], value: String) {
+        let field = match path {
+            [.., "GrpHdr", "MsgId"] => &mut self.message_id,
+            [.., "GrpHdr", "CreDtTm"] => &mut self.created,
+            [.., "DbtrAcct" | "Acct", "Id", "IBAN"]
+            | [.., "DbtrAcct" | "Acct", "Id", "Othr", "Id"] => &mut self.account,
+            [.., "PmtInf", "ReqdExctnDt"] | [.., "PmtInf", "ReqdExctnDt", "Dt" | "DtTm"] => {
+                &mut self.execution_date
+            }
+            _ => return,
+        };
+        *field = Some(value);
+    }
+}
+
+/// Reads the transactions of a pain.001 or camt.053 message, any version, as records of
+/// [`COLUMNS`] in document order.
+///
+/// Every credit transfer of a payment initiation is a withdrawal from the debtor account
+/// of its payment information block, on the requested execution date. Every booked entry
+/// of a statement is a deposit (`CRDT`) or withdrawal (`DBIT`) of the statement's account
+/// on its booking date; pending and informational entries are left out. The client is the
+/// account's identification (`Othr/Id`, or its IBAN) and the transaction id the
+/// instruction or entry reference, the account servicer reference or the end to end id,
+/// whichever comes first; both have to be numbers to pass validation.
+pub fn read(mut input: impl Read) -> io::Result<(Message, Vec<Vec<String>>)> {
+    let mut xml = String::new();
+    input.read_to_string(&mut xml).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData => invalid("text isn't UTF-8"),
+        _ => e,
+    })?;
+    let mut reader = Reader::from_str(&xml);
+    reader.config_mut().expand_empty_elements = true;
+    let mut message = None;
+    let mut context = Context::default();
+    let mut payment: Option<Payment> = None;
+    let mut path = Vec::<String>::new();
+    // Text and CDATA of the innermost element read so far
+    let mut text = String::new();
+    let mut records = Vec::new();
+    loop {
+        match reader.read_event().map_err(invalid)? {
+            Event::Start(element) => {
+                text.clear();
+                path.push(local_name(element.local_name().as_ref())?.to_string());
+                let path = path.iter().map(String::as_str).collect::<Vec<_>>();
+                match (message, path.as_slice()) {
+                    (None, [.., "CstmrCdtTrfInitn"]) => message = Some(Message::PaymentInitiation),
+                    (None, [.., "BkToCstmrStmt"]) => message = Some(Message::Statement),
+                    (Some(_), [.., "CdtTrfTxInf"] | [.., "Stmt", "Ntry"]) => {
+                        payment = Some(Payment::default())
+                    }
+                    (Some(_), [.., "CdtTrfTxInf", "Amt", "InstdAmt"] | [.., "Ntry", "Amt"]) => {
+                        if let Some(payment) = &mut payment {
+                            payment.currency = attribute(&element, "Ccy", reader.decoder())?;
+                        }
+                    }
+                    (Some(_), [.., "PmtInf"]) => {
+                        context.account = None;
+                        context.execution_date = None;
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(_) => {
+                let value = std::mem::take(&mut text).trim().to_string();
+                if !value.is_empty() && message.is_some() {
+                    let path = path.iter().map(String::as_str).collect::<Vec<_>>();
+                    match &mut payment {
+                        Some(payment) => payment.set(&path, value),
+                        None => context.set(&path, value),
+                    }
+                }
+                let name = path.pop().unwrap_or_default();
+                if let (Some(message), "CdtTrfTxInf" | "Ntry") = (message, name.as_str()) {
+                    records.extend(payment.take().and_then(|p| p.record(message, &context)));
+                }
+            }
+            Event::Text(content) => text.push_str(&content.unescape().map_err(invalid)?),
+            Event::CData(content) => text.push_str(&content.decode().map_err(invalid)?),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    match message {
+        Some(message) => Ok((message, records)),
+        None => Err(invalid("not a pain.001 or camt.053 message")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, Message};
+    use crate::reader::{deserialize_iso20022, ReadOptions};
+    use crate::{Money, TransactionType};
+    use tokio::sync::mpsc;
+
+    const PAYMENT_INITIATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <GrpHdr><MsgId>MSG-1</MsgId><CreDtTm>2024-03-01T09:30:00</CreDtTm><NbOfTxs>2</NbOfTxs></GrpHdr>
+    <PmtInf>
+      <PmtInfId>P-1</PmtInfId>
+      <ReqdExctnDt><Dt>2024-03-04</Dt></ReqdExctnDt>
+      <Dbtr><Nm>Jane Doe</Nm></Dbtr>
+      <DbtrAcct><Id><Othr><Id>7</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><InstrId>101</InstrId><EndToEndId>E2E-101</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">250.75</InstdAmt></Amt>
+        <Cdtr><Nm>Acme &amp; Co</Nm></Cdtr>
+        <RmtInf><Ustrd>Invoice 42</Ustrd></RmtInf>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>102</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">10</InstdAmt></Amt>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+
+    const STATEMENT: &str = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>STMT-9</MsgId><CreDtTm>2024-03-02T06:00:00Z</CreDtTm></GrpHdr>
+    <Stmt>
+      <Id>S-1</Id>
+      <Acct><Id><IBAN>NL91ABNA0417164300</IBAN></Id></Acct>
+      <Bal><Amt Ccy="EUR">1000.00</Amt><CdtDbtInd>CRDT</CdtDbtInd></Bal>
+      <Ntry>
+        <NtryRef>501</NtryRef>
+        <Amt Ccy="EUR">99.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2024-03-01</Dt></BookgDt>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+          <AmtDtls><TxAmt><Amt Ccy="USD">108.00</Amt></TxAmt></AmtDtls>
+          <RltdPties><Dbtr><Nm>John Roe</Nm></Dbtr><Cdtr><Nm>Jane Doe</Nm></Cdtr></RltdPties>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">5.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+        <AcctSvcrRef>502</AcctSvcrRef>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn messages() {
+        let (message, records) = read(PAYMENT_INITIATION.as_bytes()).unwrap();
+        assert_eq!(message, Message::PaymentInitiation);
+        assert_eq!(
+            records,
+            [
+                [
+                    "withdrawal",
+                    "7",
+                    "101",
+                    "250.75",
+                    "EUR",
+                    "1709510400000",
+                    "MSG-1",
+                    "E2E-101",
+                    "Acme & Co",
+                    "Invoice 42"
+                ],
+                [
+                    "withdrawal",
+                    "7",
+                    "102",
+                    "10",
+                    "EUR",
+                    "1709510400000",
+                    "MSG-1",
+                    "102",
+                    "",
+                    ""
+                ]
+            ]
+        );
+
+        // The pending entry is left out, the amount of the transaction details isn't the
+        // entry's
+        let (message, records) = read(STATEMENT.as_bytes()).unwrap();
+        assert_eq!(message, Message::Statement);
+        assert_eq!(
+            records,
+            [[
+                "deposit",
+                "NL91ABNA0417164300",
+                "501",
+                "99.50",
+                "EUR",
+                "1709251200000",
+                "STMT-9",
+                "",
+                "John Roe",
+                ""
+            ]]
+        );
+
+        let error = read("<Document><Other/></Document>".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("not a pain.001 or camt.053"));
+    }
+
+    #[test]
+    fn markup() {
+        // CDATA is text, `>` may appear in attribute values
+        let xml = PAYMENT_INITIATION
+            .replace(
+                "<Ustrd>Invoice 42</Ustrd>",
+                "<Ustrd><![CDATA[Invoice <42>]]> &amp; more</Ustrd>",
+            )
+            .replace(r#"Ccy="EUR">250.75"#, r#"Ccy="EUR" Note="a>b">250.75"#);
+        let (_, records) = read(xml.as_bytes()).unwrap();
+        assert_eq!(records[0][4], "EUR");
+        assert_eq!(records[0][9], "Invoice <42> & more");
+
+        // Malformed attributes are errors, whatever characters they hold
+        let xml = PAYMENT_INITIATION.replace(r#"Ccy="EUR">250.75"#, "Ccy=é1>250.75");
+        let error = read(xml.as_bytes()).unwrap_err();
+        assert!(
+            error.to_string().contains("Invalid ISO 20022 input"),
+            "{}",
+            error
+        );
+
+        let error = read("<Document><CstmrCdtTrfInitn></Document>".as_bytes()).unwrap_err();
+        assert!(
+            error.to_string().contains("Invalid ISO 20022 input"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn transactions() {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let summary = deserialize_iso20022(
+            PAYMENT_INITIATION.as_bytes(),
+            ReadOptions::default(),
+            sender,
+        )
+        .unwrap();
+        assert_eq!((summary.rows, summary.skipped), (2, 0));
+        let transaction = receiver.try_recv().unwrap();
+        assert_eq!(transaction.transaction_type(), &TransactionType::Withdrawal);
+        assert_eq!((transaction.client(), transaction.tx()), (7, 101));
+        assert_eq!(transaction.amount(), Some(Money::new(25075, 2)));
+        assert_eq!(transaction.currency().unwrap().to_string(), "EUR");
+        assert_eq!(transaction.row(), Some(1));
+        assert_eq!(transaction.metadata()["end_to_end_id"], "E2E-101");
+        assert_eq!(transaction.metadata()["memo"], "Invoice 42");
+
+        // IBANs aren't client ids
+        let (sender, _receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            strict: true,
+            ..ReadOptions::default()
+        };
+        let error = deserialize_iso20022(STATEMENT.as_bytes(), options, sender).unwrap_err();
+        assert!(
+            error.to_string().contains("client NL91ABNA0417164300"),
+            "{}",
+            error
+        );
+    }
+}
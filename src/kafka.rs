@@ -0,0 +1,481 @@
+use crate::store::AccountUpdate;
+use rdkafka::client::ClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer as _};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::{Message, Offset, TopicPartitionList};
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Client id the cluster sees.
+const CLIENT_ID: &str = "transaction_system";
+
+/// Longest a poll waits for records before checking whether to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Longest the cluster is waited for when looking up the topic or committing.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Topic a run consumes with `--source kafka`, and the consumer group the offsets of its
+/// checkpoints are committed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaSource {
+    /// Broker the cluster is discovered from, e.g. `127.0.0.1:9092`
+    pub broker: String,
+    pub topic: String,
+    pub group: String,
+}
+
+/// How far a topic was consumed, shared between the reader and the checkpoints. The reader
+/// notes the offset of every record it reads, and once the transaction of a record is
+/// submitted, that record and every one read before it count as consumed.
+#[derive(Debug, Default)]
+pub struct Offsets(Mutex<Consumed>);
+
+#[derive(Debug, Default)]
+struct Consumed {
+    /// Next offset of every partition, past the records counted as consumed
+    next: BTreeMap<i32, i64>,
+    /// Row, partition and next offset of the records read since, oldest first
+    read: VecDeque<(u64, i32, i64)>,
+}
+
+impl Offsets {
+    /// Starts at the offsets a checkpoint got to.
+    pub fn new(next: BTreeMap<i32, i64>) -> Self {
+        Self(Mutex::new(Consumed {
+            next,
+            read: VecDeque::new(),
+        }))
+    }
+
+    /// Notes that the record numbered `row` was read from `partition`, the record after it
+    /// being at `next`.
+    pub fn read(&self, row: u64, partition: i32, next: i64) {
+        self.0
+            .lock()
+            .expect("Kafka offsets poisoned")
+            .read
+            .push_back((row, partition, next));
+    }
+
+    /// Counts the record numbered `row`, and every one read before it, as consumed.
+    pub fn submitted(&self, row: u64) {
+        let mut consumed = self.0.lock().expect("Kafka offsets poisoned");
+        while consumed
+            .read
+            .front()
+            .is_some_and(|(read, _, _)| *read <= row)
+        {
+            if let Some((_, partition, next)) = consumed.read.pop_front() {
+                consumed.next.insert(partition, next);
+            }
+        }
+    }
+
+    /// Offset of every partition consuming continues from.
+    pub fn next(&self) -> BTreeMap<i32, i64> {
+        self.0.lock().expect("Kafka offsets poisoned").next.clone()
+    }
+}
+
+/// A record of the topic.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    pub partition: i32,
+    pub offset: i64,
+    /// Empty for records without value
+    pub value: Vec<u8>,
+}
+
+/// Consumer of every partition of a topic, assigned rather than joining its consumer
+/// group: where to continue is up to the checkpoints rather than the group's committed
+/// offsets.
+pub struct Consumer {
+    consumer: BaseConsumer,
+}
+
+fn kafka_error(error: KafkaError) -> io::Error {
+    io::Error::other(format!("Kafka error: {}", error))
+}
+
+/// Client of the source's cluster, committing to its group only when asked.
+fn client(source: &KafkaSource) -> io::Result<BaseConsumer> {
+    ClientConfig::new()
+        .set("bootstrap.servers", &source.broker)
+        .set("group.id", &source.group)
+        .set("client.id", CLIENT_ID)
+        .set("enable.auto.commit", "false")
+        .set("enable.auto.offset.store", "false")
+        .create()
+        .map_err(kafka_error)
+}
+
+impl Consumer {
+    /// Connects to the cluster of the source's broker, continuing from `offsets`, or from
+    /// the earliest record of partitions without one.
+    pub fn connect(source: &KafkaSource, offsets: BTreeMap<i32, i64>) -> io::Result<Self> {
+        let consumer = client(source)?;
+        let metadata = consumer
+            .fetch_metadata(Some(&source.topic), TIMEOUT)
+            .map_err(kafka_error)?;
+        let topic = metadata
+            .topics()
+            .iter()
+            .find(|topic| topic.name() == source.topic)
+            .filter(|topic| topic.error().is_none() && !topic.partitions().is_empty())
+            .ok_or_else(|| io::Error::other(format!("No topic {}", source.topic)))?;
+        let mut assignment = TopicPartitionList::new();
+        for partition in topic.partitions() {
+            let offset = match offsets.get(&partition.id()) {
+                Some(&offset) => Offset::Offset(offset),
+                None => Offset::Beginning,
+            };
+            assignment
+                .add_partition_offset(&source.topic, partition.id(), offset)
+                .map_err(kafka_error)?;
+        }
+        consumer.assign(&assignment).map_err(kafka_error)?;
+        Ok(Self { consumer })
+    }
+
+    /// Next record of any partition, waiting for one to be produced until `stopped` says
+    /// so. Errors the client recovers from on its own, such as a broker going away, only
+    /// get a warning.
+    pub fn next_record(&mut self, stopped: impl Fn() -> bool) -> io::Result<Option<Record>> {
+        loop {
+            if stopped() {
+                return Ok(None);
+            }
+            match self.consumer.poll(POLL_INTERVAL) {
+                None => {}
+                Some(Ok(message)) => {
+                    return Ok(Some(Record {
+                        partition: message.partition(),
+                        offset: message.offset(),
+                        value: message.payload().unwrap_or_default().to_vec(),
+                    }))
+                }
+                Some(Err(KafkaError::MessageConsumption(code))) => {
+                    tracing::warn!(error = %code, "consuming kafka failed")
+                }
+                Some(Err(e)) => return Err(kafka_error(e)),
+            }
+        }
+    }
+}
+
+/// Commits the offsets a checkpoint continues from to the source's consumer group, so the
+/// cluster's tooling shows how far behind the topic the engine is.
+pub fn commit(source: &KafkaSource, offsets: &BTreeMap<i32, i64>) -> io::Result<()> {
+    let mut committed = TopicPartitionList::new();
+    for (&partition, &offset) in offsets {
+        committed
+            .add_partition_offset(&source.topic, partition, Offset::Offset(offset))
+            .map_err(kafka_error)?;
+    }
+    client(source)?
+        .commit(&committed, CommitMode::Sync)
+        .map_err(kafka_error)
+}
+
+/// Keeps the first record the cluster failed to take.
+#[derive(Default)]
+struct Deliveries {
+    failed: Mutex<Option<KafkaError>>,
+}
+
+impl ClientContext for Deliveries {}
+
+impl ProducerContext for Deliveries {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((error, _)) = result {
+            self.failed
+                .lock()
+                .expect("Kafka deliveries poisoned")
+                .get_or_insert_with(|| error.clone());
+        }
+    }
+}
+
+/// Producer publishing events as JSON records to one topic, keyed by client so the events
+/// of an account stay in order on one partition.
+pub struct KafkaPublisher {
+    producer: ThreadedProducer<Deliveries>,
+    topic: String,
+}
+
+impl KafkaPublisher {
+    /// Connects to the cluster of `broker`, e.g. `127.0.0.1:9092`, which has to have the
+    /// topic.
+    pub async fn connect(broker: &str, topic: &str) -> io::Result<Self> {
+        let (broker, topic) = (broker.to_string(), topic.to_string());
+        tokio::task::spawn_blocking(move || {
+            let producer: ThreadedProducer<Deliveries> = ClientConfig::new()
+                .set("bootstrap.servers", &broker)
+                .set("client.id", CLIENT_ID)
+                .create_with_context(Deliveries::default())
+                .map_err(kafka_error)?;
+            let metadata = producer
+                .client()
+                .fetch_metadata(Some(&topic), TIMEOUT)
+                .map_err(kafka_error)?;
+            if !metadata.topics().iter().any(|found| {
+                found.name() == topic && found.error().is_none() && !found.partitions().is_empty()
+            }) {
+                return Err(io::Error::other(format!("No topic {}", topic)));
+            }
+            Ok(Self { producer, topic })
+        })
+        .await?
+    }
+
+    /// Publishes events until the [`EventStore`](crate::store::EventStore) sending them is
+    /// dropped, then waits until the cluster has taken all of them.
+    pub async fn run(self, mut events: mpsc::UnboundedReceiver<AccountUpdate>) -> io::Result<()> {
+        while let Some(event) = events.recv().await {
+            let payload = serde_json::to_vec(&event)?;
+            let key = event.client.to_string();
+            let mut record = BaseRecord::to(&self.topic).key(&key).payload(&payload);
+            loop {
+                match self.producer.send(record) {
+                    Ok(()) => break,
+                    // The queue drains as the producer's thread delivers records
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), back)) => {
+                        record = back;
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                    }
+                    Err((e, _)) => return Err(kafka_error(e)),
+                }
+            }
+            self.delivered()?;
+        }
+        let (publisher, flushed) = tokio::task::spawn_blocking(move || {
+            let flushed = self.producer.flush(TIMEOUT);
+            (self, flushed)
+        })
+        .await?;
+        flushed.map_err(kafka_error)?;
+        publisher.delivered()
+    }
+
+    /// Fails once a record couldn't be delivered.
+    fn delivered(&self) -> io::Result<()> {
+        match self
+            .producer
+            .context()
+            .failed
+            .lock()
+            .expect("Kafka deliveries poisoned")
+            .clone()
+        {
+            Some(error) => Err(kafka_error(error)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client, commit, Consumer, KafkaPublisher, KafkaSource, Offsets, Record, TIMEOUT};
+    use crate::reader::{deserialize_kafka, ReadOptions};
+    use crate::store::{EventStore, MemoryStore};
+    use crate::{Engine, EngineConfig, Money, Transaction, TransactionType};
+    use rdkafka::config::ClientConfig;
+    use rdkafka::consumer::Consumer as _;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::producer::{BaseProducer, BaseRecord, DefaultProducerContext, Producer};
+    use rdkafka::{Message, Offset, TopicPartitionList};
+    use serde_json::Value;
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    const TOPIC: &str = "transactions";
+
+    /// Cluster with a two-partition topic holding the records, `None` standing for a null
+    /// value.
+    fn cluster(records: &[(i32, Option<&str>)]) -> MockCluster<'static, DefaultProducerContext> {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic(TOPIC, 2, 1).unwrap();
+        let producer: BaseProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        for (partition, value) in records {
+            let record = BaseRecord::<(), str>::to(TOPIC).partition(*partition);
+            let record = match value {
+                Some(value) => record.payload(*value),
+                None => record,
+            };
+            producer.send(record).map_err(|(e, _)| e).unwrap();
+        }
+        producer.flush(TIMEOUT).unwrap();
+        cluster
+    }
+
+    fn source(broker: String) -> KafkaSource {
+        KafkaSource {
+            broker,
+            topic: TOPIC.to_string(),
+            group: "engine".to_string(),
+        }
+    }
+
+    #[test]
+    fn consume_and_commit() {
+        let cluster = cluster(&[
+            (0, Some("deposit,1,1,5")),
+            (0, None),
+            (1, Some("bogus")),
+            (0, Some("deposit,2,2,3")),
+            (1, Some("withdrawal,1,3,1")),
+        ]);
+        let source = source(cluster.bootstrap_servers());
+        let offsets = Arc::new(Offsets::default());
+        let (tx, mut rx) = mpsc::channel(16);
+        let reader = {
+            let (source, offsets) = (source.clone(), offsets.clone());
+            std::thread::spawn(move || {
+                deserialize_kafka(source, offsets, ReadOptions::default(), tx)
+            })
+        };
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let transaction = rx.blocking_recv().unwrap();
+            offsets.submitted(transaction.row().unwrap());
+            received.push((transaction.transaction_type, transaction.tx));
+        }
+        // Partitions are consumed side by side, each in order
+        received.sort_by_key(|(_, tx)| *tx);
+        assert_eq!(
+            received,
+            [
+                (TransactionType::Deposit, 1),
+                (TransactionType::Deposit, 2),
+                (TransactionType::Withdrawal, 3)
+            ]
+        );
+        // The null value and the bogus row are passed over, but consumed
+        assert_eq!(offsets.next(), BTreeMap::from([(0, 3), (1, 2)]));
+        drop(rx);
+        let summary = reader.join().unwrap().unwrap();
+        assert_eq!((summary.rows, summary.skipped), (3, 1));
+
+        commit(&source, &offsets.next()).unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition(TOPIC, 0);
+        partitions.add_partition(TOPIC, 1);
+        let committed = client(&source)
+            .unwrap()
+            .committed_offsets(partitions, TIMEOUT)
+            .unwrap();
+        assert_eq!(
+            committed
+                .elements()
+                .iter()
+                .map(|e| (e.partition(), e.offset()))
+                .collect::<Vec<_>>(),
+            [(0, Offset::Offset(3)), (1, Offset::Offset(2))]
+        );
+    }
+
+    #[test]
+    fn resume() {
+        let cluster = cluster(&[
+            (0, Some("deposit,1,1,5")),
+            (0, Some("deposit,1,2,3")),
+            (0, None),
+            (1, Some("deposit,2,3,1")),
+        ]);
+        let offsets = BTreeMap::from([(0, 1), (1, 1)]);
+        let mut consumer =
+            Consumer::connect(&source(cluster.bootstrap_servers()), offsets).unwrap();
+        // Polls until nothing came for a while
+        let idle = Cell::new(0);
+        let mut records = Vec::new();
+        while let Some(record) = consumer
+            .next_record(|| {
+                idle.set(idle.get() + 1);
+                idle.get() > 8
+            })
+            .unwrap()
+        {
+            idle.set(0);
+            records.push(record);
+        }
+        // Records before the offsets are passed over
+        assert_eq!(
+            records,
+            [
+                Record {
+                    partition: 0,
+                    offset: 1,
+                    value: b"deposit,1,2,3".to_vec()
+                },
+                Record {
+                    partition: 0,
+                    offset: 2,
+                    value: Vec::new()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publish_events() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("accounts", 1, 1).unwrap();
+        let broker = cluster.bootstrap_servers();
+        let publisher = KafkaPublisher::connect(&broker, "accounts").await.unwrap();
+        let mut store = EventStore::new(MemoryStore::new(), Default::default());
+        let published = tokio::spawn(publisher.run(store.subscribe()));
+        let mut engine = Engine::with_store(EngineConfig::default(), Arc::new(store));
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(9))),
+            Transaction::new(TransactionType::Deposit, 2, 1, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            let _ = engine.process(transaction).await;
+        }
+        drop(engine);
+        published.await.unwrap().unwrap();
+
+        let consumer = Consumer::connect(
+            &KafkaSource {
+                broker,
+                topic: "accounts".to_string(),
+                group: "engine".to_string(),
+            },
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let records = tokio::task::spawn_blocking(move || {
+            (0..3)
+                .map(|_| {
+                    let message = consumer.consumer.poll(TIMEOUT).unwrap().unwrap();
+                    let key = String::from_utf8(message.key().unwrap().to_vec()).unwrap();
+                    let event: Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+                    (key, event)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap();
+        assert_eq!(records[0].0, "1");
+        assert_eq!(records[0].1["dispute_state"], "none");
+        assert_eq!(records[1].1["error"]["code"], 304);
+        assert_eq!(records[1].1["error"]["reason"], "insufficient_amount");
+        assert_eq!(records[2].0, "2");
+        assert_eq!(records[2].1["error"]["reason"], "duplicate_transaction_id");
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+/// Sub-buckets every power of two is split into, percentiles are within an eighth of the
+/// actual latency.
+const SUB_BUCKETS: u32 = 8;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+/// Histogram of how long transactions took from being handed to the engine until they
+/// were decided, see [`crate::Engine::latencies`].
+///
+/// Latencies are kept in buckets rather than one by one, so it takes the same little
+/// memory however many transactions it saw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latencies {
+    /// Transactions per bucket, indexed by [`bucket`]
+    counts: Vec<u64>,
+    count: u64,
+    max: Duration,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; bucket(u64::MAX) + 1],
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+/// Bucket of a latency of `nanos` nanoseconds.
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exponent - SUB_BITS)) & (SUB_BUCKETS as u64 - 1);
+    ((exponent - SUB_BITS + 1) * SUB_BUCKETS) as usize + sub as usize
+}
+
+/// Highest latency in nanoseconds that falls into `bucket`.
+fn upper_bound(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    let sub_buckets = SUB_BUCKETS as u64;
+    if bucket < sub_buckets {
+        return bucket;
+    }
+    let exponent = bucket / sub_buckets + SUB_BITS as u64 - 1;
+    let sub = bucket % sub_buckets;
+    let lowest = (sub_buckets + sub) << (exponent - SUB_BITS as u64);
+    lowest.saturating_add((1 << (exponent - SUB_BITS as u64)) - 1)
+}
+
+impl Latencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    pub(crate) fn merge(&mut self, other: &Latencies) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    /// Number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Latency `percentile` percent of the transactions took at most, zero before any
+    /// was recorded.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(upper_bound(bucket)).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket, upper_bound, Latencies};
+    use std::time::Duration;
+
+    #[test]
+    fn buckets() {
+        for nanos in [0, 1, 7, 8, 9, 15, 16, 100, 1_000, 123_456_789, u64::MAX] {
+            let bucket = bucket(nanos);
+            assert!(upper_bound(bucket) >= nanos);
+            assert!(bucket == 0 || upper_bound(bucket - 1) < nanos);
+        }
+        assert_eq!(upper_bound(super::bucket(u64::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut latencies = Latencies::new();
+        assert_eq!(latencies.percentile(50.0), Duration::ZERO);
+        for micros in 1..=100 {
+            latencies.record(Duration::from_micros(micros));
+        }
+        let mut slow = Latencies::new();
+        slow.record(Duration::from_millis(10));
+        latencies.merge(&slow);
+
+        assert_eq!(latencies.count(), 101);
+        assert_eq!(latencies.max(), Duration::from_millis(10));
+        for (percentile, expected) in [(50.0, 51), (90.0, 91), (99.0, 100)] {
+            let latency = latencies.percentile(percentile);
+            assert!(latency >= Duration::from_micros(expected));
+            assert!(latency <= Duration::from_micros(expected) * 9 / 8);
+        }
+        assert_eq!(latencies.percentile(100.0), Duration::from_millis(10));
+    }
+}
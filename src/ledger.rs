@@ -0,0 +1,276 @@
+use crate::account::DisputeState;
+use crate::currency::Currency;
+use crate::engine::{OutcomeStatus, Rejection};
+use crate::money::{Money, MoneyFormat};
+use crate::output::ReportFormat;
+use crate::parquet::{self, Column, Values};
+use crate::transaction::{self, TransactionType};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+
+/// Transaction of the processed ledger, see [`crate::Engine::ledger`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub client: u16,
+    pub tx: u32,
+    pub transaction_type: TransactionType,
+    pub amount: Option<Money>,
+    /// `None` for the account's default currency
+    pub currency: Option<Currency>,
+    /// Where disputes of the transaction left it, `None` for rejected transactions
+    pub dispute_state: Option<DisputeState>,
+    pub outcome: OutcomeStatus,
+    /// Why the transaction was rejected
+    pub error: Option<(u16, String)>,
+    /// Input columns of accepted transactions the engine doesn't know, by name
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl LedgerEntry {
+    pub(crate) fn rejected(rejection: &Rejection) -> Self {
+        Self {
+            client: rejection.error.client,
+            tx: rejection.error.tx,
+            transaction_type: rejection.transaction_type.clone(),
+            amount: rejection.error.amount,
+            currency: rejection.currency.clone(),
+            dispute_state: None,
+            outcome: OutcomeStatus::Rejected,
+            error: Some((rejection.error.code(), rejection.error.kind.to_string())),
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LedgerRecord {
+    client: u16,
+    tx: u32,
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    amount: Option<String>,
+    currency: Option<String>,
+    dispute_state: Option<&'static str>,
+    outcome: OutcomeStatus,
+    code: Option<u16>,
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+}
+
+impl LedgerRecord {
+    fn new(entry: &LedgerEntry, format: &MoneyFormat) -> Self {
+        Self {
+            client: entry.client,
+            tx: entry.tx,
+            transaction_type: entry.transaction_type.clone(),
+            amount: entry.amount.map(|amount| amount.format(format)),
+            currency: entry.currency.as_ref().map(ToString::to_string),
+            dispute_state: entry.dispute_state.map(DisputeState::name),
+            outcome: entry.outcome,
+            code: entry.error.as_ref().map(|(code, _)| *code),
+            reason: entry.error.as_ref().map(|(_, reason)| reason.clone()),
+            metadata: entry.metadata.clone(),
+        }
+    }
+}
+
+/// Name a value is serialized under, e.g. a transaction type's.
+fn serialized_name(value: &impl Serialize) -> Option<String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn write_parquet_ledger(
+    writer: impl io::Write,
+    records: &[LedgerRecord],
+    metadata: &[&str],
+    format: &MoneyFormat,
+) -> io::Result<()> {
+    let rows = || records.iter();
+    // Amounts were just formatted, so they parse back exactly
+    let amounts = rows().map(|r| {
+        r.amount
+            .as_ref()
+            .map(|a| a.parse::<Decimal>().expect("Formatted amount"))
+    });
+    let mut columns = vec![
+        Column::required(
+            "client",
+            Values::UInt16(rows().map(|r| Some(r.client)).collect()),
+        ),
+        Column::required("tx", Values::UInt32(rows().map(|r| Some(r.tx)).collect())),
+        Column::required(
+            "type",
+            Values::Text(
+                rows()
+                    .map(|r| serialized_name(&r.transaction_type))
+                    .collect(),
+            ),
+        ),
+        Column::optional(
+            "amount",
+            Values::Decimal(amounts.collect(), format.decimal_places),
+        ),
+        Column::optional(
+            "currency",
+            Values::Text(rows().map(|r| r.currency.clone()).collect()),
+        ),
+        Column::optional(
+            "dispute_state",
+            Values::Text(rows().map(|r| r.dispute_state.map(String::from)).collect()),
+        ),
+        Column::required(
+            "outcome",
+            Values::Text(rows().map(|r| serialized_name(&r.outcome)).collect()),
+        ),
+        Column::optional("code", Values::UInt16(rows().map(|r| r.code).collect())),
+        Column::optional(
+            "reason",
+            Values::Text(rows().map(|r| r.reason.clone()).collect()),
+        ),
+    ];
+    columns.extend(metadata.iter().map(|name| {
+        Column::optional(
+            *name,
+            Values::Text(rows().map(|r| r.metadata.get(*name).cloned()).collect()),
+        )
+    }));
+    parquet::write(writer, &columns)
+}
+
+/// Writes ledger entries with amounts in `format`, as csv, a JSON array, one JSON object
+/// per line or Parquet. JSON objects have the same fields as the csv columns, amounts being
+/// strings; Parquet has the same columns with decimal amounts. Metadata gets a column per
+/// name after the others, in JSON it is a `metadata` object of entries having any.
+pub fn write_ledger<'a, W: io::Write>(
+    mut writer: W,
+    entries: impl IntoIterator<Item = &'a LedgerEntry>,
+    format: &MoneyFormat,
+    report_format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    let entries = entries.into_iter().collect::<Vec<_>>();
+    let metadata = transaction::metadata_columns(entries.iter().map(|entry| &entry.metadata));
+    let records = entries.iter().map(|entry| LedgerRecord::new(entry, format));
+    match report_format {
+        ReportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(writer);
+            let header = [
+                "client",
+                "tx",
+                "type",
+                "amount",
+                "currency",
+                "dispute_state",
+                "outcome",
+                "code",
+                "reason",
+            ];
+            writer.write_record(header.iter().chain(&metadata))?;
+            for mut record in records {
+                let values = std::mem::take(&mut record.metadata);
+                let values = metadata.iter().map(|name| values.get(*name));
+                writer.serialize((record, values.collect::<Vec<_>>()))?;
+            }
+            writer.flush()?;
+        }
+        ReportFormat::Json => {
+            serde_json::to_writer(&mut writer, &records.collect::<Vec<_>>())?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+        ReportFormat::Jsonl => {
+            for record in records {
+                serde_json::to_writer(&mut writer, &record)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        ReportFormat::Parquet => {
+            write_parquet_ledger(writer, &records.collect::<Vec<_>>(), &metadata, format)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_ledger;
+    use crate::{Engine, Money, ReportFormat, Transaction, TransactionType};
+    use bytes::Bytes;
+    use parquet::file::reader::FileReader;
+    use parquet::file::serialized_reader::SerializedFileReader;
+
+    #[tokio::test]
+    async fn ledger() {
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 2, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from(5)))
+                .with_metadata("external_ref", "EXT-2"),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(9))),
+            Transaction::new(TransactionType::Withdrawal, 2, 4, Some(Money::from(3))),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let ledger = engine.ledger().await.unwrap();
+
+        let mut csv = Vec::new();
+        write_ledger(&mut csv, &ledger, &Default::default(), ReportFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,tx,type,amount,currency,dispute_state,outcome,code,reason,external_ref\n\
+             1,2,deposit,5.0000,,disputed,accepted,,,EXT-2\n\
+             1,3,withdrawal,9.0000,,,rejected,304,Not enough available funds,\n\
+             2,1,deposit,10.0000,,none,accepted,,,\n\
+             2,4,withdrawal,3.0000,,none,accepted,,,\n"
+        );
+
+        let mut jsonl = Vec::new();
+        write_ledger(
+            &mut jsonl,
+            &ledger,
+            &Default::default(),
+            ReportFormat::Jsonl,
+        )
+        .unwrap();
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        assert_eq!(jsonl.lines().count(), 4);
+        assert_eq!(
+            jsonl.lines().next().unwrap(),
+            r#"{"client":1,"tx":2,"type":"deposit","amount":"5.0000","currency":null,"dispute_state":"disputed","outcome":"accepted","code":null,"reason":null,"metadata":{"external_ref":"EXT-2"}}"#
+        );
+
+        // Parquet readers take the file as written
+        let mut file = Vec::new();
+        write_ledger(
+            &mut file,
+            &ledger,
+            &Default::default(),
+            ReportFormat::Parquet,
+        )
+        .unwrap();
+        let reader = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[1],
+            "{client: 1, tx: 3, type: \"withdrawal\", amount: 9.0000, currency: null, \
+             dispute_state: null, outcome: \"rejected\", code: 304, \
+             reason: \"Not enough available funds\", external_ref: null}"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+pub mod account;
+pub mod as_of;
+pub mod avro;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+pub mod currency;
+pub mod dashboard;
+pub mod decompress;
+pub mod dedup;
+pub mod determinism;
+pub mod diff;
+pub mod dlq;
+pub mod double_entry;
+pub mod drop_folder;
+pub mod encryption;
+pub mod engine;
+pub mod events;
+pub mod fees;
+pub mod fraud;
+pub mod fuzz;
+pub mod grpc;
+pub mod history;
+pub mod http;
+pub mod interest;
+pub mod invariants;
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod latency;
+pub mod ledger;
+pub mod limits;
+pub mod logging;
+pub mod money;
+pub mod nats;
+pub mod output;
+pub mod parquet;
+pub mod partition;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod rates;
+pub mod reader;
+pub mod reconcile;
+pub mod redis;
+pub mod retry;
+pub mod server;
+pub mod signature;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "persistence")]
+pub mod state;
+pub mod statement;
+pub mod store;
+pub mod summary;
+pub mod telemetry;
+pub mod timestamp;
+pub mod transaction;
+pub mod wal;
+pub mod webhook;
+pub mod workload;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+pub use account::{
+    Account, AccountState, AuthorizationState, Balance, ChargebackPolicy, DisputeState, ErrorKind,
+    HistoryEntry, TransactionProcessingError,
+};
+pub use currency::Currency;
+pub use engine::{
+    DuplicatePolicy, Engine, EngineConfig, EngineStats, OutcomeStatus, TransactionOutcome,
+};
+pub use fees::FeeSchedule;
+pub use fraud::{FraudRule, FraudRules, Verdict};
+pub use limits::{LimitRules, Limits};
+pub use money::Money;
+pub use output::{write_accounts, ReportFormat, ReportOrder};
+pub use rates::ExchangeRates;
+pub use timestamp::Timestamp;
+pub use transaction::{Transaction, TransactionType};
@@ -0,0 +1,124 @@
+use crate::money::Money;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+pub(crate) const MILLIS_PER_MINUTE: i64 = 60_000;
+
+/// Limits a client's transactions have to stay within. Rules about time only apply to
+/// transactions with a timestamp.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest amount of a single transaction
+    pub max_amount: Option<Money>,
+    /// Largest total withdrawn in a calendar day (UTC)
+    pub max_daily_withdrawal: Option<Money>,
+    /// Most transactions accepted within any minute
+    pub max_per_minute: Option<u32>,
+}
+
+/// Limits applying to every client, with overrides for single clients.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LimitRules {
+    pub default: Limits,
+    pub clients: HashMap<u16, Limits>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct LimitsRow {
+    max_amount: Option<Money>,
+    max_daily_withdrawal: Option<Money>,
+    max_per_minute: Option<u32>,
+}
+
+impl LimitsRow {
+    /// Limits set in this row, the rest taken from `base`.
+    fn over(&self, base: Limits) -> Result<Limits, String> {
+        let limits = Limits {
+            max_amount: self.max_amount.or(base.max_amount),
+            max_daily_withdrawal: self.max_daily_withdrawal.or(base.max_daily_withdrawal),
+            max_per_minute: self.max_per_minute.or(base.max_per_minute),
+        };
+        if [limits.max_amount, limits.max_daily_withdrawal]
+            .into_iter()
+            .flatten()
+            .any(|limit| limit.is_negative())
+        {
+            return Err("Limits can't be negative".to_string());
+        }
+        Ok(limits)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct LimitsFile {
+    max_amount: Option<Money>,
+    max_daily_withdrawal: Option<Money>,
+    max_per_minute: Option<u32>,
+    #[serde(default)]
+    clients: HashMap<String, LimitsRow>,
+}
+
+impl LimitRules {
+    /// Loads a TOML file with default limits at the top level and overrides of single
+    /// clients in `[clients.<id>]` tables. Overrides only replace the limits they set.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = toml::from_str::<LimitsFile>(&std::fs::read_to_string(path)?)?;
+        let default = LimitsRow {
+            max_amount: file.max_amount,
+            max_daily_withdrawal: file.max_daily_withdrawal,
+            max_per_minute: file.max_per_minute,
+        }
+        .over(Limits::default())?;
+        let mut clients = HashMap::new();
+        for (client, row) in file.clients {
+            let client = client
+                .parse::<u16>()
+                .map_err(|_| format!("Invalid client {}", client))?;
+            clients.insert(client, row.over(default)?);
+        }
+        Ok(Self { default, clients })
+    }
+
+    pub fn for_client(&self, client: u16) -> Limits {
+        self.clients.get(&client).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LimitRules, Limits};
+    use crate::money::Money;
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join(format!("limits_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "max-amount = \"100\"\n\
+             max-per-minute = 5\n\
+             [clients.7]\n\
+             max-amount = \"1000\"\n",
+        )
+        .unwrap();
+        let rules = LimitRules::load(&path).unwrap();
+        assert_eq!(
+            rules.for_client(1),
+            Limits {
+                max_amount: Some(Money::from(100)),
+                max_daily_withdrawal: None,
+                max_per_minute: Some(5),
+            }
+        );
+        assert_eq!(rules.for_client(7).max_amount, Some(Money::from(1000)));
+        assert_eq!(rules.for_client(7).max_per_minute, Some(5));
+
+        std::fs::write(&path, "max-amount = \"-1\"\n").unwrap();
+        assert!(LimitRules::load(&path).is_err());
+        std::fs::write(&path, "max-withdrawals = 1\n").unwrap();
+        assert!(LimitRules::load(&path).is_err());
+    }
+}
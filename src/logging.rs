@@ -0,0 +1,203 @@
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tracing::Subscriber;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, reload, Layer, Registry};
+
+/// Layer spans are handed to, e.g. one exporting them, see [`export_spans`].
+pub type SpanLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Where [`export_spans`] puts its layer into the subscriber set up by [`init`].
+static SPANS: OnceLock<reload::Handle<Option<SpanLayer>, Registry>> = OnceLock::new();
+
+/// Severity of a log event, from the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!(
+                "{} is not a log level, use error, warn, info, debug or trace",
+                s
+            )),
+        }
+    }
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => LevelFilter::ERROR,
+            Level::Warn => LevelFilter::WARN,
+            Level::Info => LevelFilter::INFO,
+            Level::Debug => LevelFilter::DEBUG,
+            Level::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line
+    Json,
+    /// Human readable events, their fields on lines of their own
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err(format!("{} is not a log format, use json or pretty", s)),
+        }
+    }
+}
+
+/// Writes `tracing` events of `level` and more severe ones to stderr for the rest of the
+/// process. Without it nothing is logged. Fails when logging was set up already.
+pub fn init(level: Level, format: LogFormat) -> Result<(), String> {
+    let (spans, handle) = reload::Layer::new(None);
+    let colored = std::io::stderr().is_terminal();
+    let subscriber = subscriber(spans, level, format, colored, std::io::stderr);
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|_| "Logging is set up already".to_string())?;
+    let _ = SPANS.set(handle);
+    Ok(())
+}
+
+/// Hands every span to `layer` from now on, whatever the log level, e.g. a
+/// [`telemetry::layer`](crate::telemetry::layer). Fails unless logging was set up by [`init`].
+pub fn export_spans(layer: SpanLayer) -> Result<(), String> {
+    let spans = SPANS.get().ok_or("Logging isn't set up")?;
+    spans.reload(Some(layer)).map_err(|e| e.to_string())
+}
+
+/// Subscriber handing spans to `spans` and writing events in `format` to `writer`, pretty
+/// ones in color if `colored`.
+fn subscriber<W>(
+    spans: reload::Layer<Option<SpanLayer>, Registry>,
+    level: Level,
+    format: LogFormat,
+    colored: bool,
+    writer: W,
+) -> impl Subscriber + Send + Sync
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let events = match format {
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_ansi(colored)
+            .with_writer(writer)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(spans)
+        .with(events.with_filter(LevelFilter::from(level)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{subscriber, Level, LogFormat};
+    use serde_json::{json, Value};
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::reload;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logged(format: LogFormat) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        tracing::subscriber::with_default(
+            subscriber(
+                reload::Layer::new(None).0,
+                Level::Info,
+                format,
+                false,
+                move || writer.clone(),
+            ),
+            || {
+                tracing::info!(
+                    tx = 2,
+                    error = "insufficient_amount",
+                    "transaction rejected"
+                );
+                tracing::debug!(rows = 100000, "reading");
+            },
+        );
+        let logged = buffer.0.lock().unwrap().clone();
+        String::from_utf8(logged).unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        let json = logged(LogFormat::Json);
+        let lines: Vec<_> = json.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let mut event: Value = serde_json::from_str(lines[0]).unwrap();
+        assert!(event["timestamp"].is_string());
+        event.as_object_mut().unwrap().remove("timestamp");
+        assert_eq!(
+            event,
+            json!({
+                "level": "INFO",
+                "message": "transaction rejected",
+                "tx": 2,
+                "error": "insufficient_amount",
+                "target": "transaction_system::logging::tests"
+            })
+        );
+
+        let pretty = logged(LogFormat::Pretty);
+        assert!(pretty.contains("transaction rejected"));
+        assert!(pretty.contains("error: \"insufficient_amount\""));
+        assert!(!pretty.contains("reading"));
+    }
+
+    #[test]
+    fn levels() {
+        assert!(Level::Error < Level::Warn);
+        assert_eq!("debug".parse::<Level>(), Ok(Level::Debug));
+        assert!("verbose".parse::<Level>().is_err());
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
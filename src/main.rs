@@ -1,105 +1,1394 @@
-use account::Account;
-use csv;
-use serde::Deserialize;
-use std::collections::HashMap;
+use clap::Parser;
+use cli::{Cli, Command, Settings};
+use ratatui::backend::CrosstermBackend;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashSet};
 use std::error::Error;
+use std::io::Stderr;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
-
-mod account;
-
-#[allow(dead_code)]
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub enum TransactionType {
-    #[serde(rename = "deposit")]
-    Deposit,
-    #[serde(rename = "withdrawal")]
-    Withdrawal,
-    #[serde(rename = "dispute")]
-    Dispute,
-    #[serde(rename = "resolve")]
-    Resolve,
-    #[serde(rename = "chargeback")]
-    Chargeback,
-}
-
-#[allow(dead_code)]
-#[derive(Deserialize, Debug)]
-pub struct Transaction {
-    #[serde(rename = "type")]
-    transaction_type: TransactionType,
-    client: u16,
-    tx: u32,
-    amount: Option<f32>,
-}
-
-impl Transaction {
-    pub fn new(
-        transaction_type: TransactionType,
-        client: u16,
-        tx: u32,
-        amount: Option<f32>,
-    ) -> Self {
-        Self {
-            transaction_type,
-            client,
-            tx,
-            amount,
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use transaction_system::as_of::AsOf;
+use transaction_system::dashboard::Dashboard;
+use transaction_system::determinism::check_determinism;
+use transaction_system::diff::diff_reports;
+use transaction_system::dlq::DeadLetterFile;
+use transaction_system::drop_folder::DropFolder;
+use transaction_system::encryption::{self, AuditFile, Cipher};
+#[cfg(feature = "kafka")]
+use transaction_system::kafka::{self, KafkaPublisher, KafkaSource, Offsets};
+use transaction_system::latency::Latencies;
+use transaction_system::nats::NatsPublisher;
+use transaction_system::partition::{self, read_transfer_messages, write_transfer_messages};
+#[cfg(feature = "postgres")]
+use transaction_system::postgres::PostgresDatabase;
+#[cfg(feature = "kafka")]
+use transaction_system::reader::deserialize_kafka;
+use transaction_system::reader::{
+    deserialize_file, deserialize_files, merge_files, ReadOptions, ReadSummary, STDIN,
+};
+use transaction_system::reconcile::{self, Divergence};
+use transaction_system::redis::RedisMirror;
+use transaction_system::server::{self, Server};
+use transaction_system::signature::RowVerifier;
+use transaction_system::snapshot::Snapshot;
+#[cfg(feature = "kafka")]
+use transaction_system::snapshot::TopicOffsets;
+#[cfg(feature = "sqlite")]
+use transaction_system::sqlite::SqliteStore;
+#[cfg(feature = "persistence")]
+use transaction_system::state::{KeyValueStore, SledStore};
+use transaction_system::statement::write_statement;
+use transaction_system::store::{
+    AccountUpdate, BroadcastStore, EventStore, MemoryStore, StateStore,
+};
+use transaction_system::wal::Wal;
+use transaction_system::webhook::WebhookDispatcher;
+use transaction_system::workload::Workload;
+use transaction_system::{logging, telemetry};
+use transaction_system::{Account, Engine, EngineConfig, EngineStats, ReportFormat, Transaction};
+
+mod cli;
+mod repl;
+
+/// Exit code of runs interrupted by a signal after writing their checkpoint.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Time between looks for new files in a watched folder.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time between frames of `--dashboard`.
+const DASHBOARD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Task sending account events on to NATS, Kafka, Redis or a webhook, or dead letters to
+/// their file.
+type Publishing = tokio::task::JoinHandle<std::io::Result<()>>;
+
+/// Webhook dispatcher with the events it notifies about, started once the engine's state is
+/// restored, see [`notify_webhook`].
+type Notifying = (WebhookDispatcher, mpsc::UnboundedReceiver<AccountUpdate>);
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Reloads the rules of the server whenever the process gets SIGHUP.
+async fn reload_on_hangup(server: Arc<Server>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+            return;
+        };
+        while hangups.recv().await.is_some() {
+            match server.reload().await {
+                Ok(()) => tracing::info!("rules reloaded"),
+                Err(e) => tracing::error!(error = e, "rules not reloaded"),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = server;
+}
+
+/// Puts `store` behind an [`EventStore`] when account events are published to NATS or
+/// Kafka, balances mirrored to Redis or webhooks notified, with the tasks sending the events
+/// on and the webhook dispatcher waiting to be started.
+async fn publish_events(
+    store: impl StateStore + 'static,
+    settings: &Settings,
+) -> Result<(Arc<dyn StateStore>, Vec<Publishing>, Option<Notifying>), Box<dyn Error>> {
+    #[cfg(feature = "kafka")]
+    let kafka = settings.kafka_events.is_some();
+    #[cfg(not(feature = "kafka"))]
+    let kafka = false;
+    if settings.nats.is_none() && !kafka && settings.redis.is_none() && settings.webhook.is_none() {
+        return Ok((Arc::new(store), Vec::new(), None));
+    }
+    let mut store = EventStore::new(store, settings.engine.output_format);
+    let mut publishing = Vec::new();
+    let retry = settings.engine.retry;
+    if let Some((address, subject)) = &settings.nats {
+        let publisher = retry
+            .run(|| NatsPublisher::connect(address, subject))
+            .await
+            .map_err(|e| format!("Can't publish to NATS server {}: {}", address, e))?;
+        publishing.push(tokio::spawn(publisher.run(store.subscribe())));
+    }
+    #[cfg(feature = "kafka")]
+    if let Some((broker, topic)) = &settings.kafka_events {
+        let publisher = KafkaPublisher::connect(broker, topic)
+            .await
+            .map_err(|e| format!("Can't publish to Kafka topic {}: {}", topic, e))?;
+        publishing.push(tokio::spawn(publisher.run(store.subscribe())));
+    }
+    if let Some((address, prefix)) = &settings.redis {
+        let mirror = retry
+            .run(|| RedisMirror::connect(address, prefix))
+            .await
+            .map_err(|e| format!("Can't mirror to Redis server {}: {}", address, e))?;
+        publishing.push(tokio::spawn(mirror.run(store.subscribe())));
+    }
+    let notifying = settings
+        .webhook
+        .as_ref()
+        .map(|webhook| (WebhookDispatcher::new(webhook.clone()), store.subscribe()));
+    Ok((Arc::new(store), publishing, notifying))
+}
+
+/// Starts notifying the webhook, if there is one, once the engine's state is restored, so
+/// accounts restored locked aren't notified as locked again.
+async fn notify_webhook(
+    engine: &mut Engine,
+    notifying: Option<Notifying>,
+    publishing: &mut Vec<Publishing>,
+) {
+    if let Some((dispatcher, events)) = notifying {
+        let locked = engine.locked_accounts().await;
+        let dispatcher = dispatcher.with_locked(locked.iter().map(Account::client));
+        publishing.push(tokio::spawn(dispatcher.run(events)));
+    }
+}
+
+/// Writes rejected transactions to the dead letter file, if there is one.
+async fn write_dead_letters(
+    engine: &mut Engine,
+    path: Option<&Path>,
+    cipher: Option<&Cipher>,
+    publishing: &mut Vec<Publishing>,
+) -> std::io::Result<()> {
+    if let Some(path) = path {
+        let file = DeadLetterFile::create(path, cipher.cloned()).await?;
+        publishing.push(tokio::spawn(file.run(engine.dead_letters())));
+    }
+    Ok(())
+}
+
+/// Dashboard of the engine drawn on stderr, which has to be a terminal.
+fn dashboard(engine: &mut Engine) -> Result<Dashboard<CrosstermBackend<Stderr>>, Box<dyn Error>> {
+    Dashboard::new(engine, CrosstermBackend::new(std::io::stderr()))
+        .map_err(|e| format!("Can't draw the dashboard on stderr: {}", e).into())
+}
+
+/// Waits until every event of the dropped engine was sent on.
+async fn finish_publishing(publishing: Vec<Publishing>) -> Result<(), Box<dyn Error>> {
+    for publishing in publishing {
+        publishing
+            .await?
+            .map_err(|e| format!("Failed to send events on: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Starts exporting spans, if tracing is enabled.
+fn start_tracing(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    if let Some((endpoint, rate)) = &settings.tracing {
+        let layer = telemetry::layer(endpoint, rate.to_f64().unwrap_or(1.0))?;
+        logging::export_spans(Box::new(layer))?;
+    }
+    Ok(())
+}
+
+/// Exports the spans still waiting, a collector that can't be reached only gets a warning.
+async fn finish_tracing() {
+    if let Err(e) = telemetry::flush().await {
+        tracing::warn!(error = %e, "spans not exported");
+    }
+}
+
+/// Processes the inputs, or with `until` only up to a point of them, and writes the account
+/// report, of the client's account only if one is given.
+async fn process(
+    settings: Settings,
+    until: Option<AsOf>,
+    client: Option<u16>,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    start_tracing(&settings)?;
+    #[cfg(feature = "postgres")]
+    let postgres = connect_postgres(&settings).await?;
+    #[cfg(feature = "postgres")]
+    let (store, mut publishing, notifying) = match &postgres {
+        // Reconstructed positions are only a view of the past, they aren't posted
+        Some((database, _)) if until.is_none() => {
+            publish_events(database.store(MemoryStore::new()), &settings).await?
+        }
+        _ => publish_events(MemoryStore::new(), &settings).await?,
+    };
+    #[cfg(not(feature = "postgres"))]
+    let (store, mut publishing, notifying) = publish_events(MemoryStore::new(), &settings).await?;
+    #[cfg(feature = "postgres")]
+    let database = postgres.map(|(database, writing)| {
+        publishing.push(writing);
+        database
+    });
+    #[cfg(feature = "persistence")]
+    let state = open_state(&settings)?;
+    let mut engine = Engine::with_store(settings.engine, store);
+    let dead_letters = settings.dead_letters.as_deref();
+    let cipher = settings.cipher.as_ref();
+    write_dead_letters(&mut engine, dead_letters, cipher, &mut publishing).await?;
+    // Input transactions consumed so far, checkpoints carry on after theirs
+    let mut cursor = 0;
+    #[cfg(feature = "kafka")]
+    let mut consumed = None;
+    if let Some(path) = &settings.load_state {
+        let snapshot = Snapshot::load(path, settings.cipher.as_ref())
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        cursor = snapshot.cursor().unwrap_or(0);
+        #[cfg(feature = "kafka")]
+        {
+            consumed = snapshot.topic().cloned();
+        }
+        engine.restore(snapshot)?;
+    }
+    #[cfg(feature = "persistence")]
+    if let Some(state) = &state {
+        engine.load_state(state.as_ref())?;
+    }
+    #[cfg(feature = "postgres")]
+    if let Some(database) = &database {
+        engine.restore(database.load().await?)?;
+    }
+    notify_webhook(&mut engine, notifying, &mut publishing).await;
+    settle_transfers(&mut engine, &settings.transfer_inbox).await?;
+
+    // Transactions a crashed run already submitted are replayed, and skipped when the
+    // inputs get to them again
+    let (mut wal, replayed) = match &settings.wal {
+        Some(path) => {
+            let (wal, recovered) = Wal::open(path, settings.cipher.clone())?;
+            if !recovered.is_empty() {
+                tracing::warn!(
+                    transactions = recovered.len(),
+                    wal = %path.display(),
+                    "recovering transactions"
+                );
+            }
+            let replayed = recovered.len();
+            for transaction in recovered {
+                engine.submit(transaction).await?;
+            }
+            (Some(wal), replayed)
+        }
+        None => (None, 0),
+    };
+
+    // Sequence numbers count malformed rows too, so the reader stops at those
+    let (until_row, until_time) = match until {
+        Some(AsOf::Sequence(row)) => (Some(row as usize), None),
+        until => (None, until),
+    };
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        until: until_row,
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        follow: settings.follow.is_some(),
+    };
+    let inputs = settings.inputs;
+    let read = if settings.merge_by_timestamp {
+        merge_files
+    } else {
+        deserialize_files
+    };
+    let (tx, mut px) = mpsc::channel::<Transaction>(engine.config().channel_capacity);
+    #[cfg(feature = "kafka")]
+    let topic = match settings.kafka {
+        Some(source) => Some(resume_topic(source, consumed)?),
+        None => None,
+    };
+    #[cfg(feature = "kafka")]
+    let reader = match topic.clone() {
+        Some((source, offsets)) => tokio::task::spawn_blocking(move || {
+            deserialize_kafka(source, offsets, read_options, tx)
+        }),
+        None => tokio::task::spawn_blocking(move || read(inputs, read_options, tx)),
+    };
+    #[cfg(not(feature = "kafka"))]
+    let reader = tokio::task::spawn_blocking(move || read(inputs, read_options, tx));
+
+    let mut skip = cursor + replayed as u64;
+    cursor = skip;
+    // A topic continues at the checkpoint's offsets rather than skipping what it consumed
+    #[cfg(feature = "kafka")]
+    if topic.is_some() {
+        skip = 0;
+    }
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut dashboard = match settings.dashboard {
+        true => Some(dashboard(&mut engine)?),
+        false => None,
+    };
+    let mut frames = tokio::time::interval(DASHBOARD_INTERVAL);
+    // A followed input never ends, so its progress is checkpointed as it goes
+    let snapshot_interval = settings.follow.unwrap_or(Duration::from_secs(60));
+    let mut checkpoints = tokio::time::interval_at(
+        tokio::time::Instant::now() + snapshot_interval,
+        snapshot_interval,
+    );
+    loop {
+        let transaction = tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                // Closing the channel stops the reader, the workers drain what they got
+                drop(px);
+                let checkpoint = settings.checkpoint;
+                let snapshot = engine.snapshot().await?.with_cursor(cursor);
+                #[cfg(feature = "kafka")]
+                let snapshot = with_topic(snapshot, &topic);
+                snapshot.save(&checkpoint, settings.cipher.as_ref())?;
+                #[cfg(feature = "kafka")]
+                commit_topic(&snapshot, &topic).await;
+                write_transfer_outbox(&mut engine, settings.transfer_outbox.as_deref())?;
+                if let Some(wal) = wal {
+                    wal.finish()?;
+                }
+                tracing::warn!(
+                    transactions = cursor,
+                    checkpoint = %checkpoint.display(),
+                    "interrupted, resume with --load-state <checkpoint>"
+                );
+                finish_tracing().await;
+                // Exiting skips destructors, the dashboard gives the cursor back first
+                drop(dashboard.take());
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            _ = frames.tick(), if dashboard.is_some() => {
+                if let Some(dashboard) = &mut dashboard {
+                    dashboard.draw(&engine).await?;
+                }
+                continue;
+            }
+            _ = checkpoints.tick(), if settings.follow.is_some() => {
+                let snapshot = engine.snapshot().await?.with_cursor(cursor);
+                #[cfg(feature = "kafka")]
+                let snapshot = with_topic(snapshot, &topic);
+                snapshot.save(&settings.checkpoint, settings.cipher.as_ref())?;
+                #[cfg(feature = "kafka")]
+                commit_topic(&snapshot, &topic).await;
+                continue;
+            }
+            transaction = px.recv() => match transaction {
+                Some(transaction) => transaction,
+                None => break,
+            },
+        };
+        if until_time.is_some_and(|until| until.passed(cursor + 1, &transaction)) {
+            break;
+        }
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        if let Some(wal) = &mut wal {
+            wal.append(&transaction)?;
+        }
+        #[cfg(feature = "kafka")]
+        let row = transaction.row();
+        engine.submit(transaction).await?;
+        cursor += 1;
+        #[cfg(feature = "kafka")]
+        if let (Some((_, offsets)), Some(row)) = (&topic, row) {
+            offsets.submitted(row);
+        }
+        if engine.invariants_broken() {
+            break;
+        }
+    }
+    // Closing the channel stops the reader when the loop stopped early
+    drop(px);
+    if let Some(dashboard) = &mut dashboard {
+        engine.wait().await;
+        dashboard.draw(&engine).await?;
+    }
+
+    let summary = reader.await??;
+    if summary.skipped > 0 {
+        tracing::warn!(skipped = summary.skipped, "malformed rows skipped");
+    }
+    let violations = engine.check_invariants().await;
+    if !violations.is_empty() {
+        for violation in &violations {
+            tracing::error!(%violation, "invariant violated");
+        }
+        finish_tracing().await;
+        return Err(format!("{} invariant violations", violations.len()).into());
+    }
+
+    match (settings.output, client) {
+        (Some(path), Some(client)) => {
+            let file = std::fs::File::create(path)?;
+            engine.write_client_report(file, client).await?
+        }
+        (Some(path), None) => engine.write_report(std::fs::File::create(path)?).await?,
+        (None, Some(client)) => {
+            engine
+                .write_client_report(std::io::stdout(), client)
+                .await?
+        }
+        (None, None) => engine.write_report(std::io::stdout()).await?,
+    }
+    // Reconstructed positions are only a view of the past, they don't replace the saved state
+    #[cfg(feature = "persistence")]
+    if let (Some(state), None) = (&state, until) {
+        engine.save_state(state.as_ref()).await?;
+    }
+    #[cfg(feature = "postgres")]
+    if let (Some(database), None) = (&database, until) {
+        database.save(engine.snapshot().await?);
+    }
+    if let Some(path) = settings.save_state {
+        engine
+            .snapshot()
+            .await?
+            .save(path, settings.cipher.as_ref())?;
+    }
+    let cipher = &settings.cipher;
+    if let Some(path) = settings.event_log {
+        let mut file = AuditFile::create(path, cipher.clone())?;
+        engine.write_events(&mut file).await?;
+        file.finish()?;
+    }
+    if let Some(path) = settings.postings {
+        let mut file = AuditFile::create(path, cipher.clone())?;
+        engine.write_postings(&mut file).await?;
+        file.finish()?;
+    }
+    if let Some((path, format)) = settings.ledger {
+        let mut file = AuditFile::create(path, cipher.clone())?;
+        engine.write_ledger(&mut file, format).await?;
+        file.finish()?;
+    }
+    if let Some(path) = settings.fraud_report {
+        engine
+            .write_fraud_hits(std::fs::File::create(path)?)
+            .await?;
+    }
+    if let Some(path) = settings.errors {
+        engine
+            .write_rejections(std::fs::File::create(path)?)
+            .await?;
+    }
+    write_transfer_outbox(&mut engine, settings.transfer_outbox.as_deref())?;
+    if settings.summary.is_some() || settings.print_summary {
+        let mut run = engine.summary(started.elapsed()).await;
+        run.skipped_rows = summary.skipped;
+        if let Some(path) = &settings.summary {
+            serde_json::to_writer_pretty(std::fs::File::create(path)?, &run)?;
+        }
+        if settings.print_summary {
+            eprintln!("{}", run);
+        }
+    }
+    if let Some(wal) = wal {
+        wal.finish()?;
+    }
+    drop(engine);
+    #[cfg(feature = "postgres")]
+    drop(database);
+    finish_tracing().await;
+    finish_publishing(publishing).await
+}
+
+/// Source of a run consuming Kafka, with how far its topic was consumed, picking up at the
+/// offsets of the checkpoint it resumes.
+#[cfg(feature = "kafka")]
+fn resume_topic(
+    source: KafkaSource,
+    checkpoint: Option<TopicOffsets>,
+) -> Result<(KafkaSource, Arc<Offsets>), Box<dyn Error>> {
+    let offsets = match checkpoint {
+        Some(checkpoint) if checkpoint.topic != source.topic => {
+            return Err(format!(
+                "The checkpoint continues topic {}, not {}",
+                checkpoint.topic, source.topic
+            )
+            .into())
         }
+        Some(checkpoint) => checkpoint.offsets,
+        None => Default::default(),
+    };
+    Ok((source, Arc::new(Offsets::new(offsets))))
+}
+
+/// Marks a checkpoint with where the topic of a run consuming Kafka continues.
+#[cfg(feature = "kafka")]
+fn with_topic(snapshot: Snapshot, topic: &Option<(KafkaSource, Arc<Offsets>)>) -> Snapshot {
+    match topic {
+        Some((source, offsets)) => snapshot.with_topic(TopicOffsets {
+            topic: source.topic.clone(),
+            offsets: offsets.next(),
+        }),
+        None => snapshot,
     }
 }
 
-fn deserialize_csv_file(path: String, sender: mpsc::UnboundedSender<Transaction>) {
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(path)
-        .unwrap();
+/// Commits the offsets of a saved checkpoint to the consumer group. Runs continue from the
+/// checkpoint rather than the group, so a commit that fails only gets a warning.
+#[cfg(feature = "kafka")]
+async fn commit_topic(snapshot: &Snapshot, topic: &Option<(KafkaSource, Arc<Offsets>)>) {
+    let (Some((source, _)), Some(consumed)) = (topic, snapshot.topic()) else {
+        return;
+    };
+    let (source, offsets) = (source.clone(), consumed.offsets.clone());
+    let committed = tokio::task::spawn_blocking(move || kafka::commit(&source, &offsets)).await;
+    if let Ok(Err(e)) = committed {
+        tracing::warn!(error = %e, "offsets not committed to kafka");
+    }
+}
 
-    for transaction in reader.deserialize() {
-        if let Ok(t) = transaction {
-            let _ = sender.send(t);
+/// Opens the store account state is kept in between runs, if there is one.
+#[cfg(feature = "persistence")]
+fn open_state(settings: &Settings) -> Result<Option<Box<dyn KeyValueStore>>, Box<dyn Error>> {
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &settings.state_db {
+        let store = SqliteStore::open(path)
+            .map_err(|e| format!("Can't open state database {}: {}", path.display(), e))?;
+        return Ok(Some(Box::new(store)));
+    }
+    Ok(match &settings.state_dir {
+        Some(dir) => Some(Box::new(SledStore::open(dir, settings.cipher.clone())?)),
+        None => None,
+    })
+}
+
+/// Connects to the PostgreSQL database account state is kept in, if there is one, with the
+/// task writing to it.
+#[cfg(feature = "postgres")]
+async fn connect_postgres(
+    settings: &Settings,
+) -> Result<Option<(PostgresDatabase, Publishing)>, Box<dyn Error>> {
+    let Some(config) = &settings.state_postgres else {
+        return Ok(None);
+    };
+    let (database, writer) = PostgresDatabase::connect(config)
+        .await
+        .map_err(|e| format!("Can't connect to state database: {}", e))?;
+    Ok(Some((database, tokio::spawn(writer.run()))))
+}
+
+/// Settles the transfer messages other partitions wrote for clients of the engine's
+/// partition. Refused messages end up with the rejections.
+async fn settle_transfers(engine: &mut Engine, inbox: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    for path in inbox {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Can't read transfer inbox {}: {}", path.display(), e))?;
+        let messages = read_transfer_messages(std::io::BufReader::new(file))
+            .map_err(|e| format!("Invalid transfer inbox {}: {}", path.display(), e))?;
+        let partition = engine.config().partition;
+        for message in messages {
+            if partition.is_some_and(|p| p.contains(message.recipient())) {
+                let _ = engine.settle(message).await;
+            }
         }
     }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let filename = match std::env::args().nth(1) {
-        Some(f) => f,
-        None => {
-            return Err("Please provide csv filename".into());
+/// Writes the messages to other partitions settling transfers since the last call.
+fn write_transfer_outbox(engine: &mut Engine, outbox: Option<&Path>) -> std::io::Result<()> {
+    match outbox {
+        Some(path) => {
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            write_transfer_messages(file, &engine.take_transfer_messages())
         }
+        None => Ok(()),
+    }
+}
+
+/// Processes the inputs into a new engine, restoring `--load-state` first, without writing
+/// any of the outputs `process` writes.
+async fn process_quietly(settings: Settings) -> Result<Engine, Box<dyn Error>> {
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        ..ReadOptions::default()
+    };
+    let read = if settings.merge_by_timestamp {
+        merge_files
+    } else {
+        deserialize_files
     };
+    let mut engine = Engine::with_config(settings.engine);
+    if let Some(path) = &settings.load_state {
+        let snapshot = Snapshot::load(path, settings.cipher.as_ref())
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        engine.restore(snapshot)?;
+    }
+    let (tx, mut px) = mpsc::channel::<Transaction>(engine.config().channel_capacity);
+    let inputs = settings.inputs;
+    let reader = tokio::task::spawn_blocking(move || read(inputs, read_options, tx));
+    while let Some(transaction) = px.recv().await {
+        engine.submit(transaction).await?;
+    }
+    reader.await??;
+    engine.wait().await;
+    Ok(engine)
+}
 
-    let mut bank = HashMap::<u16, Arc<Mutex<Account>>>::default();
+/// Processes the inputs keeping a statement of the client's account only, and writes it as
+/// csv instead of the account report.
+async fn statement(mut settings: Settings, client: u16) -> Result<(), Box<dyn Error>> {
+    settings.engine.statements = HashSet::from([client]);
+    let output = settings.output.take();
+    let engine = process_quietly(settings).await?;
+    let account = engine
+        .account(client)
+        .await
+        .ok_or_else(|| format!("Client {} has no account", client))?;
+    let format = engine.config().output_format;
+    match output {
+        Some(path) => write_statement(std::fs::File::create(path)?, account.statement(), &format)?,
+        None => write_statement(std::io::stdout(), account.statement(), &format)?,
+    }
+    Ok(())
+}
 
-    let (tx, mut px) = mpsc::unbounded_channel::<Transaction>();
-    tokio::task::spawn_blocking(move || {
-        deserialize_csv_file(filename.to_string(), tx);
-    });
+/// Processes the inputs keeping the accounts' events, and writes the trial balance of their
+/// postings as csv instead of the account report.
+async fn trial_balance(mut settings: Settings) -> Result<(), Box<dyn Error>> {
+    settings.engine.event_log = true;
+    let output = settings.output.take();
+    let mut engine = process_quietly(settings).await?;
+    match output {
+        Some(path) => {
+            engine
+                .write_trial_balance(std::fs::File::create(path)?)
+                .await
+        }
+        None => engine.write_trial_balance(std::io::stdout()).await,
+    }
+}
 
+/// Prints a file encrypted at rest in plaintext, with the key from the environment.
+fn decrypt(path: &Path) -> Result<(), Box<dyn Error>> {
+    let cipher = Cipher::from_env()?.ok_or("No encryption key set to decrypt with")?;
+    let plaintext = encryption::decrypt_file(&cipher, std::fs::read(path)?)?;
+    std::io::Write::write_all(&mut std::io::stdout(), &plaintext)?;
+    Ok(())
+}
+
+/// Processes the input files, or only restores `--load-state` if there are none, and hands
+/// the engine to the prompt. Stdin is left for commands.
+async fn repl(mut settings: Settings) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    if settings.inputs.iter().any(|input| input == STDIN) {
+        if settings.inputs.len() > 1 || settings.load_state.is_none() {
+            return Err("repl reads commands from stdin, give input files or --load-state".into());
+        }
+        settings.inputs.clear();
+    }
+    let mut engine = process_quietly(settings).await?;
+    repl::run_stdio(&mut engine, started).await
+}
+
+/// Reads a single input into the engine, returning how many rows were read and skipped.
+async fn ingest(
+    engine: &mut Engine,
+    input: String,
+    read_options: ReadOptions,
+) -> Result<ReadSummary, Box<dyn Error>> {
+    let (tx, mut px) = mpsc::channel::<Transaction>(engine.config().channel_capacity);
+    let reader = tokio::task::spawn_blocking(move || deserialize_file(input, read_options, tx));
     while let Some(transaction) = px.recv().await {
-        let client = match bank.get(&transaction.client) {
-            Some(client) => client.clone(),
-            None => {
-                let new_client = Arc::new(Mutex::new(Account::new(transaction.client)));
-                bank.insert(transaction.client, new_client.clone());
+        engine.submit(transaction).await?;
+    }
+    Ok(reader.await??)
+}
+
+/// Processes every input file dropped into the folder against the same engine, moving each
+/// to `done/` once processed, until SIGINT or SIGTERM. The report, rejections and state are
+/// written after every file, so they are current while the folder is watched.
+async fn watch(dir: PathBuf, settings: Settings) -> Result<(), Box<dyn Error>> {
+    if settings.inputs != [STDIN] {
+        return Err("watch takes no inputs, they are dropped into the folder".into());
+    }
+    let folder =
+        DropFolder::open(&dir).map_err(|e| format!("Can't watch {}: {}", dir.display(), e))?;
+    #[cfg(feature = "persistence")]
+    let state = open_state(&settings)?;
+    #[cfg(feature = "postgres")]
+    let postgres = connect_postgres(&settings).await?;
+    #[cfg(feature = "postgres")]
+    let mut engine = match &postgres {
+        Some((database, _)) => Engine::with_store(
+            settings.engine,
+            Arc::new(database.store(MemoryStore::new())),
+        ),
+        None => Engine::with_config(settings.engine),
+    };
+    #[cfg(not(feature = "postgres"))]
+    let mut engine = Engine::with_config(settings.engine);
+    if let Some(path) = &settings.load_state {
+        let snapshot = Snapshot::load(path, settings.cipher.as_ref())
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        engine.restore(snapshot)?;
+    }
+    #[cfg(feature = "persistence")]
+    if let Some(state) = &state {
+        engine.load_state(state.as_ref())?;
+    }
+    #[cfg(feature = "postgres")]
+    if let Some((database, _)) = &postgres {
+        engine.restore(database.load().await?)?;
+    }
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        ..ReadOptions::default()
+    };
 
-                new_client
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut polls = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            _ = polls.tick() => {}
+        }
+        for path in folder.pending()? {
+            let input = path.to_string_lossy().into_owned();
+            let summary = ingest(&mut engine, input, read_options.clone()).await?;
+            engine.wait().await;
+            let done = folder.done(&path)?;
+            tracing::info!(
+                input = %done.display(),
+                rows = summary.rows,
+                skipped = summary.skipped,
+                "input processed"
+            );
+            if let Some(path) = &settings.output {
+                engine.write_report(std::fs::File::create(path)?).await?;
             }
-        };
+            if let Some(path) = &settings.errors {
+                engine
+                    .write_rejections(std::fs::File::create(path)?)
+                    .await?;
+            }
+            #[cfg(feature = "persistence")]
+            if let Some(state) = &state {
+                engine.save_state(state.as_ref()).await?;
+            }
+            #[cfg(feature = "postgres")]
+            if let Some((database, _)) = &postgres {
+                database.save(engine.snapshot().await?);
+            }
+            if let Some(path) = &settings.save_state {
+                engine
+                    .snapshot()
+                    .await?
+                    .save(path, settings.cipher.as_ref())?;
+            }
+        }
+    }
+    if settings.output.is_none() {
+        engine.write_report(std::io::stdout()).await?;
+    }
+    #[cfg(feature = "postgres")]
+    if let Some((database, writing)) = postgres {
+        drop((engine, database));
+        finish_publishing(vec![writing]).await?;
+    }
+    Ok(())
+}
 
+/// Binds the address of a server protocol, if it is enabled.
+async fn listen(
+    address: Option<SocketAddr>,
+    protocol: &str,
+) -> std::io::Result<Option<TcpListener>> {
+    let Some(address) = address else {
+        return Ok(None);
+    };
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!(protocol, address = %listener.local_addr()?, "listening");
+    Ok(Some(listener))
+}
+
+/// Serves the engine over the network until SIGINT or SIGTERM, then writes the report and saves
+/// the state like a processing run would.
+async fn serve(
+    http: Option<SocketAddr>,
+    tcp: Option<SocketAddr>,
+    grpc: Option<SocketAddr>,
+    settings: Settings,
+) -> Result<(), Box<dyn Error>> {
+    if settings.inputs != [STDIN] {
+        return Err("serve takes no inputs, transactions arrive over the network".into());
+    }
+    start_tracing(&settings)?;
+    let broadcast = BroadcastStore::new(settings.engine.output_format);
+    let events = broadcast.events();
+    let (store, mut publishing, notifying) = publish_events(broadcast, &settings).await?;
+    let mut engine = Engine::with_store(settings.engine, store);
+    let dead_letters = settings.dead_letters.as_deref();
+    let cipher = settings.cipher.as_ref();
+    write_dead_letters(&mut engine, dead_letters, cipher, &mut publishing).await?;
+    if let Some(path) = &settings.load_state {
+        let snapshot = Snapshot::load(path, settings.cipher.as_ref())
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        engine.restore(snapshot)?;
+    }
+    notify_webhook(&mut engine, notifying, &mut publishing).await;
+    let read_options = ReadOptions {
+        verifier: RowVerifier::from_env(),
+        ..ReadOptions::default()
+    };
+    let dashboard = match settings.dashboard {
+        true => Some(dashboard(&mut engine)?),
+        false => None,
+    };
+    let rules = settings.rules;
+    let mut server = Server::new(engine, read_options)
+        .with_events(events)
+        .with_reload(move || rules.reload().map_err(|e| e.to_string()));
+    match std::env::var(server::ADMIN_TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => server = server.with_admin_token(token),
+        _ => {}
+    }
+    let server = Arc::new(server);
+    let reloads = tokio::spawn(reload_on_hangup(server.clone()));
+    let dashboard = dashboard.map(|mut dashboard| {
+        let server = server.clone();
         tokio::spawn(async move {
-            let mut client = client.lock_owned().await;
-            client.add_transaction(transaction);
-            client.process_pending_transaction()
-        });
+            let mut frames = tokio::time::interval(DASHBOARD_INTERVAL);
+            loop {
+                frames.tick().await;
+                let engine = server.engine().await;
+                if dashboard.draw(&engine).await.is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    let http = listen(http, "HTTP API").await?;
+    let tcp = listen(tcp, "Line protocol").await?;
+    let grpc = listen(grpc, "gRPC service").await?;
+    let run_http = async {
+        match http {
+            Some(listener) => server.clone().run_http(listener).await,
+            None => std::future::pending().await,
+        }
+    };
+    let run_tcp = async {
+        match tcp {
+            Some(listener) => server.clone().run_tcp(listener).await,
+            None => std::future::pending().await,
+        }
+    };
+    let run_grpc = async {
+        match grpc {
+            Some(listener) => server.clone().run_grpc(listener).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        served = run_http => served?,
+        served = run_tcp => served?,
+        served = run_grpc => served?,
+        _ = shutdown_signal() => {}
+    }
+    if let Some(dashboard) = dashboard {
+        dashboard.abort();
     }
+    reloads.abort();
 
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
-    for (_, account) in bank {
-        writer.serialize(account.lock().await.to_owned())?;
+    let mut engine = server.engine().await;
+    if let Some(path) = settings.output {
+        engine.write_report(std::fs::File::create(path)?).await?;
     }
+    if let Some(path) = settings.save_state {
+        engine
+            .snapshot()
+            .await?
+            .save(path, settings.cipher.as_ref())?;
+    }
+    // Connections may still hold the server, taking the engine out lets go of its store
+    drop(std::mem::take(&mut *engine));
+    drop(engine);
+    finish_tracing().await;
+    finish_publishing(publishing).await
+}
 
-    Ok(())
+/// Checks that every row of the inputs parses or, with expected balances, processes the
+/// inputs and prints every account whose balances differ from them.
+async fn verify(settings: Settings, expected: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let Some(expected) = expected else {
+        let read_options = ReadOptions {
+            format: settings.input_format,
+            delimiter: settings.delimiter,
+            schema: settings.schema.clone(),
+            verifier: RowVerifier::from_env(),
+            strict: true,
+            ..ReadOptions::default()
+        };
+        let (tx, mut px) = mpsc::channel::<Transaction>(1024);
+        let inputs = settings.inputs;
+        let reader =
+            tokio::task::spawn_blocking(move || deserialize_files(inputs, read_options, tx));
+        while px.recv().await.is_some() {}
+
+        let summary = reader.await??;
+        println!("{} rows OK", summary.rows);
+        return Ok(());
+    };
+
+    // Like `process`, but the report is compared instead of written, as csv whatever
+    // `--output-format` says
+    let expected = std::fs::read(&expected)
+        .map_err(|e| format!("Can't read {}: {}", expected.display(), e))?;
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        ..ReadOptions::default()
+    };
+    let read = if settings.merge_by_timestamp {
+        merge_files
+    } else {
+        deserialize_files
+    };
+    let mut engine = Engine::with_config(EngineConfig {
+        report_format: ReportFormat::Csv,
+        ..settings.engine
+    });
+    let (tx, mut px) = mpsc::channel::<Transaction>(engine.config().channel_capacity);
+    let inputs = settings.inputs;
+    let reader = tokio::task::spawn_blocking(move || read(inputs, read_options, tx));
+    while let Some(transaction) = px.recv().await {
+        engine.submit(transaction).await?;
+    }
+    reader.await??;
+    let mut report = Vec::new();
+    engine.write_report(&mut report).await?;
+
+    let discrepancies = diff_reports(expected.as_slice(), report.as_slice())?;
+    for discrepancy in &discrepancies {
+        println!("{}", discrepancy);
+    }
+    match discrepancies.len() {
+        0 => {
+            println!("Balances match");
+            Ok(())
+        }
+        n => Err(format!("{} discrepancies with the expected balances", n).into()),
+    }
+}
+
+/// Processes the inputs from `--load-state`, or from no accounts, and prints every way the
+/// resulting state diverges from the known-good snapshot `against`. Nothing is written, the
+/// state is only kept by processing the inputs once they reconcile or their divergences are
+/// the intended corrections.
+async fn reconcile(settings: Settings, against: PathBuf) -> Result<(), Box<dyn Error>> {
+    let expected = Snapshot::load(&against, settings.cipher.as_ref())
+        .map_err(|e| format!("Invalid snapshot {}: {}", against.display(), e))?;
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        ..ReadOptions::default()
+    };
+    let read = if settings.merge_by_timestamp {
+        merge_files
+    } else {
+        deserialize_files
+    };
+    let mut engine = Engine::with_config(settings.engine);
+    if let Some(path) = &settings.load_state {
+        let snapshot = Snapshot::load(path, settings.cipher.as_ref())
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        engine.restore(snapshot)?;
+    }
+    let (tx, mut px) = mpsc::channel::<Transaction>(engine.config().channel_capacity);
+    let inputs = settings.inputs;
+    let reader = tokio::task::spawn_blocking(move || read(inputs, read_options, tx));
+    while let Some(transaction) = px.recv().await {
+        engine.submit(transaction).await?;
+    }
+    reader.await??;
+
+    let divergences = reconcile::reconcile(&expected, &engine.snapshot().await?);
+    for divergence in &divergences {
+        println!("{}", divergence);
+    }
+    let clients = divergences
+        .iter()
+        .map(Divergence::client)
+        .collect::<BTreeSet<_>>();
+    match divergences.len() {
+        0 => {
+            println!("State matches the snapshot");
+            Ok(())
+        }
+        n => Err(format!("{} divergences in {} accounts", n, clients.len()).into()),
+    }
+}
+
+/// Reads the inputs into memory and processes them `runs` times with more and more
+/// workers, failing with the first transaction or account the runs disagree on.
+async fn replay(settings: Settings, runs: u32) -> Result<(), Box<dyn Error>> {
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        ..ReadOptions::default()
+    };
+    let read = if settings.merge_by_timestamp {
+        merge_files
+    } else {
+        deserialize_files
+    };
+    let (tx, mut px) = mpsc::channel::<Transaction>(1024);
+    let inputs = settings.inputs;
+    let reader = tokio::task::spawn_blocking(move || read(inputs, read_options, tx));
+    let mut transactions = Vec::new();
+    while let Some(transaction) = px.recv().await {
+        transactions.push(transaction);
+    }
+    reader.await??;
+
+    match check_determinism(&transactions, &settings.engine, runs).await? {
+        None => {
+            println!(
+                "{} transactions processed {} times with the same outcome",
+                transactions.len(),
+                runs
+            );
+            Ok(())
+        }
+        Some(divergence) => Err(format!("Runs diverged at {}", divergence).into()),
+    }
+}
+
+/// Highest resident set size of the process so far in bytes, where the OS tells.
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes = line["VmHWM:".len()..].trim().strip_suffix("kB")?;
+    kilobytes.trim().parse::<u64>().ok().map(|kb| kb * 1024)
+}
+
+/// Lets [`peak_rss`] start over from the current resident set size, where the OS allows.
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+/// Figures of one run of `bench`.
+struct BenchRun {
+    workers: usize,
+    channel_capacity: usize,
+    read: ReadSummary,
+    elapsed: Duration,
+    reading: Duration,
+    submitting: Duration,
+    draining: Duration,
+    reporting: Duration,
+    stats: EngineStats,
+    latencies: Latencies,
+    peak_rss: Option<u64>,
+}
+
+impl BenchRun {
+    fn throughput(&self) -> f64 {
+        self.read.rows as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn mebibytes(bytes: Option<u64>) -> String {
+    match bytes {
+        Some(bytes) => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Processes the inputs once with `config`, without writing anything.
+async fn bench_run(
+    inputs: Vec<String>,
+    settings: &Settings,
+    config: EngineConfig,
+) -> Result<BenchRun, Box<dyn Error>> {
+    let read_options = ReadOptions {
+        format: settings.input_format,
+        delimiter: settings.delimiter,
+        schema: settings.schema.clone(),
+        verifier: RowVerifier::from_env(),
+        strict: settings.strict,
+        ..ReadOptions::default()
+    };
+    let read = if settings.merge_by_timestamp {
+        merge_files
+    } else {
+        deserialize_files
+    };
+    let mut engine = Engine::with_config(config);
+
+    let started = Instant::now();
+    let (tx, mut px) = mpsc::channel::<Transaction>(engine.config().channel_capacity);
+    let reader = tokio::task::spawn_blocking(move || {
+        let started = Instant::now();
+        read(inputs, read_options, tx).map(|summary| (summary, started.elapsed()))
+    });
+    let mut submitting = Duration::ZERO;
+    while let Some(transaction) = px.recv().await {
+        let submitted = Instant::now();
+        engine.submit(transaction).await?;
+        submitting += submitted.elapsed();
+    }
+    let (read, reading) = reader.await??;
+    let draining = Instant::now();
+    engine.wait().await;
+    let draining = draining.elapsed();
+    let reporting = Instant::now();
+    engine.write_report(std::io::sink()).await?;
+    let reporting = reporting.elapsed();
+
+    Ok(BenchRun {
+        workers: engine.config().workers,
+        channel_capacity: engine.config().channel_capacity,
+        read,
+        elapsed: started.elapsed(),
+        reading,
+        submitting,
+        draining,
+        reporting,
+        stats: engine.stats(),
+        latencies: engine.latencies().clone(),
+        peak_rss: peak_rss(),
+    })
+}
+
+/// Prints throughput, memory, queue saturation, latency and the time spent in every
+/// stage of a single run.
+fn print_bench_run(run: &BenchRun) {
+    println!("transactions   {}", run.read.rows);
+    println!("skipped rows   {}", run.read.skipped);
+    println!("elapsed        {:.3}s", run.elapsed.as_secs_f64());
+    println!("throughput     {:.0} tx/s", run.throughput());
+    println!("peak rss       {}", mebibytes(run.peak_rss));
+    println!(
+        "queue peak     {} of {} per worker, full {} times",
+        run.stats.peak_queue, run.channel_capacity, run.stats.queue_full
+    );
+    println!(
+        "latency        p50 {}, p90 {}, p99 {}, max {}",
+        millis(run.latencies.percentile(50.0)),
+        millis(run.latencies.percentile(90.0)),
+        millis(run.latencies.percentile(99.0)),
+        millis(run.latencies.max())
+    );
+    println!("read           {:.3}s", run.reading.as_secs_f64());
+    println!("submit         {:.3}s", run.submitting.as_secs_f64());
+    println!(
+        "apply          {:.3}s over {} workers",
+        run.stats.applying.as_secs_f64(),
+        run.workers
+    );
+    println!("drain          {:.3}s", run.draining.as_secs_f64());
+    println!("report         {:.3}s", run.reporting.as_secs_f64());
+}
+
+/// Prints one row per run, to pick the worker count that suits the machine.
+fn print_bench_comparison(runs: &[BenchRun]) {
+    println!(
+        "{:>7}  {:>12}  {:>9}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+        "workers", "throughput", "elapsed", "peak rss", "p50", "p90", "p99", "max"
+    );
+    for run in runs {
+        println!(
+            "{:>7}  {:>7.0} tx/s  {:>8.3}s  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+            run.workers,
+            run.throughput(),
+            run.elapsed.as_secs_f64(),
+            mebibytes(run.peak_rss),
+            millis(run.latencies.percentile(50.0)),
+            millis(run.latencies.percentile(90.0)),
+            millis(run.latencies.percentile(99.0)),
+            millis(run.latencies.max())
+        );
+    }
+}
+
+/// Processes the inputs once with each of the worker counts.
+async fn bench_workers(
+    inputs: &[String],
+    settings: &Settings,
+    worker_counts: Vec<usize>,
+) -> Result<Vec<BenchRun>, Box<dyn Error>> {
+    let mut runs = Vec::new();
+    for workers in worker_counts {
+        reset_peak_rss();
+        let config = EngineConfig {
+            workers,
+            ..settings.engine.clone()
+        };
+        runs.push(bench_run(inputs.to_vec(), settings, config).await?);
+    }
+    Ok(runs)
+}
+
+/// Runs the generated workload, or the inputs without one, through the engine and prints
+/// how it went. With several worker counts the same transactions are processed once with
+/// each of them and the runs are compared in a table.
+async fn bench(
+    workload: Option<Workload>,
+    settings: Settings,
+    worker_counts: Vec<usize>,
+) -> Result<(), Box<dyn Error>> {
+    let generated = match workload {
+        Some(workload) => {
+            let path = std::env::temp_dir().join(format!("bench-{}.csv", std::process::id()));
+            workload.write_csv(std::io::BufWriter::new(std::fs::File::create(&path)?))?;
+            Some(path)
+        }
+        None => None,
+    };
+    let inputs = match &generated {
+        Some(path) => vec![path.to_string_lossy().into_owned()],
+        None => settings.inputs.clone(),
+    };
+    let result = if worker_counts.is_empty() {
+        bench_run(inputs, &settings, settings.engine.clone())
+            .await
+            .map(|run| print_bench_run(&run))
+    } else if inputs.iter().any(|input| input == STDIN) {
+        Err("Comparing worker counts needs input files, stdin can only be read once".into())
+    } else {
+        bench_workers(&inputs, &settings, worker_counts)
+            .await
+            .map(|runs| print_bench_comparison(&runs))
+    };
+    if let Some(path) = generated {
+        std::fs::remove_file(path)?;
+    }
+    result
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    cli.init_logging()?;
+    match cli.into_command() {
+        Command::Process(args) => process(args.settings()?, None, None).await,
+        Command::Reconstruct {
+            until,
+            client,
+            process: args,
+        } => process(args.settings()?, Some(until), client).await,
+        Command::Statement {
+            client,
+            process: args,
+        } => statement(args.settings()?.reading_files()?, client).await,
+        Command::Report {
+            trial_balance: _,
+            process: args,
+        } => trial_balance(args.settings()?.reading_files()?).await,
+        Command::Repl(args) => repl(args.settings()?.reading_files()?).await,
+        Command::Watch { dir, process: args } => {
+            watch(dir, args.settings()?.reading_files()?).await
+        }
+        Command::Serve {
+            http,
+            tcp,
+            grpc,
+            process: args,
+        } => serve(http, tcp, grpc, args.settings()?.reading_files()?).await,
+        Command::Decrypt { file } => decrypt(&file),
+        Command::Merge { reports } => partition::merge_reports(&reports, std::io::stdout()),
+        Command::Verify {
+            expected,
+            replays: Some(runs),
+            process: args,
+        } if expected.is_none() => replay(args.settings()?.reading_files()?, runs).await,
+        Command::Verify {
+            expected,
+            process: args,
+            ..
+        } => verify(args.settings()?.reading_files()?, expected).await,
+        Command::Reconcile {
+            against,
+            process: args,
+        } => reconcile(args.settings()?.reading_files()?, against).await,
+        Command::Bench {
+            transactions,
+            clients,
+            seed,
+            process: mut args,
+        } => {
+            let worker_counts = args.take_worker_counts();
+            let workload = transactions.map(|transactions| Workload {
+                clients,
+                transactions,
+                dispute_rate: Decimal::new(5, 2),
+                invalid_rate: Decimal::new(1, 2),
+                seed,
+            });
+            bench(workload, args.settings()?.reading_files()?, worker_counts).await
+        }
+        Command::Generate {
+            clients,
+            transactions,
+            dispute_rate,
+            invalid_rate,
+            seed,
+        } => {
+            let seed = seed.unwrap_or_else(|| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                now.as_nanos() as u64
+            });
+            let workload = Workload {
+                clients,
+                transactions,
+                dispute_rate,
+                invalid_rate,
+                seed,
+            };
+            Ok(workload.write_csv(std::io::BufWriter::new(std::io::stdout().lock()))?)
+        }
+    }
 }
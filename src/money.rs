@@ -0,0 +1,265 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+/// Exact decimal amount of money.
+///
+/// Amounts are parsed straight from their textual representation, so values like `0.1`
+/// never pick up binary floating point error.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(Decimal);
+
+impl Money {
+    pub const ZERO: Money = Money(Decimal::ZERO);
+    pub const MAX: Money = Money(Decimal::MAX);
+
+    /// Creates `mantissa * 10^-scale`, e.g. `Money::new(15, 1)` is `1.5`.
+    pub fn new(mantissa: i64, scale: u32) -> Self {
+        Self(Decimal::new(mantissa, scale))
+    }
+
+    /// Splits the amount into minor units and their scale, e.g. `1.5` into `(15, 1)`, as
+    /// long as the units fit an `i64`.
+    pub fn to_minor_units(self) -> Option<(i64, u32)> {
+        i64::try_from(self.0.mantissa())
+            .ok()
+            .map(|units| (units, self.0.scale()))
+    }
+
+    pub fn checked_add(self, rhs: Money) -> Option<Money> {
+        self.0.checked_add(rhs.0).map(Money)
+    }
+
+    pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+        self.0.checked_sub(rhs.0).map(Money)
+    }
+
+    pub fn checked_mul(self, factor: Decimal) -> Option<Money> {
+        self.0.checked_mul(factor).map(Money)
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > Decimal::ZERO
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < Decimal::ZERO
+    }
+}
+
+/// How midpoints are rounded when an amount is cut down to the output precision.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// 0.00005 becomes 0.0001
+    #[default]
+    HalfUp,
+    /// 0.00005 becomes 0.0000, 0.00015 becomes 0.0002
+    Bankers,
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "bankers" | "half-even" => Ok(RoundingMode::Bankers),
+            _ => Err(format!(
+                "Invalid rounding mode {}, expected half-up or bankers",
+                s
+            )),
+        }
+    }
+}
+
+/// Precision and rounding applied when amounts are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoneyFormat {
+    pub decimal_places: u32,
+    pub rounding: RoundingMode,
+}
+
+impl Default for MoneyFormat {
+    fn default() -> Self {
+        Self {
+            decimal_places: 4,
+            rounding: RoundingMode::default(),
+        }
+    }
+}
+
+impl Money {
+    /// Rounds to the format's precision using its rounding mode.
+    pub fn round(&self, format: &MoneyFormat) -> Money {
+        let strategy = match format.rounding {
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::Bankers => RoundingStrategy::MidpointNearestEven,
+        };
+        Money(
+            self.0
+                .round_dp_with_strategy(format.decimal_places, strategy),
+        )
+    }
+
+    /// Rounds to the format's precision and always prints exactly that many decimal places.
+    pub fn format(&self, format: &MoneyFormat) -> String {
+        let mut value = self.round(format).0;
+        value.rescale(format.decimal_places);
+        value.to_string()
+    }
+}
+
+impl From<i64> for Money {
+    fn from(units: i64) -> Self {
+        Self(Decimal::from(units))
+    }
+}
+
+impl From<Decimal> for Money {
+    fn from(value: Decimal) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Money> for Decimal {
+    fn from(money: Money) -> Self {
+        money.0
+    }
+}
+
+impl FromStr for Money {
+    type Err = rust_decimal::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Decimal::from_str(s.trim()).map(Self)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        Money::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Money, MoneyFormat, RoundingMode};
+
+    #[test]
+    fn parse() {
+        assert_eq!("1.5".parse::<Money>().unwrap(), Money::new(15, 1));
+        assert_eq!(" 2 ".parse::<Money>().unwrap(), Money::from(2));
+        assert_eq!("-0.0001".parse::<Money>().unwrap(), Money::new(-1, 4));
+        assert!("abc".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn repeated_small_deposits_do_not_drift() {
+        let mut balance = Money::ZERO;
+        for _ in 0..1000 {
+            balance += "0.1".parse().unwrap();
+        }
+        assert_eq!(balance, Money::from(100));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader("0.1234,\n".as_bytes());
+        let (amount, missing): (Money, Option<Money>) =
+            reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(amount, Money::new(1234, 4));
+        assert_eq!(missing, None);
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize((amount, Money::ZERO)).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "0.1234,0\n");
+    }
+
+    #[test]
+    fn format() {
+        let half_up = MoneyFormat::default();
+        let bankers = MoneyFormat {
+            decimal_places: 4,
+            rounding: RoundingMode::Bankers,
+        };
+        let two_places = MoneyFormat {
+            decimal_places: 2,
+            ..MoneyFormat::default()
+        };
+
+        assert_eq!(Money::from(1).format(&half_up), "1.0000");
+        assert_eq!(Money::new(123456, 5).format(&half_up), "1.2346");
+        assert_eq!(Money::new(5, 5).format(&half_up), "0.0001");
+        assert_eq!(Money::new(5, 5).format(&bankers), "0.0000");
+        assert_eq!(Money::new(15, 5).format(&bankers), "0.0002");
+        assert_eq!(Money::new(-5, 5).format(&half_up), "-0.0001");
+        assert_eq!(Money::new(1005, 3).format(&two_places), "1.01");
+    }
+
+    #[test]
+    fn parse_rounding_mode() {
+        assert_eq!("half-up".parse(), Ok(RoundingMode::HalfUp));
+        assert_eq!("bankers".parse(), Ok(RoundingMode::Bankers));
+        assert!("up".parse::<RoundingMode>().is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::store::AccountUpdate;
+use async_nats::{Client, ConnectErrorKind, ConnectOptions};
+use std::io;
+use tokio::sync::mpsc;
+
+/// Name the server lists the connection under.
+const CLIENT_NAME: &str = "transaction_system";
+
+/// Reconnects to a server that went away before the events still queued count as lost.
+const MAX_RECONNECTS: usize = 5;
+
+/// Connection to a NATS server publishing events as JSON messages to one subject.
+pub struct NatsPublisher {
+    client: Client,
+    subject: String,
+}
+
+impl NatsPublisher {
+    /// Connects to the NATS server at `address`, e.g. `127.0.0.1:4222`.
+    pub async fn connect(address: &str, subject: &str) -> io::Result<Self> {
+        if subject.is_empty() || subject.contains(char::is_whitespace) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid subject {:?}", subject),
+            ));
+        }
+        let client = ConnectOptions::new()
+            .name(CLIENT_NAME)
+            .max_reconnects(MAX_RECONNECTS)
+            .connect(address)
+            .await
+            .map_err(|e| {
+                // Servers that can't be reached yet are worth trying again
+                let kind = match e.kind() {
+                    ConnectErrorKind::Io => io::ErrorKind::ConnectionRefused,
+                    ConnectErrorKind::TimedOut => io::ErrorKind::TimedOut,
+                    _ => io::ErrorKind::Other,
+                };
+                io::Error::new(kind, e)
+            })?;
+        Ok(Self {
+            client,
+            subject: subject.to_string(),
+        })
+    }
+
+    /// Publishes events until the [`EventStore`](crate::store::EventStore) sending them is
+    /// dropped, then waits until the server has received all of them.
+    pub async fn run(self, mut events: mpsc::UnboundedReceiver<AccountUpdate>) -> io::Result<()> {
+        while let Some(event) = events.recv().await {
+            let payload = serde_json::to_vec(&event)?;
+            self.client
+                .publish(self.subject.clone(), payload.into())
+                .await
+                .map_err(io::Error::other)?;
+        }
+        self.client.flush().await.map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NatsPublisher;
+    use crate::store::{EventStore, MemoryStore};
+    use crate::{Engine, EngineConfig, Money, Transaction, TransactionType};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn publish_events() {
+        // Just enough of a NATS server to collect what gets published
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer
+                .write_all(b"INFO {\"max_payload\":1048576}\r\n")
+                .await
+                .unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            let (mut published, mut control) = (Vec::new(), Vec::new());
+            while let Some(line) = lines.next_line().await.unwrap() {
+                match line.split_whitespace().next() {
+                    Some("PUB") => published.push(lines.next_line().await.unwrap().unwrap()),
+                    Some("PING") => writer.write_all(b"PONG\r\n").await.unwrap(),
+                    _ => control.push(line),
+                }
+            }
+            (published, control)
+        });
+
+        let publisher = NatsPublisher::connect(&address, "accounts").await.unwrap();
+        let mut store = EventStore::new(MemoryStore::new(), Default::default());
+        let events = store.subscribe();
+        let published = tokio::spawn(publisher.run(events));
+        let mut engine = Engine::with_store(EngineConfig::default(), Arc::new(store));
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(9))),
+            Transaction::new(TransactionType::Deposit, 2, 1, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            let _ = engine.process(transaction).await;
+        }
+        drop(engine);
+        published.await.unwrap().unwrap();
+
+        let (published, control) = server.await.unwrap();
+        assert!(control[0].starts_with("CONNECT "));
+        assert!(control[0].contains(r#""name":"transaction_system""#));
+        let events = published
+            .iter()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                json!({
+                    "client": 1,
+                    "tx": 1,
+                    "dispute_state": "none",
+                    "locked": false,
+                    "balances": [
+                        { "client": 1, "available": "5.0000", "held": "0.0000", "total": "5.0000", "locked": false }
+                    ]
+                }),
+                json!({
+                    "client": 1,
+                    "tx": 2,
+                    "error": {
+                        "code": 304,
+                        "reason": "insufficient_amount",
+                        "message": "Not enough available funds"
+                    },
+                    "locked": false,
+                    "balances": [
+                        { "client": 1, "available": "5.0000", "held": "0.0000", "total": "5.0000", "locked": false }
+                    ]
+                }),
+                json!({
+                    "client": 2,
+                    "tx": 1,
+                    "error": {
+                        "code": 200,
+                        "reason": "duplicate_transaction_id",
+                        "message": "Transaction id 1 was seen before"
+                    }
+                }),
+            ]
+        );
+    }
+}
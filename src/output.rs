@@ -0,0 +1,538 @@
+use crate::account::{Account, AccountRecord};
+use crate::money::MoneyFormat;
+use crate::parquet::{self, Column, Values};
+use crate::partition::{Partition, PARTITION_COLUMN};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io;
+use std::str::FromStr;
+
+/// Layout of the account report.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    /// A single JSON array of account objects
+    Json,
+    /// One account object per line
+    Jsonl,
+    /// A Parquet file with decimal typed balances
+    Parquet,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            "jsonl" | "ndjson" => Ok(ReportFormat::Jsonl),
+            "parquet" => Ok(ReportFormat::Parquet),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Order of the rows of the account report.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportOrder {
+    /// Ascending by client and, in multi-currency reports, by currency, with the default
+    /// currency first
+    #[default]
+    Client,
+}
+
+impl FromStr for ReportOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(ReportOrder::Client),
+            _ => Err(format!("Unknown sort order {}, expected client", s)),
+        }
+    }
+}
+
+/// Trailing status columns of the account report. They follow from the configuration
+/// rather than from the accounts, so every report of a setup has the same header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatusColumns {
+    /// `closed`, for setups accounts can be closed in
+    pub closed: bool,
+    /// `overdrawn`, for setups with overdrafts
+    pub overdrawn: bool,
+}
+
+/// Report rows of the accounts, one per account or, as soon as any account holds more than
+/// the default currency, one per account and currency. Rows get the fields of the `status`
+/// columns. Rows are in [`ReportOrder::Client`] order, whatever order the accounts come in.
+/// Returns the rows together with the report's columns.
+fn report_records<'a>(
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+) -> (Vec<AccountRecord>, Vec<&'static str>) {
+    let mut accounts = accounts.into_iter().collect::<Vec<_>>();
+    // Accounts keep their currencies sorted, so sorting accounts sorts the rows
+    accounts.sort_by_key(|account| account.client());
+    let multi_currency = accounts.iter().any(|account| account.is_multi_currency());
+    let records = accounts
+        .into_iter()
+        .flat_map(|account| {
+            let records = match multi_currency {
+                true => account.records(format),
+                false => vec![account.record(format)],
+            };
+            records.into_iter().map(move |mut record| {
+                record.closed = status.closed.then(|| account.closed());
+                record.overdrawn = status.overdrawn.then(|| record.overdrawn.unwrap_or(false));
+                record
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut columns = vec!["client", "available", "held", "total", "locked"];
+    if multi_currency {
+        columns.insert(1, "currency");
+    }
+    if status.closed {
+        columns.push("closed");
+    }
+    if status.overdrawn {
+        columns.push("overdrawn");
+    }
+    (records, columns)
+}
+
+/// Writes accounts as a csv report to any writer, e.g. a file, a socket or a `Vec<u8>`.
+///
+/// Rows are sorted by client, see [`ReportOrder`]. The header is written even when there
+/// are no accounts. A `currency` column is added only when some account holds funds in a
+/// currency other than the default one, `closed` and `overdrawn` columns as `status` asks.
+pub fn write_accounts<'a, W: io::Write>(
+    writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+) -> Result<(), csv::Error> {
+    let (records, columns) = report_records(accounts, format, status);
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record(columns)?;
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Same as [`write_accounts`] with every row tagged by a leading `partition` column.
+pub fn write_partitioned_accounts<'a, W: io::Write>(
+    writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+    partition: Partition,
+) -> Result<(), csv::Error> {
+    let (records, columns) = report_records(accounts, format, status);
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record(std::iter::once(PARTITION_COLUMN).chain(columns))?;
+    for record in records {
+        writer.serialize((partition.to_string(), record))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Account object of the JSON reports, carrying the partition only when there is one.
+#[derive(Serialize)]
+struct JsonRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partition: Option<String>,
+    #[serde(flatten)]
+    record: AccountRecord,
+}
+
+fn json_records<'a>(
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+    partition: Option<Partition>,
+) -> impl Iterator<Item = JsonRecord> {
+    let (records, _) = report_records(accounts, format, status);
+    records.into_iter().map(move |record| JsonRecord {
+        partition: partition.map(|p| p.to_string()),
+        record,
+    })
+}
+
+/// Writes accounts as a JSON array with the same fields as the csv report. Balances are
+/// strings so that their precision survives JSON parsers working with floats.
+pub fn write_json_accounts<'a, W: io::Write>(
+    mut writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+    partition: Option<Partition>,
+) -> Result<(), serde_json::Error> {
+    writer.write_all(b"[").map_err(serde_json::Error::io)?;
+    for (i, record) in json_records(accounts, format, status, partition).enumerate() {
+        if i > 0 {
+            writer.write_all(b",").map_err(serde_json::Error::io)?;
+        }
+        serde_json::to_writer(&mut writer, &record)?;
+    }
+    writer.write_all(b"]\n").map_err(serde_json::Error::io)?;
+    writer.flush().map_err(serde_json::Error::io)
+}
+
+/// Same as [`write_json_accounts`], but with one account object per line.
+pub fn write_jsonl_accounts<'a, W: io::Write>(
+    mut writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+    partition: Option<Partition>,
+) -> Result<(), serde_json::Error> {
+    for record in json_records(accounts, format, status, partition) {
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+    }
+    writer.flush().map_err(serde_json::Error::io)
+}
+
+/// Writes accounts as a Parquet file with the same columns as the csv report, led by the
+/// partition when there is one. Balances are decimals with the format's decimal places and
+/// the currency is null for the default currency.
+pub fn write_parquet_accounts<'a, W: io::Write>(
+    writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+    format: &MoneyFormat,
+    status: StatusColumns,
+    partition: Option<Partition>,
+) -> io::Result<()> {
+    let (records, names) = report_records(accounts, format, status);
+    let places = format.decimal_places;
+    // Balances were just formatted, so they parse back exactly
+    let amount = |amount: &String| Some(amount.parse::<Decimal>().expect("Formatted amount"));
+    let mut columns = Vec::new();
+    if let Some(partition) = partition {
+        let values = records.iter().map(|_| Some(partition.to_string()));
+        columns.push(Column::required(
+            PARTITION_COLUMN,
+            Values::Text(values.collect()),
+        ));
+    }
+    for name in names {
+        let rows = records.iter();
+        let column = match name {
+            "client" => {
+                Column::required(name, Values::UInt16(rows.map(|r| Some(r.client)).collect()))
+            }
+            "currency" => {
+                let currencies = rows.map(|r| r.currency.clone().filter(|c| !c.is_empty()));
+                Column::optional(name, Values::Text(currencies.collect()))
+            }
+            "available" => Column::required(
+                name,
+                Values::Decimal(rows.map(|r| amount(&r.available)).collect(), places),
+            ),
+            "held" => Column::required(
+                name,
+                Values::Decimal(rows.map(|r| amount(&r.held)).collect(), places),
+            ),
+            "total" => Column::required(
+                name,
+                Values::Decimal(rows.map(|r| amount(&r.total)).collect(), places),
+            ),
+            "locked" => Column::required(
+                name,
+                Values::Boolean(rows.map(|r| Some(r.locked)).collect()),
+            ),
+            "closed" => Column::required(name, Values::Boolean(rows.map(|r| r.closed).collect())),
+            "overdrawn" => {
+                Column::required(name, Values::Boolean(rows.map(|r| r.overdrawn).collect()))
+            }
+            _ => unreachable!("Unknown report column {}", name),
+        };
+        columns.push(column);
+    }
+    parquet::write(writer, &columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        write_accounts, write_json_accounts, write_jsonl_accounts, write_parquet_accounts,
+        write_partitioned_accounts, ReportFormat, StatusColumns,
+    };
+    use crate::money::MoneyFormat;
+    use crate::parquet::{self, Column, Values};
+    use crate::partition::Partition;
+    use crate::{Account, Money, Transaction, TransactionType};
+    use ::parquet::file::reader::FileReader;
+    use ::parquet::file::serialized_reader::SerializedFileReader;
+    use bytes::Bytes;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn write_to_buffer() {
+        // Rows come out sorted by client whatever order the accounts are in
+        let accounts = [Account::new(2), Account::new(1)];
+
+        let mut buffer = Vec::new();
+        write_accounts(
+            &mut buffer,
+            &accounts,
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n2,0.0000,0.0000,0.0000,false\n"
+        );
+
+        let mut buffer = Vec::new();
+        write_accounts(
+            &mut buffer,
+            &[],
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked\n"
+        );
+    }
+
+    #[test]
+    fn write_partitioned() {
+        let mut buffer = Vec::new();
+        write_partitioned_accounts(
+            &mut buffer,
+            &[Account::new(3)],
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+            Partition::new(0, 9).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "partition,client,available,held,total,locked\n0-9,3,0.0000,0.0000,0.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn write_json() {
+        let accounts = [Account::new(1), Account::new(2)];
+
+        let mut buffer = Vec::new();
+        write_json_accounts(
+            &mut buffer,
+            &accounts,
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "[{\"client\":1,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false},\
+             {\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}]\n"
+        );
+
+        let mut buffer = Vec::new();
+        write_json_accounts(
+            &mut buffer,
+            &[],
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "[]\n");
+
+        let mut buffer = Vec::new();
+        write_jsonl_accounts(
+            &mut buffer,
+            &accounts,
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+            Partition::new(0, 9),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "{\"partition\":\"0-9\",\"client\":1,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}\n\
+             {\"partition\":\"0-9\",\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}\n"
+        );
+
+        assert_eq!("json".parse(), Ok(ReportFormat::Json));
+        assert_eq!("jsonl".parse(), Ok(ReportFormat::Jsonl));
+        assert_eq!("parquet".parse(), Ok(ReportFormat::Parquet));
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+
+    #[test]
+    fn write_currencies() {
+        let mut multi = Account::new(1);
+        for (tx, currency) in [(1, None), (2, Some("usd")), (3, Some("eur"))] {
+            let mut deposit =
+                Transaction::new(TransactionType::Deposit, 1, tx, Some(Money::from(1)));
+            if let Some(currency) = currency {
+                deposit = deposit.with_currency(currency.parse().unwrap());
+            }
+            multi.add_transaction(deposit);
+            multi.process_pending_transaction().unwrap();
+        }
+        let accounts = [multi, Account::new(2)];
+
+        let mut buffer = Vec::new();
+        write_accounts(
+            &mut buffer,
+            &accounts,
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,,1.0000,0.0000,1.0000,false\n\
+             1,EUR,1.0000,0.0000,1.0000,false\n\
+             1,USD,1.0000,0.0000,1.0000,false\n\
+             2,,0.0000,0.0000,0.0000,false\n"
+        );
+
+        let mut buffer = Vec::new();
+        write_jsonl_accounts(
+            &mut buffer,
+            &accounts[1..],
+            &MoneyFormat::default(),
+            StatusColumns::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "{\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn write_parquet() {
+        let mut multi = Account::new(1);
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(2)));
+        multi.add_transaction(deposit.with_currency("eur".parse().unwrap()));
+        multi.add_transaction(Transaction::new(
+            TransactionType::Deposit,
+            1,
+            2,
+            Some(Money::from(1)),
+        ));
+        multi.process_pending_transaction().unwrap();
+        multi.process_pending_transaction().unwrap();
+        let accounts = [multi];
+        let format = MoneyFormat {
+            decimal_places: 2,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write_parquet_accounts(
+            &mut buffer,
+            &accounts,
+            &format,
+            StatusColumns::default(),
+            Partition::new(0, 9),
+        )
+        .unwrap();
+        let zero = Some(Decimal::ZERO);
+        let (one, two) = (Some(Decimal::from(1)), Some(Decimal::from(2)));
+        let columns = [
+            Column::required("partition", Values::Text(vec![Some("0-9".into()); 2])),
+            Column::required("client", Values::UInt16(vec![Some(1); 2])),
+            Column::optional("currency", Values::Text(vec![None, Some("EUR".into())])),
+            Column::required("available", Values::Decimal(vec![one, two], 2)),
+            Column::required("held", Values::Decimal(vec![zero, zero], 2)),
+            Column::required("total", Values::Decimal(vec![one, two], 2)),
+            Column::required("locked", Values::Boolean(vec![Some(false); 2])),
+        ];
+        let mut expected = Vec::new();
+        parquet::write(&mut expected, &columns).unwrap();
+        assert_eq!(buffer, expected);
+
+        // Parquet readers take the file as written
+        let reader = SerializedFileReader::new(Bytes::from(buffer)).unwrap();
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                "{partition: \"0-9\", client: 1, currency: null, available: 1.00, held: 0.00, \
+                 total: 1.00, locked: false}",
+                "{partition: \"0-9\", client: 1, currency: \"EUR\", available: 2.00, \
+                 held: 0.00, total: 2.00, locked: false}",
+            ]
+        );
+    }
+
+    #[test]
+    fn write_status() {
+        let mut closed = Account::new(1);
+        closed.add_transaction(Transaction::new(TransactionType::Close, 1, 1, None));
+        closed.process_pending_transaction().unwrap();
+        let accounts = [closed, Account::new(2)];
+        let status = StatusColumns {
+            closed: true,
+            overdrawn: true,
+        };
+
+        let mut buffer = Vec::new();
+        write_accounts(&mut buffer, &accounts, &MoneyFormat::default(), status).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked,closed,overdrawn\n\
+             1,0.0000,0.0000,0.0000,false,true,false\n\
+             2,0.0000,0.0000,0.0000,false,false,false\n"
+        );
+
+        // Columns don't follow the accounts, a setup without them has none
+        let mut buffer = Vec::new();
+        let without = StatusColumns::default();
+        write_accounts(&mut buffer, &accounts, &MoneyFormat::default(), without).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked\n\
+             1,0.0000,0.0000,0.0000,false\n\
+             2,0.0000,0.0000,0.0000,false\n"
+        );
+
+        let mut buffer = Vec::new();
+        let status = StatusColumns {
+            closed: true,
+            overdrawn: false,
+        };
+        write_jsonl_accounts(
+            &mut buffer,
+            &accounts[..1],
+            &MoneyFormat::default(),
+            status,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "{\"client\":1,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false,\"closed\":true}\n"
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use ::parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use ::parquet::column::writer::ColumnWriter;
+use ::parquet::data_type::{ByteArray, FixedLenByteArray};
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::SerializedFileWriter;
+use ::parquet::schema::types::Type;
+use rust_decimal::Decimal;
+use std::io;
+use std::sync::Arc;
+
+/// Bytes of a decimal, a big-endian two's complement i128.
+const DECIMAL_BYTES: i32 = 16;
+/// Digits an i128 always holds.
+const DECIMAL_PRECISION: i32 = 38;
+
+/// Values of a [`Column`], `None` being null.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Values {
+    UInt16(Vec<Option<u16>>),
+    UInt32(Vec<Option<u32>>),
+    Boolean(Vec<Option<bool>>),
+    Text(Vec<Option<String>>),
+    /// Decimals written with the given number of decimal places
+    Decimal(Vec<Option<Decimal>>, u32),
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Values::UInt16(values) => values.len(),
+            Values::UInt32(values) => values.len(),
+            Values::Boolean(values) => values.len(),
+            Values::Text(values) => values.len(),
+            Values::Decimal(values, _) => values.len(),
+        }
+    }
+
+    fn is_null(&self, i: usize) -> bool {
+        match self {
+            Values::UInt16(values) => values[i].is_none(),
+            Values::UInt32(values) => values[i].is_none(),
+            Values::Boolean(values) => values[i].is_none(),
+            Values::Text(values) => values[i].is_none(),
+            Values::Decimal(values, _) => values[i].is_none(),
+        }
+    }
+}
+
+/// Named column of a Parquet file, see [`write`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    name: String,
+    optional: bool,
+    values: Values,
+}
+
+impl Column {
+    /// Column every row has a value in.
+    pub fn required(name: impl Into<String>, values: Values) -> Self {
+        Self {
+            name: name.into(),
+            optional: false,
+            values,
+        }
+    }
+
+    /// Column that may hold nulls.
+    pub fn optional(name: impl Into<String>, values: Values) -> Self {
+        Self {
+            name: name.into(),
+            optional: true,
+            values,
+        }
+    }
+
+    fn schema(&self) -> io::Result<Type> {
+        let (physical, logical) = match self.values {
+            Values::UInt16(_) => (PhysicalType::INT32, Some(unsigned(16))),
+            Values::UInt32(_) => (PhysicalType::INT32, Some(unsigned(32))),
+            Values::Boolean(_) => (PhysicalType::BOOLEAN, None),
+            Values::Text(_) => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+            Values::Decimal(_, scale) => (
+                PhysicalType::FIXED_LEN_BYTE_ARRAY,
+                Some(LogicalType::Decimal {
+                    scale: scale as i32,
+                    precision: DECIMAL_PRECISION,
+                }),
+            ),
+        };
+        let mut builder = Type::primitive_type_builder(&self.name, physical)
+            .with_repetition(match self.optional {
+                true => Repetition::OPTIONAL,
+                false => Repetition::REQUIRED,
+            })
+            .with_logical_type(logical);
+        if let Values::Decimal(_, scale) = self.values {
+            builder = builder
+                .with_length(DECIMAL_BYTES)
+                .with_precision(DECIMAL_PRECISION)
+                .with_scale(scale as i32);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Writes the column's values, with their definition levels when it's optional.
+    fn write(&self, writer: &mut ColumnWriter) -> io::Result<()> {
+        let levels = self.optional.then(|| {
+            (0..self.values.len())
+                .map(|i| i16::from(!self.values.is_null(i)))
+                .collect::<Vec<_>>()
+        });
+        let levels = levels.as_deref();
+        match (&self.values, writer) {
+            (Values::UInt16(values), ColumnWriter::Int32ColumnWriter(writer)) => {
+                let values = values.iter().flatten().map(|v| i32::from(*v));
+                writer.write_batch(&values.collect::<Vec<_>>(), levels, None)?;
+            }
+            (Values::UInt32(values), ColumnWriter::Int32ColumnWriter(writer)) => {
+                // Unsigned 32 bit integers are stored with the bits of an INT32
+                let values = values.iter().flatten().map(|v| *v as i32);
+                writer.write_batch(&values.collect::<Vec<_>>(), levels, None)?;
+            }
+            (Values::Boolean(values), ColumnWriter::BoolColumnWriter(writer)) => {
+                let values = values.iter().flatten().copied();
+                writer.write_batch(&values.collect::<Vec<_>>(), levels, None)?;
+            }
+            (Values::Text(values), ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                let values = values.iter().flatten().map(|v| ByteArray::from(v.as_str()));
+                writer.write_batch(&values.collect::<Vec<_>>(), levels, None)?;
+            }
+            (
+                Values::Decimal(values, scale),
+                ColumnWriter::FixedLenByteArrayColumnWriter(writer),
+            ) => {
+                let values = values
+                    .iter()
+                    .flatten()
+                    .map(|value| decimal(&self.name, *value, *scale))
+                    .collect::<io::Result<Vec<_>>>()?;
+                writer.write_batch(&values, levels, None)?;
+            }
+            _ => unreachable!(
+                "Column {} written with the writer of another type",
+                self.name
+            ),
+        }
+        Ok(())
+    }
+}
+
+fn unsigned(bit_width: i8) -> LogicalType {
+    LogicalType::Integer {
+        bit_width,
+        is_signed: false,
+    }
+}
+
+/// The decimal with `scale` decimal places, failing rather than rounding it.
+fn decimal(column: &str, value: Decimal, scale: u32) -> io::Result<FixedLenByteArray> {
+    let mut scaled = value;
+    scaled.rescale(scale);
+    if scaled.scale() != scale || scaled != value {
+        return Err(invalid(format!(
+            "{} of column {} has more than {} decimal places",
+            value, column, scale
+        )));
+    }
+    Ok(ByteArray::from(scaled.mantissa().to_be_bytes().to_vec()).into())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Writes the columns as a Parquet file of a single row group with the
+/// [`parquet`](https://docs.rs/parquet) crate. Unsigned integers are INT32s annotated
+/// unsigned, text is UTF8 annotated BYTE_ARRAYs and decimals are DECIMAL annotated 16 byte
+/// FIXED_LEN_BYTE_ARRAYs of precision 38, so analytics tools read amounts exactly.
+///
+/// Fails if the columns don't have the same number of rows, a required one has nulls or a
+/// decimal has more decimal places than its column.
+pub fn write(mut writer: impl io::Write, columns: &[Column]) -> io::Result<()> {
+    let rows = columns.first().map_or(0, |column| column.values.len());
+    for column in columns {
+        if column.values.len() != rows {
+            return Err(invalid(format!("Column {} is short", column.name)));
+        }
+        if !column.optional && (0..rows).any(|i| column.values.is_null(i)) {
+            return Err(invalid(format!(
+                "Required column {} has nulls",
+                column.name
+            )));
+        }
+    }
+
+    let fields = columns
+        .iter()
+        .map(|column| column.schema().map(Arc::new))
+        .collect::<io::Result<Vec<_>>>()?;
+    let schema = Type::group_type_builder("schema")
+        .with_fields(fields)
+        .build()?;
+    let properties = WriterProperties::builder()
+        .set_created_by(concat!("transaction_system version ", env!("CARGO_PKG_VERSION")).into())
+        .build();
+    // The file is put together in memory, as the writer has to be Send
+    let mut file = SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = file.next_row_group()?;
+    for column in columns {
+        let mut column_writer = row_group
+            .next_column()?
+            .expect("Schema has a column for every column");
+        column.write(column_writer.untyped())?;
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.write_all(&file.into_inner()?)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write, Column, Values};
+    use ::parquet::file::reader::FileReader;
+    use ::parquet::file::serialized_reader::SerializedFileReader;
+    use bytes::Bytes;
+    use rust_decimal::Decimal;
+    use std::io;
+
+    #[test]
+    fn columns() {
+        let columns = [
+            Column::required("client", Values::UInt16(vec![Some(1), Some(2)])),
+            Column::required("tx", Values::UInt32(vec![Some(u32::MAX), Some(2)])),
+            Column::optional("currency", Values::Text(vec![Some("EUR".into()), None])),
+            Column::optional(
+                "amount",
+                Values::Decimal(vec![None, Some(Decimal::new(-15, 1))], 2),
+            ),
+            Column::required("locked", Values::Boolean(vec![Some(false), Some(true)])),
+        ];
+        let mut file = Vec::new();
+        write(&mut file, &columns).unwrap();
+
+        let reader = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                "{client: 1, tx: 4294967295, currency: \"EUR\", amount: null, locked: false}",
+                "{client: 2, tx: 2, currency: null, amount: -1.50, locked: true}",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_columns() {
+        let error = |columns: &[Column]| write(io::sink(), columns).unwrap_err().to_string();
+        let client = Column::required("client", Values::UInt16(vec![Some(1), Some(2)]));
+
+        let short = Column::required("tx", Values::UInt32(vec![Some(1)]));
+        assert_eq!(error(&[client.clone(), short]), "Column tx is short");
+        let nulls = Column::required("tx", Values::UInt32(vec![Some(1), None]));
+        assert_eq!(
+            error(&[client.clone(), nulls]),
+            "Required column tx has nulls"
+        );
+        // Amounts aren't rounded to fit their column
+        let amounts = vec![Some(Decimal::ONE), Some(Decimal::new(1005, 3))];
+        let amount = Column::required("amount", Values::Decimal(amounts, 2));
+        assert_eq!(
+            error(&[client, amount]),
+            "1.005 of column amount has more than 2 decimal places"
+        );
+    }
+}
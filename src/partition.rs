@@ -0,0 +1,410 @@
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::transaction::{Transaction, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+const CURRENCY_COLUMN: &str = "currency";
+
+/// Trailing status columns reports carry when their instance is set up for them, in order.
+const STATUS_COLUMNS: [&str; 2] = ["closed", "overdrawn"];
+
+pub const PARTITION_COLUMN: &str = "partition";
+
+/// Inclusive range of client ids handled by a single instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    first: u16,
+    last: u16,
+}
+
+impl Partition {
+    pub fn new(first: u16, last: u16) -> Option<Self> {
+        if first <= last {
+            Some(Self { first, last })
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, client: u16) -> bool {
+        self.first <= client && client <= self.last
+    }
+
+    pub fn overlaps(&self, other: &Partition) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+impl FromStr for Partition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid partition {}, expected <first>-<last>", s);
+        let (first, last) = s.split_once('-').ok_or_else(invalid)?;
+        let first = first.trim().parse::<u16>().map_err(|_| invalid())?;
+        let last = last.trim().parse::<u16>().map_err(|_| invalid())?;
+
+        Partition::new(first, last).ok_or_else(invalid)
+    }
+}
+
+/// Step of settling a transfer between clients of different partitions.
+///
+/// The sender's partition takes the amount from the sender and sends a prepare. The
+/// destination's partition credits it and replies with a commit, or replies with an abort
+/// when the destination account refuses it, upon which the sender gets the amount back.
+/// Until then the amount is in transit, on the `transfer` account of the books.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStep {
+    Prepare,
+    Commit,
+    Abort,
+}
+
+/// Message between partitions settling a transfer, see [`crate::Engine::settle`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferMessage {
+    pub step: TransferStep,
+    pub tx: u32,
+    pub from_client: u16,
+    pub to_client: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub amount: Money,
+    /// Why the destination refused the transfer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl TransferMessage {
+    /// Prepare of the transfer, which has to name its destination and amount.
+    pub(crate) fn prepare(transaction: &Transaction) -> Option<Self> {
+        Some(Self {
+            step: TransferStep::Prepare,
+            tx: transaction.tx,
+            from_client: transaction.client,
+            to_client: transaction.to_client?,
+            currency: transaction.currency.clone(),
+            amount: transaction.amount?,
+            reason: None,
+        })
+    }
+
+    /// Answer of the destination's partition to a prepare.
+    pub(crate) fn reply(&self, step: TransferStep, reason: Option<String>) -> Self {
+        Self {
+            step,
+            reason,
+            ..self.clone()
+        }
+    }
+
+    /// Client whose partition acts on the message: the destination of a prepare and the
+    /// sender of a commit or abort.
+    pub fn recipient(&self) -> u16 {
+        match self.step {
+            TransferStep::Prepare => self.to_client,
+            TransferStep::Commit | TransferStep::Abort => self.from_client,
+        }
+    }
+
+    /// The transfer the message settles.
+    pub fn transaction(&self) -> Transaction {
+        let transaction = Transaction::new(
+            TransactionType::Transfer,
+            self.from_client,
+            self.tx,
+            Some(self.amount),
+        )
+        .with_to_client(self.to_client);
+        match &self.currency {
+            Some(currency) => transaction.with_currency(currency.clone()),
+            None => transaction,
+        }
+    }
+}
+
+/// Reads transfer messages written by [`write_transfer_messages`], one JSON object per line.
+pub fn read_transfer_messages(
+    reader: impl BufRead,
+) -> Result<Vec<TransferMessage>, Box<dyn Error>> {
+    let mut messages = Vec::new();
+    for (line, text) in reader.lines().enumerate() {
+        let text = text?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let message = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid transfer message on line {}: {}", line + 1, e))?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+pub fn write_transfer_messages(
+    mut writer: impl io::Write,
+    messages: &[TransferMessage],
+) -> io::Result<()> {
+    for message in messages {
+        serde_json::to_writer(&mut writer, message)?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+/// Merges partition tagged account reports into a single untagged report.
+///
+/// Fails when partitions overlap, when a client is reported outside of its partition
+/// or when the same client (and currency) shows up more than once.
+pub fn merge_reports(paths: &[String], writer: impl io::Write) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut partitions = Vec::<Partition>::new();
+    let mut clients = HashSet::<(String, String)>::new();
+
+    let mut reports = Vec::new();
+    for path in paths {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers = reader.headers()?.clone();
+        if headers.get(0) != Some(PARTITION_COLUMN) || headers.get(1) != Some("client") {
+            return Err(format!("{} is not a partitioned account report", path).into());
+        }
+        // Position of every status column in this report
+        let status = STATUS_COLUMNS.map(|column| headers.iter().position(|h| h == column));
+        reports.push((
+            path,
+            headers.get(2) == Some(CURRENCY_COLUMN),
+            status,
+            reader,
+        ));
+    }
+
+    // Reports of single currency instances have no currency column, the merged report gets
+    // one (empty for their rows) as soon as any of the instances saw other currencies.
+    // Likewise for the status columns, which are false for rows of reports without them.
+    let multi_currency = reports.iter().any(|(_, has_currency, _, _)| *has_currency);
+    let merged_status = STATUS_COLUMNS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| reports.iter().any(|(_, _, status, _)| status[*i].is_some()))
+        .collect::<Vec<_>>();
+    let mut headers_written = false;
+
+    for (path, has_currency, status, mut reader) in reports {
+        // Columns following partition, client and currency up to the status columns are
+        // copied as they are
+        let balances = if has_currency { 3 } else { 2 };
+        let end = status.iter().flatten().min().copied();
+        if !headers_written {
+            let headers = reader.headers()?.clone();
+            let mut merged = csv::StringRecord::new();
+            merged.push_field("client");
+            if multi_currency {
+                merged.push_field(CURRENCY_COLUMN);
+            }
+            let columns = end.unwrap_or(headers.len()) - balances;
+            merged.extend(headers.iter().skip(balances).take(columns));
+            merged.extend(merged_status.iter().map(|(_, column)| **column));
+            writer.write_record(&merged)?;
+            headers_written = true;
+        }
+
+        let mut partition = None;
+        for record in reader.records() {
+            let record = record?;
+            let tag = record[0].parse::<Partition>()?;
+            match partition {
+                None => {
+                    if let Some(other) = partitions.iter().find(|p| p.overlaps(&tag)) {
+                        return Err(format!("Partition {} overlaps {}", tag, other).into());
+                    }
+                    partitions.push(tag);
+                    partition = Some(tag);
+                }
+                Some(p) if p != tag => {
+                    return Err(format!("{} mixes partitions {} and {}", path, p, tag).into());
+                }
+                Some(_) => {}
+            }
+
+            let client = &record[1];
+            if !client.parse::<u16>().is_ok_and(|c| tag.contains(c)) {
+                return Err(format!("Client {} is outside of partition {}", client, tag).into());
+            }
+            let currency = if has_currency { &record[2] } else { "" };
+            if !clients.insert((client.to_string(), currency.to_string())) {
+                return Err(format!("Client {} is reported more than once", client).into());
+            }
+
+            let mut merged = csv::StringRecord::new();
+            merged.push_field(client);
+            if multi_currency {
+                merged.push_field(currency);
+            }
+            let columns = end.unwrap_or(record.len()) - balances;
+            merged.extend(record.iter().skip(balances).take(columns));
+            for (i, _) in &merged_status {
+                merged.push_field(status[*i].map_or("false", |position| &record[position]));
+            }
+            writer.write_record(&merged)?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_reports, Partition};
+    use std::io::Write;
+
+    #[test]
+    fn parse_partition() {
+        let partition = "10-20".parse::<Partition>().unwrap();
+        assert_eq!(partition, Partition::new(10, 20).unwrap());
+        assert_eq!(partition.to_string(), "10-20");
+        assert!(partition.contains(10));
+        assert!(partition.contains(20));
+        assert!(!partition.contains(21));
+
+        assert!("20-10".parse::<Partition>().is_err());
+        assert!("10".parse::<Partition>().is_err());
+        assert!("a-b".parse::<Partition>().is_err());
+    }
+
+    #[test]
+    fn overlapping_partitions() {
+        let partition = Partition::new(10, 20).unwrap();
+        assert!(partition.overlaps(&Partition::new(20, 30).unwrap()));
+        assert!(partition.overlaps(&Partition::new(0, 10).unwrap()));
+        assert!(!partition.overlaps(&Partition::new(21, 30).unwrap()));
+    }
+
+    fn report(name: &str, content: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("partition_{}_{}.csv", std::process::id(), name));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn merge() {
+        const HEADER: &str = "partition,client,available,held,total,locked\n";
+        let low = report(
+            "low",
+            &format!("{}0-9,1,1.0000,0.0000,1.0000,false\n", HEADER),
+        );
+        let high = report(
+            "high",
+            &format!("{}10-19,12,2.0000,0.0000,2.0000,true\n", HEADER),
+        );
+        let overlapping = report(
+            "overlapping",
+            &format!("{}5-15,6,1.0000,0.0000,1.0000,false\n", HEADER),
+        );
+        let outside = report(
+            "outside",
+            &format!("{}20-29,1,1.0000,0.0000,1.0000,false\n", HEADER),
+        );
+
+        let mut output = Vec::new();
+        merge_reports(&[low.clone(), high.clone()], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n12,2.0000,0.0000,2.0000,true\n"
+        );
+
+        assert!(merge_reports(&[low.clone(), overlapping], Vec::new()).is_err());
+        assert!(merge_reports(&[outside], Vec::new()).is_err());
+        assert!(merge_reports(&[low.clone(), low], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn merge_currencies() {
+        let single = report(
+            "single",
+            "partition,client,available,held,total,locked\n0-9,1,1.0000,0.0000,1.0000,false\n",
+        );
+        let multi = report(
+            "multi",
+            "partition,client,currency,available,held,total,locked\n\
+             10-19,12,,2.0000,0.0000,2.0000,false\n\
+             10-19,12,EUR,3.0000,0.0000,3.0000,false\n",
+        );
+        let mut output = Vec::new();
+        merge_reports(&[single, multi.clone()], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,,1.0000,0.0000,1.0000,false\n\
+             12,,2.0000,0.0000,2.0000,false\n\
+             12,EUR,3.0000,0.0000,3.0000,false\n"
+        );
+
+        let duplicate = report(
+            "duplicate",
+            "partition,client,currency,available,held,total,locked\n\
+             20-29,22,EUR,2.0000,0.0000,2.0000,false\n\
+             20-29,22,EUR,3.0000,0.0000,3.0000,false\n",
+        );
+        assert!(merge_reports(&[multi, duplicate], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn merge_status() {
+        let overdrawn = report(
+            "overdrawn",
+            "partition,client,available,held,total,locked,overdrawn\n\
+             0-9,1,-1.0000,0.0000,-1.0000,false,true\n",
+        );
+        let closed = report(
+            "closed_only",
+            "partition,client,available,held,total,locked,closed\n\
+             10-19,12,0.0000,0.0000,0.0000,false,true\n",
+        );
+        let mut output = Vec::new();
+        merge_reports(&[overdrawn, closed], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,closed,overdrawn\n\
+             1,-1.0000,0.0000,-1.0000,false,false,true\n\
+             12,0.0000,0.0000,0.0000,false,true,false\n"
+        );
+    }
+
+    #[test]
+    fn merge_closed() {
+        let open = report(
+            "open",
+            "partition,client,available,held,total,locked\n0-9,1,1.0000,0.0000,1.0000,false\n",
+        );
+        let closed = report(
+            "closed",
+            "partition,client,available,held,total,locked,closed\n\
+             10-19,12,0.0000,0.0000,0.0000,false,true\n",
+        );
+        let mut output = Vec::new();
+        merge_reports(&[open, closed], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,closed\n\
+             1,1.0000,0.0000,1.0000,false,false\n\
+             12,0.0000,0.0000,0.0000,false,true\n"
+        );
+    }
+}
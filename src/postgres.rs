@@ -0,0 +1,488 @@
+use crate::account::Account;
+use crate::engine::Rejection;
+use crate::snapshot::{AccountSnapshot, Snapshot, SNAPSHOT_VERSION};
+use crate::store::{MemoryStore, StateStore};
+use deadpool_postgres::{Config, GenericClient, Pool, PoolConfig, Runtime, Timeouts};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio_postgres::NoTls;
+
+/// Transaction ids inserted per statement.
+const ID_BATCH: usize = 1 << 16;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    client INTEGER PRIMARY KEY,
+    locked BOOLEAN NOT NULL,
+    closed BOOLEAN NOT NULL,
+    -- Everything else the account learned, fees, interest and limits
+    state JSONB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS balances (
+    client INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    -- NULL for the default currency
+    currency TEXT,
+    available NUMERIC NOT NULL,
+    held NUMERIC NOT NULL,
+    total NUMERIC NOT NULL,
+    PRIMARY KEY (client, position)
+);
+CREATE TABLE IF NOT EXISTS history (
+    client INTEGER NOT NULL,
+    tx BIGINT NOT NULL,
+    type TEXT NOT NULL,
+    amount NUMERIC,
+    currency TEXT,
+    dispute_state TEXT NOT NULL,
+    -- The whole entry, with the transaction's other fields and refunds
+    entry JSONB NOT NULL,
+    PRIMARY KEY (client, tx)
+);
+CREATE TABLE IF NOT EXISTS transaction_ids (
+    tx BIGINT PRIMARY KEY
+);
+";
+
+/// Database and connection pool of a [`PostgresStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostgresConfig {
+    /// Connection URL, e.g. `postgres://engine@localhost/accounts`
+    pub url: String,
+    /// Connections the pool opens at most
+    pub pool_size: usize,
+    /// Longest wait for a free connection, unbounded when `None`
+    pub wait_timeout: Option<Duration>,
+    /// Longest wait for a new connection to be established, unbounded when `None`
+    pub connect_timeout: Option<Duration>,
+}
+
+impl PostgresConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            pool_size: 16,
+            wait_timeout: None,
+            connect_timeout: None,
+        }
+    }
+}
+
+/// What the writer of a [`PostgresStore`] has to do, in the order it was asked to.
+enum Write {
+    /// State of an account right after it posted a transaction
+    Posting(AccountSnapshot),
+    /// Whole state of an engine
+    Snapshot(Snapshot),
+}
+
+/// PostgreSQL database engine state is kept in, shared by every process of a deployment:
+/// a row in `accounts` per client, with its rows in `balances` and `history`, and the ids
+/// seen so far in `transaction_ids`.
+///
+/// Writes are queued for the [`PostgresWriter`] of the database, in order, so processing
+/// never waits for the database.
+#[derive(Clone)]
+pub struct PostgresDatabase {
+    pool: Pool,
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+/// Task writing what was queued for a [`PostgresDatabase`] to it.
+pub struct PostgresWriter {
+    writes: mpsc::UnboundedReceiver<Write>,
+    pool: Pool,
+}
+
+/// Store keeping accounts in the store `S` and updating the rows of an account in its
+/// [`PostgresDatabase`] for every transaction the account posts, in a database transaction
+/// of its own: the account's lock, balances and the history entry of the transaction.
+pub struct PostgresStore<S = MemoryStore> {
+    accounts: S,
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+impl PostgresDatabase {
+    /// Opens a connection pool to the database, creating the tables if needed, with the
+    /// writer of the database.
+    pub async fn connect(
+        config: &PostgresConfig,
+    ) -> Result<(Self, PostgresWriter), Box<dyn Error>> {
+        let pool = Config {
+            url: Some(config.url.clone()),
+            connect_timeout: config.connect_timeout,
+            pool: Some(PoolConfig {
+                max_size: config.pool_size,
+                timeouts: Timeouts {
+                    wait: config.wait_timeout,
+                    create: config.connect_timeout,
+                    recycle: None,
+                },
+                ..PoolConfig::default()
+            }),
+            ..Config::default()
+        }
+        .create_pool(Some(Runtime::Tokio1), NoTls)?;
+        pool.get().await?.batch_execute(SCHEMA).await?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let database = Self {
+            pool: pool.clone(),
+            writes: sender,
+        };
+        let writer = PostgresWriter {
+            writes: receiver,
+            pool,
+        };
+        Ok((database, writer))
+    }
+
+    /// Store posting to the database, keeping the accounts in `accounts`.
+    pub fn store<S: StateStore>(&self, accounts: S) -> PostgresStore<S> {
+        PostgresStore {
+            accounts,
+            writes: self.writes.clone(),
+        }
+    }
+
+    /// Reads every account and transaction id in the database as a snapshot, for
+    /// [`crate::Engine::restore`].
+    pub async fn load(&self) -> Result<Snapshot, Box<dyn Error>> {
+        let client = self.pool.get().await?;
+        let mut accounts = BTreeMap::new();
+        for row in client
+            .query("SELECT client, state FROM accounts", &[])
+            .await?
+        {
+            let state = match row.get::<_, Value>(1) {
+                Value::Object(state) => state,
+                _ => Map::new(),
+            };
+            accounts.insert(row.get::<_, i32>(0), (state, Vec::new(), Vec::new()));
+        }
+        for row in client
+            .query(
+                "SELECT client, currency, available::text, held::text, total::text
+                 FROM balances ORDER BY client, position",
+                &[],
+            )
+            .await?
+        {
+            if let Some((_, balances, _)) = accounts.get_mut(&row.get::<_, i32>(0)) {
+                let balance = serde_json::json!({
+                    "available": row.get::<_, String>(2),
+                    "held": row.get::<_, String>(3),
+                    "total": row.get::<_, String>(4),
+                });
+                balances.push(Value::Array(vec![
+                    row.get::<_, Option<String>>(1).into(),
+                    balance,
+                ]));
+            }
+        }
+        for row in client
+            .query("SELECT client, entry FROM history ORDER BY client, tx", &[])
+            .await?
+        {
+            if let Some((_, _, history)) = accounts.get_mut(&row.get::<_, i32>(0)) {
+                history.push(row.get::<_, Value>(1));
+            }
+        }
+
+        let mut snapshots = Vec::new();
+        for (client, (mut state, balances, history)) in accounts {
+            state.insert("client".to_string(), client.into());
+            state.insert("balances".to_string(), balances.into());
+            state.insert("history".to_string(), history.into());
+            let snapshot = serde_json::from_value::<AccountSnapshot>(Value::Object(state))
+                .map_err(|e| format!("Invalid state of client {}: {}", client, e))?;
+            snapshots.push(snapshot);
+        }
+        let transaction_ids = client
+            .query("SELECT tx FROM transaction_ids ORDER BY tx", &[])
+            .await?
+            .iter()
+            .map(|row| row.get::<_, i64>(0) as u32)
+            .collect();
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts: snapshots,
+            transaction_ids,
+            cursor: None,
+            topic: None,
+        })
+    }
+
+    /// Queues the whole state of an engine behind the updates before it, replacing the
+    /// rows of its accounts and adding its transaction ids.
+    pub fn save(&self, snapshot: Snapshot) {
+        // Sending only fails once the writer is gone, which reports its own error
+        let _ = self.writes.send(Write::Snapshot(snapshot));
+    }
+}
+
+impl<S: StateStore> StateStore for PostgresStore<S> {
+    fn get(&self, client: u16) -> Option<Arc<Mutex<Account>>> {
+        self.accounts.get(client)
+    }
+
+    fn put(&self, client: u16, account: Arc<Mutex<Account>>) {
+        self.accounts.put(client, account)
+    }
+
+    fn clients(&self) -> Vec<u16> {
+        self.accounts.clients()
+    }
+
+    fn append_history(&self, account: &Account, tx: u32) {
+        self.accounts.append_history(account, tx);
+        let _ = self
+            .writes
+            .send(Write::Posting(account.posting_snapshot(tx)));
+    }
+
+    fn reject(&self, rejection: &Rejection, account: Option<&Account>) {
+        self.accounts.reject(rejection, account);
+    }
+}
+
+impl PostgresWriter {
+    /// Writes until the database and its stores are dropped.
+    pub async fn run(mut self) -> io::Result<()> {
+        while let Some(write) = self.writes.recv().await {
+            self.write(write).await.map_err(database_error)?;
+        }
+        Ok(())
+    }
+
+    async fn write(&self, write: Write) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        match write {
+            Write::Posting(account) => {
+                let transaction = client.transaction().await?;
+                let tx = account.history.first().map(|entry| entry.transaction.tx);
+                put_account(&transaction, &account, false).await?;
+                if let Some(tx) = tx {
+                    put_transaction_ids(&transaction, &[tx]).await?;
+                }
+                transaction.commit().await?;
+            }
+            Write::Snapshot(snapshot) => {
+                for account in &snapshot.accounts {
+                    let transaction = client.transaction().await?;
+                    put_account(&transaction, account, true).await?;
+                    transaction.commit().await?;
+                }
+                for ids in snapshot.transaction_ids.chunks(ID_BATCH) {
+                    put_transaction_ids(&client, ids).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the account's row and balances and puts its history entries, replacing all of
+/// its history if the account has `whole_history`.
+async fn put_account(
+    client: &impl GenericClient,
+    account: &AccountSnapshot,
+    whole_history: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let id = i32::from(account.client);
+    let mut state = match serde_json::to_value(account)? {
+        Value::Object(state) => state,
+        _ => unreachable!("Accounts are objects"),
+    };
+    let balances = state.remove("balances").unwrap_or_default();
+    let history = state.remove("history").unwrap_or_default();
+    state.remove("client");
+    client
+        .execute(
+            "INSERT INTO accounts (client, locked, closed, state) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (client) DO UPDATE
+             SET locked = EXCLUDED.locked, closed = EXCLUDED.closed, state = EXCLUDED.state",
+            &[&id, &account.locked, &account.closed, &Value::Object(state)],
+        )
+        .await?;
+    client
+        .execute("DELETE FROM balances WHERE client = $1", &[&id])
+        .await?;
+    for (position, balance) in balances.as_array().into_iter().flatten().enumerate() {
+        let text = |name: &str| balance[1][name].as_str().unwrap_or_default().to_string();
+        client
+            .execute(
+                "INSERT INTO balances (client, position, currency, available, held, total)
+                 VALUES ($1, $2, $3, $4::text::numeric, $5::text::numeric, $6::text::numeric)",
+                &[
+                    &id,
+                    &(position as i32),
+                    &balance[0].as_str(),
+                    &text("available"),
+                    &text("held"),
+                    &text("total"),
+                ],
+            )
+            .await?;
+    }
+    if whole_history {
+        client
+            .execute("DELETE FROM history WHERE client = $1", &[&id])
+            .await?;
+    }
+    for entry in history.as_array().into_iter().flatten() {
+        let transaction_field = |name: &str| entry["transaction"][name].as_str();
+        client
+            .execute(
+                "INSERT INTO history (client, tx, type, amount, currency, dispute_state, entry)
+                 VALUES ($1, $2, $3, $4::text::numeric, $5, $6, $7)
+                 ON CONFLICT (client, tx) DO UPDATE
+                 SET type = EXCLUDED.type, amount = EXCLUDED.amount,
+                     currency = EXCLUDED.currency, dispute_state = EXCLUDED.dispute_state,
+                     entry = EXCLUDED.entry",
+                &[
+                    &id,
+                    &(entry["transaction"]["tx"].as_u64().unwrap_or_default() as i64),
+                    &transaction_field("type").unwrap_or_default(),
+                    &transaction_field("amount"),
+                    &transaction_field("currency"),
+                    &entry["dispute_state"].as_str().unwrap_or_default(),
+                    entry,
+                ],
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Adds transaction ids, ids other processes already added are kept.
+async fn put_transaction_ids(
+    client: &impl GenericClient,
+    ids: &[u32],
+) -> Result<(), tokio_postgres::Error> {
+    let ids = ids.iter().map(|&id| i64::from(id)).collect::<Vec<_>>();
+    client
+        .execute(
+            "INSERT INTO transaction_ids (tx) SELECT UNNEST($1::BIGINT[]) ON CONFLICT DO NOTHING",
+            &[&ids],
+        )
+        .await?;
+    Ok(())
+}
+
+fn database_error(e: Box<dyn Error + Send + Sync>) -> io::Error {
+    io::Error::other(format!("PostgreSQL error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PostgresConfig, PostgresDatabase};
+    use crate::store::MemoryStore;
+    use crate::{Engine, EngineConfig, ErrorKind, Money, Transaction, TransactionType};
+    use std::sync::Arc;
+    use tokio_postgres::NoTls;
+
+    /// Runs against the database of `POSTGRES_URL`, in a schema of its own, and is skipped
+    /// without one.
+    #[tokio::test]
+    async fn postgres_store() {
+        let Ok(url) = std::env::var("POSTGRES_URL") else {
+            return;
+        };
+        let schema = format!("engine_test_{}", std::process::id());
+        let (admin, connection) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+        tokio::spawn(connection);
+        admin
+            .batch_execute(&format!("CREATE SCHEMA {}", schema))
+            .await
+            .unwrap();
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let config = PostgresConfig {
+            pool_size: 2,
+            ..PostgresConfig::new(format!(
+                "{}{}options=-csearch_path%3D{}",
+                url, separator, schema
+            ))
+        };
+
+        let (database, writer) = PostgresDatabase::connect(&config).await.unwrap();
+        let writing = tokio::spawn(writer.run());
+        let store = Arc::new(database.store(MemoryStore::new()));
+        let mut engine = Engine::with_store(EngineConfig::default(), store);
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(7))),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(4))),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        engine.wait().await;
+        // Postings alone already bring the rows up to date
+        while database.load().await.unwrap().transaction_ids.len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        let balances = admin
+            .query(
+                &format!(
+                    "SELECT client, available::text, held::text FROM {}.balances ORDER BY client",
+                    schema
+                ),
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect::<Vec<(i32, String, String)>>();
+        assert_eq!(
+            balances,
+            [
+                (1, "6".to_string(), "0".to_string()),
+                (2, "0".to_string(), "7".to_string())
+            ]
+        );
+        let disputed = admin
+            .query_one(
+                &format!("SELECT dispute_state FROM {}.history WHERE tx = 2", schema),
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(disputed.get::<_, String>(0), "Disputed");
+
+        database.save(engine.snapshot().await.unwrap());
+        drop((engine, database));
+        writing.await.unwrap().unwrap();
+
+        // Another process takes over where this one stopped
+        let (database, _) = PostgresDatabase::connect(&config).await.unwrap();
+        let mut restored = Engine::new();
+        restored.restore(database.load().await.unwrap()).unwrap();
+        let transactions = [
+            Transaction::new(TransactionType::Resolve, 2, 2, None),
+            Transaction::new(TransactionType::Deposit, 3, 1, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            restored.submit(transaction).await.unwrap();
+        }
+        let rejections = restored.wait().await;
+        assert_eq!(rejections.len(), 1);
+        assert!(matches!(
+            rejections[0].error.kind,
+            ErrorKind::DuplicateTransactionId(1)
+        ));
+        let account = restored.account(2).await.unwrap();
+        assert_eq!(account.available(), Money::from(7));
+
+        admin
+            .batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+use crate::account::ErrorKind;
+use crate::currency::Currency;
+use crate::money::{Money, MoneyFormat};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Exchange rates applied by `convert` transactions.
+///
+/// A rate converts one unit of `from` into `to`. When only the opposite direction is known,
+/// its inverse is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExchangeRates {
+    rates: HashMap<(Currency, Currency), Decimal>,
+    /// Fraction of every converted amount kept as a fee, e.g. 0.01 for 1%
+    pub spread: Decimal,
+    /// Precision and rounding of converted amounts
+    pub rounding: MoneyFormat,
+}
+
+impl Default for ExchangeRates {
+    fn default() -> Self {
+        Self {
+            rates: HashMap::new(),
+            spread: Decimal::ZERO,
+            rounding: MoneyFormat::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RateRow {
+    from: Currency,
+    to: Currency,
+    rate: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RatesFile {
+    rates: Vec<RateRow>,
+}
+
+impl ExchangeRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads `from,to,rate` rows from a csv file, or a `rates` array of tables with the same
+    /// keys from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let rows = if path.extension().is_some_and(|e| e == "toml") {
+            toml::from_str::<RatesFile>(&std::fs::read_to_string(path)?)?.rates
+        } else {
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_path(path)?
+                .deserialize()
+                .collect::<Result<Vec<RateRow>, _>>()?
+        };
+
+        let mut rates = Self::new();
+        for row in rows {
+            let rate = Decimal::from_str(row.rate.trim())
+                .map_err(|e| format!("Invalid rate {}: {}", row.rate, e))?;
+            rates.insert(row.from, row.to, rate)?;
+        }
+        Ok(rates)
+    }
+
+    pub fn insert(&mut self, from: Currency, to: Currency, rate: Decimal) -> Result<(), String> {
+        if rate <= Decimal::ZERO {
+            return Err(format!(
+                "Rate {} from {} to {} is not positive",
+                rate, from, to
+            ));
+        }
+        self.rates.insert((from, to), rate);
+        Ok(())
+    }
+
+    pub fn rate(&self, from: &Currency, to: &Currency) -> Option<Decimal> {
+        if let Some(rate) = self.rates.get(&(from.clone(), to.clone())) {
+            return Some(*rate);
+        }
+        self.rates
+            .get(&(to.clone(), from.clone()))
+            .and_then(|rate| Decimal::ONE.checked_div(*rate))
+    }
+
+    /// Amount credited in `to` for `amount` debited in `from`, after the spread and rounding.
+    pub fn convert(
+        &self,
+        from: &Currency,
+        to: &Currency,
+        amount: Money,
+    ) -> Result<Money, ErrorKind> {
+        let rate = self.rate(from, to).ok_or(ErrorKind::MissingExchangeRate)?;
+        let factor = rate
+            .checked_mul(Decimal::ONE - self.spread)
+            .ok_or(ErrorKind::InvariantViolation("conversion overflow"))?;
+        amount
+            .checked_mul(factor)
+            .map(|converted| converted.round(&self.rounding))
+            .ok_or(ErrorKind::InvariantViolation("conversion overflow"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExchangeRates;
+    use crate::currency::Currency;
+    use crate::money::{Money, MoneyFormat, RoundingMode};
+    use crate::ErrorKind;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn convert() {
+        let (eur, usd, gbp): (Currency, Currency, Currency) = (
+            "EUR".parse().unwrap(),
+            "USD".parse().unwrap(),
+            "GBP".parse().unwrap(),
+        );
+        let mut rates = ExchangeRates::new();
+        rates
+            .insert(eur.clone(), usd.clone(), Decimal::new(125, 2))
+            .unwrap();
+        assert!(rates
+            .insert(eur.clone(), gbp.clone(), Decimal::ZERO)
+            .is_err());
+
+        assert_eq!(
+            rates.convert(&eur, &usd, Money::from(10)).unwrap(),
+            Money::new(125, 1)
+        );
+        assert_eq!(
+            rates.convert(&usd, &eur, Money::from(10)).unwrap(),
+            Money::from(8)
+        );
+        assert!(matches!(
+            rates.convert(&eur, &gbp, Money::from(10)),
+            Err(ErrorKind::MissingExchangeRate)
+        ));
+
+        rates.spread = Decimal::new(1, 2);
+        rates.rounding = MoneyFormat {
+            decimal_places: 2,
+            rounding: RoundingMode::Bankers,
+        };
+        // 1.5 * 1.25 * 0.99 = 1.85625
+        assert_eq!(
+            rates.convert(&eur, &usd, Money::new(15, 1)).unwrap(),
+            Money::new(186, 2)
+        );
+    }
+
+    #[test]
+    fn load() {
+        let dir = std::env::temp_dir();
+        let csv = dir.join(format!("rates_{}.csv", std::process::id()));
+        std::fs::write(&csv, "from,to,rate\neur,USD,1.25\n").unwrap();
+        let toml = dir.join(format!("rates_{}.toml", std::process::id()));
+        std::fs::write(
+            &toml,
+            "rates = [{ from = \"EUR\", to = \"USD\", rate = \"1.25\" }]\n",
+        )
+        .unwrap();
+
+        let from_csv = ExchangeRates::load(&csv).unwrap();
+        assert_eq!(from_csv, ExchangeRates::load(&toml).unwrap());
+        assert_eq!(
+            from_csv.rate(&"EUR".parse().unwrap(), &"USD".parse().unwrap()),
+            Some(Decimal::new(125, 2))
+        );
+
+        std::fs::write(&csv, "from,to,rate\nEUR,USD,-1\n").unwrap();
+        assert!(ExchangeRates::load(&csv).is_err());
+    }
+}
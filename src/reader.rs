@@ -0,0 +1,1305 @@
+use crate::avro::AvroReader;
+use crate::decompress;
+#[cfg(feature = "kafka")]
+use crate::kafka::{Consumer, KafkaSource, Offsets};
+use crate::signature::{RowVerifier, SIGNATURE_COLUMN};
+use crate::timestamp::Timestamp;
+use crate::transaction::Transaction;
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(feature = "kafka")]
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    /// A single JSON array of transaction objects
+    Json,
+    /// One transaction object per line
+    Jsonl,
+    /// An Avro object container file of records of [`crate::avro::TRANSACTION_SCHEMA`]
+    Avro,
+    /// The first sheet of an Excel workbook, with a header row
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+    /// An ISO 20022 pain.001 payment initiation or camt.053 bank statement
+    #[cfg(feature = "iso20022")]
+    Iso20022,
+}
+
+impl InputFormat {
+    /// Guesses the format from the file extension, falling back to csv. The extension of a
+    /// compressed file is looked past, `tx.jsonl.gz` is JSON lines.
+    pub fn detect(path: &str) -> InputFormat {
+        let mut path = Path::new(path);
+        let mut extension = path.extension().and_then(|e| e.to_str());
+        if extension.is_some_and(|e| decompress::COMPRESSED_EXTENSIONS.contains(&e)) {
+            path = Path::new(path.file_stem().unwrap_or_default());
+            extension = path.extension().and_then(|e| e.to_str());
+        }
+        match extension {
+            Some("json") => InputFormat::Json,
+            Some("jsonl") | Some("ndjson") => InputFormat::Jsonl,
+            Some("avro") => InputFormat::Avro,
+            #[cfg(feature = "xlsx")]
+            Some("xlsx") => InputFormat::Xlsx,
+            #[cfg(feature = "iso20022")]
+            Some("xml") => InputFormat::Iso20022,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            "avro" => Ok(InputFormat::Avro),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(InputFormat::Xlsx),
+            #[cfg(not(feature = "xlsx"))]
+            "xlsx" => Err("Reading xlsx needs the xlsx feature".to_string()),
+            #[cfg(feature = "iso20022")]
+            "iso20022" => Ok(InputFormat::Iso20022),
+            #[cfg(not(feature = "iso20022"))]
+            "iso20022" => Err("Reading ISO 20022 needs the iso20022 feature".to_string()),
+            _ => Err(format!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct ReadOptions {
+    /// Format of the input, detected from the file extension when not set
+    pub format: Option<InputFormat>,
+    /// Stop after the row with this 1-based sequence number
+    pub until: Option<usize>,
+    /// Verify row signatures before accepting a row
+    pub verifier: Option<RowVerifier>,
+    /// Abort on the first row that can't be accepted instead of skipping it
+    pub strict: bool,
+    /// Keep reading files at their end as rows are appended, see [`Follow`]
+    pub follow: bool,
+    /// Field delimiter of csv inputs, detected from the header when not set, see
+    /// [`detect_delimiter`]
+    pub delimiter: Option<u8>,
+    /// Columns of csv inputs without a header, which have one when not set
+    pub schema: Option<Columns>,
+}
+
+/// Columns a csv input must have, in any order next to any others.
+pub const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// Columns of rows that arrive one by one without a header, like those of the line
+/// protocol, unless given otherwise.
+pub(crate) const DEFAULT_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Columns read into the fields of a transaction, any others end up in its metadata.
+const TRANSACTION_COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "to_client",
+    "to_currency",
+    "reason",
+    "expires_at",
+    "timestamp",
+];
+
+/// Columns of a csv input without header, parsed from their comma separated names, e.g.
+/// `type,client,tx,amount`. Unknown names are columns that are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Columns(pub Vec<String>);
+
+impl FromStr for Columns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(|c| c.trim().to_string())
+            .collect::<Vec<_>>();
+        if let Some(missing) = REQUIRED_COLUMNS
+            .iter()
+            .find(|r| !columns.iter().any(|c| c == *r))
+        {
+            return Err(format!("Columns {} lack {}", s, missing));
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if column.is_empty() || columns[..i].contains(column) {
+                return Err(format!(
+                    "Columns {} repeat {} or have an empty one",
+                    s, column
+                ));
+            }
+        }
+        Ok(Columns(columns))
+    }
+}
+
+/// Field delimiter of csv inputs, parsed from a single ASCII character or `tab`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delimiter(pub u8);
+
+impl FromStr for Delimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tab" | "\\t" | "\t" => Ok(Delimiter(b'\t')),
+            s if s.len() == 1 && s.is_ascii() && s != "\"" && s != "\n" => {
+                Ok(Delimiter(s.as_bytes()[0]))
+            }
+            _ => Err(format!(
+                "Invalid delimiter {}, expected a single character or tab",
+                s
+            )),
+        }
+    }
+}
+
+/// Delimiters told apart when none is given, the first one winning ties.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// Guesses the delimiter of csv input from its header line: the one of `,`, `;`, tab and
+/// `|` it holds the most of outside quotes, `,` if it holds none.
+pub fn detect_delimiter(input: &[u8]) -> u8 {
+    let header = input.split(|&b| b == b'\n').next().unwrap_or_default();
+    let mut counts = [0; DELIMITERS.len()];
+    let mut quoted = false;
+    for &byte in header {
+        if byte == b'"' {
+            quoted = !quoted;
+        } else if let Some(i) = DELIMITERS
+            .iter()
+            .position(|&d| d == byte)
+            .filter(|_| !quoted)
+        {
+            counts[i] += 1;
+        }
+    }
+    let most = counts.iter().copied().max().unwrap_or(0);
+    match counts.iter().position(|&count| count == most && count > 0) {
+        Some(i) => DELIMITERS[i],
+        None => b',',
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReadSummary {
+    /// Rows sent down the channel
+    pub rows: u64,
+    /// Rows dropped because they were malformed or failed signature verification
+    pub skipped: u64,
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    MalformedRow { line: u64, reason: String },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "Failed to read transactions: {}", e),
+            ReadError::Csv(e) => write!(f, "Failed to read transactions: {}", e),
+            ReadError::Json(e) => write!(f, "Failed to read transactions: {}", e),
+            ReadError::MalformedRow { line, reason } => {
+                write!(f, "Malformed row at line {}: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<csv::Error> for ReadError {
+    fn from(e: csv::Error) -> Self {
+        ReadError::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for ReadError {
+    fn from(e: serde_json::Error) -> Self {
+        ReadError::Json(e)
+    }
+}
+
+/// A row that failed to parse, with the line (or array position) it came from.
+type RowError = (u64, String);
+
+/// Verifies and deserializes a single row. Every input format ends up here, so all of
+/// them are validated the same way, and keep their unknown columns as metadata.
+fn accept(
+    headers: &StringRecord,
+    record: &StringRecord,
+    line: u64,
+    options: &ReadOptions,
+) -> Result<Transaction, RowError> {
+    accept_with(headers, record, line, options, |e| e.to_string())
+}
+
+/// [`accept`] with deserialization errors described by `describe`.
+fn accept_with(
+    headers: &StringRecord,
+    record: &StringRecord,
+    line: u64,
+    options: &ReadOptions,
+    describe: impl FnOnce(csv::Error) -> String,
+) -> Result<Transaction, RowError> {
+    if let Some(verifier) = &options.verifier {
+        verifier
+            .verify(headers, record)
+            .map_err(|e| (line, e.to_string()))?;
+    }
+    let mut transaction = record
+        .deserialize::<Transaction>(Some(headers))
+        .map(|t| t.with_row(line))
+        .map_err(|e| (line, describe(e)))?;
+    for (name, value) in headers.iter().zip(record) {
+        if !value.is_empty() && name != SIGNATURE_COLUMN && !TRANSACTION_COLUMNS.contains(&name) {
+            transaction
+                .metadata
+                .insert(name.to_string(), value.to_string());
+        }
+    }
+    Ok(transaction)
+}
+
+/// Rows between two progress events of an input.
+const PROGRESS_ROWS: u64 = 100_000;
+
+/// Sends accepted rows down the channel, skipping (or in strict mode failing on) the rest.
+fn forward(
+    rows: impl Iterator<Item = Result<Transaction, RowError>>,
+    options: &ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let mut summary = ReadSummary::default();
+    for row in rows.take(options.until.unwrap_or(usize::MAX)) {
+        match row {
+            Ok(t) => {
+                if sender.blocking_send(t).is_err() {
+                    break;
+                }
+                summary.rows += 1;
+                if summary.rows % PROGRESS_ROWS == 0 {
+                    tracing::debug!(rows = summary.rows, "reading");
+                }
+            }
+            Err((line, reason)) if options.strict => {
+                return Err(ReadError::MalformedRow { line, reason });
+            }
+            Err((line, reason)) => {
+                tracing::warn!(line, reason, "malformed row skipped");
+                summary.skipped += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Flattens a JSON object into the header and field records the csv path works with.
+/// Columns keep their order in the object, which matters for signatures.
+fn json_row(value: Value, line: u64) -> Result<(StringRecord, StringRecord), RowError> {
+    let object: Map<String, Value> = match value {
+        Value::Object(object) => object,
+        _ => return Err((line, "Expected a JSON object".to_string())),
+    };
+    let mut headers = StringRecord::new();
+    let mut record = StringRecord::new();
+    for (key, value) in object {
+        headers.push_field(&key);
+        match value {
+            Value::Null => record.push_field(""),
+            Value::String(s) => record.push_field(s.trim()),
+            Value::Number(n) => record.push_field(&n.to_string()),
+            Value::Bool(b) => record.push_field(&b.to_string()),
+            _ => return Err((line, format!("Unsupported value of field {}", key))),
+        }
+    }
+    Ok((headers, record))
+}
+
+/// Verifies and deserializes a single JSON object the way rows of JSON inputs are, for
+/// transactions that don't come from a file.
+pub fn parse_json_transaction(
+    value: Value,
+    row: u64,
+    options: &ReadOptions,
+) -> Result<Transaction, String> {
+    let (headers, record) = json_row(value, row).map_err(|(_, reason)| reason)?;
+    accept(&headers, &record, row, options).map_err(|(_, reason)| reason)
+}
+
+/// Verifies and deserializes a single csv line with the given columns, for transactions
+/// that don't come from a file.
+pub fn parse_csv_line(
+    headers: &StringRecord,
+    line: &str,
+    row: u64,
+    options: &ReadOptions,
+) -> Result<Transaction, String> {
+    let record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes())
+        .records()
+        .next()
+        .ok_or_else(|| "Empty row".to_string())?
+        .map_err(|e| e.to_string())?;
+    accept(headers, &record, row, options).map_err(|(_, reason)| reason)
+}
+
+/// Input path standing for stdin.
+pub const STDIN: &str = "-";
+
+/// Prefix of input paths standing for a Unix socket rows are streamed to.
+pub const UNIX_SOCKET: &str = "unix:";
+
+/// Listens on a Unix socket and reads the rows of every producer connecting to it, one
+/// connection after another, each an input of its own with a header. Goes on until nobody
+/// receives the transactions anymore, replacing a socket file left behind by an earlier run.
+#[cfg(unix)]
+fn deserialize_socket(
+    path: &Path,
+    format: InputFormat,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Accepting doesn't block, so a closed channel is noticed while no producer connects
+    listener.set_nonblocking(true)?;
+    let mut summary = ReadSummary::default();
+    while !sender.is_closed() {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(FOLLOW_POLL);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_nonblocking(false)?;
+        tracing::info!(socket = %path.display(), "producer connected");
+        let options = options.clone();
+        let connection = match format {
+            InputFormat::Csv => deserialize_csv(stream, options, sender.clone()),
+            InputFormat::Json => deserialize_json(stream, options, sender.clone()),
+            InputFormat::Jsonl => deserialize_jsonl(stream, options, sender.clone()),
+            InputFormat::Avro => deserialize_avro(stream, options, sender.clone()),
+            #[cfg(feature = "xlsx")]
+            InputFormat::Xlsx => deserialize_xlsx(stream, options, sender.clone()),
+            #[cfg(feature = "iso20022")]
+            InputFormat::Iso20022 => deserialize_iso20022(stream, options, sender.clone()),
+        }?;
+        summary.rows += connection.rows;
+        summary.skipped += connection.skipped;
+    }
+    Ok(summary)
+}
+
+/// Time a followed file is left alone after reaching its end before reading it again.
+const FOLLOW_POLL: Duration = Duration::from_millis(250);
+
+/// File read with `tail -f` semantics: at its end, reads wait for more to be appended
+/// instead of ending the input, until nobody receives the transactions anymore. Rows
+/// written in pieces are picked up whole, the parser simply waits for the rest.
+struct Follow {
+    file: File,
+    sender: mpsc::Sender<Transaction>,
+}
+
+impl io::Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() || self.sender.is_closed() {
+                return Ok(read);
+            }
+            std::thread::sleep(FOLLOW_POLL);
+        }
+    }
+}
+
+/// Span of reading an input, the first of a trace of its own.
+fn read_span(input: &str) -> tracing::Span {
+    tracing::info_span!(
+        parent: None,
+        "read",
+        input,
+        rows = tracing::field::Empty,
+        skipped = tracing::field::Empty,
+    )
+}
+
+/// Reads transactions from a file, or from stdin when the path is [`STDIN`], in the given
+/// (or detected) format and sends them down the channel in file order. Stdin is read as
+/// csv unless a format is given.
+pub fn deserialize_file(
+    path: String,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let span = read_span(&path);
+    tracing::info!(input = path.as_str(), "reading input");
+    let format = options.format.unwrap_or_else(|| InputFormat::detect(&path));
+    #[cfg(unix)]
+    if let Some(socket) = path.strip_prefix(UNIX_SOCKET) {
+        let summary = deserialize_socket(Path::new(socket), format, options, sender)?;
+        span.record("rows", summary.rows);
+        span.record("skipped", summary.skipped);
+        return Ok(summary);
+    }
+    let input: Box<dyn io::Read + Send> = if path == STDIN {
+        Box::new(io::stdin())
+    } else if options.follow {
+        let file = File::open(&path)?;
+        let sender = sender.clone();
+        Box::new(Follow { file, sender })
+    } else {
+        Box::new(File::open(&path)?)
+    };
+    let input = decompress::decompressed(input)?;
+    let summary = match format {
+        InputFormat::Csv => deserialize_csv(input, options, sender),
+        InputFormat::Json => deserialize_json(input, options, sender),
+        InputFormat::Jsonl => deserialize_jsonl(input, options, sender),
+        InputFormat::Avro => deserialize_avro(input, options, sender),
+        #[cfg(feature = "xlsx")]
+        InputFormat::Xlsx => deserialize_xlsx(input, options, sender),
+        #[cfg(feature = "iso20022")]
+        InputFormat::Iso20022 => deserialize_iso20022(input, options, sender),
+    }?;
+    tracing::info!(
+        input = path,
+        rows = summary.rows,
+        skipped = summary.skipped,
+        "input read"
+    );
+    span.record("rows", summary.rows);
+    span.record("skipped", summary.skipped);
+    Ok(summary)
+}
+
+/// Reads several inputs one after another into the same channel, as if they were one.
+///
+/// `until` counts rows across all inputs. Rows keep the line numbers of their own input.
+pub fn deserialize_files(
+    paths: Vec<String>,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let mut summary = ReadSummary::default();
+    for path in paths {
+        let read = (summary.rows + summary.skipped) as usize;
+        let until = match options.until {
+            Some(until) if until <= read => break,
+            until => until.map(|until| until - read),
+        };
+        if sender.is_closed() {
+            break;
+        }
+        let options = ReadOptions {
+            until,
+            ..options.clone()
+        };
+        let file_summary = deserialize_file(path, options, sender.clone())?;
+        summary.rows += file_summary.rows;
+        summary.skipped += file_summary.skipped;
+    }
+    Ok(summary)
+}
+
+/// Reads several inputs concurrently and interleaves their transactions by timestamp,
+/// oldest first.
+///
+/// Every input must already be in chronological order; rows with equal timestamps keep the
+/// order of the inputs they come from. Rows without a timestamp can't be placed and are
+/// treated like malformed rows. `until` counts the timestamped rows in merged order.
+pub fn merge_files(
+    paths: Vec<String>,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let mut readers = Vec::new();
+    let mut receivers = Vec::new();
+    for path in paths {
+        let (tx, rx) = mpsc::channel(sender.max_capacity());
+        let options = ReadOptions {
+            until: None,
+            ..options.clone()
+        };
+        readers.push(std::thread::spawn(move || {
+            deserialize_file(path, options, tx)
+        }));
+        receivers.push(rx);
+    }
+
+    let mut summary = ReadSummary::default();
+    let merged = interleave(&mut receivers, &options, &sender, &mut summary);
+
+    // Readers still blocked on a full channel give up once their receiver is gone
+    drop(receivers);
+    let mut result = merged;
+    for reader in readers {
+        match reader
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        {
+            Ok(reader_summary) => summary.skipped += reader_summary.skipped,
+            Err(e) => result = result.and(Err(e)),
+        }
+    }
+    result.map(|_| summary)
+}
+
+/// Next transaction of an input that carries a timestamp, skipping (or in strict mode
+/// failing on) the ones that don't.
+fn next_timestamped(
+    receiver: &mut mpsc::Receiver<Transaction>,
+    options: &ReadOptions,
+    summary: &mut ReadSummary,
+) -> Result<Option<(Timestamp, Transaction)>, ReadError> {
+    while let Some(t) = receiver.blocking_recv() {
+        match t.timestamp() {
+            Some(timestamp) => return Ok(Some((timestamp, t))),
+            None if options.strict => {
+                return Err(ReadError::MalformedRow {
+                    line: t.row().unwrap_or(0),
+                    reason: "Missing timestamp".to_string(),
+                })
+            }
+            None => {
+                tracing::warn!(line = t.row(), "row without timestamp skipped");
+                summary.skipped += 1;
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn interleave(
+    receivers: &mut [mpsc::Receiver<Transaction>],
+    options: &ReadOptions,
+    sender: &mpsc::Sender<Transaction>,
+    summary: &mut ReadSummary,
+) -> Result<(), ReadError> {
+    // Oldest unsent transaction of every input that isn't exhausted yet
+    let mut pending: Vec<Option<Transaction>> = Vec::new();
+    let mut heap = BinaryHeap::new();
+    for (input, receiver) in receivers.iter_mut().enumerate() {
+        pending.push(None);
+        if let Some((timestamp, t)) = next_timestamped(receiver, options, summary)? {
+            pending[input] = Some(t);
+            heap.push(Reverse((timestamp, input)));
+        }
+    }
+
+    let until = options.until.map_or(u64::MAX, |until| until as u64);
+    while let Some(Reverse((_, input))) = heap.pop() {
+        if summary.rows >= until {
+            break;
+        }
+        let t = pending[input]
+            .take()
+            .expect("Queued inputs have a pending transaction");
+        if sender.blocking_send(t).is_err() {
+            break;
+        }
+        summary.rows += 1;
+        if let Some((timestamp, t)) = next_timestamped(&mut receivers[input], options, summary)? {
+            pending[input] = Some(t);
+            heap.push(Reverse((timestamp, input)));
+        }
+    }
+    Ok(())
+}
+
+/// Reads csv transactions from any reader and sends them down the channel in input order.
+/// Fields are separated by the delimiter of the options or, without one, the delimiter
+/// detected from the header. Columns are matched by name, in any order; unknown ones are
+/// ignored and optional ones may be left out. Inputs without header take the columns of
+/// the options' schema. A header lacking a required column fails reading at once.
+///
+/// Rows that fail to deserialize or fail signature verification are skipped and counted,
+/// or abort reading in strict mode. Sending blocks while the channel is full, so a slow
+/// consumer throttles reading instead of letting the input pile up in memory.
+pub fn deserialize_csv<R: io::Read>(
+    input: R,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let mut input = io::BufReader::new(input);
+    let delimiter = match options.delimiter {
+        Some(delimiter) => delimiter,
+        None => detect_delimiter(input.fill_buf()?),
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(delimiter)
+        .has_headers(options.schema.is_none())
+        .from_reader(input);
+    let headers = match &options.schema {
+        Some(Columns(columns)) => StringRecord::from(columns.clone()),
+        None => reader.headers()?.clone(),
+    };
+    // Rather than rejecting every row, e.g. of an input without header. Empty inputs have
+    // no header at all
+    let missing = REQUIRED_COLUMNS
+        .iter()
+        .find(|r| !headers.iter().any(|h| h == **r));
+    if let Some(missing) = missing.filter(|_| !headers.is_empty()) {
+        return Err(ReadError::MalformedRow {
+            line: 1,
+            reason: format!(
+                "Header {} has no {} column, give the columns of inputs without header with --schema",
+                headers.iter().collect::<Vec<_>>().join(","),
+                missing
+            ),
+        });
+    }
+
+    let rows = reader.records().map(|record| {
+        let record = record.map_err(|e| (e.position().map_or(0, |p| p.line()), e.to_string()))?;
+        let line = record.position().map_or(0, |p| p.line());
+        accept(&headers, &record, line, &options)
+    });
+    forward(rows, &options, sender)
+}
+
+/// Reads newline delimited JSON transactions, one object per line.
+/// Blank lines are ignored; rows are numbered by their line in the input.
+pub fn deserialize_jsonl<R: io::Read>(
+    input: R,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let rows = io::BufReader::new(input)
+        .lines()
+        .zip(1..)
+        .filter(|(l, _)| !l.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|(l, line)| {
+            let l = l.map_err(|e| (line, e.to_string()))?;
+            let value = serde_json::from_str(&l).map_err(|e| (line, e.to_string()))?;
+            let (headers, record) = json_row(value, line)?;
+            accept(&headers, &record, line, &options)
+        });
+    forward(rows, &options, sender)
+}
+
+/// Reads transactions from a single JSON array of objects. Rows are numbered by their
+/// 1-based position in the array.
+pub fn deserialize_json<R: io::Read>(
+    input: R,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let values: Vec<Value> = serde_json::from_reader(io::BufReader::new(input))?;
+    let rows = values.into_iter().zip(1..).map(|(value, line)| {
+        let (headers, record) = json_row(value, line)?;
+        accept(&headers, &record, line, &options)
+    });
+    forward(rows, &options, sender)
+}
+
+/// Reads transactions from an Avro object container file, once its schema is validated
+/// against [`crate::avro::TRANSACTION_SCHEMA`]. Records are rows like csv ones with the
+/// schema's fields as columns, numbered by their 1-based position in the file. A file that
+/// is corrupt past its header ends reading with an error, even in lenient mode.
+pub fn deserialize_avro<R: io::Read>(
+    input: R,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let mut reader = AvroReader::new(input)?;
+    let headers = StringRecord::from_iter(reader.fields());
+    let mut failure = None;
+    let mut line = 0;
+    let rows = std::iter::from_fn(|| match reader.next_record() {
+        Ok(record) => {
+            line += 1;
+            let record = StringRecord::from(record?);
+            Some(accept(&headers, &record, line, &options))
+        }
+        Err(e) => {
+            failure = Some(e);
+            None
+        }
+    });
+    let summary = forward(rows, &options, sender)?;
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(summary),
+    }
+}
+
+/// Reads transactions from the first sheet of an Excel workbook. Its first row names the
+/// columns, like a csv header; rows are numbered as Excel numbers them and errors name the
+/// cell they're about, e.g. `Cell B7 (client): invalid digit found in string`.
+#[cfg(feature = "xlsx")]
+pub fn deserialize_xlsx<R: io::Read>(
+    input: R,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    use crate::xlsx::{self, Cell};
+
+    let mut rows = xlsx::first_sheet(input)?.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(ReadSummary::default());
+    };
+    let headers = StringRecord::from_iter(header.cells.iter().map(Cell::text));
+    let cell = |column: usize, line: u64| {
+        let name = headers.get(column).unwrap_or_default();
+        format!("Cell {}{} ({})", xlsx::column_name(column), line, name)
+    };
+    let rows = rows.map(|row| {
+        let line = u64::from(row.number);
+        if let Some((column, Cell::Error(error))) = row
+            .cells
+            .iter()
+            .enumerate()
+            .find(|(_, cell)| matches!(cell, Cell::Error(_)))
+        {
+            return Err((
+                line,
+                format!("{}: holds the error {}", cell(column, line), error),
+            ));
+        }
+        let record = StringRecord::from_iter(row.cells.iter().map(Cell::text));
+        accept_with(&headers, &record, line, &options, |e| match e.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => match err.field() {
+                Some(column) => format!("{}: {}", cell(column as usize, line), err.kind()),
+                None => err.kind().to_string(),
+            },
+            _ => e.to_string(),
+        })
+    });
+    forward(rows, &options, sender)
+}
+
+/// Reads the transfers of an ISO 20022 pain.001 message or the booked entries of a camt.053
+/// statement as transactions, see [`crate::iso20022::read`]. They are rows of
+/// [`crate::iso20022::COLUMNS`] numbered by their 1-based position in the message, and
+/// errors name the value they're about, e.g. `client NL91ABNA0417164300: invalid digit
+/// found in string`.
+#[cfg(feature = "iso20022")]
+pub fn deserialize_iso20022<R: io::Read>(
+    input: R,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    use crate::iso20022;
+
+    let (_, records) = iso20022::read(input)?;
+    let headers = StringRecord::from_iter(iso20022::COLUMNS);
+    let rows = records.into_iter().zip(1..).map(|(record, line)| {
+        let record = StringRecord::from(record);
+        accept_with(&headers, &record, line, &options, |e| match e.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => match err.field() {
+                Some(field) => format!(
+                    "{} {}: {}",
+                    headers.get(field as usize).unwrap_or_default(),
+                    record.get(field as usize).unwrap_or_default(),
+                    err.kind()
+                ),
+                None => err.kind().to_string(),
+            },
+            _ => e.to_string(),
+        })
+    });
+    forward(rows, &options, sender)
+}
+
+/// Consumes transactions from a Kafka topic until nobody receives them anymore, continuing
+/// from `offsets` and from the earliest record of partitions without one. Record values are
+/// csv lines with the columns of the options' schema, [`DEFAULT_COLUMNS`] without one, or
+/// JSON objects with the json or jsonl format. Rows are numbered in the order records are
+/// consumed; records without value are passed over.
+#[cfg(feature = "kafka")]
+pub fn deserialize_kafka(
+    source: KafkaSource,
+    offsets: Arc<Offsets>,
+    options: ReadOptions,
+    sender: mpsc::Sender<Transaction>,
+) -> Result<ReadSummary, ReadError> {
+    let span = read_span(&source.topic);
+    tracing::info!(
+        topic = source.topic.as_str(),
+        broker = source.broker.as_str(),
+        "consuming topic"
+    );
+    let mut consumer = Consumer::connect(&source, offsets.next())?;
+    let json = matches!(options.format, Some(InputFormat::Json | InputFormat::Jsonl));
+    let headers = match &options.schema {
+        Some(Columns(columns)) => StringRecord::from(columns.clone()),
+        None => StringRecord::from(DEFAULT_COLUMNS.to_vec()),
+    };
+    let receiving = sender.clone();
+    let mut failure = None;
+    let mut line = 0;
+    let rows = std::iter::from_fn(|| loop {
+        let record = match consumer.next_record(|| receiving.is_closed()) {
+            Ok(Some(record)) => record,
+            Ok(None) => return None,
+            Err(e) => {
+                failure = Some(e);
+                return None;
+            }
+        };
+        line += 1;
+        offsets.read(line, record.partition, record.offset + 1);
+        if record.value.is_empty() {
+            continue;
+        }
+        let row = match std::str::from_utf8(&record.value) {
+            Ok(value) if json => serde_json::from_str(value)
+                .map_err(|e| e.to_string())
+                .and_then(|value| parse_json_transaction(value, line, &options)),
+            Ok(value) => parse_csv_line(&headers, value, line, &options),
+            Err(e) => Err(e.to_string()),
+        };
+        return Some(row.map_err(|reason| (line, reason)));
+    });
+    let summary = forward(rows, &options, sender)?;
+    span.record("rows", summary.rows);
+    span.record("skipped", summary.skipped);
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(summary),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        deserialize_csv, deserialize_file, deserialize_files, detect_delimiter, merge_files,
+        Columns, Delimiter, InputFormat, ReadError, ReadOptions, ReadSummary,
+    };
+    use crate::signature::RowVerifier;
+    use std::io::Write;
+    use tokio::sync::mpsc;
+
+    fn input(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("reader_{}_{}", std::process::id(), name));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    const MALFORMED: &str =
+        "type,client,tx,amount\ndeposit,1,1,1.0\nteleport,1,2,1.0\ndeposit,1,3,abc\ndeposit,1,4,2.0\n";
+
+    #[test]
+    fn lenient_counts_skipped_rows() {
+        let path = input("lenient.csv", MALFORMED);
+        let (sender, mut receiver) = mpsc::channel(16);
+        let summary = deserialize_file(path, ReadOptions::default(), sender).unwrap();
+        assert_eq!(
+            summary,
+            ReadSummary {
+                rows: 2,
+                skipped: 2
+            }
+        );
+
+        let rows = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|t| t.row())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![Some(2), Some(5)]);
+    }
+
+    #[test]
+    fn strict_fails_on_malformed_row() {
+        let path = input("strict.csv", MALFORMED);
+        let (sender, _receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            strict: true,
+            ..ReadOptions::default()
+        };
+        match deserialize_file(path, options, sender) {
+            Err(ReadError::MalformedRow { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("Expected malformed row error"),
+        }
+    }
+
+    #[test]
+    fn follow_appended_rows() {
+        let path = input("follow.csv", "type,client,tx,amount\ndeposit,1,1,1.0\n");
+        let (sender, mut receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            follow: true,
+            ..ReadOptions::default()
+        };
+        let reader = {
+            let path = path.clone();
+            std::thread::spawn(move || deserialize_file(path, options, sender))
+        };
+        assert_eq!(receiver.blocking_recv().unwrap().row(), Some(2));
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"deposit,1,2,").unwrap();
+        file.flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        file.write_all(b"2.0\n").unwrap();
+        let t = receiver.blocking_recv().unwrap();
+        assert_eq!((t.row(), t.tx), (Some(3), 2));
+
+        // Reading stops once nobody receives the rows
+        drop(receiver);
+        let summary = reader.join().unwrap().unwrap();
+        assert_eq!(summary.rows, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let socket = std::env::temp_dir().join(format!("reader_{}.sock", std::process::id()));
+        let path = format!("{}{}", super::UNIX_SOCKET, socket.display());
+        let (sender, mut receiver) = mpsc::channel(16);
+        let reader =
+            std::thread::spawn(move || deserialize_file(path, ReadOptions::default(), sender));
+        let connect = || loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => return stream,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        // Every connection is an input of its own, header included
+        for tx in [1, 2] {
+            let mut producer = connect();
+            write!(producer, "type,client,tx,amount\ndeposit,1,{},1.0\n", tx).unwrap();
+            drop(producer);
+            let t = receiver.blocking_recv().unwrap();
+            assert_eq!((t.row(), t.tx), (Some(2), tx));
+        }
+
+        drop(receiver);
+        let summary = reader.join().unwrap().unwrap();
+        assert_eq!(summary.rows, 2);
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(InputFormat::detect("tx.csv"), InputFormat::Csv);
+        assert_eq!(InputFormat::detect("tx"), InputFormat::Csv);
+        assert_eq!(InputFormat::detect("tx.json"), InputFormat::Json);
+        assert_eq!(InputFormat::detect("tx.jsonl"), InputFormat::Jsonl);
+        assert_eq!(InputFormat::detect("tx.ndjson"), InputFormat::Jsonl);
+        assert_eq!(InputFormat::detect("tx.avro"), InputFormat::Avro);
+        assert_eq!(InputFormat::detect("tx.jsonl.gz"), InputFormat::Jsonl);
+        assert_eq!(InputFormat::detect("tx.zst"), InputFormat::Csv);
+        assert!("yaml".parse::<InputFormat>().is_err());
+    }
+
+    const JSONL: &str = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}\n\n{\"type\":\"teleport\",\"client\":1,\"tx\":2}\n{\"type\":\"dispute\",\"client\":1,\"tx\":1,\"amount\":null}\n[1]\n{\"type\":\"withdrawal\",\"client\":1,\"tx\":3,\"amount\":0.25}\n";
+
+    #[test]
+    fn jsonl_input() {
+        let path = input("lenient.jsonl", JSONL);
+        let (sender, mut receiver) = mpsc::channel(16);
+        let summary = deserialize_file(path, ReadOptions::default(), sender).unwrap();
+        assert_eq!(
+            summary,
+            ReadSummary {
+                rows: 3,
+                skipped: 2
+            }
+        );
+
+        let rows = std::iter::from_fn(|| receiver.try_recv().ok()).collect::<Vec<_>>();
+        assert_eq!(
+            rows.iter().map(|t| t.row()).collect::<Vec<_>>(),
+            vec![Some(1), Some(4), Some(6)]
+        );
+        assert_eq!(rows[0].amount(), Some("1.5".parse().unwrap()));
+        assert_eq!(rows[1].amount(), None);
+        assert_eq!(rows[2].amount(), Some("0.25".parse().unwrap()));
+
+        let path = input("strict.jsonl", JSONL);
+        let (sender, _receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            strict: true,
+            ..ReadOptions::default()
+        };
+        match deserialize_file(path, options, sender) {
+            Err(ReadError::MalformedRow { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("Expected malformed row error"),
+        }
+    }
+
+    #[test]
+    fn json_input() {
+        let path = input(
+            "array.json",
+            "[{\"type\":\"deposit\",\"client\":2,\"tx\":1,\"amount\":\"3.0\"},\"x\"]",
+        );
+        let (sender, mut receiver) = mpsc::channel(16);
+        let summary = deserialize_file(path.clone(), ReadOptions::default(), sender).unwrap();
+        assert_eq!(
+            summary,
+            ReadSummary {
+                rows: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(receiver.try_recv().unwrap().client(), 2);
+
+        let (sender, _receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            format: Some(InputFormat::Jsonl),
+            strict: true,
+            ..ReadOptions::default()
+        };
+        assert!(deserialize_file(path, options, sender).is_err());
+    }
+
+    #[test]
+    fn signed_jsonl_input() {
+        let verifier = RowVerifier::new(b"key");
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "signature"]);
+        let record = csv::StringRecord::from(vec!["deposit", "1", "1", "2.0", ""]);
+        let signature = verifier.sign(&headers, &record);
+
+        let path = input(
+            "signed.jsonl",
+            &format!(
+                "{{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.0\",\"signature\":\"{}\"}}\n{{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"2.0\",\"signature\":\"{}\"}}\n",
+                signature, signature
+            ),
+        );
+        let (sender, _receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            verifier: Some(verifier),
+            ..ReadOptions::default()
+        };
+        let summary = deserialize_file(path, options, sender).unwrap();
+        assert_eq!(
+            summary,
+            ReadSummary {
+                rows: 1,
+                skipped: 1
+            }
+        );
+    }
+
+    #[test]
+    fn csv_from_reader() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes();
+        let (sender, mut receiver) = mpsc::channel(16);
+        let summary = deserialize_csv(input, ReadOptions::default(), sender).unwrap();
+        assert_eq!(summary.rows, 1);
+        assert_eq!(receiver.try_recv().unwrap().row(), Some(2));
+    }
+
+    #[test]
+    fn metadata() {
+        let input = "type,client,tx,amount,memo,external_ref\n\
+                     deposit,1,1,2.0,rent,EXT-1\n\
+                     deposit,1,2,3.0,,EXT-2\n";
+        let (sender, mut receiver) = mpsc::channel(16);
+        deserialize_csv(input.as_bytes(), ReadOptions::default(), sender).unwrap();
+        let transaction = receiver.try_recv().unwrap();
+        assert_eq!(
+            transaction.metadata().iter().collect::<Vec<_>>(),
+            [
+                (&"external_ref".to_string(), &"EXT-1".to_string()),
+                (&"memo".to_string(), &"rent".to_string())
+            ]
+        );
+        // Empty values aren't kept
+        let transaction = receiver.try_recv().unwrap();
+        assert_eq!(transaction.metadata().len(), 1);
+    }
+
+    #[test]
+    fn columns() {
+        // Columns in any order, an unknown one and no amount
+        let input = "memo,tx,client,type\nrent,7,1,deposit\n";
+        let (sender, mut receiver) = mpsc::channel(16);
+        deserialize_csv(input.as_bytes(), ReadOptions::default(), sender).unwrap();
+        let transaction = receiver.try_recv().unwrap();
+        assert_eq!((transaction.tx, transaction.client), (7, 1));
+        assert_eq!(transaction.amount, None);
+
+        let options = ReadOptions {
+            schema: Some("client, type,tx,amount".parse().unwrap()),
+            ..ReadOptions::default()
+        };
+        let (sender, mut receiver) = mpsc::channel(16);
+        let input = "2,deposit,1,1.5\n3,deposit,2,2.5\n";
+        let summary = deserialize_csv(input.as_bytes(), options, sender).unwrap();
+        assert_eq!(summary.rows, 2);
+        assert_eq!(receiver.try_recv().unwrap().row(), Some(1));
+
+        // Without header nor schema the first row would be taken for one
+        let (sender, _receiver) = mpsc::channel(16);
+        match deserialize_csv(input.as_bytes(), ReadOptions::default(), sender) {
+            Err(ReadError::MalformedRow { line: 1, reason }) => {
+                assert!(reason.contains("has no type column"), "{}", reason)
+            }
+            result => panic!("Expected malformed header error, got {:?}", result),
+        }
+
+        assert!("type,client,amount".parse::<Columns>().is_err());
+        assert!("type,client,tx,client".parse::<Columns>().is_err());
+        assert!("type,client,,tx".parse::<Columns>().is_err());
+    }
+
+    #[test]
+    fn delimiters() {
+        let inputs = [
+            (None, "type;client;tx;amount\ndeposit;1;1;1.5\n"),
+            (None, "type\tclient\ttx\tamount\ndeposit\t1\t1\t1.5\n"),
+            (None, "\"type\",client,tx,amount\ndeposit,1,1,1.5\n"),
+            (Some(b'|'), "type|client|tx|amount\ndeposit|1|1|1.5\n"),
+        ];
+        for (delimiter, input) in inputs {
+            let (sender, mut receiver) = mpsc::channel(16);
+            let options = ReadOptions {
+                delimiter,
+                ..ReadOptions::default()
+            };
+            deserialize_csv(input.as_bytes(), options, sender).unwrap();
+            assert_eq!(receiver.try_recv().unwrap().tx, 1, "{}", input);
+        }
+
+        assert_eq!(detect_delimiter(b"type,client;tx,amount"), b',');
+        assert_eq!(detect_delimiter(b"\"a;b\",c\nx;y;z"), b',');
+        assert_eq!(detect_delimiter(b"amount"), b',');
+        assert_eq!("tab".parse(), Ok(Delimiter(b'\t')));
+        assert_eq!(";".parse(), Ok(Delimiter(b';')));
+        assert!(";;".parse::<Delimiter>().is_err());
+    }
+
+    #[test]
+    fn multiple_files() {
+        let first = input(
+            "first.csv",
+            "type,client,tx,amount\ndeposit,1,1,1.0\nbad,1,2,1.0\n",
+        );
+        let second = input("second.jsonl", "{\"type\":\"deposit\",\"client\":2,\"tx\":3,\"amount\":\"1\"}\n{\"type\":\"deposit\",\"client\":2,\"tx\":4,\"amount\":\"1\"}\n");
+
+        let (sender, mut receiver) = mpsc::channel(16);
+        let summary = deserialize_files(
+            vec![first.clone(), second.clone()],
+            ReadOptions::default(),
+            sender,
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            ReadSummary {
+                rows: 3,
+                skipped: 1
+            }
+        );
+        let txs = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|t| t.tx())
+            .collect::<Vec<_>>();
+        assert_eq!(txs, vec![1, 3, 4]);
+
+        let (sender, mut receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            until: Some(3),
+            ..ReadOptions::default()
+        };
+        deserialize_files(vec![first, second], options, sender).unwrap();
+        let txs = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|t| t.tx())
+            .collect::<Vec<_>>();
+        assert_eq!(txs, vec![1, 3]);
+    }
+
+    #[test]
+    fn merge_by_timestamp() {
+        let bank = input(
+            "bank.csv",
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,1.0,1000\n\
+             deposit,1,2,1.0,2023-11-14T22:13:20Z\n\
+             deposit,1,3,1.0,1700000003000\n",
+        );
+        let cards = input(
+            "cards.jsonl",
+            "{\"type\":\"deposit\",\"client\":2,\"tx\":4,\"amount\":\"1\",\"timestamp\":500}\n\
+             {\"type\":\"deposit\",\"client\":2,\"tx\":5,\"amount\":\"1\"}\n\
+             {\"type\":\"deposit\",\"client\":2,\"tx\":6,\"amount\":\"1\",\"timestamp\":1700000000000}\n",
+        );
+
+        let (sender, mut receiver) = mpsc::channel(1);
+        let paths = vec![bank.clone(), cards.clone()];
+        let reader =
+            std::thread::spawn(move || merge_files(paths, ReadOptions::default(), sender).unwrap());
+        let mut txs = Vec::new();
+        while let Some(t) = receiver.blocking_recv() {
+            txs.push(t.tx());
+        }
+        assert_eq!(txs, vec![4, 1, 2, 6, 3]);
+        assert_eq!(
+            reader.join().unwrap(),
+            ReadSummary {
+                rows: 5,
+                skipped: 1
+            }
+        );
+
+        let (sender, mut receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            until: Some(2),
+            ..ReadOptions::default()
+        };
+        merge_files(vec![bank.clone(), cards.clone()], options, sender).unwrap();
+        let txs = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|t| t.tx())
+            .collect::<Vec<_>>();
+        assert_eq!(txs, vec![4, 1]);
+
+        let (sender, _receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            strict: true,
+            ..ReadOptions::default()
+        };
+        match merge_files(vec![bank, cards], options, sender) {
+            Err(ReadError::MalformedRow { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("Expected malformed row error"),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::account::DisputeState;
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::snapshot::{AccountSnapshot, Snapshot};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Difference between the state reprocessing ended with and a known-good snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// Account of the snapshot that reprocessing didn't end up with
+    MissingAccount { client: u16 },
+    /// Account reprocessing ended up with that the snapshot doesn't have
+    UnexpectedAccount { client: u16 },
+    /// Funds of a currency of an account that aren't the snapshot's
+    Balance {
+        client: u16,
+        currency: Option<Currency>,
+        field: &'static str,
+        expected: Money,
+        actual: Money,
+    },
+    /// Lock or closure of an account that isn't the snapshot's
+    State {
+        client: u16,
+        field: &'static str,
+        expected: bool,
+        actual: bool,
+    },
+    /// Transaction in the snapshot's history of an account but not in the reprocessed one
+    MissingTransaction { client: u16, tx: u32 },
+    /// Transaction in the reprocessed history of an account but not in the snapshot's
+    UnexpectedTransaction { client: u16, tx: u32 },
+    /// Transaction whose dispute ended up elsewhere than in the snapshot
+    DisputeState {
+        client: u16,
+        tx: u32,
+        expected: DisputeState,
+        actual: DisputeState,
+    },
+}
+
+impl Divergence {
+    pub fn client(&self) -> u16 {
+        match self {
+            Divergence::MissingAccount { client }
+            | Divergence::UnexpectedAccount { client }
+            | Divergence::Balance { client, .. }
+            | Divergence::State { client, .. }
+            | Divergence::MissingTransaction { client, .. }
+            | Divergence::UnexpectedTransaction { client, .. }
+            | Divergence::DisputeState { client, .. } => *client,
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "client {}", self.client())?;
+        match self {
+            Divergence::MissingAccount { .. } => write!(f, ": in the snapshot but not reprocessed"),
+            Divergence::UnexpectedAccount { .. } => {
+                write!(f, ": reprocessed but not in the snapshot")
+            }
+            Divergence::Balance {
+                currency,
+                field,
+                expected,
+                actual,
+                ..
+            } => {
+                if let Some(currency) = currency {
+                    write!(f, " ({})", currency)?;
+                }
+                write!(f, ": {} expected {} but was {}", field, expected, actual)
+            }
+            Divergence::State {
+                field,
+                expected,
+                actual,
+                ..
+            } => write!(f, ": {} expected {} but was {}", field, expected, actual),
+            Divergence::MissingTransaction { tx, .. } => {
+                write!(
+                    f,
+                    ": transaction {} in the snapshot but not reprocessed",
+                    tx
+                )
+            }
+            Divergence::UnexpectedTransaction { tx, .. } => {
+                write!(
+                    f,
+                    ": transaction {} reprocessed but not in the snapshot",
+                    tx
+                )
+            }
+            Divergence::DisputeState {
+                tx,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                ": transaction {} expected {:?} but was {:?}",
+                tx, expected, actual
+            ),
+        }
+    }
+}
+
+/// Compares the state an engine ended with after reprocessing, `actual`, with a known-good
+/// snapshot, account by account: balances in every currency, locks and closures, and which
+/// transactions each history holds and where their disputes stand. Accounts and
+/// transactions are listed by client and id.
+pub fn reconcile(expected: &Snapshot, actual: &Snapshot) -> Vec<Divergence> {
+    let by_client = |snapshot: &Snapshot| -> BTreeMap<u16, usize> {
+        snapshot
+            .accounts
+            .iter()
+            .enumerate()
+            .map(|(index, account)| (account.client, index))
+            .collect()
+    };
+    let (accounts, mut reprocessed) = (by_client(expected), by_client(actual));
+
+    let mut divergences = Vec::new();
+    for (client, index) in accounts {
+        match reprocessed.remove(&client) {
+            Some(reprocessed) => reconcile_account(
+                &expected.accounts[index],
+                &actual.accounts[reprocessed],
+                &mut divergences,
+            ),
+            None => divergences.push(Divergence::MissingAccount { client }),
+        }
+    }
+    divergences.extend(
+        reprocessed
+            .into_keys()
+            .map(|client| Divergence::UnexpectedAccount { client }),
+    );
+    divergences
+}
+
+fn reconcile_account(
+    expected: &AccountSnapshot,
+    actual: &AccountSnapshot,
+    divergences: &mut Vec<Divergence>,
+) {
+    let client = expected.client;
+    let mut currencies = BTreeMap::new();
+    for (currency, balance) in &expected.balances {
+        currencies.entry(currency).or_insert((None, None)).0 = Some(balance);
+    }
+    for (currency, balance) in &actual.balances {
+        currencies.entry(currency).or_insert((None, None)).1 = Some(balance);
+    }
+    // A currency only one side has is compared with no funds at all
+    for (currency, (expected, actual)) in currencies {
+        let (expected, actual) = (
+            expected.copied().unwrap_or_default(),
+            actual.copied().unwrap_or_default(),
+        );
+        for (field, expected, actual) in [
+            ("available", expected.available(), actual.available()),
+            ("held", expected.held(), actual.held()),
+            ("total", expected.total(), actual.total()),
+        ] {
+            if expected != actual {
+                divergences.push(Divergence::Balance {
+                    client,
+                    currency: currency.clone(),
+                    field,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    for (field, expected, actual) in [
+        ("locked", expected.locked, actual.locked),
+        ("closed", expected.closed, actual.closed),
+    ] {
+        if expected != actual {
+            divergences.push(Divergence::State {
+                client,
+                field,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let history = |account: &AccountSnapshot| -> BTreeMap<u32, DisputeState> {
+        account
+            .history
+            .iter()
+            .map(|entry| (entry.transaction.tx, entry.dispute_state))
+            .collect()
+    };
+    let (expected, mut actual) = (history(expected), history(actual));
+    for (tx, expected) in expected {
+        match actual.remove(&tx) {
+            Some(actual) if actual != expected => divergences.push(Divergence::DisputeState {
+                client,
+                tx,
+                expected,
+                actual,
+            }),
+            Some(_) => {}
+            None => divergences.push(Divergence::MissingTransaction { client, tx }),
+        }
+    }
+    divergences.extend(
+        actual
+            .into_keys()
+            .map(|tx| Divergence::UnexpectedTransaction { client, tx }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconcile, Divergence};
+    use crate::{Engine, Money, Transaction, TransactionType};
+
+    async fn snapshot(transactions: Vec<Transaction>) -> crate::snapshot::Snapshot {
+        let mut engine = Engine::new();
+        for transaction in transactions {
+            let _ = engine.submit(transaction).await;
+        }
+        engine.snapshot().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn divergences() {
+        let deposit = |client, tx, amount| {
+            Transaction::new(
+                TransactionType::Deposit,
+                client,
+                tx,
+                Some(Money::from(amount)),
+            )
+        };
+        let known_good = snapshot(vec![
+            deposit(1, 1, 10),
+            deposit(1, 2, 5),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            deposit(2, 3, 1),
+        ])
+        .await;
+        assert_eq!(reconcile(&known_good, &known_good), Vec::new());
+
+        let reprocessed = snapshot(vec![
+            deposit(1, 1, 10),
+            deposit(1, 2, 5),
+            deposit(1, 4, 1),
+            deposit(3, 5, 1),
+        ])
+        .await;
+        let divergences = reconcile(&known_good, &reprocessed);
+        assert_eq!(
+            divergences.first(),
+            Some(&Divergence::Balance {
+                client: 1,
+                currency: None,
+                field: "available",
+                expected: Money::from(10),
+                actual: Money::from(16),
+            })
+        );
+        assert_eq!(
+            divergences
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "client 1: available expected 10 but was 16",
+                "client 1: held expected 5 but was 0",
+                "client 1: total expected 15 but was 16",
+                "client 1: transaction 2 expected Disputed but was None",
+                "client 1: transaction 4 reprocessed but not in the snapshot",
+                "client 2: in the snapshot but not reprocessed",
+                "client 3: reprocessed but not in the snapshot",
+            ]
+        );
+    }
+}
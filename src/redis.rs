@@ -0,0 +1,174 @@
+use crate::store::AccountUpdate;
+use ::redis::aio::MultiplexedConnection;
+use ::redis::{Client, RedisError};
+use serde_json::Value;
+use std::io;
+use tokio::sync::mpsc;
+
+/// Commands sent in one pipeline before their replies are awaited.
+const PIPELINE: usize = 1024;
+
+/// Connection to a Redis server mirroring the balances of account events into hashes.
+///
+/// Every report row of an account is a hash at `<prefix>:<client>`, or
+/// `<prefix>:<client>:<currency>` for currencies other than the default one, with the
+/// fields of the row, e.g. `available`, `held`, `total` and `locked`.
+pub struct RedisMirror {
+    connection: MultiplexedConnection,
+    prefix: String,
+}
+
+/// Error of the `redis` crate as an I/O error, keeping whether it's worth retrying.
+fn io_error(e: RedisError) -> io::Error {
+    let kind = if e.is_connection_refusal() {
+        io::ErrorKind::ConnectionRefused
+    } else if e.is_timeout() {
+        io::ErrorKind::TimedOut
+    } else if e.is_connection_dropped() {
+        io::ErrorKind::ConnectionReset
+    } else {
+        io::ErrorKind::Other
+    };
+    io::Error::new(kind, e)
+}
+
+impl RedisMirror {
+    /// Connects to the Redis server at `address`, a `redis://` URL such as
+    /// `redis://:password@127.0.0.1:6379/2` with the password and database, or just a
+    /// `host:port` such as `127.0.0.1:6379`.
+    pub async fn connect(address: &str, prefix: &str) -> io::Result<Self> {
+        let url = match address.contains("://") {
+            true => address.to_string(),
+            false => format!("redis://{}", address),
+        };
+        let client =
+            Client::open(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(io_error)?;
+        Ok(Self {
+            connection,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Writes balances until the [`EventStore`](crate::store::EventStore) sending the
+    /// events is dropped. Rejected transactions leave balances as they were and are skipped.
+    pub async fn run(
+        mut self,
+        mut events: mpsc::UnboundedReceiver<AccountUpdate>,
+    ) -> io::Result<()> {
+        while let Some(event) = events.recv().await {
+            let mut pipeline = ::redis::pipe();
+            self.write(&mut pipeline, &event)?;
+            while pipeline.len() < PIPELINE {
+                match events.try_recv() {
+                    Ok(event) => self.write(&mut pipeline, &event)?,
+                    Err(_) => break,
+                }
+            }
+            pipeline
+                .query_async::<()>(&mut self.connection)
+                .await
+                .map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Adds one `HSET` per report row of the event to the pipeline.
+    fn write(&self, pipeline: &mut ::redis::Pipeline, event: &AccountUpdate) -> io::Result<()> {
+        if event.error.is_some() {
+            return Ok(());
+        }
+        for record in &event.balances {
+            let mut key = format!("{}:{}", self.prefix, record.client);
+            if let Some(currency) = record.currency.as_deref().filter(|c| !c.is_empty()) {
+                key = format!("{}:{}", key, currency);
+            }
+            let mut fields = Vec::new();
+            if let Value::Object(record) = serde_json::to_value(record)? {
+                for (field, value) in record {
+                    if field == "client" || field == "currency" {
+                        continue;
+                    }
+                    let value = match value {
+                        Value::String(value) => value,
+                        value => value.to_string(),
+                    };
+                    fields.push((field, value));
+                }
+            }
+            pipeline.cmd("HSET").arg(key).arg(fields).ignore();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RedisMirror;
+    use crate::store::{EventStore, MemoryStore};
+    use crate::{Engine, EngineConfig, Money, Transaction, TransactionType};
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn mirror_balances() {
+        // Just enough of a Redis server to collect the commands, answering each with OK
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut commands = Vec::new();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let args = line[1..].parse::<usize>().unwrap();
+                let mut command = Vec::new();
+                for _ in 0..args {
+                    lines.next_line().await.unwrap();
+                    command.push(lines.next_line().await.unwrap().unwrap());
+                }
+                commands.push(command.join(" "));
+                writer.write_all(b"+OK\r\n").await.unwrap();
+            }
+            commands
+        });
+
+        let url = format!("redis://:secret@{}/2", address);
+        let mirror = RedisMirror::connect(&url, "account").await.unwrap();
+        let mut store = EventStore::new(MemoryStore::new(), Default::default());
+        let mirrored = tokio::spawn(mirror.run(store.subscribe()));
+        let mut engine = Engine::with_store(EngineConfig::default(), Arc::new(store));
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from(2)))
+                .with_currency("EUR".parse().unwrap()),
+        ];
+        for transaction in transactions {
+            let _ = engine.process(transaction).await;
+        }
+        drop(engine);
+        mirrored.await.unwrap().unwrap();
+
+        let commands = server.await.unwrap();
+        // Authenticated and on the database of the URL before anything else
+        assert_eq!(commands[..2], ["AUTH secret", "SELECT 2"]);
+        let commands = commands
+            .into_iter()
+            .filter(|command| command.starts_with("HSET"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                "HSET account:1 available 5.0000 held 0.0000 total 5.0000 locked false",
+                // The rejected duplicate changes nothing, the EUR deposit writes both rows
+                "HSET account:1 available 5.0000 held 0.0000 total 5.0000 locked false",
+                "HSET account:1:EUR available 2.0000 held 0.0000 total 2.0000 locked false",
+            ]
+        );
+    }
+}
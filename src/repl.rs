@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+use transaction_system::ledger::write_ledger;
+use transaction_system::{Engine, ReportFormat, Transaction, TransactionType};
+
+const HELP: &str = "\
+balance <client>          account report row of the client
+history <client>          the client's transactions with their dispute state, rejected input rows too
+dispute <client> <tx>     dispute the client's transaction
+resolve <client> <tx>     resolve the dispute of the client's transaction
+chargeback <client> <tx>  charge back the client's disputed transaction
+stats                     summary of everything processed so far
+help                      this list
+quit                      leave, also on end of input";
+
+/// Command typed at the prompt.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Balance(u16),
+    History(u16),
+    /// Dispute, resolve or chargeback of a transaction
+    Apply(TransactionType, u16, u32),
+    Stats,
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parses a line, `None` for an empty one.
+    fn parse(line: &str) -> Result<Option<Self>, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let client = |word: &str| {
+            word.parse::<u16>()
+                .map_err(|_| format!("{} is not a client id", word))
+        };
+        let tx = |word: &str| {
+            word.parse::<u32>()
+                .map_err(|_| format!("{} is not a transaction id", word))
+        };
+        let command = match words[..] {
+            [] => return Ok(None),
+            ["balance", id] => Command::Balance(client(id)?),
+            ["history", id] => Command::History(client(id)?),
+            [name @ ("dispute" | "resolve" | "chargeback"), id, transaction] => {
+                let transaction_type = match name {
+                    "dispute" => TransactionType::Dispute,
+                    "resolve" => TransactionType::Resolve,
+                    _ => TransactionType::Chargeback,
+                };
+                Command::Apply(transaction_type, client(id)?, tx(transaction)?)
+            }
+            ["stats"] => Command::Stats,
+            ["help"] => Command::Help,
+            ["quit" | "exit"] => Command::Quit,
+            [name, ..] => {
+                return Err(format!(
+                    "Unknown command or arguments of {}, try help",
+                    name
+                ))
+            }
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Reads commands from `input` and writes their results to `output` until `quit` or the
+/// end of the input. Commands that fail print their error and leave the engine as it was.
+/// `stats` counts the time elapsed since `started`.
+pub async fn run(
+    engine: &mut Engine,
+    started: Instant,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        let command = match Command::parse(&line) {
+            Ok(Some(Command::Quit)) => return Ok(()),
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(error) => {
+                writeln!(output, "error: {}", error)?;
+                continue;
+            }
+        };
+        if let Err(error) = execute(engine, command, &mut output, started).await {
+            writeln!(output, "error: {}", error)?;
+        }
+    }
+}
+
+async fn execute(
+    engine: &mut Engine,
+    command: Command,
+    output: &mut impl Write,
+    started: Instant,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Balance(client) => {
+            if engine.account(client).await.is_none() {
+                return Err(format!("Client {} has no account", client).into());
+            }
+            engine.write_client_report(&mut *output, client).await?;
+        }
+        Command::History(client) => {
+            let ledger = engine.ledger().await?;
+            let entries = ledger.iter().filter(|entry| entry.client == client);
+            let format = engine.config().output_format;
+            write_ledger(&mut *output, entries, &format, ReportFormat::Csv)?;
+        }
+        Command::Apply(transaction_type, client, tx) => {
+            engine
+                .process(Transaction::new(transaction_type, client, tx, None))
+                .await?;
+            writeln!(output, "ok")?;
+        }
+        Command::Stats => writeln!(output, "{}", engine.summary(started.elapsed()).await)?,
+        Command::Help => writeln!(output, "{}", HELP)?,
+        Command::Quit => {}
+    }
+    Ok(())
+}
+
+/// Runs the prompt on stdin and stdout.
+pub async fn run_stdio(engine: &mut Engine, started: Instant) -> Result<(), Box<dyn Error>> {
+    run(engine, started, io::stdin().lock(), io::stdout()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Command};
+    use std::time::Instant;
+    use transaction_system::{Engine, Money, Transaction, TransactionType};
+
+    #[test]
+    fn parse() {
+        assert_eq!(Command::parse("  \n"), Ok(None));
+        assert_eq!(
+            Command::parse("balance 42\n"),
+            Ok(Some(Command::Balance(42)))
+        );
+        assert_eq!(
+            Command::parse("dispute 42 17"),
+            Ok(Some(Command::Apply(TransactionType::Dispute, 42, 17)))
+        );
+        assert!(Command::parse("balance").is_err());
+        assert!(Command::parse("history 70000").is_err());
+        assert!(Command::parse("dispute 1").is_err());
+        assert!(Command::parse("deposit 1 2").is_err());
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let mut engine = Engine::new();
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                42,
+                17,
+                Some(Money::from(5)),
+            ))
+            .await
+            .unwrap();
+        let input =
+            "balance 42\ndispute 42 17\ndispute 42 17\nhistory 42\nbalance 7\nquit\nstats\n";
+        let mut output = Vec::new();
+        run(&mut engine, Instant::now(), input.as_bytes(), &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "> client,available,held,total,locked\n\
+             42,5.0000,0.0000,5.0000,false\n\
+             > ok\n\
+             > error: Transaction 17 of client 42 rejected: Disputed transaction can't be disputed\n\
+             > client,tx,type,amount,currency,dispute_state,outcome,code,reason\n\
+             42,17,deposit,5.0000,,disputed,accepted,,\n\
+             42,17,dispute,,,,rejected,400,Disputed transaction can't be disputed\n\
+             > error: Client 7 has no account\n\
+             > "
+        );
+    }
+}
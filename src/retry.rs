@@ -0,0 +1,155 @@
+use crate::account::ErrorKind;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+/// Errors that may go away when the operation is simply tried again.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for io::Error {
+    fn is_transient(&self) -> bool {
+        matches!(
+            self.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+        )
+    }
+}
+
+/// Only failures to read or write backing storage are transient, every other rejection
+/// comes out the same however often the transaction is applied.
+impl Transient for ErrorKind {
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::DuplicateCheckFailed(_) | ErrorKind::HistoryUnavailable(_)
+        )
+    }
+}
+
+/// How often and how patiently an operation failing transiently is tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, 1 never tries again
+    pub attempts: u32,
+    /// Wait before the first retry, doubling with every further one
+    pub backoff: Duration,
+    /// Longest wait between two attempts
+    pub max_backoff: Duration,
+}
+
+/// A single attempt, nothing is tried again.
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether another attempt follows the failed attempt `attempt`, counting from 1.
+    pub fn retries(&self, attempt: u32) -> bool {
+        attempt < self.attempts
+    }
+
+    /// Wait after the failed attempt `attempt`, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.backoff
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// Runs `operation` until it succeeds, fails for good or runs out of attempts.
+    pub async fn run<T, E, F, Fut>(&self, mut operation: F) -> Result<T, E>
+    where
+        E: Transient,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(e) if e.is_transient() && self.retries(attempt) => {
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            attempts: 10,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        let waits = (1..=5).map(|attempt| policy.backoff(attempt).as_millis());
+        assert_eq!(waits.collect::<Vec<_>>(), [100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+        assert!(policy.retries(9));
+        assert!(!policy.retries(10));
+    }
+
+    #[tokio::test]
+    async fn run() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+        let mut calls = 0;
+        let result = policy
+            .run(|| {
+                calls += 1;
+                let result = match calls {
+                    1 => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+                    _ => Ok(calls),
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+
+        // Permanent errors aren't tried again, transient ones only as often as allowed
+        calls = 0;
+        let result: Result<(), _> = policy
+            .run(|| {
+                calls += 1;
+                async { Err(io::Error::from(io::ErrorKind::InvalidData)) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        calls = 0;
+        let result: Result<(), _> = policy
+            .run(|| {
+                calls += 1;
+                async { Err(io::Error::from(io::ErrorKind::TimedOut)) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,686 @@
+use crate::engine::{Engine, EngineConfig};
+use crate::grpc;
+use crate::output;
+use crate::reader::{self, ReadOptions, DEFAULT_COLUMNS};
+use crate::store::AccountUpdate;
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use csv::StringRecord;
+use serde_json::{json, Value};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, Mutex, MutexGuard};
+
+/// Environment variable holding the token `POST /admin/reload` has to be called with.
+pub const ADMIN_TOKEN_ENV: &str = "TRANSACTION_ADMIN_TOKEN";
+
+/// Largest request body accepted, transactions are far smaller.
+const MAX_BODY: usize = 1 << 20;
+
+/// Longest line accepted by the line protocol.
+const MAX_LINE: u64 = 1 << 16;
+
+/// Network front of an engine, so transactions can arrive one by one from online services
+/// instead of a batch input, over an HTTP API, gRPC or a plain TCP line protocol.
+///
+/// The HTTP API:
+/// - `POST /transactions` applies the JSON transaction in the body, an object with the
+///   same fields as a row of a JSON input, and answers whether it was accepted or rejected
+/// - `GET /accounts` returns the account report as JSON
+/// - `GET /accounts/{client}` returns the report rows of a single client
+/// - `GET /ws` upgrades to a WebSocket pushing an [`AccountUpdate`] for every accepted
+///   transaction, only those of one client with `/ws?client={client}`, once the server
+///   was given events with [`Server::with_events`]
+/// - `POST /admin/reload` reloads the engine's rules, see [`Server::reload`], once the
+///   server was given a way to with [`Server::with_reload`]. Requests have to carry the
+///   token of [`Server::with_admin_token`] as `Authorization: Bearer <token>`, without one
+///   the endpoint refuses every request
+///
+/// Errors are answered with a JSON object holding the `error`. Request bodies may be sent
+/// chunked and are refused beyond 1 MiB.
+///
+/// The line protocol takes one csv row per line, with the columns `type,client,tx,amount`
+/// unless the first line of a connection is a header naming them. Every line is answered
+/// with one line, `OK <tx>` once applied, `REJECTED <tx> <reason>` when the engine
+/// refused it or `ERROR <reason>` for a malformed row. A header is answered with `OK`.
+///
+/// The gRPC service, [`crate::grpc::SERVICE_PROTO`], takes a stream of transactions and
+/// answers every one with an ack, and returns accounts one at a time or all of them. It
+/// is served without TLS.
+///
+/// Transactions are applied one at a time in the order they arrive, and every response is
+/// only sent once the transaction has been applied.
+pub struct Server {
+    engine: Mutex<Engine>,
+    read_options: ReadOptions,
+    /// Transactions received so far, numbering them like rows of an input
+    received: AtomicU64,
+    events: Option<broadcast::Sender<AccountUpdate>>,
+    reload: Option<Reload>,
+    admin_token: Option<String>,
+}
+
+/// Loads the engine's rules anew, see [`Server::with_reload`].
+type Reload = Box<dyn Fn() -> Result<EngineConfig, String> + Send + Sync>;
+
+/// JSON response of the HTTP API.
+fn respond(status: StatusCode, body: Value) -> Response {
+    let body = format!("{}\n", body);
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+fn error(status: StatusCode, error: impl Into<String>) -> Response {
+    respond(status, json!({ "error": error.into() }))
+}
+
+impl Server {
+    /// Server applying transactions to `engine`, parsing them with `read_options` (only
+    /// the signature verifier of the options matters).
+    pub fn new(engine: Engine, read_options: ReadOptions) -> Self {
+        Self {
+            engine: Mutex::new(engine),
+            read_options,
+            received: AtomicU64::new(0),
+            events: None,
+            reload: None,
+            admin_token: None,
+        }
+    }
+
+    /// Pushes `events` to WebSocket subscribers, e.g. those of a
+    /// [`BroadcastStore`](crate::store::BroadcastStore) the engine keeps its accounts in.
+    pub fn with_events(mut self, events: broadcast::Sender<AccountUpdate>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Reloads the engine's rules with `reload`, which returns a config holding the new fees,
+    /// overdraft limits, limits and fraud rules, e.g. as read again from their files.
+    pub fn with_reload(
+        mut self,
+        reload: impl Fn() -> Result<EngineConfig, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.reload = Some(Box::new(reload));
+        self
+    }
+
+    /// Token `POST /admin/reload` has to be called with, e.g. the one of [`ADMIN_TOKEN_ENV`].
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// Loads the rules anew and hands them to the engine with [`Engine::reconfigure`].
+    /// Transactions arriving meanwhile wait and are checked against the new rules. When
+    /// they can't be loaded the engine keeps the rules it has.
+    pub async fn reload(&self) -> Result<(), String> {
+        let reload = self.reload.as_ref().ok_or("Reloading isn't enabled")?;
+        let mut engine = self.engine.lock().await;
+        let config = reload()?;
+        engine.reconfigure(config).await;
+        Ok(())
+    }
+
+    /// Serves HTTP connections accepted from `listener` until it fails.
+    pub async fn run_http(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+
+    /// Routes of the HTTP API.
+    pub(crate) fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/transactions", post(transaction))
+            .route("/accounts", get(accounts))
+            .route("/accounts/{client}", get(account))
+            .route("/ws", get(subscribe))
+            .route("/admin/reload", post(reload))
+            .method_not_allowed_fallback(method_not_allowed)
+            .fallback(not_found)
+            .layer(DefaultBodyLimit::max(MAX_BODY))
+            .with_state(self)
+    }
+
+    /// Serves line protocol connections accepted from `listener` until it fails.
+    pub async fn run_tcp(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let (reader, writer) = stream.into_split();
+                let _ = server.line_connection(BufReader::new(reader), writer).await;
+            });
+        }
+    }
+
+    /// Serves gRPC connections accepted from `listener` until it fails.
+    pub async fn run_grpc(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        grpc::serve(self, listener).await
+    }
+
+    /// The engine behind the server, e.g. to save its state on shutdown. Requests wait
+    /// while it is held.
+    pub async fn engine(&self) -> MutexGuard<'_, Engine> {
+        self.engine.lock().await
+    }
+
+    pub(crate) fn read_options(&self) -> &ReadOptions {
+        &self.read_options
+    }
+
+    /// Number of the transaction received next, counting like the rows of an input.
+    pub(crate) fn next_row(&self) -> u64 {
+        self.received.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub(crate) async fn line_connection(
+        &self,
+        mut reader: impl AsyncBufRead + Unpin,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> io::Result<()> {
+        let mut headers = StringRecord::from(DEFAULT_COLUMNS.to_vec());
+        let mut first = true;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if (&mut reader).take(MAX_LINE).read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') && line.len() as u64 == MAX_LINE {
+                return writer.write_all(b"ERROR Line too long\n").await;
+            }
+            let row = line.trim();
+            if row.is_empty() {
+                continue;
+            }
+            let response = if std::mem::take(&mut first) && row.starts_with("type") {
+                headers = row.split(',').map(str::trim).collect();
+                "OK".to_string()
+            } else {
+                let row = self.next_row();
+                match reader::parse_csv_line(&headers, &line, row, &self.read_options) {
+                    Ok(transaction) => {
+                        let tx = transaction.tx;
+                        match self.engine.lock().await.process(transaction).await {
+                            Ok(()) => format!("OK {}", tx),
+                            Err(e) => format!("REJECTED {} {}", tx, e.kind),
+                        }
+                    }
+                    Err(reason) => format!("ERROR {}", reason),
+                }
+            };
+            writer
+                .write_all(format!("{}\n", response).as_bytes())
+                .await?;
+        }
+    }
+
+    async fn transaction(&self, body: &[u8]) -> Response {
+        let value = match serde_json::from_slice::<Value>(body) {
+            Ok(value) => value,
+            Err(e) => return error(StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)),
+        };
+        let row = self.next_row();
+        let transaction = match reader::parse_json_transaction(value, row, &self.read_options) {
+            Ok(transaction) => transaction,
+            Err(reason) => return error(StatusCode::BAD_REQUEST, reason),
+        };
+        let tx = transaction.tx;
+        match self.engine.lock().await.process(transaction).await {
+            Ok(()) => respond(StatusCode::OK, json!({ "tx": tx, "status": "accepted" })),
+            Err(e) => respond(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({
+                    "tx": tx,
+                    "status": "rejected",
+                    "code": e.code(),
+                    "reason": e.reason(),
+                    "message": e.kind.to_string(),
+                }),
+            ),
+        }
+    }
+
+    async fn accounts(&self, client: Option<u16>) -> Response {
+        let engine = self.engine.lock().await;
+        let accounts = match client {
+            Some(client) => match engine.account(client).await {
+                Some(account) => vec![account],
+                None => {
+                    let message = format!("No account of client {}", client);
+                    return error(StatusCode::NOT_FOUND, message);
+                }
+            },
+            None => engine.accounts().await,
+        };
+        let config = engine.config();
+        let mut report = Vec::new();
+        let format = &config.output_format;
+        let status = config.status_columns();
+        let written = output::write_json_accounts(&mut report, &accounts, format, status, None);
+        match written.and_then(|()| serde_json::from_slice(&report)) {
+            Ok(report) => respond(StatusCode::OK, report),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+
+    /// Whether the request carries the admin token. Without a token nobody is.
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(expected) = &self.admin_token else {
+            return false;
+        };
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())))
+    }
+}
+
+async fn transaction(
+    State(server): State<Arc<Server>>,
+    body: Result<Bytes, BytesRejection>,
+) -> Response {
+    match body {
+        Ok(body) => server.transaction(&body).await,
+        Err(rejection) => error(rejection.status(), rejection.body_text()),
+    }
+}
+
+async fn accounts(State(server): State<Arc<Server>>) -> Response {
+    server.accounts(None).await
+}
+
+async fn account(State(server): State<Arc<Server>>, Path(client): Path<String>) -> Response {
+    match client.parse::<u16>() {
+        Ok(client) => server.accounts(Some(client)).await,
+        Err(_) => error(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid client {}", client),
+        ),
+    }
+}
+
+async fn reload(State(server): State<Arc<Server>>, uri: Uri, headers: HeaderMap) -> Response {
+    if server.reload.is_none() {
+        return not_found(uri).await;
+    }
+    if !server.authorized(&headers) {
+        let mut response = error(StatusCode::UNAUTHORIZED, "Missing or wrong admin token");
+        let challenge = header::HeaderValue::from_static("Bearer");
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, challenge);
+        return response;
+    }
+    match server.reload().await {
+        Ok(()) => respond(StatusCode::OK, json!({ "status": "reloaded" })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// Upgrades the connection to a WebSocket pushing account events, see [`subscription`].
+async fn subscribe(
+    State(server): State<Arc<Server>>,
+    uri: Uri,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    let Some(events) = &server.events else {
+        return not_found(uri).await;
+    };
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return error(rejection.status(), rejection.body_text()),
+    };
+    let client = match query_client(uri.query()) {
+        Ok(client) => client,
+        Err(reason) => return error(StatusCode::BAD_REQUEST, reason),
+    };
+    let events = events.subscribe();
+    upgrade.on_upgrade(move |socket| async move {
+        // A connection failing only concerns its own client
+        let _ = subscription(socket, events, client).await;
+    })
+}
+
+async fn method_not_allowed(method: Method) -> Response {
+    error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        format!("{} not allowed", method),
+    )
+}
+
+async fn not_found(uri: Uri) -> Response {
+    error(StatusCode::NOT_FOUND, format!("No resource at {}", uri))
+}
+
+/// Client a subscription is filtered to, from the `client` parameter of its query.
+fn query_client(query: Option<&str>) -> Result<Option<u16>, String> {
+    let Some(query) = query else {
+        return Ok(None);
+    };
+    match query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("client="))
+    {
+        Some(client) => client
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid client {}", client)),
+        None => Ok(None),
+    }
+}
+
+/// Pushes events to a WebSocket client until it closes the connection. Pings and the
+/// client's close are answered by the socket itself. Subscribers too slow to keep up miss
+/// the events they fell behind on.
+async fn subscription(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<AccountUpdate>,
+    client: Option<u16>,
+) -> Result<(), axum::Error> {
+    loop {
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            },
+            event = events.recv() => match event {
+                Ok(event) if client.is_none_or(|client| client == event.client) => {
+                    let payload = serde_json::to_string(&event).map_err(axum::Error::new)?;
+                    socket.send(Message::Text(payload.into())).await?;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return socket.send(Message::Close(None)).await,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Server, MAX_BODY};
+    use crate::reader::ReadOptions;
+    use crate::store::BroadcastStore;
+    use crate::{Engine, EngineConfig, Money};
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::Message;
+    use tower::ServiceExt;
+
+    fn request(method: &str, path: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Status and JSON body of the server's response to the request.
+    async fn call(server: &Arc<Server>, request: Request<Body>) -> (StatusCode, Value) {
+        let response = server.clone().router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn listen(server: &Arc<Server>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(server.clone().run_http(listener));
+        address
+    }
+
+    #[tokio::test]
+    async fn connection() {
+        let server = Arc::new(Server::new(Engine::new(), ReadOptions::default()));
+        let address = listen(&server).await;
+
+        // Chunked bodies are read like any other
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5"}"#;
+        let chunked = format!(
+            "POST /transactions HTTP/1.1\r\nhost: test\r\ntransfer-encoding: chunked\r\n\
+             connection: close\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            deposit.len(),
+            deposit
+        );
+        stream.write_all(chunked.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("{\"tx\":1,\"status\":\"accepted\"}\n"));
+
+        // Requests don't grow without bound
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let header = format!("x-padding: {}\r\n", "a".repeat(1 << 12));
+        let oversized = format!("GET /accounts HTTP/1.1\r\n{}\r\n", header.repeat(1 << 8));
+        let _ = stream.write_all(oversized.as_bytes()).await;
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+        assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+
+        let body = " ".repeat(MAX_BODY + 1);
+        let (status, body) = call(&server, request("POST", "/transactions", &body)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(body["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn endpoints() {
+        let server = Arc::new(Server::new(Engine::new(), ReadOptions::default()));
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5"}"#;
+        let (status, body) = call(&server, request("POST", "/transactions", deposit)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "tx": 1, "status": "accepted" }));
+
+        let (status, body) = call(&server, request("POST", "/transactions", deposit)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["reason"], "duplicate_transaction_id");
+        assert_eq!(body["message"], "Transaction id 1 was seen before");
+        assert_eq!(body["code"], 200);
+        let invalid = request("POST", "/transactions", r#"{"type": "deposit"}"#);
+        assert_eq!(call(&server, invalid).await.0, StatusCode::BAD_REQUEST);
+
+        let (status, body) = call(&server, request("GET", "/accounts/1", "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["available"], "5.0000");
+        let (_, body) = call(&server, request("GET", "/accounts", "")).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        let (status, body) = call(&server, request("GET", "/accounts/2", "")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "No account of client 2");
+        let (status, body) = call(&server, request("GET", "/accounts/x", "")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Invalid client x");
+        let (status, body) = call(&server, request("DELETE", "/accounts", "")).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body["error"], "DELETE not allowed");
+        let (status, _) = call(&server, request("GET", "/", "")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&server, request("GET", "/ws", "")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let server = Arc::new(Server::new(Engine::new(), ReadOptions::default()));
+        let reload = |token: Option<&str>| {
+            let mut reload = request("POST", "/admin/reload", "");
+            if let Some(token) = token {
+                let value = format!("Bearer {}", token).parse().unwrap();
+                reload.headers_mut().insert(header::AUTHORIZATION, value);
+            }
+            reload
+        };
+        assert_eq!(
+            call(&server, reload(Some("secret"))).await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        let max_amount = Arc::new(std::sync::Mutex::new(Some(Money::from(10))));
+        let reloading = || {
+            let rules = max_amount.clone();
+            Server::new(Engine::new(), ReadOptions::default()).with_reload(move || {
+                let mut config = EngineConfig::default();
+                config.limits.default.max_amount =
+                    Some(rules.lock().unwrap().ok_or("Invalid limits file")?);
+                Ok(config)
+            })
+        };
+        // Without a token of its own the endpoint takes none
+        let server = Arc::new(reloading());
+        assert_eq!(
+            call(&server, reload(Some(""))).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let server = Arc::new(reloading().with_admin_token("secret"));
+        let deposit = |tx, amount| {
+            let deposit = format!(
+                r#"{{"type": "deposit", "client": 1, "tx": {}, "amount": "{}"}}"#,
+                tx, amount
+            );
+            request("POST", "/transactions", &deposit)
+        };
+        assert_eq!(call(&server, deposit(1, 20)).await.0, StatusCode::OK);
+
+        for token in [None, Some("wrong")] {
+            let (status, body) = call(&server, reload(token)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body["error"], "Missing or wrong admin token");
+        }
+        assert_eq!(call(&server, deposit(2, 20)).await.0, StatusCode::OK);
+
+        let (_, body) = call(&server, reload(Some("secret"))).await;
+        assert_eq!(body, json!({ "status": "reloaded" }));
+        let (_, body) = call(&server, deposit(3, 20)).await;
+        assert_eq!(body["code"], 500);
+        assert_eq!(call(&server, deposit(4, 5)).await.0, StatusCode::OK);
+
+        *max_amount.lock().unwrap() = None;
+        let (status, body) = call(&server, reload(Some("secret"))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"], "Invalid limits file");
+        assert_eq!(
+            call(&server, deposit(5, 20)).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let (_, body) = call(&server, request("GET", "/accounts/1", "")).await;
+        assert_eq!(body[0]["available"], "45.0000");
+    }
+
+    #[tokio::test]
+    async fn line_protocol() {
+        let server = Server::new(Engine::new(), ReadOptions::default());
+        let input = "deposit,1,1,5\nwithdrawal,1,2,9\n\ndeposit,1\n".as_bytes();
+        let mut output = Vec::new();
+        server.line_connection(input, &mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[..2],
+            ["OK 1", "REJECTED 2 Not enough available funds"]
+        );
+        assert!(lines[2].starts_with("ERROR "));
+        assert_eq!(lines.len(), 3);
+
+        // A header line picks the columns of the connection
+        let input = "type,client,tx,amount,currency\ndeposit,2,3,1,EUR\n".as_bytes();
+        let mut output = Vec::new();
+        server.line_connection(input, &mut output).await.unwrap();
+        assert_eq!(output, b"OK\nOK 3\n");
+        assert!(server
+            .engine()
+            .await
+            .account(2)
+            .await
+            .unwrap()
+            .is_multi_currency());
+    }
+
+    #[tokio::test]
+    async fn subscription() {
+        let store = Arc::new(BroadcastStore::new(Default::default()));
+        let engine = Engine::with_store(EngineConfig::default(), store.clone());
+        let server =
+            Arc::new(Server::new(engine, ReadOptions::default()).with_events(store.events()));
+        let address = listen(&server).await;
+
+        // Handshakes of another WebSocket version are refused
+        let mut handshake = request("GET", "/ws", "");
+        for (name, value) in [
+            (header::CONNECTION, "Upgrade"),
+            (header::UPGRADE, "websocket"),
+            (header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ=="),
+            (header::SEC_WEBSOCKET_VERSION, "8"),
+        ] {
+            handshake.headers_mut().insert(name, value.parse().unwrap());
+        }
+        let (status, body) = call(&server, handshake).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string());
+
+        let url = format!("ws://{}/ws?client=2", address);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        for (client, tx) in [(1, 1), (2, 2)] {
+            let deposit = json!({ "type": "deposit", "client": client, "tx": tx, "amount": "5" });
+            let deposit = request("POST", "/transactions", &deposit.to_string());
+            assert_eq!(call(&server, deposit).await.0, StatusCode::OK);
+        }
+        let dispute = r#"{"type": "dispute", "client": 2, "tx": 2}"#;
+        call(&server, request("POST", "/transactions", dispute)).await;
+
+        // Only events of client 2 arrive
+        let text = |message: Option<Result<Message, _>>| match message {
+            Some(Ok(Message::Text(text))) => serde_json::from_str::<Value>(&text).unwrap(),
+            message => panic!("Expected an event, got {:?}", message),
+        };
+        let event = text(socket.next().await);
+        assert_eq!(
+            event,
+            json!({
+                "client": 2,
+                "tx": 2,
+                "dispute_state": "none",
+                "locked": false,
+                "balances": [
+                    { "client": 2, "available": "5.0000", "held": "0.0000", "total": "5.0000", "locked": false }
+                ]
+            })
+        );
+        let event = text(socket.next().await);
+        assert_eq!(event["dispute_state"], "disputed");
+        assert_eq!(event["balances"][0]["held"], "5.0000");
+
+        // Pings are answered and a close is answered with a close
+        socket.send(Message::Ping("ping".into())).await.unwrap();
+        assert_eq!(
+            socket.next().await.unwrap().unwrap(),
+            Message::Pong("ping".into())
+        );
+        socket.close(None).await.unwrap();
+        assert!(matches!(socket.next().await, Some(Ok(Message::Close(_)))));
+    }
+}
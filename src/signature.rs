@@ -0,0 +1,173 @@
+use csv::StringRecord;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_COLUMN: &str = "signature";
+pub const SIGNING_KEY_ENV: &str = "TRANSACTION_SIGNING_KEY";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    Malformed,
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Transaction signature verification failed {:?}", self)
+    }
+}
+
+/// Verifies the optional `signature` column of transaction rows.
+///
+/// The signature is a hex encoded HMAC-SHA256 over every other column of the row, in the
+/// order they appear in the file: the column's name and then its value, each preceded by
+/// its length in bytes as a big endian `u64`. Signing the names keeps columns from being
+/// swapped, and the lengths keep values from running into each other.
+#[derive(Clone)]
+pub struct RowVerifier {
+    key: Vec<u8>,
+}
+
+impl RowVerifier {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        match std::env::var(SIGNING_KEY_ENV) {
+            Ok(key) if !key.is_empty() => Some(Self::new(key.as_bytes())),
+            _ => None,
+        }
+    }
+
+    fn mac(&self, headers: &StringRecord, record: &StringRecord) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        let columns = headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(header, _)| *header != SIGNATURE_COLUMN);
+        for (header, field) in columns {
+            for part in [header, field] {
+                mac.update(&(part.len() as u64).to_be_bytes());
+                mac.update(part.as_bytes());
+            }
+        }
+        mac
+    }
+
+    pub fn sign(&self, headers: &StringRecord, record: &StringRecord) -> String {
+        hex::encode(self.mac(headers, record).finalize().into_bytes())
+    }
+
+    pub fn verify(
+        &self,
+        headers: &StringRecord,
+        record: &StringRecord,
+    ) -> Result<(), SignatureError> {
+        let signature = headers
+            .iter()
+            .position(|header| header == SIGNATURE_COLUMN)
+            .and_then(|index| record.get(index))
+            .filter(|signature| !signature.is_empty())
+            .ok_or(SignatureError::Missing)?;
+        let signature = hex::decode(signature).map_err(|_| SignatureError::Malformed)?;
+
+        self.mac(headers, record)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RowVerifier, SignatureError};
+    use csv::StringRecord;
+
+    fn headers() -> StringRecord {
+        StringRecord::from(vec!["type", "client", "tx", "amount", "signature"])
+    }
+
+    fn signed_row(verifier: &RowVerifier, fields: [&str; 4]) -> StringRecord {
+        let mut record = StringRecord::from(fields.to_vec());
+        record.push_field("");
+        let signature = verifier.sign(&headers(), &record);
+
+        let mut signed = StringRecord::from(fields.to_vec());
+        signed.push_field(&signature);
+        signed
+    }
+
+    #[test]
+    fn valid_signature() {
+        let verifier = RowVerifier::new(b"secret");
+        let row = signed_row(&verifier, ["deposit", "1", "1", "1.0"]);
+        assert_eq!(verifier.verify(&headers(), &row), Ok(()));
+    }
+
+    #[test]
+    fn tampered_row() {
+        let verifier = RowVerifier::new(b"secret");
+        let row = signed_row(&verifier, ["deposit", "1", "1", "1.0"]);
+        let tampered = StringRecord::from(vec!["deposit", "1", "1", "100.0", &row[4]]);
+        assert_eq!(
+            verifier.verify(&headers(), &tampered),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn swapped_columns() {
+        let verifier = RowVerifier::new(b"secret");
+        let row = signed_row(&verifier, ["deposit", "1", "2", "1.0"]);
+        let swapped = StringRecord::from(vec!["type", "tx", "client", "amount", "signature"]);
+        assert_eq!(
+            verifier.verify(&swapped, &row),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn shifted_separator() {
+        let verifier = RowVerifier::new(b"secret");
+        let headers = StringRecord::from(vec!["type", "client", "tx", "reason", "signature"]);
+        let mut record = StringRecord::from(vec!["deposit", "1", "1", "a,b"]);
+        record.push_field("");
+        let signature = verifier.sign(&headers, &record);
+        let shifted = StringRecord::from(vec!["deposit", "1", "1,a", "b", &signature]);
+        assert_eq!(
+            verifier.verify(&headers, &shifted),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn forged_signature() {
+        let forger = RowVerifier::new(b"not the secret");
+        let row = signed_row(&forger, ["deposit", "1", "1", "1.0"]);
+        assert_eq!(
+            RowVerifier::new(b"secret").verify(&headers(), &row),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn missing_or_malformed_signature() {
+        let verifier = RowVerifier::new(b"secret");
+        let unsigned = StringRecord::from(vec!["deposit", "1", "1", "1.0", ""]);
+        assert_eq!(
+            verifier.verify(&headers(), &unsigned),
+            Err(SignatureError::Missing)
+        );
+
+        let malformed = StringRecord::from(vec!["deposit", "1", "1", "1.0", "xyz"]);
+        assert_eq!(
+            verifier.verify(&headers(), &malformed),
+            Err(SignatureError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::account::{
+    AuthorizationState, Balance, DisputeState, IncomingTransfer, OutgoingTransfer,
+};
+use crate::currency::Currency;
+use crate::encryption::{self, Cipher};
+use crate::fees::FeeEntry;
+use crate::interest::InterestPosting;
+use crate::money::Money;
+use crate::timestamp::Timestamp;
+use crate::transaction::StoredTransaction;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Version of the snapshot format, bumped whenever it changes incompatibly.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct HistoryState {
+    pub transaction: StoredTransaction,
+    pub dispute_state: DisputeState,
+    pub dispute_lifecycle: Vec<DisputeState>,
+    pub refunded: Money,
+    pub disputed: Money,
+    pub authorization_state: Option<AuthorizationState>,
+}
+
+/// Everything an account learned from its transactions. Its configuration (policies, fees,
+/// limits, fraud rules) comes from the engine config of the run it is loaded into, and
+/// fraud rules start over without state.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AccountSnapshot {
+    pub client: u16,
+    pub balances: Vec<(Option<Currency>, Balance)>,
+    pub locked: bool,
+    pub closed: bool,
+    pub history: Vec<HistoryState>,
+    pub open_authorizations: Vec<u32>,
+    #[serde(default)]
+    pub outgoing_transfers: Vec<(u32, OutgoingTransfer)>,
+    #[serde(default)]
+    pub incoming_transfers: Vec<(u32, IncomingTransfer)>,
+    pub fees: Vec<FeeEntry>,
+    pub maintenance_month: Option<i64>,
+    pub accrued_interest: Vec<(Option<Currency>, Money)>,
+    pub accrual_day: Option<i64>,
+    pub interest: Vec<InterestPosting>,
+    pub daily_withdrawals: Option<(i64, Money, u32)>,
+    pub recent_transactions: Vec<Timestamp>,
+}
+
+/// Full state of an engine: every account with its balances, locks and history (including
+/// open disputes and authorizations), and the transaction ids seen so far.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub(crate) version: u32,
+    pub(crate) accounts: Vec<AccountSnapshot>,
+    pub(crate) transaction_ids: Vec<u32>,
+    /// Number of input transactions consumed when the snapshot is a checkpoint of an
+    /// interrupted run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cursor: Option<u64>,
+    /// Where the topic continues when the snapshot is a checkpoint of a run consuming Kafka
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) topic: Option<TopicOffsets>,
+}
+
+/// Topic a checkpointed run consumed, and the offset of every partition it continues from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicOffsets {
+    pub topic: String,
+    pub offsets: BTreeMap<i32, i64>,
+}
+
+impl Snapshot {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Marks the snapshot as a checkpoint taken after `cursor` input transactions.
+    pub fn with_cursor(mut self, cursor: u64) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    pub fn cursor(&self) -> Option<u64> {
+        self.cursor
+    }
+
+    /// Marks the checkpoint as continuing the topic at the given offsets.
+    pub fn with_topic(mut self, topic: TopicOffsets) -> Self {
+        self.topic = Some(topic);
+        self
+    }
+
+    pub fn topic(&self) -> Option<&TopicOffsets> {
+        self.topic.as_ref()
+    }
+
+    /// Saves the snapshot as JSON, encrypted with `cipher` when given.
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        cipher: Option<&Cipher>,
+    ) -> Result<(), Box<dyn Error>> {
+        match cipher {
+            Some(cipher) => std::fs::write(path, cipher.seal_file(&serde_json::to_vec(self)?))?,
+            None => {
+                let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                serde_json::to_writer(file, self)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a snapshot, its version is checked once it is restored. Encrypted snapshots
+    /// need the `cipher` they were saved with.
+    pub fn load(path: impl AsRef<Path>, cipher: Option<&Cipher>) -> Result<Self, Box<dyn Error>> {
+        let file = encryption::open_file(cipher, std::fs::read(path)?)?;
+        Ok(serde_json::from_slice(&file)?)
+    }
+}
@@ -0,0 +1,317 @@
+use crate::state::{self, KeyValueStore, TRANSACTION_IDS_KEY};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    client INTEGER PRIMARY KEY,
+    locked INTEGER NOT NULL,
+    closed INTEGER NOT NULL,
+    -- Everything else the account learned, fees, interest and limits, as JSON
+    state TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS balances (
+    client INTEGER NOT NULL,
+    -- NULL for the default currency
+    currency TEXT,
+    available TEXT NOT NULL,
+    held TEXT NOT NULL,
+    total TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS balances_client ON balances (client);
+CREATE TABLE IF NOT EXISTS history (
+    client INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    tx INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    amount TEXT,
+    currency TEXT,
+    dispute_state TEXT NOT NULL,
+    -- The whole entry as JSON, with the transaction's other fields and refunds
+    entry TEXT NOT NULL,
+    PRIMARY KEY (client, position)
+);
+CREATE TABLE IF NOT EXISTS transaction_ids (
+    tx INTEGER PRIMARY KEY
+);
+";
+
+/// Store keeping engine state in the tables of a SQLite database, where it can be queried
+/// with SQL: a row in `accounts` per client, with its rows in `balances` and `history`, and
+/// the ids seen so far in `transaction_ids`. Every value is replaced in a database
+/// transaction of its own, so a crash mid-write leaves the previous value in place.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(database_error)?;
+        connection.execute_batch(SCHEMA).map_err(database_error)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn account(&self, client: u16) -> rusqlite::Result<Option<Value>> {
+        let connection = self.connection.lock().expect("SQLite connection poisoned");
+        let state = connection
+            .query_row(
+                "SELECT state FROM accounts WHERE client = ?1",
+                [client],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        let Some(state) = state else {
+            return Ok(None);
+        };
+        let mut account = serde_json::from_str::<Map<String, Value>>(&state).map_err(json_error)?;
+
+        let mut balances = connection.prepare(
+            "SELECT currency, available, held, total FROM balances WHERE client = ?1 ORDER BY rowid",
+        )?;
+        let balances = balances
+            .query_map([client], |row| {
+                let balance = serde_json::json!({
+                    "available": row.get::<_, String>(1)?,
+                    "held": row.get::<_, String>(2)?,
+                    "total": row.get::<_, String>(3)?,
+                });
+                Ok(Value::Array(vec![
+                    row.get::<_, Option<String>>(0)?.into(),
+                    balance,
+                ]))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut history =
+            connection.prepare("SELECT entry FROM history WHERE client = ?1 ORDER BY position")?;
+        let history = history
+            .query_map([client], |row| row.get::<_, String>(0))?
+            .map(|entry| serde_json::from_str::<Value>(&entry?).map_err(json_error))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        account.insert("client".to_string(), client.into());
+        account.insert("balances".to_string(), balances.into());
+        account.insert("history".to_string(), history.into());
+        Ok(Some(Value::Object(account)))
+    }
+
+    fn put_account(&self, client: u16, mut account: Map<String, Value>) -> rusqlite::Result<()> {
+        let balances = account.remove("balances").unwrap_or_default();
+        let history = account.remove("history").unwrap_or_default();
+        let flag = |name: &str| account.get(name).and_then(Value::as_bool).unwrap_or(false);
+        let (locked, closed) = (flag("locked"), flag("closed"));
+
+        let mut connection = self.connection.lock().expect("SQLite connection poisoned");
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM balances WHERE client = ?1", [client])?;
+        transaction.execute("DELETE FROM history WHERE client = ?1", [client])?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO accounts (client, locked, closed, state) VALUES (?1, ?2, ?3, ?4)",
+            params![client, locked, closed, Value::Object(account).to_string()],
+        )?;
+        for balance in balances.as_array().into_iter().flatten() {
+            let text = |name: &str| balance[1][name].as_str().unwrap_or_default().to_string();
+            transaction.execute(
+                "INSERT INTO balances (client, currency, available, held, total) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    client,
+                    balance[0].as_str(),
+                    text("available"),
+                    text("held"),
+                    text("total")
+                ],
+            )?;
+        }
+        for (position, entry) in history.as_array().into_iter().flatten().enumerate() {
+            let transaction_field = |name: &str| entry["transaction"][name].as_str();
+            transaction.execute(
+                "INSERT INTO history (client, position, tx, type, amount, currency, dispute_state, entry)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    client,
+                    position as i64,
+                    entry["transaction"]["tx"].as_u64().unwrap_or_default() as i64,
+                    transaction_field("type").unwrap_or_default(),
+                    transaction_field("amount"),
+                    transaction_field("currency"),
+                    entry["dispute_state"].as_str().unwrap_or_default(),
+                    entry.to_string(),
+                ],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    fn transaction_ids(&self) -> rusqlite::Result<Vec<u32>> {
+        let connection = self.connection.lock().expect("SQLite connection poisoned");
+        let mut ids = connection.prepare("SELECT tx FROM transaction_ids ORDER BY tx")?;
+        let ids = ids.query_map([], |row| row.get(0))?.collect();
+        ids
+    }
+
+    fn put_transaction_ids(&self, ids: &[u32]) -> rusqlite::Result<()> {
+        let mut connection = self.connection.lock().expect("SQLite connection poisoned");
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM transaction_ids", [])?;
+        {
+            let mut insert = transaction.prepare("INSERT INTO transaction_ids (tx) VALUES (?1)")?;
+            for id in ids {
+                insert.execute([id])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+impl KeyValueStore for SqliteStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        if key == TRANSACTION_IDS_KEY {
+            let ids = self.transaction_ids().map_err(database_error)?;
+            return Ok(Some(serde_json::to_vec(&ids)?));
+        }
+        match state::account_client(key) {
+            Some(client) => match self.account(client).map_err(database_error)? {
+                Some(account) => Ok(Some(serde_json::to_vec(&account)?)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        if key == TRANSACTION_IDS_KEY {
+            let ids = serde_json::from_slice::<Vec<u32>>(value)?;
+            return self.put_transaction_ids(&ids).map_err(database_error);
+        }
+        let Some(client) = state::account_client(key) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown key {}", key),
+            ));
+        };
+        let account = serde_json::from_slice::<Map<String, Value>>(value)?;
+        self.put_account(client, account).map_err(database_error)
+    }
+
+    fn keys(&self) -> io::Result<Vec<String>> {
+        let connection = self.connection.lock().expect("SQLite connection poisoned");
+        let mut clients = connection
+            .prepare("SELECT client FROM accounts ORDER BY client")
+            .map_err(database_error)?;
+        let mut keys = clients
+            .query_map([], |row| row.get::<_, u16>(0))
+            .map_err(database_error)?
+            .map(|client| client.map(state::account_key))
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(database_error)?;
+        keys.push(TRANSACTION_IDS_KEY.to_string());
+        Ok(keys)
+    }
+}
+
+fn json_error(e: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(0, Type::Text, e.into())
+}
+
+fn database_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(format!("SQLite error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteStore;
+    use crate::state::SledStore;
+    use crate::{Engine, EngineConfig, ErrorKind, Money, Transaction, TransactionType};
+
+    #[tokio::test]
+    async fn sqlite_store() {
+        let path = std::env::temp_dir().join(format!("state_{}.db", std::process::id()));
+        let store = SqliteStore::open(&path).unwrap();
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(7))),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(4))),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        engine.save_state(&store).await.unwrap();
+        // Saving again replaces the rows rather than adding to them
+        engine.save_state(&store).await.unwrap();
+
+        {
+            let connection = store.connection.lock().unwrap();
+            let balances = connection
+                .prepare("SELECT client, available, held, total FROM balances ORDER BY client")
+                .unwrap()
+                .query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .unwrap()
+                .collect::<Result<Vec<(u16, String, String, String)>, _>>()
+                .unwrap();
+            let strings = |s: [&str; 3]| s.map(str::to_string);
+            let [a, h, t] = strings(["6", "0", "6"]);
+            assert_eq!(balances[0], (1, a, h, t));
+            let [a, h, t] = strings(["0", "7", "7"]);
+            assert_eq!(balances[1], (2, a, h, t));
+            let history = connection
+                .prepare("SELECT client, tx, type, amount, dispute_state FROM history ORDER BY client, position")
+                .unwrap()
+                .query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+                })
+                .unwrap()
+                .collect::<Result<Vec<(u16, u32, String, Option<String>, String)>, _>>()
+                .unwrap();
+            assert_eq!(history.len(), 3);
+            assert_eq!(
+                history[2],
+                (
+                    2,
+                    2,
+                    "deposit".to_string(),
+                    Some("7".to_string()),
+                    "Disputed".to_string()
+                )
+            );
+        }
+
+        // The state comes back the same as from a state directory
+        let dir = tempfile::tempdir().unwrap();
+        let dir_store = SledStore::open(dir.path(), None).unwrap();
+        engine.save_state(&dir_store).await.unwrap();
+        let mut from_sqlite = Engine::with_config(EngineConfig::default());
+        from_sqlite.load_state(&store).unwrap();
+        let mut from_dir = Engine::with_config(EngineConfig::default());
+        from_dir.load_state(&dir_store).unwrap();
+        assert_eq!(
+            serde_json::to_value(from_sqlite.snapshot().await.unwrap()).unwrap(),
+            serde_json::to_value(from_dir.snapshot().await.unwrap()).unwrap()
+        );
+        let transactions = [
+            Transaction::new(TransactionType::Resolve, 2, 2, None),
+            Transaction::new(TransactionType::Deposit, 3, 1, Some(Money::from(1))),
+        ];
+        for transaction in transactions {
+            from_sqlite.submit(transaction).await.unwrap();
+        }
+        let rejections = from_sqlite.wait().await;
+        assert_eq!(rejections.len(), 1);
+        assert!(matches!(
+            rejections[0].error.kind,
+            ErrorKind::DuplicateTransactionId(1)
+        ));
+        let account = from_sqlite.account(2).await.unwrap();
+        assert_eq!(account.available(), Money::from(7));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use crate::encryption::{self, Cipher};
+use std::io;
+use std::path::Path;
+
+/// Key of the transaction ids seen so far, shared by all accounts.
+pub(crate) const TRANSACTION_IDS_KEY: &str = "transaction-ids";
+
+const ACCOUNT_KEY_PREFIX: &str = "account-";
+
+pub(crate) fn account_key(client: u16) -> String {
+    format!("{}{}", ACCOUNT_KEY_PREFIX, client)
+}
+
+pub(crate) fn account_client(key: &str) -> Option<u16> {
+    key.strip_prefix(ACCOUNT_KEY_PREFIX)?.parse().ok()
+}
+
+/// Embedded key-value store engine state is persisted in between runs.
+pub trait KeyValueStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()>;
+    fn keys(&self) -> io::Result<Vec<String>>;
+
+    /// Makes every value put so far durable, called once all of them are put.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Store keeping every key in a [sled](https://docs.rs/sled) database within a directory.
+/// Values are replaced atomically and are durable once the store is flushed, so a crash
+/// mid-save leaves either the previous or the new value of every key in place.
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    db: sled::Db,
+    cipher: Option<Cipher>,
+}
+
+impl SledStore {
+    /// Opens the database in `dir`, creating it if needed. With a cipher values are written
+    /// encrypted and plaintext ones already in the database are refused.
+    pub fn open(dir: impl AsRef<Path>, cipher: Option<Cipher>) -> io::Result<Self> {
+        Ok(Self {
+            db: sled::open(dir).map_err(io::Error::from)?,
+            cipher,
+        })
+    }
+}
+
+impl KeyValueStore for SledStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.db.get(key)? {
+            Some(value) => encryption::open_file(self.cipher.as_ref(), value.to_vec())
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        match &self.cipher {
+            Some(cipher) => self.db.insert(key, cipher.seal_file(value))?,
+            None => self.db.insert(key, value)?,
+        };
+        Ok(())
+    }
+
+    fn keys(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for key in self.db.iter().keys() {
+            if let Ok(key) = String::from_utf8(key?.to_vec()) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{account_client, account_key, KeyValueStore, SledStore};
+    use crate::encryption::Cipher;
+
+    #[test]
+    fn sled_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledStore::open(dir.path(), None).unwrap();
+        assert_eq!(store.get("missing").unwrap(), None);
+        store.put(&account_key(7), b"first").unwrap();
+        store.put(&account_key(7), b"second").unwrap();
+        assert_eq!(store.get("account-7").unwrap(), Some(b"second".to_vec()));
+        assert_eq!(store.keys().unwrap(), ["account-7"]);
+        assert_eq!(account_client("account-7"), Some(7));
+        assert_eq!(account_client("transaction-ids"), None);
+        store.flush().unwrap();
+        // The database is locked while it's open
+        drop(store);
+
+        let encrypted = SledStore::open(dir.path(), Some(Cipher::new(&[1; 32]))).unwrap();
+        assert!(encrypted.get("account-7").is_err());
+        encrypted.put(&account_key(7), b"third").unwrap();
+        assert_eq!(encrypted.get("account-7").unwrap(), Some(b"third".to_vec()));
+        encrypted.flush().unwrap();
+        drop(encrypted);
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                let bytes = std::fs::read(path).unwrap();
+                assert!(!bytes.windows(5).any(|window| window == b"third"));
+            }
+        }
+        let store = SledStore::open(dir.path(), None).unwrap();
+        assert!(store.get("account-7").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::account::Balance;
+use crate::currency::Currency;
+use crate::money::{Money, MoneyFormat};
+use crate::timestamp::Timestamp;
+use crate::transaction::{self, TransactionType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+
+/// Transaction an account accepted, with the balance of its currency right after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    /// Line of the input the transaction was read from
+    pub row: Option<u64>,
+    pub tx: u32,
+    pub timestamp: Option<Timestamp>,
+    pub transaction_type: TransactionType,
+    pub amount: Option<Money>,
+    /// `None` for the account's default currency
+    pub currency: Option<Currency>,
+    /// For transfers, the other account of the transfer
+    pub counterparty: Option<u16>,
+    pub balance: Balance,
+    /// Input columns of the transaction the engine doesn't know, by name
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct StatementRecord {
+    row: Option<u64>,
+    tx: u32,
+    timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    amount: Option<String>,
+    currency: Option<String>,
+    counterparty: Option<u16>,
+    available: String,
+    held: String,
+    total: String,
+}
+
+/// Writes statement lines as csv, oldest first, with amounts and balances in `format`.
+/// Fees and interest posted along with a transaction show in its balances. Metadata gets a
+/// column per name after the balances.
+pub fn write_statement<'a, W: io::Write>(
+    writer: W,
+    lines: impl IntoIterator<Item = &'a StatementLine>,
+    format: &MoneyFormat,
+) -> Result<(), csv::Error> {
+    let lines = lines.into_iter().collect::<Vec<_>>();
+    let metadata = transaction::metadata_columns(lines.iter().map(|line| &line.metadata));
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    let header = [
+        "row",
+        "tx",
+        "timestamp",
+        "type",
+        "amount",
+        "currency",
+        "counterparty",
+        "available",
+        "held",
+        "total",
+    ];
+    writer.write_record(header.iter().chain(&metadata))?;
+    for line in lines {
+        let record = StatementRecord {
+            row: line.row,
+            tx: line.tx,
+            timestamp: line.timestamp,
+            transaction_type: line.transaction_type.clone(),
+            amount: line.amount.map(|amount| amount.format(format)),
+            currency: line.currency.as_ref().map(ToString::to_string),
+            counterparty: line.counterparty,
+            available: line.balance.available().format(format),
+            held: line.balance.held().format(format),
+            total: line.balance.total().format(format),
+        };
+        let values = metadata.iter().map(|name| line.metadata.get(*name));
+        writer.serialize((record, values.collect::<Vec<_>>()))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_statement;
+    use crate::money::MoneyFormat;
+    use crate::{Account, Money, Transaction, TransactionType};
+
+    #[test]
+    fn running_balances() {
+        let mut account = Account::new(1).with_statement();
+        let mut other = Account::new(2).with_statement();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10)))
+                .with_row(2)
+                .with_metadata("memo", "salary"),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Money::from(20))).with_row(3),
+            Transaction::new(TransactionType::Dispute, 1, 1, None).with_row(4),
+            Transaction::new(TransactionType::Resolve, 1, 1, None).with_row(5),
+        ];
+        for transaction in transactions {
+            account.add_transaction(transaction);
+            let _ = account.process_pending_transaction();
+        }
+        let mut transfer =
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Money::from(4))).with_row(6);
+        transfer.to_client = Some(2);
+        account.transfer(&mut other, transfer).unwrap();
+
+        // The rejected withdrawal isn't on the statement
+        let mut buffer = Vec::new();
+        write_statement(&mut buffer, account.statement(), &MoneyFormat::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "row,tx,timestamp,type,amount,currency,counterparty,available,held,total,memo\n\
+             2,1,,deposit,10.0000,,,10.0000,0.0000,10.0000,salary\n\
+             4,1,,dispute,,,,0.0000,10.0000,10.0000,\n\
+             5,1,,resolve,,,,10.0000,0.0000,10.0000,\n\
+             6,3,,transfer,4.0000,,2,6.0000,0.0000,6.0000,\n"
+        );
+        assert_eq!(other.statement().len(), 1);
+        assert_eq!(other.statement()[0].counterparty, Some(1));
+        assert_eq!(other.statement()[0].balance.total(), Money::from(4));
+        assert_eq!(account.clone().statement(), account.statement());
+        assert!(Account::new(3).statement().is_empty());
+    }
+}
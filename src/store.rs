@@ -0,0 +1,254 @@
+use crate::account::{Account, AccountRecord, DisputeState, ErrorKind};
+use crate::engine::Rejection;
+use crate::money::MoneyFormat;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+/// Events buffered per subscriber before slow ones start missing events.
+const EVENT_BUFFER: usize = 1024;
+
+/// Where the engine keeps its accounts.
+///
+/// The engine looks accounts up and adds new ones through the store, and tells it about
+/// every transaction an account accepted, so stores can persist or cache accounts, or
+/// record what happened in tests, without the processing logic knowing about it. Workers
+/// share the store, hence every method takes `&self`.
+pub trait StateStore: Send + Sync {
+    fn get(&self, client: u16) -> Option<Arc<Mutex<Account>>>;
+
+    fn put(&self, client: u16, account: Arc<Mutex<Account>>);
+
+    /// Clients of all stored accounts, in ascending order.
+    fn clients(&self) -> Vec<u16>;
+
+    /// Called once `account` accepted the transaction `tx`, with the account still locked.
+    /// Its history entry is `account.history_entry(tx)`, if the transaction has one.
+    fn append_history(&self, _account: &Account, _tx: u32) {}
+
+    /// Called once the engine rejected a transaction, with its account still locked when
+    /// the transaction got as far as the account.
+    fn reject(&self, _rejection: &Rejection, _account: Option<&Account>) {}
+}
+
+/// Default store keeping accounts in memory only.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    accounts: RwLock<HashMap<u16, Arc<Mutex<Account>>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, client: u16) -> Option<Arc<Mutex<Account>>> {
+        self.accounts
+            .read()
+            .expect("Account store poisoned")
+            .get(&client)
+            .cloned()
+    }
+
+    fn put(&self, client: u16, account: Arc<Mutex<Account>>) {
+        self.accounts
+            .write()
+            .expect("Account store poisoned")
+            .insert(client, account);
+    }
+
+    fn clients(&self) -> Vec<u16> {
+        let mut clients = self
+            .accounts
+            .read()
+            .expect("Account store poisoned")
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        clients.sort_unstable();
+        clients
+    }
+}
+
+/// What a processed transaction did to its account.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AccountUpdate {
+    pub client: u16,
+    pub tx: u32,
+    /// Dispute state of the transaction's history entry, if it has one
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "dispute_state_name"
+    )]
+    pub dispute_state: Option<DisputeState>,
+    /// Why the transaction was rejected, serialized with its code and reason
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorKind>,
+    /// Unknown for transactions rejected before they got to the account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked: Option<bool>,
+    /// Report rows of the account after the transaction, one per currency it holds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub balances: Vec<AccountRecord>,
+}
+
+impl AccountUpdate {
+    /// Event of `account` accepting the transaction `tx`.
+    pub fn accepted(account: &Account, tx: u32, format: &MoneyFormat) -> Self {
+        Self {
+            client: account.client(),
+            tx,
+            dispute_state: account.history_entry(tx).map(|entry| entry.dispute_state()),
+            error: None,
+            locked: Some(account.locked()),
+            balances: match account.is_multi_currency() {
+                true => account.records(format),
+                false => vec![account.record(format)],
+            },
+        }
+    }
+
+    /// Event of the rejection, with the account when the transaction got to it.
+    pub fn rejected(
+        rejection: &Rejection,
+        account: Option<&Account>,
+        format: &MoneyFormat,
+    ) -> Self {
+        let event = match account {
+            Some(account) => Self::accepted(account, rejection.error.tx, format),
+            None => Self {
+                client: rejection.error.client,
+                tx: rejection.error.tx,
+                dispute_state: None,
+                error: None,
+                locked: None,
+                balances: Vec::new(),
+            },
+        };
+        Self {
+            error: Some(rejection.error.kind.clone()),
+            ..event
+        }
+    }
+}
+
+fn dispute_state_name<S: Serializer>(
+    state: &Option<DisputeState>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    state.map(DisputeState::name).serialize(serializer)
+}
+
+/// Store keeping accounts in memory and broadcasting an [`AccountUpdate`] for every
+/// accepted transaction, e.g. to push live updates to subscribers of a server.
+#[derive(Debug)]
+pub struct BroadcastStore {
+    accounts: MemoryStore,
+    events: broadcast::Sender<AccountUpdate>,
+    format: MoneyFormat,
+}
+
+impl BroadcastStore {
+    /// Store formatting the balances of events with `format`.
+    pub fn new(format: MoneyFormat) -> Self {
+        Self {
+            accounts: MemoryStore::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            format,
+        }
+    }
+
+    /// Sender of the events, call `subscribe` on it to receive them.
+    pub fn events(&self) -> broadcast::Sender<AccountUpdate> {
+        self.events.clone()
+    }
+}
+
+impl StateStore for BroadcastStore {
+    fn get(&self, client: u16) -> Option<Arc<Mutex<Account>>> {
+        self.accounts.get(client)
+    }
+
+    fn put(&self, client: u16, account: Arc<Mutex<Account>>) {
+        self.accounts.put(client, account)
+    }
+
+    fn clients(&self) -> Vec<u16> {
+        self.accounts.clients()
+    }
+
+    fn append_history(&self, account: &Account, tx: u32) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        // Sending only fails without subscribers
+        let _ = self
+            .events
+            .send(AccountUpdate::accepted(account, tx, &self.format));
+    }
+}
+
+/// Store queueing an [`AccountUpdate`] for every transaction the engine processed, accepted
+/// or rejected, on top of the store `S` keeping the accounts, e.g. for a
+/// [`NatsPublisher`](crate::nats::NatsPublisher) to send on.
+///
+/// Queues are unbounded so processing never waits for subscribers, events pile up in
+/// memory while a subscriber is slower than the engine.
+#[derive(Debug)]
+pub struct EventStore<S = MemoryStore> {
+    accounts: S,
+    subscribers: Vec<mpsc::UnboundedSender<AccountUpdate>>,
+    format: MoneyFormat,
+}
+
+impl<S: StateStore> EventStore<S> {
+    /// Store formatting the balances of events with `format`.
+    pub fn new(accounts: S, format: MoneyFormat) -> Self {
+        Self {
+            accounts,
+            subscribers: Vec::new(),
+            format,
+        }
+    }
+
+    /// Queue receiving every event from now on, it ends once the store is dropped.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<AccountUpdate> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn send(&self, event: AccountUpdate) {
+        for subscriber in &self.subscribers {
+            // Sending only fails once the subscriber is gone, which reports its own error
+            let _ = subscriber.send(event.clone());
+        }
+    }
+}
+
+impl<S: StateStore> StateStore for EventStore<S> {
+    fn get(&self, client: u16) -> Option<Arc<Mutex<Account>>> {
+        self.accounts.get(client)
+    }
+
+    fn put(&self, client: u16, account: Arc<Mutex<Account>>) {
+        self.accounts.put(client, account)
+    }
+
+    fn clients(&self) -> Vec<u16> {
+        self.accounts.clients()
+    }
+
+    fn append_history(&self, account: &Account, tx: u32) {
+        self.accounts.append_history(account, tx);
+        self.send(AccountUpdate::accepted(account, tx, &self.format));
+    }
+
+    fn reject(&self, rejection: &Rejection, account: Option<&Account>) {
+        self.accounts.reject(rejection, account);
+        self.send(AccountUpdate::rejected(rejection, account, &self.format));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::account::Account;
+use crate::currency::Currency;
+use crate::engine::Rejection;
+use crate::money::Money;
+use crate::transaction::TransactionType;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Duration;
+
+/// Transactions the engine accepted so far, see [`crate::Engine::tally`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    /// Accepted transactions by type
+    pub accepted: BTreeMap<TransactionType, u64>,
+    /// Accepted deposits and withdrawals by currency, `None` for the default one. A total
+    /// that no longer fits into [`Money`] is `None` from then on.
+    pub deposited: BTreeMap<Option<Currency>, Option<Money>>,
+    pub withdrawn: BTreeMap<Option<Currency>, Option<Money>>,
+    /// Clients with an accepted transaction
+    pub clients: BTreeSet<u16>,
+}
+
+impl Tally {
+    pub(crate) fn accept(
+        &mut self,
+        client: u16,
+        transaction_type: TransactionType,
+        amount: Option<Money>,
+        currency: Option<Currency>,
+    ) {
+        let amount = Some(amount.unwrap_or(Money::ZERO));
+        match transaction_type {
+            TransactionType::Deposit => add(&mut self.deposited, currency, amount),
+            TransactionType::Withdrawal => add(&mut self.withdrawn, currency, amount),
+            _ => {}
+        }
+        *self.accepted.entry(transaction_type).or_default() += 1;
+        self.clients.insert(client);
+    }
+
+    pub(crate) fn merge(&mut self, other: Tally) {
+        for (transaction_type, count) in other.accepted {
+            *self.accepted.entry(transaction_type).or_default() += count;
+        }
+        for (currency, amount) in other.deposited {
+            add(&mut self.deposited, currency, amount);
+        }
+        for (currency, amount) in other.withdrawn {
+            add(&mut self.withdrawn, currency, amount);
+        }
+        self.clients.extend(other.clients);
+    }
+}
+
+/// Adds `amount` to the total of `currency`, which stays `None` once it overflowed.
+fn add(
+    totals: &mut BTreeMap<Option<Currency>, Option<Money>>,
+    currency: Option<Currency>,
+    amount: Option<Money>,
+) {
+    let total = totals.entry(currency).or_insert(Some(Money::ZERO));
+    *total = total
+        .zip(amount)
+        .and_then(|(total, amount)| total.checked_add(amount));
+}
+
+/// Rejections sharing an error code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rejected {
+    pub count: u64,
+    /// Reason of the first of them
+    pub reason: String,
+}
+
+/// Aggregate figures of a run, printed with `--print-summary` or written as JSON with
+/// `--summary`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    /// Clients with a transaction in this run, accepted or rejected
+    pub accounts_touched: usize,
+    /// Accounts in the report, including those restored from earlier runs
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    /// Deposits still under dispute at the end of the run
+    pub open_disputes: usize,
+    /// Accepted transactions by type
+    pub transactions: BTreeMap<TransactionType, u64>,
+    /// Accepted deposits and withdrawals by currency, empty for the default one, `null`
+    /// for a total too large for [`Money`]
+    pub deposited: BTreeMap<String, Option<Money>>,
+    pub withdrawn: BTreeMap<String, Option<Money>>,
+    /// Rejected transactions by error code
+    pub rejected: BTreeMap<u16, Rejected>,
+    /// Malformed rows the reader skipped
+    pub skipped_rows: u64,
+    pub elapsed_seconds: f64,
+    /// Transactions handed to the engine per second
+    pub throughput: f64,
+}
+
+fn by_currency(
+    amounts: &BTreeMap<Option<Currency>, Option<Money>>,
+) -> BTreeMap<String, Option<Money>> {
+    amounts
+        .iter()
+        .map(|(currency, amount)| {
+            let currency = currency.as_ref().map(ToString::to_string);
+            (currency.unwrap_or_default(), *amount)
+        })
+        .collect()
+}
+
+impl Summary {
+    /// Sums up the tally, the accounts as they ended up and the rejections of a run that
+    /// took `elapsed`. Skipped rows are left for the caller to fill in.
+    ///
+    /// Copies of accounts leave their history behind, so open disputes are only counted
+    /// on the accounts themselves.
+    pub fn new<'a>(
+        tally: &Tally,
+        accounts: impl IntoIterator<Item = &'a Account>,
+        rejections: &[Rejection],
+        elapsed: Duration,
+    ) -> Self {
+        let (mut count, mut locked_accounts, mut open_disputes) = (0, 0, 0);
+        for account in accounts {
+            count += 1;
+            locked_accounts += usize::from(account.locked());
+            open_disputes += account.open_disputes();
+        }
+        let mut rejected = BTreeMap::<u16, Rejected>::new();
+        for rejection in rejections {
+            rejected
+                .entry(rejection.error.code())
+                .or_insert_with(|| Rejected {
+                    count: 0,
+                    reason: rejection.error.kind.to_string(),
+                })
+                .count += 1;
+        }
+        let mut touched = tally.clients.clone();
+        touched.extend(rejections.iter().map(|rejection| rejection.error.client));
+        let handled = tally.accepted.values().sum::<u64>() + rejections.len() as u64;
+        let elapsed_seconds = elapsed.as_secs_f64();
+        Self {
+            accounts_touched: touched.len(),
+            accounts: count,
+            locked_accounts,
+            open_disputes,
+            transactions: tally.accepted.clone(),
+            deposited: by_currency(&tally.deposited),
+            withdrawn: by_currency(&tally.withdrawn),
+            rejected,
+            skipped_rows: 0,
+            elapsed_seconds,
+            throughput: match elapsed_seconds > 0.0 {
+                true => handled as f64 / elapsed_seconds,
+                false => 0.0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let currency = |currency: &String| match currency.is_empty() {
+            true => String::new(),
+            false => format!(" {}", currency),
+        };
+        writeln!(f, "accounts touched  {}", self.accounts_touched)?;
+        writeln!(f, "accounts          {}", self.accounts)?;
+        writeln!(f, "locked accounts   {}", self.locked_accounts)?;
+        writeln!(f, "open disputes     {}", self.open_disputes)?;
+        for (transaction_type, count) in &self.transactions {
+            let name = format!("{:?}", transaction_type).to_lowercase();
+            writeln!(f, "{:<18}{}", name, count)?;
+        }
+        for (name, amounts) in [
+            ("deposited", &self.deposited),
+            ("withdrawn", &self.withdrawn),
+        ] {
+            for (code, amount) in amounts {
+                match amount {
+                    Some(amount) => writeln!(f, "{:<18}{}{}", name, amount, currency(code))?,
+                    None => writeln!(f, "{:<18}overflowed{}", name, currency(code))?,
+                }
+            }
+        }
+        for (code, rejected) in &self.rejected {
+            writeln!(
+                f,
+                "rejected {:<9}{} ({})",
+                code, rejected.count, rejected.reason
+            )?;
+        }
+        writeln!(f, "skipped rows      {}", self.skipped_rows)?;
+        writeln!(f, "elapsed           {:.3}s", self.elapsed_seconds)?;
+        write!(f, "throughput        {:.0} tx/s", self.throughput)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Money, Transaction, TransactionType};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn summary() {
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(10))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from(5))),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Money::from(4))),
+            Transaction::new(TransactionType::Withdrawal, 2, 4, Some(Money::from(9))),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Dispute, 3, 9, None),
+        ];
+        for transaction in transactions {
+            engine.submit(transaction).await.unwrap();
+        }
+        let summary = engine.summary(Duration::from_secs(2)).await;
+        assert_eq!(summary.accounts_touched, 3);
+        assert_eq!(summary.accounts, 3);
+        assert_eq!(summary.locked_accounts, 0);
+        assert_eq!(summary.open_disputes, 1);
+        assert_eq!(summary.transactions[&TransactionType::Deposit], 2);
+        assert_eq!(summary.transactions[&TransactionType::Withdrawal], 1);
+        assert_eq!(summary.deposited[""], Some(Money::from(15)));
+        assert_eq!(summary.withdrawn[""], Some(Money::from(4)));
+        assert_eq!(
+            summary
+                .rejected
+                .values()
+                .map(|rejected| rejected.count)
+                .sum::<u64>(),
+            2
+        );
+        assert_eq!(summary.throughput, 3.0);
+    }
+
+    #[tokio::test]
+    async fn overflowing_totals() {
+        let amount = Some("40000000000000000000000000000".parse::<Money>().unwrap());
+        let deposit = |client, tx| Transaction::new(TransactionType::Deposit, client, tx, amount);
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 3, 4, amount);
+
+        // Totals of different workers, merged once they are done
+        let mut engine = Engine::new();
+        for transaction in [deposit(1, 1), deposit(2, 2)] {
+            engine.submit(transaction).await.unwrap();
+        }
+        let summary = engine.summary(Duration::from_secs(1)).await;
+        assert_eq!(summary.transactions[&TransactionType::Deposit], 2);
+        assert_eq!(summary.deposited[""], None);
+        assert!(summary.to_string().contains("deposited         overflowed"));
+
+        // Totals of a single account
+        let mut engine = Engine::new();
+        for transaction in [deposit(3, 3), withdrawal, deposit(3, 5)] {
+            engine.process(transaction).await.unwrap();
+        }
+        let summary = engine.summary(Duration::from_secs(1)).await;
+        assert_eq!(summary.deposited[""], None);
+        assert_eq!(summary.withdrawn[""], amount);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::http::HttpUrl;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_http::hyper::HyperClient;
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::span_processor_with_async_runtime::BatchSpanProcessor;
+use opentelemetry_sdk::trace::{BatchConfigBuilder, Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Longest time a finished span waits for its batch to fill up.
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest time an export waits for the collector.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SERVICE_NAME: &str = "transaction_system";
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Layer exporting the `tracing` spans of a subscriber to an OpenTelemetry collector, as
+/// OTLP JSON over HTTP, sampling `sample_rate` of all traces. Spans are exported in
+/// batches from a task on the Tokio runtime, which has to be running. Fails when spans are
+/// exported already.
+pub fn layer<S>(endpoint: &HttpUrl, sample_rate: f64) -> Result<impl Layer<S>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_http_client(HyperClient::with_default_connector(EXPORT_TIMEOUT, None))
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(endpoint.to_string())
+        .with_timeout(EXPORT_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Invalid OTLP endpoint {}: {}", endpoint, e))?;
+    let batches = BatchConfigBuilder::default()
+        .with_scheduled_delay(BATCH_DELAY)
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_span_processor(
+            BatchSpanProcessor::builder(exporter, runtime::Tokio)
+                .with_batch_config(batches)
+                .build(),
+        )
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            sample_rate,
+        ))))
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+    PROVIDER
+        .set(provider)
+        .map_err(|_| "Spans are exported already".to_string())?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Waits until every span that ended so far was exported, returning the error of a failed
+/// export. Does nothing when no spans are exported.
+pub async fn flush() -> Result<(), String> {
+    let Some(provider) = PROVIDER.get() else {
+        return Ok(());
+    };
+    // Flushing blocks until the export task on the runtime is done
+    tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::layer;
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn export() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            let body = loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().unwrap())
+                    })
+                    .unwrap();
+                if body.len() >= length {
+                    assert!(head.starts_with("POST /v1/traces"));
+                    break body.to_string();
+                }
+            };
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                .await
+                .unwrap();
+            serde_json::from_str::<Value>(&body).unwrap()
+        });
+
+        let subscriber =
+            tracing_subscriber::registry().with(layer(&endpoint.parse().unwrap(), 1.0).unwrap());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(parent: None, "transaction", tx = 7);
+            let _apply = tracing::info_span!(parent: &span, "apply");
+        });
+        super::flush().await.unwrap();
+
+        let request = collector.await.unwrap();
+        let scope = &request["resourceSpans"][0]["scopeSpans"][0];
+        let spans = scope["spans"].as_array().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span["name"].clone()).collect();
+        assert_eq!(names, ["apply", "transaction"]);
+        assert_eq!(spans[0]["parentSpanId"], spans[1]["spanId"]);
+        assert_eq!(spans[0]["traceId"], spans[1]["traceId"]);
+        assert!(spans[1]["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|attribute| attribute["key"] == "tx"));
+        assert!(layer::<tracing_subscriber::Registry>(&endpoint.parse().unwrap(), 1.0).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Datelike, SecondsFormat, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Point in time a transaction happened, with millisecond precision.
+///
+/// Parsed either from milliseconds since the unix epoch or from an RFC3339 date, and
+/// always printed as RFC3339 in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_millis(millis: i64) -> Self {
+        Self(millis)
+    }
+
+    pub fn as_millis(&self) -> i64 {
+        self.0
+    }
+
+    /// Calendar month in UTC, counted from year 0, so consecutive months differ by one.
+    pub fn month(&self) -> Option<i64> {
+        DateTime::<Utc>::from_timestamp_millis(self.0)
+            .map(|date| i64::from(date.year()) * 12 + i64::from(date.month0()))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(millis) = s.parse::<i64>() {
+            return Ok(Self(millis));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|date| Self(date.timestamp_millis()))
+            .map_err(|_| format!("Invalid timestamp {}, expected RFC3339 or epoch millis", s))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match DateTime::<Utc>::from_timestamp_millis(self.0) {
+            Some(date) => f.write_str(&date.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            None => self.0.fmt(f),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        Timestamp::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timestamp;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "1700000000000".parse::<Timestamp>(),
+            Ok(Timestamp::from_millis(1_700_000_000_000))
+        );
+        assert_eq!(
+            "2023-11-14T22:13:20Z".parse::<Timestamp>(),
+            Ok(Timestamp::from_millis(1_700_000_000_000))
+        );
+        assert_eq!(
+            "2023-11-15T00:13:20.5+02:00".parse::<Timestamp>(),
+            Ok(Timestamp::from_millis(1_700_000_000_500))
+        );
+        assert!("yesterday".parse::<Timestamp>().is_err());
+        assert!("2023-11-14".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Timestamp::from_millis(1_700_000_000_500).to_string(),
+            "2023-11-14T22:13:20.500Z"
+        );
+    }
+}
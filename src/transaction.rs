@@ -0,0 +1,304 @@
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::timestamp::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TransactionType {
+    #[serde(rename = "deposit")]
+    Deposit,
+    #[serde(rename = "withdrawal")]
+    Withdrawal,
+    #[serde(rename = "dispute")]
+    Dispute,
+    #[serde(rename = "resolve")]
+    Resolve,
+    #[serde(rename = "chargeback")]
+    Chargeback,
+    #[serde(rename = "convert")]
+    Convert,
+    #[serde(rename = "transfer")]
+    Transfer,
+    /// Administrative operation reinstating a locked account
+    #[serde(rename = "unlock")]
+    Unlock,
+    /// Reverses the chargeback of the transaction with the same tx id after the merchant won
+    /// representment
+    #[serde(rename = "representment", alias = "chargeback_reversal")]
+    Representment,
+    /// Closes an account without any funds left, it accepts no transactions afterwards
+    #[serde(rename = "close_account")]
+    Close,
+    /// Returns (part of) the deposit with the same tx id without locking the account
+    #[serde(rename = "refund")]
+    Refund,
+    /// Holds funds until the authorization with this tx id is captured, voided or expires
+    #[serde(rename = "authorize")]
+    Authorize,
+    /// Takes (part of) the held funds of the authorization with the same tx id
+    #[serde(rename = "capture")]
+    Capture,
+    /// Releases the held funds of the authorization with the same tx id
+    #[serde(rename = "void")]
+    Void,
+    /// Administrative credit (positive amount) or debit (negative amount) of available funds
+    #[serde(rename = "adjustment")]
+    Adjustment,
+}
+
+impl TransactionType {
+    /// Transactions that create a new history entry and so need an id of their own.
+    /// The others refer to an earlier transaction by its id.
+    pub fn has_own_id(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Convert
+                | TransactionType::Transfer
+                | TransactionType::Adjustment
+                | TransactionType::Authorize
+        )
+    }
+
+    /// Operations only accepted when administrative transactions are allowed.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock | TransactionType::Adjustment | TransactionType::Close
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub(crate) transaction_type: TransactionType,
+    pub(crate) client: u16,
+    pub(crate) tx: u32,
+    pub(crate) amount: Option<Money>,
+    /// Currency of the amount, the account's default currency when not given
+    #[serde(default)]
+    pub(crate) currency: Option<Currency>,
+    /// Client a `transfer` transaction credits
+    #[serde(default)]
+    pub(crate) to_client: Option<u16>,
+    /// Currency a `convert` transaction moves funds into
+    #[serde(default)]
+    pub(crate) to_currency: Option<Currency>,
+    /// Amount a `convert` transaction credits in `to_currency`, quoted by the engine
+    #[serde(skip)]
+    pub(crate) converted: Option<Money>,
+    /// Reason code an `adjustment` has to carry
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+    /// When an `authorize` lapses if it hasn't been captured or voided
+    #[serde(default)]
+    pub(crate) expires_at: Option<Timestamp>,
+    /// When the transaction happened, if the input has a `timestamp` column
+    #[serde(default)]
+    pub(crate) timestamp: Option<Timestamp>,
+    /// Line of the input the transaction was read from
+    #[serde(skip)]
+    pub(crate) row: Option<u64>,
+    /// Input columns the engine doesn't know, e.g. `memo` or `external_ref`, by name. They
+    /// are kept in history and written out again with statements and the ledger
+    #[serde(skip)]
+    pub(crate) metadata: BTreeMap<String, String>,
+}
+
+impl Transaction {
+    pub fn new(
+        transaction_type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<Money>,
+    ) -> Self {
+        Self {
+            transaction_type,
+            client,
+            tx,
+            amount,
+            currency: None,
+            to_client: None,
+            to_currency: None,
+            converted: None,
+            reason: None,
+            expires_at: None,
+            timestamp: None,
+            row: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_row(mut self, row: u64) -> Self {
+        self.row = Some(row);
+        self
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_to_client(mut self, client: u16) -> Self {
+        self.to_client = Some(client);
+        self
+    }
+
+    pub fn with_to_currency(mut self, currency: Currency) -> Self {
+        self.to_currency = Some(currency);
+        self
+    }
+
+    /// Sets the amount a conversion credits, which [`crate::Engine`] quotes from its
+    /// exchange rates.
+    pub fn with_converted_amount(mut self, converted: Money) -> Self {
+        self.converted = Some(converted);
+        self
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: Timestamp) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_metadata(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn transaction_type(&self) -> &TransactionType {
+        &self.transaction_type
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    pub fn amount(&self) -> Option<Money> {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    pub fn to_client(&self) -> Option<u16> {
+        self.to_client
+    }
+
+    pub fn to_currency(&self) -> Option<&Currency> {
+        self.to_currency.as_ref()
+    }
+
+    pub fn converted_amount(&self) -> Option<Money> {
+        self.converted
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn expires_at(&self) -> Option<Timestamp> {
+        self.expires_at
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    pub fn row(&self) -> Option<u64> {
+        self.row
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+}
+
+/// Names of the metadata of any of the transactions, in order, for exports giving each
+/// its own column.
+pub(crate) fn metadata_columns<'a>(
+    metadata: impl IntoIterator<Item = &'a BTreeMap<String, String>>,
+) -> Vec<&'a str> {
+    let mut names = metadata
+        .into_iter()
+        .flat_map(|m| m.keys().map(String::as_str))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// Transaction with every field, including the ones the engine fills in, as written to
+/// the write-ahead log and persisted account history.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct StoredTransaction {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: u16,
+    pub(crate) tx: u32,
+    amount: Option<Money>,
+    currency: Option<Currency>,
+    to_client: Option<u16>,
+    to_currency: Option<Currency>,
+    converted: Option<Money>,
+    reason: Option<String>,
+    expires_at: Option<Timestamp>,
+    timestamp: Option<Timestamp>,
+    row: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+}
+
+impl From<&Transaction> for StoredTransaction {
+    fn from(t: &Transaction) -> Self {
+        Self {
+            transaction_type: t.transaction_type.clone(),
+            client: t.client,
+            tx: t.tx,
+            amount: t.amount,
+            currency: t.currency.clone(),
+            to_client: t.to_client,
+            to_currency: t.to_currency.clone(),
+            converted: t.converted,
+            reason: t.reason.clone(),
+            expires_at: t.expires_at,
+            timestamp: t.timestamp,
+            row: t.row,
+            metadata: t.metadata.clone(),
+        }
+    }
+}
+
+impl From<StoredTransaction> for Transaction {
+    fn from(t: StoredTransaction) -> Self {
+        let mut transaction = Transaction::new(t.transaction_type, t.client, t.tx, t.amount);
+        transaction.currency = t.currency;
+        transaction.to_client = t.to_client;
+        transaction.to_currency = t.to_currency;
+        transaction.converted = t.converted;
+        transaction.reason = t.reason;
+        transaction.expires_at = t.expires_at;
+        transaction.timestamp = t.timestamp;
+        transaction.row = t.row;
+        transaction.metadata = t.metadata;
+        transaction
+    }
+}
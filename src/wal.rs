@@ -0,0 +1,188 @@
+use crate::encryption::{self, Cipher, StreamId};
+use crate::transaction::{StoredTransaction, Transaction};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Write-ahead log of the transactions of a run, one JSON line each.
+///
+/// Every transaction is appended and synced before it is submitted, so after a crash the
+/// log holds at least everything the engine may have applied. Replaying the log and then
+/// skipping as many transactions of the same inputs resumes the run exactly where it
+/// stopped. With a cipher every entry is encrypted, see [`encryption::seal_line`].
+#[derive(Debug)]
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    cipher: Option<Cipher>,
+    stream: StreamId,
+    /// Sequence number of the next entry
+    next: u64,
+}
+
+impl Wal {
+    /// Opens the log at `path`, returning the transactions a crashed run left in it.
+    ///
+    /// A last line cut short by the crash is dropped, the transaction it held was never
+    /// submitted.
+    pub fn open(
+        path: impl AsRef<Path>,
+        cipher: Option<Cipher>,
+    ) -> Result<(Self, Vec<Transaction>), Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut transactions = Vec::new();
+        let mut logged = 0;
+        let mut stream = None;
+        let mut reader = io::BufReader::new(&mut file);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if !line.ends_with('\n') {
+                break;
+            }
+            let sequence = transactions.len() as u64;
+            let transaction = encryption::open_line(cipher.as_ref(), &mut stream, sequence, &line)
+                .and_then(|entry| Ok(serde_json::from_slice::<StoredTransaction>(&entry)?))
+                .map_err(|e| {
+                    format!(
+                        "Invalid entry {} of {}: {}",
+                        transactions.len() + 1,
+                        path.display(),
+                        e
+                    )
+                })?;
+            transactions.push(Transaction::from(transaction));
+            logged += line.len() as u64;
+            line.clear();
+        }
+        file.set_len(logged)?;
+        file.seek(io::SeekFrom::End(0))?;
+        let wal = Self {
+            path,
+            file,
+            cipher,
+            stream: stream.unwrap_or_else(encryption::new_stream),
+            next: transactions.len() as u64,
+        };
+        Ok((wal, transactions))
+    }
+
+    pub fn append(&mut self, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+        let entry = serde_json::to_vec(&StoredTransaction::from(transaction))?;
+        let mut line = encryption::seal_line(self.cipher.as_ref(), &self.stream, self.next, entry);
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Removes the log once the run finished, there's nothing left to recover.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        std::fs::remove_file(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Wal;
+    use crate::encryption::Cipher;
+    use crate::money::Money;
+    use crate::timestamp::Timestamp;
+    use crate::transaction::{Transaction, TransactionType};
+    use std::io::Write;
+
+    #[test]
+    fn recover() {
+        let path = std::env::temp_dir().join(format!("wal_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (mut wal, recovered) = Wal::open(&path, None).unwrap();
+        assert!(recovered.is_empty());
+        wal.append(
+            &Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5)))
+                .with_row(2)
+                .with_timestamp(Timestamp::from_millis(1_000)),
+        )
+        .unwrap();
+        wal.append(&Transaction::new(TransactionType::Dispute, 1, 1, None).with_row(3))
+            .unwrap();
+        drop(wal);
+        // Crash in the middle of writing the third entry
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"type\":\"with").unwrap();
+
+        let (mut wal, recovered) = Wal::open(&path, None).unwrap();
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(recovered[0].amount(), Some(Money::from(5)));
+        assert_eq!(recovered[0].row(), Some(2));
+        assert_eq!(
+            recovered[0].timestamp(),
+            Some(Timestamp::from_millis(1_000))
+        );
+        assert_eq!(recovered[1].transaction_type(), &TransactionType::Dispute);
+        wal.append(&Transaction::new(TransactionType::Resolve, 1, 1, None))
+            .unwrap();
+        drop(wal);
+
+        let (wal, recovered) = Wal::open(&path, None).unwrap();
+        assert_eq!(recovered.len(), 3);
+        wal.finish().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn encrypted() {
+        let path = std::env::temp_dir().join(format!("wal_enc_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let cipher = Cipher::new(&[5; 32]);
+        let (mut wal, _) = Wal::open(&path, Some(cipher.clone())).unwrap();
+        wal.append(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::from(5)),
+        ))
+        .unwrap();
+        drop(wal);
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("deposit"));
+        assert!(Wal::open(&path, None).is_err());
+
+        let (mut wal, recovered) = Wal::open(&path, Some(cipher.clone())).unwrap();
+        assert_eq!(recovered[0].amount(), Some(Money::from(5)));
+        wal.append(&Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .unwrap();
+        drop(wal);
+        let logged = std::fs::read_to_string(&path).unwrap();
+        let (wal, recovered) = Wal::open(&path, Some(cipher.clone())).unwrap();
+        assert_eq!(recovered.len(), 2);
+        drop(wal);
+
+        // Entries only replay in the order they were logged
+        let lines = logged.lines().collect::<Vec<_>>();
+        std::fs::write(&path, format!("{}\n{}\n", lines[1], lines[0])).unwrap();
+        assert!(Wal::open(&path, Some(cipher.clone())).is_err());
+        std::fs::write(&path, format!("{}\n", lines[1])).unwrap();
+        assert!(Wal::open(&path, Some(cipher.clone())).is_err());
+        std::fs::write(&path, logged).unwrap();
+
+        // A plaintext entry slipped into the log isn't replayed
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"100\"}\n")
+            .unwrap();
+        assert!(Wal::open(&path, Some(cipher)).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
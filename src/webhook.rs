@@ -0,0 +1,316 @@
+use crate::account::DisputeState;
+use crate::http::HttpUrl;
+use crate::retry::RetryPolicy;
+use crate::store::AccountUpdate;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Time a webhook has to answer before the attempt counts as failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and how notifications are delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: HttpUrl,
+    /// Attempts per notification before it is given up on, whatever the delivery failed for
+    pub retry: RetryPolicy,
+    /// File notifications that could never be delivered are appended to, one JSON per line
+    pub failed: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    DisputeOpened,
+    Chargeback,
+    AccountLocked,
+}
+
+/// Body of a webhook request. Notifications are delivered at least once, receivers tell
+/// repeated deliveries apart by their `id`: the id of the dispatcher's run and the
+/// notification's sequence number within it, unique across runs and processes.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Notification {
+    pub id: String,
+    pub kind: NotificationKind,
+    pub event: AccountUpdate,
+}
+
+/// Posts a [`Notification`] to a webhook for every high severity account event: a dispute
+/// opened, a chargeback applied or an account locked.
+///
+/// Notifications are delivered one at a time in the order of their events. A failed
+/// delivery is retried with exponential backoff before later notifications are sent, and
+/// written to the failed file once every attempt failed.
+///
+/// Delivery is best-effort across crashes: notifications waiting to be delivered are only
+/// kept in memory, so those of a process that dies are lost rather than sent by the next
+/// run.
+pub struct WebhookDispatcher {
+    webhook: Webhook,
+    /// Last known lock status of every client
+    locked: HashMap<u16, bool>,
+    run: Uuid,
+    next_id: u64,
+}
+
+impl WebhookDispatcher {
+    pub fn new(webhook: Webhook) -> Self {
+        Self {
+            webhook,
+            locked: HashMap::new(),
+            run: Uuid::new_v4(),
+            next_id: 1,
+        }
+    }
+
+    /// Takes the clients of accounts that are locked already, e.g. restored from an earlier
+    /// run, so only accounts locked from now on are notified.
+    pub fn with_locked(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        self.locked
+            .extend(clients.into_iter().map(|client| (client, true)));
+        self
+    }
+
+    /// Delivers notifications until the [`EventStore`](crate::store::EventStore) sending
+    /// the events is dropped.
+    pub async fn run(
+        mut self,
+        mut events: mpsc::UnboundedReceiver<AccountUpdate>,
+    ) -> io::Result<()> {
+        while let Some(event) = events.recv().await {
+            for notification in self.notifications(event) {
+                self.deliver(&notification).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn notifications(&mut self, event: AccountUpdate) -> Vec<Notification> {
+        if event.error.is_some() {
+            return Vec::new();
+        }
+        let mut kinds = Vec::new();
+        match event.dispute_state {
+            Some(DisputeState::Disputed) => kinds.push(NotificationKind::DisputeOpened),
+            Some(DisputeState::ChargedBack) => kinds.push(NotificationKind::Chargeback),
+            _ => {}
+        }
+        if let Some(locked) = event.locked {
+            let was_locked = self.locked.insert(event.client, locked).unwrap_or(false);
+            if locked && !was_locked {
+                kinds.push(NotificationKind::AccountLocked);
+            }
+        }
+        kinds
+            .into_iter()
+            .map(|kind| {
+                let id = format!("{}-{}", self.run, self.next_id);
+                self.next_id += 1;
+                Notification {
+                    id,
+                    kind,
+                    event: event.clone(),
+                }
+            })
+            .collect()
+    }
+
+    async fn deliver(&self, notification: &Notification) -> io::Result<()> {
+        let body = serde_json::to_vec(notification)?;
+        let retry = &self.webhook.retry;
+        let mut attempt = 1;
+        loop {
+            if let Ok(Ok(())) =
+                tokio::time::timeout(REQUEST_TIMEOUT, self.webhook.url.post_json(&body)).await
+            {
+                return Ok(());
+            }
+            if !retry.retries(attempt) {
+                break;
+            }
+            tokio::time::sleep(retry.backoff(attempt)).await;
+            attempt += 1;
+        }
+        let mut failed = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.webhook.failed)?;
+        failed.write_all(&body)?;
+        failed.write_all(b"\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NotificationKind, Webhook, WebhookDispatcher};
+    use crate::retry::RetryPolicy;
+    use crate::store::{AccountUpdate, EventStore, MemoryStore};
+    use crate::{Engine, EngineConfig, Money, Transaction, TransactionType};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn deliver_notifications() {
+        // Fails every first delivery of a notification, accepts the retry
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            while bodies.len() < 6 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    if let Some(value) = line.strip_prefix("content-length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                let status = match bodies.len() % 2 {
+                    0 => "500 Internal Server Error",
+                    _ => "204 No Content",
+                };
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+                bodies.push(serde_json::from_slice::<Value>(&body).unwrap());
+            }
+            bodies
+        });
+
+        let failed = std::env::temp_dir().join(format!("webhooks_{}.jsonl", std::process::id()));
+        let dispatcher = WebhookDispatcher::new(Webhook {
+            url: format!("http://{}/hooks", address).parse().unwrap(),
+            retry: RetryPolicy {
+                attempts: 3,
+                backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            },
+            failed: failed.clone(),
+        });
+        let mut store = EventStore::new(MemoryStore::new(), Default::default());
+        let delivered = tokio::spawn(dispatcher.run(store.subscribe()));
+        let mut engine = Engine::with_store(EngineConfig::default(), Arc::new(store));
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+        ];
+        for transaction in transactions {
+            engine.process(transaction).await.unwrap();
+        }
+        drop(engine);
+        delivered.await.unwrap().unwrap();
+
+        let bodies = server.await.unwrap();
+        let delivered = bodies
+            .iter()
+            .map(|body| {
+                let id = body["id"].as_str().unwrap();
+                let (run, sequence) = id.rsplit_once('-').unwrap();
+                (run, sequence, body["kind"].as_str().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let run = delivered[0].0;
+        assert_eq!(
+            delivered,
+            [
+                (run, "1", "dispute_opened"),
+                (run, "1", "dispute_opened"),
+                (run, "2", "chargeback"),
+                (run, "2", "chargeback"),
+                (run, "3", "account_locked"),
+                (run, "3", "account_locked"),
+            ]
+        );
+        assert_eq!(bodies[5]["event"]["locked"], true);
+        assert!(!failed.exists());
+    }
+
+    #[test]
+    fn notification_ids() {
+        let webhook = Webhook {
+            url: "http://localhost/".parse().unwrap(),
+            retry: RetryPolicy::default(),
+            failed: "failed.jsonl".into(),
+        };
+        let locked = AccountUpdate {
+            client: 1,
+            tx: 1,
+            dispute_state: None,
+            error: None,
+            locked: Some(true),
+            balances: Vec::new(),
+        };
+        let mut first = WebhookDispatcher::new(webhook.clone());
+        let mut second = WebhookDispatcher::new(webhook.clone());
+        let first = first.notifications(locked.clone());
+        let second = second.notifications(locked.clone());
+        assert_eq!(first.len(), 1);
+        assert_ne!(first[0].id, second[0].id);
+
+        // Accounts restored locked aren't locked again
+        let mut restored = WebhookDispatcher::new(webhook).with_locked([1]);
+        assert!(restored.notifications(locked.clone()).is_empty());
+        let other = AccountUpdate {
+            client: 2,
+            ..locked
+        };
+        assert_eq!(
+            restored.notifications(other)[0].kind,
+            NotificationKind::AccountLocked
+        );
+    }
+
+    #[tokio::test]
+    async fn undeliverable() {
+        // Nothing listens on the port once the listener is dropped
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let failed =
+            std::env::temp_dir().join(format!("webhooks_failed_{}.jsonl", std::process::id()));
+        let dispatcher = WebhookDispatcher::new(Webhook {
+            url: format!("http://{}/", address).parse().unwrap(),
+            retry: RetryPolicy {
+                attempts: 2,
+                backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            },
+            failed: failed.clone(),
+        });
+        let mut store = EventStore::new(MemoryStore::new(), Default::default());
+        let delivered = tokio::spawn(dispatcher.run(store.subscribe()));
+        let mut engine = Engine::with_store(EngineConfig::default(), Arc::new(store));
+        for transaction in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from(5))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ] {
+            engine.process(transaction).await.unwrap();
+        }
+        drop(engine);
+        delivered.await.unwrap().unwrap();
+
+        let written = std::fs::read_to_string(&failed).unwrap();
+        std::fs::remove_file(&failed).unwrap();
+        let notification = serde_json::from_str::<Value>(written.trim()).unwrap();
+        assert_eq!(notification["kind"], "dispute_opened");
+        assert_eq!(notification["event"]["client"], 1);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::dedup::mix;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::io::{self, Write};
+
+/// Smallest amount generated, amounts are whole multiples of it.
+const SCALE: u32 = 4;
+
+/// Largest deposit, in units of the smallest amount.
+const MAX_DEPOSIT: u64 = 10_000_000;
+
+/// Resolution chances are drawn with.
+const CHANCE_RESOLUTION: u64 = 1_000_000;
+
+/// Random but reproducible transactions for load testing the engine.
+///
+/// Deposits and withdrawals never overdraw, disputes only reference deposits of the same
+/// client that are still covered by its available funds, and every dispute is eventually
+/// resolved or charged back. Only the `invalid_rate` share of rows is rejected or skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workload {
+    /// Clients 1 to `clients` transactions are spread over
+    pub clients: u16,
+    /// Rows written
+    pub transactions: u64,
+    /// Share of rows that are disputes, resolves or chargebacks
+    pub dispute_rate: Decimal,
+    /// Share of rows that are malformed or rejected by the engine
+    pub invalid_rate: Decimal,
+    /// Same seed, same rows
+    pub seed: u64,
+}
+
+#[derive(Default)]
+struct Client {
+    /// Available funds in units of the smallest amount
+    available: u64,
+    /// Deposits that were never disputed, by tx and amount
+    deposits: Vec<(u32, u64)>,
+    /// Deposit under dispute
+    disputed: Option<(u32, u64)>,
+}
+
+/// SplitMix64 generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.0)
+    }
+
+    /// Uniform number in `0..n`, `n` must not be 0.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, rate: Decimal) -> bool {
+        let threshold = (rate * Decimal::from(CHANCE_RESOLUTION))
+            .to_u64()
+            .unwrap_or(0);
+        self.below(CHANCE_RESOLUTION) < threshold
+    }
+}
+
+fn amount(units: u64) -> Decimal {
+    Decimal::new(units as i64, SCALE)
+}
+
+impl Workload {
+    /// Writes the workload as csv with a `type,client,tx,amount` header.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut rng = Rng(self.seed);
+        let mut clients = (0..self.clients)
+            .map(|_| Client::default())
+            .collect::<Vec<_>>();
+        // Clients that aren't locked by a chargeback
+        let mut active = (1..=self.clients).collect::<Vec<_>>();
+        let mut next_tx: u32 = 1;
+        writeln!(writer, "type,client,tx,amount")?;
+        for _ in 0..self.transactions {
+            if active.is_empty() {
+                break;
+            }
+            let slot = rng.below(active.len() as u64) as usize;
+            let id = active[slot];
+            let client = &mut clients[usize::from(id) - 1];
+
+            if rng.chance(self.invalid_rate) {
+                match rng.below(4) {
+                    0 => writeln!(writer, "dispute,{},{},", id, next_tx)?,
+                    1 => writeln!(writer, "deposit,{},{},abc", id, next_tx)?,
+                    2 if next_tx > 1 => writeln!(
+                        writer,
+                        "deposit,{},{},1",
+                        id,
+                        rng.below(u64::from(next_tx - 1)) + 1
+                    )?,
+                    _ => writeln!(
+                        writer,
+                        "withdrawal,{},{},{}",
+                        id,
+                        next_tx,
+                        amount(client.available + 1)
+                    )?,
+                }
+                next_tx += 1;
+                continue;
+            }
+
+            if rng.chance(self.dispute_rate) {
+                if let Some((tx, units)) = client.disputed.take() {
+                    // The last active client is never locked, so rows can still be written
+                    if active.len() > 1 && rng.below(4) == 0 {
+                        writeln!(writer, "chargeback,{},{},", id, tx)?;
+                        active.swap_remove(slot);
+                    } else {
+                        writeln!(writer, "resolve,{},{},", id, tx)?;
+                        client.available += units;
+                    }
+                    continue;
+                }
+                let covered = client
+                    .deposits
+                    .iter()
+                    .position(|&(_, units)| units <= client.available);
+                if let Some(index) = covered {
+                    let (tx, units) = client.deposits.swap_remove(index);
+                    writeln!(writer, "dispute,{},{},", id, tx)?;
+                    client.available -= units;
+                    client.disputed = Some((tx, units));
+                    continue;
+                }
+            }
+
+            let tx = next_tx;
+            next_tx += 1;
+            if client.available > 0 && rng.below(5) < 2 {
+                let units = rng.below(client.available) + 1;
+                writeln!(writer, "withdrawal,{},{},{}", id, tx, amount(units))?;
+                client.available -= units;
+            } else {
+                let units = rng.below(MAX_DEPOSIT) + 1;
+                writeln!(writer, "deposit,{},{},{}", id, tx, amount(units))?;
+                client.available += units;
+                client.deposits.push((tx, units));
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Workload;
+    use crate::reader::{deserialize_file, ReadOptions};
+    use crate::{Engine, OutcomeStatus};
+    use rust_decimal::Decimal;
+    use tokio::sync::mpsc;
+
+    fn workload(invalid_rate: Decimal) -> Workload {
+        Workload {
+            clients: 20,
+            transactions: 5_000,
+            dispute_rate: Decimal::new(1, 1),
+            invalid_rate,
+            seed: 7,
+        }
+    }
+
+    /// Generated rows and the outcomes of processing them.
+    async fn process(workload: &Workload) -> (String, Vec<OutcomeStatus>) {
+        let mut csv = Vec::new();
+        workload.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "workload_{}_{}.csv",
+            workload.invalid_rate,
+            std::process::id()
+        ));
+        std::fs::write(&path, &csv).unwrap();
+
+        let mut engine = Engine::new();
+        let mut outcomes = engine.outcomes();
+        let (sender, mut receiver) = mpsc::channel(64);
+        let path_string = path.to_str().unwrap().to_string();
+        let read = std::thread::spawn(move || {
+            deserialize_file(path_string, ReadOptions::default(), sender)
+        });
+        while let Some(transaction) = receiver.recv().await {
+            engine.submit(transaction).await.unwrap();
+        }
+        read.join().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        engine.wait().await;
+        drop(engine);
+        let mut statuses = Vec::new();
+        while let Some(outcome) = outcomes.recv().await {
+            statuses.push(outcome.status);
+        }
+        (csv, statuses)
+    }
+
+    #[tokio::test]
+    async fn valid() {
+        let (csv, statuses) = process(&workload(Decimal::ZERO)).await;
+        assert_eq!(csv.lines().count(), 5_001);
+        for kind in ["deposit", "withdrawal", "dispute", "resolve", "chargeback"] {
+            assert!(
+                csv.lines().any(|line| line.starts_with(kind)),
+                "no {}",
+                kind
+            );
+        }
+        assert_eq!(statuses.len(), 5_000);
+        assert!(statuses
+            .iter()
+            .all(|status| *status == OutcomeStatus::Accepted));
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        let (csv, statuses) = process(&workload(Decimal::new(5, 2))).await;
+        let rejected = statuses
+            .iter()
+            .filter(|status| **status == OutcomeStatus::Rejected)
+            .count();
+        // Malformed rows never reach the engine
+        assert!(statuses.len() < 5_000);
+        assert!(rejected > 100 && rejected < 400, "{} rejected", rejected);
+
+        let mut again = Vec::new();
+        workload(Decimal::new(5, 2)).write_csv(&mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), csv);
+    }
+}
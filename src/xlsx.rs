@@ -0,0 +1,234 @@
+use calamine::{Data, Reader, Xlsx};
+use rust_decimal::Decimal;
+use std::io::{self, Cursor, Read};
+use std::str::FromStr;
+
+fn invalid(reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid xlsx input: {}", reason),
+    )
+}
+
+/// Value of a spreadsheet cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cell {
+    Empty,
+    Text(String),
+    /// A number as Excel shows it, to 15 significant digits
+    Number(String),
+    Boolean(bool),
+    /// An error value such as `#DIV/0!`
+    Error(String),
+}
+
+impl Cell {
+    /// The cell as the text of a csv field.
+    pub fn text(&self) -> String {
+        match self {
+            Cell::Empty => String::new(),
+            Cell::Text(text) | Cell::Number(text) | Cell::Error(text) => text.clone(),
+            Cell::Boolean(value) => value.to_string(),
+        }
+    }
+
+    fn new(data: &Data) -> Cell {
+        match data {
+            Data::Empty => Cell::Empty,
+            Data::String(text) | Data::DateTimeIso(text) | Data::DurationIso(text) => {
+                Cell::Text(text.clone())
+            }
+            Data::Int(number) => Cell::Number(number.to_string()),
+            Data::Float(number) => Cell::number(&number.to_string()),
+            Data::DateTime(date) => Cell::number(&date.as_f64().to_string()),
+            Data::Bool(value) => Cell::Boolean(*value),
+            Data::Error(error) => Cell::Error(error.to_string()),
+        }
+    }
+
+    fn number(value: &str) -> Cell {
+        let number = Decimal::from_str(value).or_else(|_| Decimal::from_scientific(value));
+        match number {
+            // Doubles carry binary noise beyond what Excel shows, 0.1 + 0.2 is stored as
+            // 0.30000000000000004
+            Ok(number) => Cell::Number(
+                number
+                    .round_sf(15)
+                    .unwrap_or(number)
+                    .normalize()
+                    .to_string(),
+            ),
+            Err(_) => Cell::Text(value.to_string()),
+        }
+    }
+}
+
+/// Row of a sheet, cells in column order from column A, empty ones included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    /// 1-based row number, as Excel shows it
+    pub number: u32,
+    pub cells: Vec<Cell>,
+}
+
+/// Name of the column with this 0-based index, `A` to `Z`, then `AA` and so on.
+pub fn column_name(index: usize) -> String {
+    let mut name = Vec::new();
+    let mut index = index + 1;
+    while index > 0 {
+        name.push(b'A' + ((index - 1) % 26) as u8);
+        index = (index - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("Column names are ASCII")
+}
+
+/// Reads the rows of the first sheet of a workbook, the one Excel shows first. Empty rows
+/// are left out.
+pub fn first_sheet(mut input: impl Read) -> io::Result<Vec<Row>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let mut workbook = Xlsx::new(Cursor::new(bytes)).map_err(invalid)?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or_else(|| invalid("no sheet"))?
+        .map_err(invalid)?;
+    let Some((first_row, first_column)) = range.start() else {
+        return Ok(Vec::new());
+    };
+    // The range is a rectangle, rows end with their last cell that isn't empty
+    let rows = range.rows().zip(first_row..).filter_map(|(cells, row)| {
+        let length = cells.iter().rposition(|cell| *cell != Data::Empty)? + 1;
+        let leading = std::iter::repeat_n(Cell::Empty, first_column as usize);
+        Some(Row {
+            number: row + 1,
+            cells: leading
+                .chain(cells[..length].iter().map(Cell::new))
+                .collect(),
+        })
+    });
+    Ok(rows.collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{column_name, first_sheet, Cell, Row};
+    use crate::reader::{deserialize_xlsx, ReadError, ReadOptions};
+    use std::io::{Cursor, Write};
+    use tokio::sync::mpsc;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// Zip archive of deflated entries.
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            archive
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(content.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    }
+
+    fn workbook(sheet: &str) -> Vec<u8> {
+        zip(&[
+            (
+                "xl/workbook.xml",
+                r#"<?xml version="1.0"?><workbook xmlns:r="rels"><sheets>
+                <sheet name="Transactions" sheetId="1" r:id="rId2"/>
+                <sheet name="Notes" sheetId="2" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships><Relationship Id="rId1" Target="worksheets/sheet2.xml"/>
+                <Relationship Id="rId2" Target="/xl/worksheets/sheet1.xml"/></Relationships>"#,
+            ),
+            (
+                "xl/sharedStrings.xml",
+                r#"<sst><si><t>type</t></si><si><t>client</t></si><si><t>tx</t></si>
+                <si><t>amount</t></si><si><r><t>depo</t></r><r><t>sit</t></r></si>
+                <si><t>R&amp;D</t><rPh><t>x</t></rPh></si></sst>"#,
+            ),
+            ("xl/worksheets/sheet1.xml", sheet),
+            ("xl/worksheets/sheet2.xml", "<worksheet/>"),
+        ])
+    }
+
+    #[test]
+    fn read_sheet() {
+        let sheet = r#"<worksheet><sheetData>
+            <row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c>
+            <c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c></row>
+            <row r="3"><c r="A3" t="s"><v>4</v></c><c r="B3"><v>2</v></c>
+            <c r="D3"><f>0.1+0.2</f><v>0.30000000000000004</v></c><c r="E3" t="e"><v>#N/A</v></c></row>
+            <row><c t="inlineStr"><is><t>dispute</t></is></c><c t="b"><v>1</v></c><c t="s"><v>5</v></c><c><v>1E-3</v></c></row>
+            </sheetData></worksheet>"#;
+        let rows = first_sheet(workbook(sheet).as_slice()).unwrap();
+        let text = |text: &str| Cell::Text(text.to_string());
+        let number = |number: &str| Cell::Number(number.to_string());
+        assert_eq!(
+            rows,
+            [
+                Row {
+                    number: 1,
+                    cells: vec![text("type"), text("client"), text("tx"), text("amount")]
+                },
+                Row {
+                    number: 3,
+                    cells: vec![
+                        text("deposit"),
+                        number("2"),
+                        Cell::Empty,
+                        number("0.3"),
+                        Cell::Error("#N/A".to_string())
+                    ]
+                },
+                Row {
+                    number: 4,
+                    cells: vec![
+                        text("dispute"),
+                        Cell::Boolean(true),
+                        text("R&D"),
+                        number("0.001")
+                    ]
+                },
+            ]
+        );
+
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(702), "AAA");
+        assert!(first_sheet(&b"type,client,tx,amount\n"[..]).is_err());
+    }
+
+    #[test]
+    fn cell_errors() {
+        let sheet = r#"<worksheet><sheetData>
+            <row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c>
+            <c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c></row>
+            <row r="2"><c r="A2" t="s"><v>4</v></c><c r="B2"><v>1</v></c><c r="C2"><v>1</v></c></row>
+            <row r="5"><c r="A5" t="s"><v>4</v></c><c r="B5"><v>1.5</v></c><c r="C5"><v>2</v></c></row>
+            <row r="6"><c r="A6" t="s"><v>4</v></c><c r="B6"><v>1</v></c><c r="C6"><v>3</v></c>
+            <c r="D6" t="e"><v>#DIV/0!</v></c></row>
+            </sheetData></worksheet>"#;
+        let (sender, mut receiver) = mpsc::channel(16);
+        let options = ReadOptions {
+            strict: true,
+            ..ReadOptions::default()
+        };
+        match deserialize_xlsx(workbook(sheet).as_slice(), options, sender) {
+            Err(ReadError::MalformedRow { line, reason }) => {
+                assert_eq!(line, 5);
+                assert_eq!(reason, "Cell B5 (client): invalid digit found in string");
+            }
+            result => panic!("Expected malformed row error, got {:?}", result),
+        }
+        assert_eq!(receiver.try_recv().unwrap().row(), Some(2));
+
+        let (sender, _receiver) = mpsc::channel(16);
+        let summary = deserialize_xlsx(workbook(sheet).as_slice(), ReadOptions::default(), sender);
+        assert_eq!(summary.unwrap().skipped, 2);
+    }
+}